@@ -78,7 +78,7 @@ fn bench_proof_verification(c: &mut Criterion) {
             let result = tree.verify_proof(
                 black_box(&proof),
                 black_box(leaf_data),
-                black_box(root),
+                black_box(&root),
             );
             black_box(result);
         });
@@ -0,0 +1,62 @@
+//! Derive macro for `merkle_tree::MerkleLeaf`
+//!
+//! Enable the `derive` feature of `merkle-tree-rs` and use the re-export
+//! `merkle_tree::MerkleLeaf` rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Index};
+
+/// Encode a struct as the concatenation of its fields' leaf encodings, in
+/// declaration order
+///
+/// Every field type must implement `MerkleLeaf`. Generic parameters get a
+/// `MerkleLeaf` bound.
+#[proc_macro_derive(MerkleLeaf)]
+pub fn derive_merkle_leaf(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return syn::Error::new_spanned(
+                &input.ident,
+                "MerkleLeaf can only be derived for structs",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+    let encode_fields: Vec<_> = match fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| {
+                let name = &field.ident;
+                quote! { ::merkle_tree::MerkleLeaf::encode_leaf(&self.#name, out); }
+            })
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|index| {
+                let index = Index::from(index);
+                quote! { ::merkle_tree::MerkleLeaf::encode_leaf(&self.#index, out); }
+            })
+            .collect(),
+        Fields::Unit => vec![quote! { let _ = out; }],
+    };
+
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::merkle_tree::MerkleLeaf));
+    }
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::merkle_tree::MerkleLeaf for #name #type_generics #where_clause {
+            fn encode_leaf(&self, out: &mut ::std::vec::Vec<u8>) {
+                #(#encode_fields)*
+            }
+        }
+    }
+    .into()
+}
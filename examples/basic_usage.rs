@@ -1,6 +1,6 @@
 use merkle_tree::{
     MerkleTree, SparseMerkleTree, Sha256Hasher, Sha3Hasher, Blake3Hasher,
-    Result, sparse
+    Result
 };
 
 fn main() -> Result<()> {
@@ -102,8 +102,9 @@ fn sparse_tree_example() -> Result<()> {
     println!("Proof for Alice's account: {}", if is_valid { "✓ Valid" } else { "✗ Invalid" });
     
     // Prove non-existence of account at index 2000
-    let non_existence_proof = sparse_tree.generate_proof(2000)?;
-    let empty_valid = sparse_tree.verify_proof(&non_existence_proof, 2000, &sparse::DEFAULT_HASH);
+    let root = sparse_tree.root().clone();
+    let non_existence_proof = sparse_tree.prove_empty(2000)?;
+    let empty_valid = sparse_tree.verify_empty(&root, 2000, &non_existence_proof);
     println!("Proof of non-existence at index 2000: {}", if empty_valid { "✓ Valid" } else { "✗ Invalid" });
     
     println!();
@@ -122,9 +123,9 @@ fn proof_verification_example() -> Result<()> {
     ];
     
     let tree = MerkleTree::new(documents, Blake3Hasher::new())?;
-    let root_hash = tree.root().to_vec();
+    let root_hash = tree.root();
     
-    println!("Document tree root: {}", hex::encode(&root_hash));
+    println!("Document tree root: {}", root_hash);
     
     // Generate proof for Contract B
     let proof = tree.generate_proof(1)?;
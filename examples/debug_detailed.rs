@@ -43,8 +43,8 @@ fn main() {
 
     // Check if they match
     println!("\nComparisons:");
-    println!("  Root matches: {}", root == tree.root());
-    println!("  Leaf 0 matches: {}", leaf_a == tree.get_leaf(0).unwrap());
+    println!("  Root matches: {}", tree.root() == *root);
+    println!("  Leaf 0 matches: {}", leaf_a == *tree.get_leaf(0).unwrap());
 
     // Test proof for leaf 0 (should need: leaf_b, right_internal)
     let proof = tree.generate_proof(0).unwrap();
@@ -60,7 +60,7 @@ fn main() {
     println!("  Start with leaf_a: {:?}", current);
 
     // Step 1: Should combine with leaf_b to get left_internal
-    if proof.steps.len() > 0 {
+    if !proof.steps.is_empty() {
         let step1_expected = leaf_b.clone();
         let step1_actual = proof.steps[0].hash.clone();
         println!("  Step 1 - Expected sibling (leaf_b): {:?}", step1_expected);
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merkle_tree::{Hasher, MerkleProof, RootHash, Sha256Hasher};
+
+fuzz_target!(|data: &[u8]| {
+    let hasher = Sha256Hasher::new();
+    let root = RootHash::from(hasher.hash(b"root"));
+    let _ = MerkleProof::verify_streaming(data, &hasher, b"leaf", &root);
+    if let Ok(proof) = MerkleProof::deserialize_from(data) {
+        let _ = proof.verify(&hasher, b"leaf", &root);
+        let _ = MerkleProof::from_canonical_bytes(&proof.to_canonical_bytes(&hasher), &hasher);
+    }
+    let _ = MerkleProof::from_canonical_bytes(data, &hasher);
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merkle_tree::{Hasher, RootHash, Sha256Hasher, SparseProof};
+
+fuzz_target!(|data: &[u8]| {
+    let hasher = Sha256Hasher::new();
+    if let Ok(proof) = SparseProof::from_bytes(data) {
+        let root = RootHash::from(hasher.hash(b"root"));
+        let _ = proof.verify(&hasher, b"leaf", &root);
+        let _ = proof.to_merkle_proof(&hasher);
+        let _ = proof.to_bytes();
+    }
+});
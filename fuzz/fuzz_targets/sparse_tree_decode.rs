@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merkle_tree::{Sha256Hasher, SparseMerkleTree};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut tree) = SparseMerkleTree::deserialize_from(data, Sha256Hasher::new()) {
+        let _ = tree.verify_integrity();
+        let _ = tree.generate_proof(0);
+        let _ = tree.generate_sparse_proof(0);
+    }
+});
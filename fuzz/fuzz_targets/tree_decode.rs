@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merkle_tree::{MerkleTree, SerializedTree, Sha256Hasher};
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let hasher = Sha256Hasher::new();
+    if let Ok(tree) = MerkleTree::deserialize_from(data, hasher.clone()) {
+        let _ = tree.verify_integrity();
+        for index in 0..tree.len().min(16) {
+            let _ = tree.generate_proof(index);
+        }
+    }
+    if let Ok(mut tree) = SerializedTree::from_reader(Cursor::new(data), hasher) {
+        for index in 0..tree.len().min(16) {
+            let _ = tree.generate_proof(index);
+        }
+    }
+});
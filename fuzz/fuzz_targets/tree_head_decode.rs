@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merkle_tree::TreeHead;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(head) = TreeHead::from_bytes(data) {
+        // Decoding is canonical, so re-encoding gives the input back
+        assert_eq!(head.to_bytes(), data);
+    }
+});
@@ -0,0 +1,144 @@
+//! Ethereum ABI leaf layouts
+//!
+//! Solidity contracts usually derive a leaf as `keccak256(abi.encode(..))`
+//! or `keccak256(abi.encodePacked(..))` of a few fixed fields. [`AbiLeaf`]
+//! encodes the common layouts byte for byte as Solidity does, so trees built
+//! here verify on-chain:
+//!
+//! - `abi.encode` pads every field to 32 bytes, with addresses right-aligned
+//! - `abi.encodePacked` writes addresses as their 20 bytes and `uint256` as
+//!   32 bytes
+//!
+//! Build over the encodings with [`Keccak256Hasher`] to get
+//! `keccak256(encoding)` leaves, or with
+//! [`LeafEncoding::DoubleHash`](crate::builder::LeafEncoding::DoubleHash) for
+//! OpenZeppelin's `StandardMerkleTree`.
+
+use crate::airdrop::Address;
+use crate::hash::Hash;
+use crate::hasher::{Hasher, Keccak256Hasher};
+
+/// 32-byte big-endian `uint256`
+pub type Uint256 = [u8; 32];
+
+/// Widen an integer to a `uint256`
+pub fn uint256(value: u128) -> Uint256 {
+    let mut word = [0u8; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// Solidity encoding function a leaf is hashed over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AbiEncoding {
+    /// `abi.encode`, every field padded to 32 bytes
+    #[default]
+    Standard,
+    /// `abi.encodePacked`, every field at its natural width
+    Packed,
+}
+
+/// Common Ethereum leaf layouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbiLeaf {
+    /// `(address)`, e.g. an allowlist
+    Address(Address),
+    /// `(address, uint256)`, e.g. an account and an amount
+    AddressUint(Address, Uint256),
+    /// `(uint256, address, uint256)`, e.g. Uniswap's `MerkleDistributor`
+    /// `(index, account, amount)`
+    UintAddressUint(Uint256, Address, Uint256),
+}
+
+impl AbiLeaf {
+    /// Encode the leaf with the given function
+    pub fn encode(&self, encoding: AbiEncoding) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(96);
+        let address = |encoded: &mut Vec<u8>, address: &Address| {
+            if encoding == AbiEncoding::Standard {
+                encoded.extend_from_slice(&[0u8; 12]);
+            }
+            encoded.extend_from_slice(address);
+        };
+
+        match self {
+            Self::Address(account) => address(&mut encoded, account),
+            Self::AddressUint(account, value) => {
+                address(&mut encoded, account);
+                encoded.extend_from_slice(value);
+            }
+            Self::UintAddressUint(first, account, last) => {
+                encoded.extend_from_slice(first);
+                address(&mut encoded, account);
+                encoded.extend_from_slice(last);
+            }
+        }
+        encoded
+    }
+
+    /// Compute `keccak256` of the encoded leaf
+    pub fn keccak(&self, encoding: AbiEncoding) -> Hash {
+        Keccak256Hasher::new().hash(&self.encode(encoding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::airdrop;
+    use crate::builder::{LeafEncoding, MerkleTreeBuilder};
+
+    const ACCOUNT: Address = [0xab; 20];
+
+    #[test]
+    fn test_uint256() {
+        let word = uint256(0x0102);
+        assert_eq!(word[..30], [0u8; 30]);
+        assert_eq!(word[30..], [1, 2]);
+    }
+
+    #[test]
+    fn test_layouts() {
+        let leaf = AbiLeaf::Address(ACCOUNT);
+        let encoded = leaf.encode(AbiEncoding::Standard);
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(encoded[..12], [0u8; 12]);
+        assert_eq!(leaf.encode(AbiEncoding::Packed), ACCOUNT);
+
+        let leaf = AbiLeaf::AddressUint(ACCOUNT, uint256(5));
+        assert_eq!(leaf.encode(AbiEncoding::Standard).len(), 64);
+        assert_eq!(
+            leaf.encode(AbiEncoding::Standard),
+            airdrop::abi_encode(&ACCOUNT, 5)
+        );
+        assert_eq!(
+            leaf.encode(AbiEncoding::Packed),
+            [&ACCOUNT[..], &uint256(5)].concat()
+        );
+
+        let leaf = AbiLeaf::UintAddressUint(uint256(1), ACCOUNT, uint256(7));
+        let encoded = leaf.encode(AbiEncoding::Standard);
+        assert_eq!(encoded.len(), 96);
+        assert_eq!(encoded[32..44], [0u8; 12]);
+        assert_eq!(encoded[44..64], ACCOUNT);
+        let packed = leaf.encode(AbiEncoding::Packed);
+        assert_eq!(packed.len(), 84);
+        assert_eq!(packed, [&uint256(1)[..], &ACCOUNT, &uint256(7)].concat());
+    }
+
+    #[test]
+    fn test_matches_airdrop_leaves() {
+        let leaf = AbiLeaf::AddressUint(ACCOUNT, uint256(1000));
+        let tree = MerkleTreeBuilder::new(Keccak256Hasher::new())
+            .leaf_encoding(LeafEncoding::DoubleHash)
+            .build(vec![leaf.encode(AbiEncoding::Standard)])
+            .unwrap();
+        assert_eq!(tree.leaves()[0], airdrop::leaf_hash(&ACCOUNT, 1000));
+
+        let hasher = Keccak256Hasher::new();
+        assert_eq!(
+            leaf.keccak(AbiEncoding::Packed),
+            hasher.hash(&leaf.encode(AbiEncoding::Packed))
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! Append-only Merkle accumulator with self-updating witnesses
+//!
+//! The accumulator only keeps the roots ("peaks") of the perfect subtrees
+//! that make up the binary decomposition of its size, so appending is
+//! `O(log n)` in time and memory. The root is formed by folding the peaks
+//! from right to left, which gives the same tree shape as RFC 6962: for a
+//! power-of-two number of leaves it equals the [`MerkleTree`](crate::MerkleTree)
+//! root over the same leaves.
+//!
+//! Leaf holders keep a [`Witness`] and feed it the [`AppendUpdate`] published
+//! for every later append, so their inclusion proof stays valid against the
+//! latest root without asking the accumulator for a new one.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Append-only accumulator over leaf hashes
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MerkleAccumulator<H: Hasher> {
+    hasher: H,
+    size: usize,
+    peaks: Vec<Hash>,
+}
+
+/// Change published after each append, consumed by [`Witness::apply_append`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AppendUpdate {
+    /// Index of the appended leaf
+    pub index: usize,
+    /// Hash of the appended leaf
+    pub leaf: Hash,
+    /// Peaks of the accumulator after the append, largest subtree first
+    pub peaks: Vec<Hash>,
+}
+
+/// Inclusion witness for a single leaf that follows the accumulator as it grows
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Witness {
+    index: usize,
+    leaf: Hash,
+    /// Siblings from the leaf up to the root of the peak containing it
+    path: Vec<Hash>,
+    /// Accumulator size this witness is valid for
+    size: usize,
+    /// Accumulator peaks at `size`
+    peaks: Vec<Hash>,
+}
+
+impl<H: Hasher> MerkleAccumulator<H> {
+    /// Create an empty accumulator
+    pub fn new(hasher: H) -> Self {
+        Self {
+            hasher,
+            size: 0,
+            peaks: Vec::new(),
+        }
+    }
+
+    /// Hash and append a data item
+    pub fn append(&mut self, data: &[u8]) -> (Witness, AppendUpdate) {
+        let leaf = self.hasher.hash(data);
+        self.append_leaf(leaf)
+    }
+
+    /// Append a pre-hashed leaf
+    ///
+    /// Returns the witness for the new leaf and the update that holders of
+    /// older witnesses must apply.
+    pub fn append_leaf(&mut self, leaf: Hash) -> (Witness, AppendUpdate) {
+        let index = self.size;
+        let mut path = Vec::new();
+        let mut carry = leaf.clone();
+
+        // Merge with every peak of equal height, i.e. each trailing one bit
+        let mut size = self.size;
+        while size & 1 == 1 {
+            let peak = self.peaks.pop().expect("one peak per set bit");
+            carry = self.hasher.hash_pair(&peak, &carry);
+            path.push(peak);
+            size >>= 1;
+        }
+
+        self.peaks.push(carry);
+        self.size += 1;
+
+        let update = AppendUpdate {
+            index,
+            leaf: leaf.clone(),
+            peaks: self.peaks.clone(),
+        };
+        let witness = Witness {
+            index,
+            leaf,
+            path,
+            size: self.size,
+            peaks: self.peaks.clone(),
+        };
+
+        (witness, update)
+    }
+
+    /// Get the current root hash
+    ///
+    /// An empty accumulator has an empty root.
+    pub fn root(&self) -> RootHash {
+        bag_peaks(&self.hasher, &self.peaks)
+            .map(RootHash::from)
+            .unwrap_or_default()
+    }
+
+    /// Get the peaks, largest subtree first
+    pub fn peaks(&self) -> &[Hash] {
+        &self.peaks
+    }
+
+    /// Get the number of leaves appended so far
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Check if nothing has been appended yet
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Get the hasher used by the accumulator
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+}
+
+impl Witness {
+    /// Get the index of the witnessed leaf
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Get the witnessed leaf hash
+    pub fn leaf(&self) -> &Hash {
+        &self.leaf
+    }
+
+    /// Get the accumulator size this witness is currently valid for
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Bring the witness up to date with the next append
+    ///
+    /// Updates must be applied in order. An update that does not follow the
+    /// witness's size, or whose peaks do not match the witness's own view of
+    /// the accumulator, is rejected and leaves the witness unchanged.
+    pub fn apply_append<H: Hasher>(&mut self, hasher: &H, update: &AppendUpdate) -> Result<()> {
+        if update.index != self.size {
+            return Err(MerkleError::InvalidProof {
+                reason: format!(
+                    "Update for leaf {} does not follow accumulator size {}",
+                    update.index, self.size
+                ),
+            });
+        }
+
+        let own_peak = peak_position(self.index, self.size).0;
+        let mut peaks = self.peaks.clone();
+        let mut path = self.path.clone();
+        let mut carry = update.leaf.clone();
+        let mut merged = false;
+
+        let mut size = self.size;
+        while size & 1 == 1 {
+            let peak = peaks.pop().expect("one peak per set bit");
+            if merged {
+                // A larger peak joins from the left
+                path.push(peak.clone());
+            } else if peaks.len() == own_peak {
+                // Everything appended after our peak joins from the right
+                path.push(carry.clone());
+                merged = true;
+            }
+            carry = hasher.hash_pair(&peak, &carry);
+            size >>= 1;
+        }
+        peaks.push(carry);
+
+        if peaks != update.peaks {
+            return Err(MerkleError::InvalidProof {
+                reason: "Update peaks do not match the witnessed accumulator".to_string(),
+            });
+        }
+
+        self.path = path;
+        self.peaks = peaks;
+        self.size += 1;
+        Ok(())
+    }
+
+    /// Build an inclusion proof against the current root
+    pub fn proof<H: Hasher>(&self, hasher: &H) -> MerkleProof {
+        let (own_peak, offset) = peak_position(self.index, self.size);
+        let position = self.index - offset;
+
+        let mut steps: Vec<ProofStep> = self
+            .path
+            .iter()
+            .enumerate()
+            .map(|(level, hash)| ProofStep {
+                hash: hash.clone(),
+                direction: if (position >> level) & 1 == 1 {
+                    ProofDirection::Left
+                } else {
+                    ProofDirection::Right
+                },
+            })
+            .collect();
+
+        // Peaks to the right are bagged into one sibling, then each peak to
+        // the left wraps the result
+        if let Some(right) = bag_peaks(hasher, &self.peaks[own_peak + 1..]) {
+            steps.push(ProofStep {
+                hash: right,
+                direction: ProofDirection::Right,
+            });
+        }
+        steps.extend(self.peaks[..own_peak].iter().rev().map(|hash| ProofStep {
+            hash: hash.clone(),
+            direction: ProofDirection::Left,
+        }));
+
+        MerkleProof::new(self.index, steps)
+    }
+
+    /// Verify the witness against a root
+    pub fn verify<H: Hasher>(&self, hasher: &H, root: &RootHash) -> bool {
+        self.proof(hasher)
+            .verify_with_leaf_hash(hasher, &self.leaf, root)
+    }
+}
+
+/// Fold peaks from right to left into a single root
+fn bag_peaks<H: Hasher>(hasher: &H, peaks: &[Hash]) -> Option<Hash> {
+    let (last, rest) = peaks.split_last()?;
+    Some(
+        rest.iter()
+            .rev()
+            .fold(last.clone(), |acc, peak| hasher.hash_pair(peak, &acc)),
+    )
+}
+
+/// Find which peak holds `index` and the index of that peak's first leaf
+fn peak_position(index: usize, size: usize) -> (usize, usize) {
+    let mut offset = 0;
+    let mut peak = 0;
+
+    for height in (0..usize::BITS).rev() {
+        let span = 1usize << height;
+        if size & span == 0 {
+            continue;
+        }
+        if index < offset + span {
+            break;
+        }
+        offset += span;
+        peak += 1;
+    }
+
+    (peak, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+    use crate::merkle_tree::MerkleTree;
+
+    /// Root per RFC 6962: split at the largest power of two below the size
+    fn reference_root(hasher: &Sha256Hasher, leaves: &[Hash]) -> Hash {
+        if leaves.len() == 1 {
+            return leaves[0].clone();
+        }
+        let split = leaves.len().next_power_of_two() / 2;
+        hasher.hash_pair(
+            &reference_root(hasher, &leaves[..split]),
+            &reference_root(hasher, &leaves[split..]),
+        )
+    }
+
+    #[test]
+    fn test_root_shape() {
+        let hasher = Sha256Hasher::new();
+        let mut acc = MerkleAccumulator::new(hasher.clone());
+        let mut leaves = Vec::new();
+        assert!(acc.root().is_empty());
+
+        for i in 0..33u32 {
+            acc.append(&i.to_be_bytes());
+            leaves.push(hasher.hash(&i.to_be_bytes()));
+
+            assert_eq!(acc.len(), leaves.len());
+            assert_eq!(acc.peaks().len(), leaves.len().count_ones() as usize);
+            assert_eq!(*acc.root().as_hash(), reference_root(&hasher, &leaves));
+
+            if leaves.len().is_power_of_two() {
+                let tree = MerkleTree::from_leaves(leaves.clone(), hasher.clone()).unwrap();
+                assert_eq!(acc.root(), tree.root());
+            }
+        }
+    }
+
+    #[test]
+    fn test_witnesses_follow_appends() {
+        let hasher = Sha256Hasher::new();
+        let mut acc = MerkleAccumulator::new(hasher.clone());
+        let mut witnesses: Vec<Witness> = Vec::new();
+
+        for i in 0..40u32 {
+            let (witness, update) = acc.append(format!("leaf_{}", i).as_bytes());
+            for old in &mut witnesses {
+                old.apply_append(&hasher, &update).unwrap();
+            }
+            witnesses.push(witness);
+
+            let root = acc.root();
+            for witness in &witnesses {
+                assert_eq!(witness.size(), acc.len());
+                assert!(witness.verify(&hasher, &root), "leaf {}", witness.index());
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_updates() {
+        let hasher = Sha256Hasher::new();
+        let mut acc = MerkleAccumulator::new(hasher.clone());
+        let (mut witness, _) = acc.append(b"a");
+        let (_, first) = acc.append(b"b");
+        let (_, second) = acc.append(b"c");
+
+        // Skipping an update is detected
+        assert!(witness.apply_append(&hasher, &second).is_err());
+
+        // So is an update whose leaf does not match its peaks
+        let mut forged = first.clone();
+        forged.leaf = hasher.hash(b"forged");
+        assert!(witness.apply_append(&hasher, &forged).is_err());
+        assert_eq!(witness.size(), 1);
+
+        witness.apply_append(&hasher, &first).unwrap();
+        witness.apply_append(&hasher, &second).unwrap();
+        assert!(witness.verify(&hasher, &acc.root()));
+    }
+}
@@ -0,0 +1,306 @@
+//! Token distribution (airdrop) trees
+//!
+//! Builds the tree most on-chain distributors expect: each `(address, amount)`
+//! pair is ABI-encoded as `(address, uint256)`, hashed twice with Keccak-256
+//! as in OpenZeppelin's `StandardMerkleTree`, and combined with sorted-pair
+//! hashing so that proofs verify with `MerkleProof.verify`.
+
+use crate::abi::uint256;
+use crate::builder::{LeafEncoding, MerkleTreeBuilder};
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::{Hasher, Keccak256Hasher, SortedPairHasher};
+use crate::merkle_tree::MerkleTree;
+use std::collections::HashMap;
+
+/// 20-byte Ethereum address
+pub type Address = [u8; 20];
+
+/// Hasher used by airdrop trees
+pub type AirdropHasher = SortedPairHasher<Keccak256Hasher>;
+
+/// ABI-encode an `(address, uint256)` pair
+pub fn abi_encode(address: &Address, amount: u128) -> [u8; 64] {
+    let mut encoded = [0u8; 64];
+    encoded[12..32].copy_from_slice(address);
+    encoded[32..].copy_from_slice(&uint256(amount));
+    encoded
+}
+
+/// Compute the leaf hash `keccak256(keccak256(abi.encode(address, amount)))`
+pub fn leaf_hash(address: &Address, amount: u128) -> Hash {
+    LeafEncoding::DoubleHash.encode(&Keccak256Hasher::new(), &abi_encode(address, amount))
+}
+
+/// Parse a `0x`-prefixed or bare hex address
+pub fn parse_address(s: &str) -> Result<Address> {
+    let bytes = Hash::from_hex(s)?;
+    bytes
+        .as_bytes()
+        .try_into()
+        .map_err(|_| MerkleError::SerializationError {
+            message: format!("Address {:?} is not 20 bytes", s),
+        })
+}
+
+/// Format an address with its EIP-55 mixed-case checksum
+pub fn format_address(address: &Address) -> String {
+    let lower = hex::encode(address);
+    let digest = Keccak256Hasher::new().hash(lower.as_bytes());
+
+    let mut formatted = String::with_capacity(42);
+    formatted.push_str("0x");
+    for (i, c) in lower.chars().enumerate() {
+        let nibble = (digest[i / 2] >> (4 * (1 - i % 2))) & 0x0f;
+        formatted.push(if nibble >= 8 {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        });
+    }
+    formatted
+}
+
+/// Claim of a single recipient, with everything needed to submit it on-chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claim {
+    pub index: usize,
+    pub address: Address,
+    pub amount: u128,
+    /// Sibling hashes from the leaf up to the root
+    pub proof: Vec<Hash>,
+}
+
+impl Claim {
+    /// Verify the claim against a distribution root
+    pub fn verify(&self, root: &RootHash) -> bool {
+        let hasher = AirdropHasher::default();
+        let computed = self
+            .proof
+            .iter()
+            .fold(leaf_hash(&self.address, self.amount), |node, sibling| {
+                hasher.hash_pair(&node, sibling)
+            });
+        computed == *root.as_hash()
+    }
+}
+
+/// Merkle tree over an airdrop's recipients
+#[derive(Debug, Clone)]
+pub struct Airdrop {
+    tree: MerkleTree<AirdropHasher>,
+    recipients: Vec<(Address, u128)>,
+    index: HashMap<Address, usize>,
+}
+
+impl Airdrop {
+    /// Build a distribution from `(address, amount)` pairs
+    ///
+    /// Repeated identical entries are collapsed into one leaf, since a
+    /// duplicated leaf could be claimed twice. Listing the same address with
+    /// different amounts is rejected.
+    pub fn new<I>(entries: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (Address, u128)>,
+    {
+        let mut recipients = Vec::new();
+        let mut index = HashMap::new();
+
+        for (address, amount) in entries {
+            match index.get(&address) {
+                Some(&i) => {
+                    let (_, existing): (Address, u128) = recipients[i];
+                    if existing != amount {
+                        return Err(MerkleError::TreeConstructionError {
+                            reason: format!(
+                                "Address {} is listed with amounts {} and {}",
+                                format_address(&address),
+                                existing,
+                                amount
+                            ),
+                        });
+                    }
+                }
+                None => {
+                    index.insert(address, recipients.len());
+                    recipients.push((address, amount));
+                }
+            }
+        }
+
+        let encoded: Vec<[u8; 64]> = recipients
+            .iter()
+            .map(|(address, amount)| abi_encode(address, *amount))
+            .collect();
+        let tree = MerkleTreeBuilder::new(AirdropHasher::default())
+            .leaf_encoding(LeafEncoding::DoubleHash)
+            .build(encoded)?;
+
+        Ok(Self {
+            tree,
+            recipients,
+            index,
+        })
+    }
+
+    /// Get the distribution root
+    pub fn root(&self) -> RootHash {
+        self.tree.root()
+    }
+
+    /// Get the underlying tree
+    pub fn tree(&self) -> &MerkleTree<AirdropHasher> {
+        &self.tree
+    }
+
+    /// Get the deduplicated recipients in leaf order
+    pub fn recipients(&self) -> &[(Address, u128)] {
+        &self.recipients
+    }
+
+    /// Get the claim for an address, if it is part of the distribution
+    pub fn claim(&self, address: &Address) -> Option<Claim> {
+        let &index = self.index.get(address)?;
+        let proof = self.tree.generate_proof(index).ok()?;
+
+        Some(Claim {
+            index,
+            address: *address,
+            amount: self.recipients[index].1,
+            proof: proof.steps.into_iter().map(|step| step.hash).collect(),
+        })
+    }
+
+    /// Get the claims of all recipients in leaf order
+    pub fn claims(&self) -> Vec<Claim> {
+        self.recipients
+            .iter()
+            .filter_map(|(address, _)| self.claim(address))
+            .collect()
+    }
+
+    /// Export the root and every recipient's claim as JSON
+    ///
+    /// The layout follows the common merkle-distributor claims file:
+    /// `{"merkleRoot": "0x..", "claims": {"0xAddr": {"index", "amount", "proof"}}}`
+    /// with checksummed addresses and amounts as decimal strings.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String> {
+        use serde_json::{json, Map, Value};
+
+        let claims: Map<String, Value> = self
+            .claims()
+            .into_iter()
+            .map(|claim| {
+                let proof: Vec<String> = claim
+                    .proof
+                    .iter()
+                    .map(|hash| format!("{:#x}", hash))
+                    .collect();
+                (
+                    format_address(&claim.address),
+                    json!({
+                        "index": claim.index,
+                        "amount": claim.amount.to_string(),
+                        "proof": proof,
+                    }),
+                )
+            })
+            .collect();
+
+        let file = json!({
+            "merkleRoot": format!("{:#x}", self.root()),
+            "claims": claims,
+        });
+        serde_json::to_string_pretty(&file).map_err(|e| MerkleError::SerializationError {
+            message: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(s: &str) -> Address {
+        parse_address(s).unwrap()
+    }
+
+    #[test]
+    fn test_matches_openzeppelin_standard_tree() {
+        // Example from the @openzeppelin/merkle-tree README
+        let airdrop = Airdrop::new([
+            (
+                address("0x1111111111111111111111111111111111111111"),
+                5_000_000_000_000_000_000,
+            ),
+            (
+                address("0x2222222222222222222222222222222222222222"),
+                2_500_000_000_000_000_000,
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            airdrop.root().to_hex(),
+            "d4dee0beab2d53f2cc83e567171bd2820e49898130a22622b10ead383e90bd77"
+        );
+    }
+
+    #[test]
+    fn test_claims_verify() {
+        let entries: Vec<(Address, u128)> = (1..=7u8).map(|i| ([i; 20], i as u128 * 100)).collect();
+        let airdrop = Airdrop::new(entries).unwrap();
+        let root = airdrop.root();
+
+        for claim in airdrop.claims() {
+            assert!(claim.verify(&root));
+
+            let mut forged = claim.clone();
+            forged.amount += 1;
+            assert!(!forged.verify(&root));
+        }
+        assert!(airdrop.claim(&[9; 20]).is_none());
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let airdrop = Airdrop::new([([1; 20], 10), ([2; 20], 20), ([1; 20], 10)]).unwrap();
+        assert_eq!(airdrop.recipients(), &[([1; 20], 10), ([2; 20], 20)]);
+        assert_eq!(airdrop.tree().len(), 2);
+
+        assert!(matches!(
+            Airdrop::new([([1; 20], 10), ([1; 20], 11)]),
+            Err(MerkleError::TreeConstructionError { .. })
+        ));
+        assert!(matches!(
+            Airdrop::new(Vec::new()),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_address_formatting() {
+        // EIP-55 test vector
+        let addr = address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+        assert_eq!(
+            format_address(&addr),
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+        assert!(parse_address("0x1234").is_err());
+        assert!(parse_address("not hex").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_to_json() {
+        let airdrop = Airdrop::new([([1; 20], 10), ([2; 20], u128::MAX)]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&airdrop.to_json().unwrap()).unwrap();
+
+        assert_eq!(json["merkleRoot"], format!("{:#x}", airdrop.root()));
+        let claim = &json["claims"][format_address(&[2; 20])];
+        assert_eq!(claim["index"], 1);
+        assert_eq!(claim["amount"], u128::MAX.to_string());
+        assert_eq!(claim["proof"].as_array().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,294 @@
+//! Augmented Merkle trees with custom node values
+//!
+//! A [`NodeCombiner`] decides what an internal node holds and how it is
+//! computed from its children, so a node can carry a count, sum or range next
+//! to its hash. Every [`Hasher`] is a combiner over plain hashes, for which
+//! [`AugmentedTree`] has the same shape and root as
+//! [`MerkleTree`](crate::MerkleTree).
+//!
+//! [`SumCombiner`] builds sum trees as used for proof of liabilities: each
+//! node commits to the total of the amounts below it, and an inclusion proof
+//! shows the partial sums of every sibling subtree.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::Hash;
+use crate::hasher::Hasher;
+use crate::proof::ProofDirection;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Computes internal nodes of an augmented tree
+pub trait NodeCombiner: Clone {
+    /// Value stored in every node
+    type Node: Clone + PartialEq + std::fmt::Debug;
+
+    /// Compute a parent from its children
+    fn combine(&self, left: &Self::Node, right: &Self::Node) -> Result<Self::Node>;
+
+    /// Sibling for the last node of a level with an odd number of nodes
+    ///
+    /// Defaults to duplicating the node, as [`MerkleTree`](crate::MerkleTree)
+    /// does.
+    fn pad(&self, last: &Self::Node) -> Self::Node {
+        last.clone()
+    }
+}
+
+impl<H: Hasher> NodeCombiner for H {
+    type Node = Hash;
+
+    fn combine(&self, left: &Hash, right: &Hash) -> Result<Hash> {
+        Ok(self.hash_pair(left, right))
+    }
+}
+
+/// Binary tree whose nodes are computed by a [`NodeCombiner`]
+#[derive(Debug, Clone)]
+pub struct AugmentedTree<C: NodeCombiner> {
+    combiner: C,
+    /// Nodes per level, leaves first, without padding
+    levels: Vec<Vec<C::Node>>,
+}
+
+/// Inclusion proof for a leaf of an [`AugmentedTree`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AugmentedProof<N> {
+    pub leaf_index: usize,
+    /// Sibling nodes from the leaf level upwards
+    pub siblings: Vec<(N, ProofDirection)>,
+}
+
+impl<C: NodeCombiner> AugmentedTree<C> {
+    /// Build a tree over leaf nodes
+    pub fn new(leaves: Vec<C::Node>, combiner: C) -> Result<Self> {
+        if leaves.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+
+        let mut levels = vec![leaves];
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let parents = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => combiner.combine(left, right),
+                    [last] => combiner.combine(last, &combiner.pad(last)),
+                    _ => unreachable!(),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            levels.push(parents);
+        }
+
+        Ok(Self { combiner, levels })
+    }
+
+    /// Get the root node
+    pub fn root(&self) -> &C::Node {
+        &self.levels[self.levels.len() - 1][0]
+    }
+
+    /// Get the leaf nodes
+    pub fn leaves(&self) -> &[C::Node] {
+        &self.levels[0]
+    }
+
+    /// Get the number of leaves
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Check if the tree is empty
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Get the combiner
+    pub fn combiner(&self) -> &C {
+        &self.combiner
+    }
+
+    /// Generate an inclusion proof for a leaf
+    pub fn generate_proof(&self, index: usize) -> Result<AugmentedProof<C::Node>> {
+        if index >= self.len() {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len(),
+            });
+        }
+
+        let mut siblings = Vec::with_capacity(self.levels.len() - 1);
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = if position.is_multiple_of(2) {
+                let node = level
+                    .get(position + 1)
+                    .cloned()
+                    .unwrap_or_else(|| self.combiner.pad(&level[position]));
+                (node, ProofDirection::Right)
+            } else {
+                (level[position - 1].clone(), ProofDirection::Left)
+            };
+            siblings.push(sibling);
+            position /= 2;
+        }
+
+        Ok(AugmentedProof {
+            leaf_index: index,
+            siblings,
+        })
+    }
+}
+
+impl<N: Clone + PartialEq> AugmentedProof<N> {
+    /// Recompute the root from a leaf node
+    pub fn compute_root<C: NodeCombiner<Node = N>>(&self, combiner: &C, leaf: &N) -> Result<N> {
+        self.siblings.iter().try_fold(
+            leaf.clone(),
+            |current, (sibling, direction)| match direction {
+                ProofDirection::Left => combiner.combine(sibling, &current),
+                ProofDirection::Right => combiner.combine(&current, sibling),
+            },
+        )
+    }
+
+    /// Verify the proof against a root node
+    pub fn verify<C: NodeCombiner<Node = N>>(&self, combiner: &C, leaf: &N, root: &N) -> bool {
+        self.compute_root(combiner, leaf)
+            .map(|computed| computed == *root)
+            .unwrap_or(false)
+    }
+}
+
+/// Node of a sum tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SumNode {
+    pub hash: Hash,
+    pub sum: u64,
+}
+
+/// Combiner for sum trees
+///
+/// A parent commits to `H(left.hash || left.sum || right.hash || right.sum)`
+/// with sums as big-endian `u64`, and its sum is the total of its children.
+/// Overflowing totals are rejected. Odd nodes are padded with a zero-sum copy,
+/// so the root sum is always the total of the leaves.
+#[derive(Debug, Clone)]
+pub struct SumCombiner<H: Hasher> {
+    hasher: H,
+}
+
+impl<H: Hasher> SumCombiner<H> {
+    pub fn new(hasher: H) -> Self {
+        Self { hasher }
+    }
+
+    /// Build a leaf committing to `data` and `amount`
+    pub fn leaf(&self, data: &[u8], amount: u64) -> SumNode {
+        let mut input = Vec::with_capacity(data.len() + 8);
+        input.extend_from_slice(data);
+        input.extend_from_slice(&amount.to_be_bytes());
+        SumNode {
+            hash: self.hasher.hash(&input),
+            sum: amount,
+        }
+    }
+}
+
+impl<H: Hasher> NodeCombiner for SumCombiner<H> {
+    type Node = SumNode;
+
+    fn combine(&self, left: &SumNode, right: &SumNode) -> Result<SumNode> {
+        let sum =
+            left.sum
+                .checked_add(right.sum)
+                .ok_or_else(|| MerkleError::TreeConstructionError {
+                    reason: "Sum overflows u64".to_string(),
+                })?;
+
+        let mut input = Vec::with_capacity(2 * (left.hash.len() + 8));
+        input.extend_from_slice(&left.hash);
+        input.extend_from_slice(&left.sum.to_be_bytes());
+        input.extend_from_slice(&right.hash);
+        input.extend_from_slice(&right.sum.to_be_bytes());
+
+        Ok(SumNode {
+            hash: self.hasher.hash(&input),
+            sum,
+        })
+    }
+
+    fn pad(&self, last: &SumNode) -> SumNode {
+        SumNode {
+            hash: last.hash.clone(),
+            sum: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+    use crate::merkle_tree::MerkleTree;
+
+    #[test]
+    fn test_hasher_matches_merkle_tree() {
+        let hasher = Sha256Hasher::new();
+        for len in 1..=9 {
+            let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+            let leaves: Vec<Hash> = data
+                .iter()
+                .map(|item| hasher.hash(item.as_bytes()))
+                .collect();
+
+            let tree = AugmentedTree::new(leaves.clone(), hasher.clone()).unwrap();
+            let reference = MerkleTree::new(data, hasher.clone()).unwrap();
+            assert_eq!(tree.root(), reference.root().as_hash());
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.generate_proof(index).unwrap();
+                assert!(proof.verify(&hasher, leaf, tree.root()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_sum_tree() {
+        let combiner = SumCombiner::new(Sha256Hasher::new());
+        let amounts = [10u64, 20, 30, 40, 50];
+        let leaves: Vec<SumNode> = amounts
+            .iter()
+            .enumerate()
+            .map(|(i, &amount)| combiner.leaf(format!("user_{}", i).as_bytes(), amount))
+            .collect();
+
+        let tree = AugmentedTree::new(leaves.clone(), combiner.clone()).unwrap();
+        assert_eq!(tree.root().sum, 150);
+
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = tree.generate_proof(index).unwrap();
+            assert!(proof.verify(&combiner, leaf, tree.root()));
+
+            // Claiming a different amount breaks the proof
+            let mut inflated = leaf.clone();
+            inflated.sum += 1;
+            assert!(!proof.verify(&combiner, &inflated, tree.root()));
+        }
+
+        // Hiding liabilities in a sibling sum changes the root
+        let mut proof = tree.generate_proof(0).unwrap();
+        proof.siblings[1].0.sum = 0;
+        assert!(!proof.verify(&combiner, &leaves[0], tree.root()));
+    }
+
+    #[test]
+    fn test_sum_overflow() {
+        let combiner = SumCombiner::new(Sha256Hasher::new());
+        let leaves = vec![combiner.leaf(b"a", u64::MAX), combiner.leaf(b"b", 1)];
+        assert!(matches!(
+            AugmentedTree::new(leaves, combiner),
+            Err(MerkleError::TreeConstructionError { .. })
+        ));
+    }
+}
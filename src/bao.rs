@@ -0,0 +1,421 @@
+//! Verified streaming of BLAKE3 content in the Bao format
+//!
+//! BLAKE3 is itself a Merkle tree: content splits into 1 KiB chunks, and
+//! the root of the tree over their chaining values is the regular BLAKE3
+//! hash, the root [`Blake3Hasher`](crate::Blake3Hasher) gives for the whole
+//! content. [`encode_outboard`] stores that tree's parent nodes next to the
+//! content, in the outboard layout of [Bao]: the content length as 8
+//! little-endian bytes, then each parent's two child chaining values in
+//! pre-order.
+//!
+//! With the content and its outboard tree, a server cuts a slice for any
+//! byte range with [`extract_slice`]: the parents on the paths to the range
+//! interleaved with the chunks it covers. A client holding only the root
+//! reads it through a [`SliceDecoder`], which checks every parent and chunk
+//! as it arrives and never returns an unverified byte, so the start of the
+//! range is usable before the rest has been downloaded. A slice of the whole
+//! content is Bao's combined encoding.
+//!
+//! [Bao]: https://github.com/oconnor663/bao
+//!
+//! ```rust
+//! use merkle_tree::bao::{encode_outboard, extract_slice, SliceDecoder};
+//! use std::io::Read;
+//!
+//! # fn main() -> merkle_tree::Result<()> {
+//! let content = vec![7u8; 100_000];
+//! let (root, outboard) = encode_outboard(&content);
+//!
+//! let slice = extract_slice(&content, &outboard, 50_000, 3000)?;
+//! let mut decoder = SliceDecoder::new(slice.as_slice(), &root, 50_000, 3000);
+//! let mut range = Vec::new();
+//! decoder.read_to_end(&mut range).unwrap();
+//! assert_eq!(range, &content[50_000..53_000]);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::{MerkleError, Result};
+use crate::hash::RootHash;
+use blake3::hazmat::{
+    left_subtree_len, merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode,
+};
+use blake3::CHUNK_LEN;
+use std::io::{self, Read};
+
+/// Bytes of the length header
+const HEADER_LEN: usize = 8;
+/// Bytes of a parent node, its two child chaining values
+const PARENT_LEN: usize = 64;
+
+/// Hash `content` with BLAKE3 and encode its tree in Bao's outboard layout
+///
+/// Returns the root, equal to `blake3::hash(content)`, and the outboard
+/// encoding: `8 + 64 * (chunks - 1)` bytes for `content.len()` rounded up to
+/// whole chunks, or 8 for empty content.
+pub fn encode_outboard(content: &[u8]) -> (RootHash, Vec<u8>) {
+    let mut outboard = Vec::with_capacity(outboard_len(content.len() as u64));
+    outboard.extend_from_slice(&(content.len() as u64).to_le_bytes());
+    let root = encode_subtree(content, 0, true, &mut outboard);
+    (RootHash::new(root.to_vec()), outboard)
+}
+
+/// Cut the slice of `outboard`'s tree and `content` covering `len` bytes
+/// from `start`
+///
+/// The slice holds the length header, then in pre-order the parents of every
+/// subtree overlapping the range and the chunks in it. As in Bao, an empty
+/// range counts as one byte, and a range past the end selects the final
+/// chunk, which is what proves the content length.
+pub fn extract_slice(content: &[u8], outboard: &[u8], start: u64, len: u64) -> Result<Vec<u8>> {
+    let content_len = content.len() as u64;
+    let header = outboard.get(..HEADER_LEN).map(read_len);
+    if header != Some(content_len) || outboard.len() != outboard_len(content_len) {
+        return Err(MerkleError::InvalidProof {
+            reason: "Outboard tree does not match the content length".to_string(),
+        });
+    }
+
+    let (start, end) = effective_range(content_len, start, len);
+    let mut slice = outboard[..HEADER_LEN].to_vec();
+    let mut parents = &outboard[HEADER_LEN..];
+    slice_subtree(content, 0, &mut parents, start, end, &mut slice);
+    Ok(slice)
+}
+
+/// Reader of the verified bytes of a slice from [`extract_slice`]
+///
+/// Reads fail with [`io::ErrorKind::InvalidData`] at the first parent or
+/// chunk that does not hash to what its parent, or the root, committed to;
+/// everything returned before that point has been verified. The decoder
+/// returns only the bytes of the requested range, clamped to the content,
+/// so `start` and `len` must be those the slice was cut with.
+#[derive(Debug)]
+pub struct SliceDecoder<R: Read> {
+    reader: R,
+    root: RootHash,
+    start: u64,
+    len: u64,
+    /// Subtrees still to read, as offset, length and expected hash, the next
+    /// one last
+    pending: Vec<(u64, u64, [u8; 32])>,
+    content_len: Option<u64>,
+    /// Verified bytes not yet returned
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> SliceDecoder<R> {
+    /// Decode the slice read from `reader` for `len` bytes from `start` of
+    /// the content with BLAKE3 hash `root`
+    pub fn new(reader: R, root: &RootHash, start: u64, len: u64) -> Self {
+        Self {
+            reader,
+            root: root.clone(),
+            start,
+            len,
+            pending: Vec::new(),
+            content_len: None,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Get the content length from the slice header, once the first read
+    /// has returned
+    ///
+    /// The length is part of what the tree commits to, but a wrong one is
+    /// only certain to be caught once the final chunk has been verified,
+    /// which a slice includes when its range reaches the end.
+    pub fn content_len(&self) -> Option<u64> {
+        self.content_len
+    }
+
+    /// Read and verify the next chunk in the range into the buffer,
+    /// returning `false` once the range is done
+    fn next_chunk(&mut self) -> Result<bool> {
+        let content_len = match self.content_len {
+            Some(len) => len,
+            None => {
+                let mut header = [0u8; HEADER_LEN];
+                self.read_raw(&mut header)?;
+                let len = read_len(&header);
+                let root = <[u8; 32]>::try_from(self.root.as_bytes()).map_err(|_| {
+                    MerkleError::InvalidProof {
+                        reason: "BLAKE3 roots are 32 bytes".to_string(),
+                    }
+                })?;
+                self.content_len = Some(len);
+                self.pending.push((0, len, root));
+                len
+            }
+        };
+        let (start, end) = effective_range(content_len, self.start, self.len);
+
+        while let Some((offset, len, expected)) = self.pending.pop() {
+            if !overlaps(offset, len, start, end) {
+                continue;
+            }
+            let is_root = offset == 0 && len == content_len;
+            if len <= CHUNK_LEN as u64 {
+                let mut chunk = vec![0u8; len as usize];
+                self.read_raw(&mut chunk)?;
+                if chunk_hash(&chunk, offset, is_root) != expected {
+                    return Err(mismatch("chunk", offset));
+                }
+                // Only the requested bytes, which may be none for a range
+                // past the end
+                let requested_end = self.start.saturating_add(self.len).min(content_len);
+                let from = self.start.clamp(offset, offset + len) - offset;
+                let to = requested_end.clamp(offset, offset + len) - offset;
+                self.buffer = chunk[from as usize..to.max(from) as usize].to_vec();
+                self.position = 0;
+                return Ok(true);
+            }
+
+            let mut parent = [0u8; PARENT_LEN];
+            self.read_raw(&mut parent)?;
+            let (left, right) = split_parent(&parent);
+            if parent_hash(&left, &right, is_root) != expected {
+                return Err(mismatch("parent", offset));
+            }
+            let left_len = left_subtree_len(len);
+            self.pending
+                .push((offset + left_len, len - left_len, right));
+            self.pending.push((offset, left_len, left));
+        }
+        Ok(false)
+    }
+
+    fn read_raw(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reader
+            .read_exact(buf)
+            .map_err(|e| MerkleError::IoError {
+                message: e.to_string(),
+            })
+    }
+}
+
+impl<R: Read> Read for SliceDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            let more = self.next_chunk().map_err(|e| match e {
+                MerkleError::IoError { message } => io::Error::other(message),
+                e => io::Error::new(io::ErrorKind::InvalidData, e),
+            })?;
+            if !more {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.buffer.len() - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Write the parents of the subtree over `content`, which starts `offset`
+/// bytes into the whole, in pre-order and return its hash
+fn encode_subtree(content: &[u8], offset: u64, is_root: bool, out: &mut Vec<u8>) -> [u8; 32] {
+    if content.len() <= CHUNK_LEN {
+        return chunk_hash(content, offset, is_root);
+    }
+    let left_len = left_subtree_len(content.len() as u64) as usize;
+    let at = out.len();
+    out.extend_from_slice(&[0u8; PARENT_LEN]);
+    let left = encode_subtree(&content[..left_len], offset, false, out);
+    let right = encode_subtree(&content[left_len..], offset + left_len as u64, false, out);
+    out[at..at + 32].copy_from_slice(&left);
+    out[at + 32..at + PARENT_LEN].copy_from_slice(&right);
+    parent_hash(&left, &right, is_root)
+}
+
+/// Append the slice of the subtree over `content` for the range
+/// `start..end`, taking its parents from the front of `parents`
+fn slice_subtree(
+    content: &[u8],
+    offset: u64,
+    parents: &mut &[u8],
+    start: u64,
+    end: u64,
+    out: &mut Vec<u8>,
+) {
+    let len = content.len() as u64;
+    if len <= CHUNK_LEN as u64 {
+        if overlaps(offset, len, start, end) {
+            out.extend_from_slice(content);
+        }
+        return;
+    }
+    // The subtree's parents come first in pre-order, one per chunk but one
+    let count = (chunk_count(len) as usize - 1) * PARENT_LEN;
+    let (own, rest) = parents.split_at(count);
+    *parents = rest;
+    if !overlaps(offset, len, start, end) {
+        return;
+    }
+    out.extend_from_slice(&own[..PARENT_LEN]);
+    let mut children = &own[PARENT_LEN..];
+    let left_len = left_subtree_len(len) as usize;
+    slice_subtree(&content[..left_len], offset, &mut children, start, end, out);
+    let right_offset = offset + left_len as u64;
+    slice_subtree(
+        &content[left_len..],
+        right_offset,
+        &mut children,
+        start,
+        end,
+        out,
+    );
+}
+
+fn chunk_hash(chunk: &[u8], offset: u64, is_root: bool) -> [u8; 32] {
+    if is_root {
+        return *blake3::hash(chunk).as_bytes();
+    }
+    blake3::Hasher::new()
+        .set_input_offset(offset)
+        .update(chunk)
+        .finalize_non_root()
+}
+
+fn parent_hash(left: &ChainingValue, right: &ChainingValue, is_root: bool) -> [u8; 32] {
+    if is_root {
+        *merge_subtrees_root(left, right, Mode::Hash).as_bytes()
+    } else {
+        merge_subtrees_non_root(left, right, Mode::Hash)
+    }
+}
+
+fn split_parent(parent: &[u8; PARENT_LEN]) -> (ChainingValue, ChainingValue) {
+    let mut left = [0u8; 32];
+    let mut right = [0u8; 32];
+    left.copy_from_slice(&parent[..32]);
+    right.copy_from_slice(&parent[32..]);
+    (left, right)
+}
+
+/// Range actually covered by a slice: at least one byte, and the final chunk
+/// for a start past the end
+fn effective_range(content_len: u64, start: u64, len: u64) -> (u64, u64) {
+    let start = if start >= content_len {
+        content_len.saturating_sub(1) / CHUNK_LEN as u64 * CHUNK_LEN as u64
+    } else {
+        start
+    };
+    (start, start.saturating_add(len.max(1)))
+}
+
+/// Whether the subtree of `len` bytes at `offset` holds any of `start..end`;
+/// the empty content is a single empty chunk, which always does
+fn overlaps(offset: u64, len: u64, start: u64, end: u64) -> bool {
+    len == 0 || (offset < end && start < offset + len)
+}
+
+fn chunk_count(content_len: u64) -> u64 {
+    content_len.div_ceil(CHUNK_LEN as u64).max(1)
+}
+
+fn outboard_len(content_len: u64) -> usize {
+    HEADER_LEN + (chunk_count(content_len) as usize - 1) * PARENT_LEN
+}
+
+fn read_len(header: &[u8]) -> u64 {
+    let mut bytes = [0u8; HEADER_LEN];
+    bytes.copy_from_slice(&header[..HEADER_LEN]);
+    u64::from_le_bytes(bytes)
+}
+
+fn mismatch(node: &str, offset: u64) -> MerkleError {
+    MerkleError::InvalidProof {
+        reason: format!("BLAKE3 {} at offset {} does not match", node, offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Blake3Hasher, Hasher};
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn decode(slice: &[u8], root: &RootHash, start: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        SliceDecoder::new(slice, root, start, len).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_outboard() {
+        let hasher = Blake3Hasher::new();
+        for len in [0, 1, 1023, 1024, 1025, 2048, 2049, 3072, 5000, 16384, 65537] {
+            let data = content(len);
+            let (root, outboard) = encode_outboard(&data);
+            assert_eq!(root.as_hash(), &hasher.hash(&data), "length {}", len);
+            assert_eq!(outboard.len(), outboard_len(len as u64));
+            assert_eq!(read_len(&outboard), len as u64);
+
+            // A slice of everything is the combined encoding
+            let all = extract_slice(&data, &outboard, 0, len as u64).unwrap();
+            assert_eq!(all.len(), outboard.len() + len);
+            assert_eq!(decode(&all, &root, 0, len as u64).unwrap(), data);
+        }
+        let (_, outboard) = encode_outboard(b"abc");
+        assert!(extract_slice(b"abcd", &outboard, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_slices() {
+        let data = content(20_000);
+        let (root, outboard) = encode_outboard(&data);
+        for (start, len) in [(0, 1), (1000, 100), (1023, 2), (5000, 7000), (19_999, 10)] {
+            let slice = extract_slice(&data, &outboard, start, len).unwrap();
+            // The covered chunks, at most one partial at each end, and the
+            // parents above them
+            let bound = len as usize + 2 * CHUNK_LEN + outboard.len();
+            assert!(slice.len() <= bound, "{}..+{}", start, len);
+            let end = (start + len).min(20_000) as usize;
+            assert_eq!(
+                decode(&slice, &root, start, len).unwrap(),
+                &data[start as usize..end]
+            );
+        }
+
+        // Past the end: no bytes, but the final chunk proves the length
+        let slice = extract_slice(&data, &outboard, 50_000, 10).unwrap();
+        assert!(decode(&slice, &root, 50_000, 10).unwrap().is_empty());
+        let mut decoder = SliceDecoder::new(slice.as_slice(), &root, 50_000, 10);
+        decoder.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(decoder.content_len(), Some(20_000));
+    }
+
+    #[test]
+    fn test_tampering() {
+        let data = content(10_000);
+        let (root, outboard) = encode_outboard(&data);
+        let slice = extract_slice(&data, &outboard, 3000, 3000).unwrap();
+
+        for i in (HEADER_LEN..slice.len()).step_by(97) {
+            let mut tampered = slice.clone();
+            tampered[i] ^= 1;
+            assert!(decode(&tampered, &root, 3000, 3000).is_err(), "byte {}", i);
+        }
+        // Verified chunks come out before a later one fails
+        let mut tampered = slice.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let mut decoder = SliceDecoder::new(tampered.as_slice(), &root, 3000, 3000);
+        let mut first = [0u8; 16];
+        decoder.read_exact(&mut first).unwrap();
+        assert_eq!(first, data[3000..3016]);
+        let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let truncated = &slice[..slice.len() - 1];
+        assert!(decode(truncated, &root, 3000, 3000).is_err());
+        let other = RootHash::new(vec![0; 32]);
+        assert!(decode(&slice, &other, 3000, 3000).is_err());
+    }
+}
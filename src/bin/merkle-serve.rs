@@ -0,0 +1,316 @@
+//! Serve roots and proofs of a Merkle tree over HTTP
+//!
+//! ```text
+//! merkle-serve [--addr 127.0.0.1:8080] [--hasher sha256|sha3|blake3|keccak256] [--lines] FILE
+//! ```
+//!
+//! `FILE` holds a tree written by `MerkleTree::serialize_into`, or with
+//! `--lines` one data item per line. Endpoints:
+//!
+//! - `GET /root` returns `{"root": "<hex>", "size": n}`
+//! - `GET /proof/{index}` returns the leaf, root and proof as JSON, or the
+//!   proof's hex text form with `?encoding=hex`
+//! - `POST /verify` takes `{"data" | "data_hex", "proof", "root"?}`, where the
+//!   proof is the JSON object or the hex text form, and returns `{"valid": bool}`
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use merkle_tree::{
+    Blake3Hasher, Hasher, Keccak256Hasher, MerkleProof, MerkleTree, RootHash, Sha256Hasher,
+    Sha3Hasher,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+const USAGE: &str =
+    "usage: merkle-serve [--addr ADDR] [--hasher sha256|sha3|blake3|keccak256] [--lines] FILE";
+
+struct Args {
+    addr: SocketAddr,
+    hasher: String,
+    lines: bool,
+    path: String,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let mut hasher = "sha256".to_string();
+    let mut lines = false;
+    let mut path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => {
+                let value = args.next().ok_or("--addr needs a value")?;
+                addr = value
+                    .parse()
+                    .map_err(|e| format!("Invalid address {}: {}", value, e))?;
+            }
+            "--hasher" => hasher = args.next().ok_or("--hasher needs a value")?,
+            "--lines" => lines = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(format!("Unexpected argument {}\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(Args {
+        addr,
+        hasher,
+        lines,
+        path: path.ok_or(USAGE)?,
+    })
+}
+
+fn load_tree<H: Hasher>(args: &Args, hasher: H) -> Result<MerkleTree<H>, String> {
+    let file = File::open(&args.path).map_err(|e| format!("{}: {}", args.path, e))?;
+    let reader = BufReader::new(file);
+
+    let tree = if args.lines {
+        let data = reader
+            .lines()
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(|e| format!("{}: {}", args.path, e))?;
+        MerkleTree::new(data, hasher)
+    } else {
+        MerkleTree::deserialize_from(reader, hasher)
+    };
+
+    tree.map_err(|e| format!("{}: {}", args.path, e))
+}
+
+type Shared<H> = Arc<MerkleTree<H>>;
+
+fn app<H: Hasher + Send + Sync + 'static>(tree: MerkleTree<H>) -> Router {
+    Router::new()
+        .route("/root", get(root::<H>))
+        .route("/proof/{index}", get(proof::<H>))
+        .route("/verify", post(verify::<H>))
+        .with_state(Arc::new(tree))
+}
+
+async fn serve<H: Hasher + Send + Sync + 'static>(args: Args, hasher: H) -> Result<(), String> {
+    let tree = load_tree(&args, hasher)?;
+    eprintln!(
+        "Serving {} leaves with root {} on http://{}",
+        tree.len(),
+        tree.root(),
+        args.addr
+    );
+
+    let listener = tokio::net::TcpListener::bind(args.addr)
+        .await
+        .map_err(|e| format!("{}: {}", args.addr, e))?;
+    axum::serve(listener, app(tree))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn error(status: StatusCode, message: impl ToString) -> Response {
+    (status, Json(json!({ "error": message.to_string() }))).into_response()
+}
+
+async fn root<H: Hasher>(State(tree): State<Shared<H>>) -> Response {
+    Json(json!({ "root": tree.root().to_hex(), "size": tree.len() })).into_response()
+}
+
+#[derive(Deserialize)]
+struct ProofQuery {
+    encoding: Option<String>,
+}
+
+async fn proof<H: Hasher>(
+    State(tree): State<Shared<H>>,
+    Path(index): Path<usize>,
+    Query(query): Query<ProofQuery>,
+) -> Response {
+    let proof = match tree.generate_proof(index) {
+        Ok(proof) => proof,
+        Err(e) => return error(StatusCode::NOT_FOUND, e),
+    };
+
+    match query.encoding.as_deref() {
+        None | Some("json") => Json(json!({
+            "index": index,
+            "leaf": tree.leaves()[index].to_hex(),
+            "root": tree.root().to_hex(),
+            "proof": proof,
+        }))
+        .into_response(),
+        Some("hex") => proof.to_hex().into_response(),
+        Some(other) => error(
+            StatusCode::BAD_REQUEST,
+            format!("Unknown encoding {}, expected json or hex", other),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProofInput {
+    Json(MerkleProof),
+    Hex(String),
+}
+
+#[derive(Deserialize)]
+struct VerifyRequest {
+    data: Option<String>,
+    data_hex: Option<String>,
+    proof: ProofInput,
+    root: Option<String>,
+}
+
+#[derive(Serialize)]
+struct VerifyResponse {
+    valid: bool,
+}
+
+async fn verify<H: Hasher>(
+    State(tree): State<Shared<H>>,
+    Json(request): Json<VerifyRequest>,
+) -> Response {
+    let data = match (request.data, request.data_hex) {
+        (Some(data), None) => data.into_bytes(),
+        (None, Some(data)) => match hex::decode(data.trim_start_matches("0x")) {
+            Ok(data) => data,
+            Err(e) => return error(StatusCode::BAD_REQUEST, format!("Invalid data_hex: {}", e)),
+        },
+        _ => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "Expected exactly one of data, data_hex",
+            )
+        }
+    };
+
+    let proof = match request.proof {
+        ProofInput::Json(proof) => proof,
+        ProofInput::Hex(text) => match text.parse::<MerkleProof>() {
+            Ok(proof) => proof,
+            Err(e) => return error(StatusCode::BAD_REQUEST, e),
+        },
+    };
+
+    let root = match request.root {
+        None => tree.root(),
+        Some(root) => match RootHash::from_hex(&root) {
+            Ok(root) => root,
+            Err(e) => return error(StatusCode::BAD_REQUEST, e),
+        },
+    };
+
+    Json(VerifyResponse {
+        valid: tree.verify_proof(&proof, &data, &root),
+    })
+    .into_response()
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let result = match parse_args() {
+        Ok(args) => match args.hasher.as_str() {
+            "sha256" => serve(args, Sha256Hasher::new()).await,
+            "sha3" => serve(args, Sha3Hasher::new()).await,
+            "blake3" => serve(args, Blake3Hasher::new()).await,
+            "keccak256" => serve(args, Keccak256Hasher::new()).await,
+            other => Err(format!("Unknown hasher {}\n{}", other, USAGE)),
+        },
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn tree() -> MerkleTree<Sha256Hasher> {
+        MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap()
+    }
+
+    async fn send(request: Request<Body>) -> (StatusCode, Vec<u8>) {
+        let response = app(tree()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn get_json(uri: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let (status, body) = send(request).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn post_verify(body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/verify")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, body) = send(request).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_root_and_proof() {
+        let (status, root) = get_json("/root").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(root["root"], tree().root().to_hex());
+        assert_eq!(root["size"], 3);
+
+        let (status, proof) = get_json("/proof/1").await;
+        assert_eq!(status, StatusCode::OK);
+        let parsed: MerkleProof = serde_json::from_value(proof["proof"].clone()).unwrap();
+        assert_eq!(parsed, tree().generate_proof(1).unwrap());
+
+        let request = Request::get("/proof/1?encoding=hex")
+            .body(Body::empty())
+            .unwrap();
+        let (status, text) = send(request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(String::from_utf8(text).unwrap(), parsed.to_hex());
+
+        let (status, _) = get_json("/proof/3").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let proof = tree().generate_proof(2).unwrap();
+
+        let (_, response) = post_verify(json!({ "data": "c", "proof": proof })).await;
+        assert_eq!(response["valid"], true);
+
+        let (_, response) = post_verify(json!({
+            "data_hex": hex::encode("c"),
+            "proof": proof.to_hex(),
+            "root": tree().root().to_hex(),
+        }))
+        .await;
+        assert_eq!(response["valid"], true);
+
+        let (_, response) = post_verify(json!({ "data": "b", "proof": proof })).await;
+        assert_eq!(response["valid"], false);
+
+        let (status, _) = post_verify(json!({ "proof": proof })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
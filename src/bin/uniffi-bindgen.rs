@@ -0,0 +1,11 @@
+//! Generate Swift/Kotlin bindings from the compiled library
+//!
+//! ```text
+//! cargo build --release --features uniffi
+//! cargo run --features uniffi-cli --bin uniffi-bindgen -- generate \
+//!     --library target/release/libmerkle_tree.so --language kotlin --out-dir out
+//! ```
+
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
@@ -0,0 +1,949 @@
+//! Configurable construction of [`MerkleTree`]s
+//!
+//! [`MerkleTree::new`] always hashes each data item once to form a leaf.
+//! External systems often derive leaves differently, so the builder lets the
+//! leaf encoding be chosen up front instead of re-implementing it on top of
+//! [`MerkleTree::from_leaves`]. It also selects the [`TreeShape`], e.g. to
+//! match the roots of RFC 6962 logs.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::{hash_all, Hasher};
+use crate::merkle_tree::{MerkleTree, NodeDomain, TreeShape};
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+/// Size of the chunks readers are consumed in
+const READ_CHUNK: usize = 64 * 1024;
+
+/// User-supplied function deriving a leaf hash from a data item
+pub type LeafEncoder = Arc<dyn Fn(&[u8]) -> Hash + Send + Sync>;
+
+/// How raw data items are turned into leaf hashes
+#[derive(Clone, Default)]
+pub enum LeafEncoding {
+    /// Hash each item once: `H(data)`
+    #[default]
+    PreHash,
+    /// Use each item's bytes as the leaf unchanged
+    Identity,
+    /// Hash each item twice: `H(H(data))`, as used by OpenZeppelin's
+    /// `StandardMerkleTree` to prevent second preimage attacks
+    DoubleHash,
+    /// Derive each leaf with a user-supplied function
+    Custom(LeafEncoder),
+}
+
+impl LeafEncoding {
+    /// Create a custom encoding from a closure
+    pub fn custom<F>(encode: F) -> Self
+    where
+        F: Fn(&[u8]) -> Hash + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(encode))
+    }
+
+    /// Encode a single data item as a leaf hash
+    pub fn encode<H: Hasher>(&self, hasher: &H, data: &[u8]) -> Hash {
+        match self {
+            Self::PreHash => hasher.hash(data),
+            Self::Identity => Hash::from(data),
+            Self::DoubleHash => hasher.hash(&hasher.hash(data)),
+            Self::Custom(encode) => encode(data),
+        }
+    }
+}
+
+impl fmt::Debug for LeafEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PreHash => f.write_str("PreHash"),
+            Self::Identity => f.write_str("Identity"),
+            Self::DoubleHash => f.write_str("DoubleHash"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Per-leaf salt mixed into each data item before it is encoded
+///
+/// A salted leaf is derived from `salt || data`, so small-domain values such
+/// as addresses or emails cannot be brute-forced from a published tree.
+#[derive(Clone, Default)]
+pub enum Salting {
+    /// Leaves are not salted
+    #[default]
+    None,
+    /// Fresh 32-byte random salt for every leaf
+    #[cfg(feature = "random-salt")]
+    Random,
+    /// Salt derived as `H(secret || index || data)`, with the index as a
+    /// big-endian `u64`, so salts can be regenerated from the secret
+    Derived(Vec<u8>),
+}
+
+impl Salting {
+    /// Produce the salt for the data item at `index`
+    fn salt_for<H: Hasher>(&self, hasher: &H, index: usize, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(Vec::new()),
+            #[cfg(feature = "random-salt")]
+            Self::Random => {
+                let mut salt = vec![0u8; 32];
+                getrandom::getrandom(&mut salt).map_err(|e| MerkleError::HashError {
+                    message: format!("Failed to generate salt: {}", e),
+                })?;
+                Ok(salt)
+            }
+            Self::Derived(secret) => {
+                let mut input = Vec::with_capacity(secret.len() + 8 + data.len());
+                input.extend_from_slice(secret);
+                input.extend_from_slice(&(index as u64).to_be_bytes());
+                input.extend_from_slice(data);
+                Ok(hasher.hash(&input).into_bytes())
+            }
+        }
+    }
+}
+
+/// What to do with data items that encode to the same leaf as an earlier item
+///
+/// Duplicates are detected on the unsalted leaf, so salting cannot hide
+/// them. Rejecting them guards allowlists and airdrops against double claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Duplicates {
+    /// Keep every item
+    #[default]
+    Allow,
+    /// Fail with [`MerkleError::DuplicateLeaves`] listing every repeated item
+    Reject,
+    /// Keep only the first occurrence of each item
+    Remove,
+}
+
+/// How the builder schedules hashing work
+///
+/// The mode never changes the tree: leaves and nodes are always combined in
+/// the same order and only the thread computing each hash differs, so every
+/// mode gives the same root on every machine, whatever its core count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConstructionMode {
+    /// Hash everything on the calling thread
+    #[default]
+    Serial,
+    /// Hash leaves and the nodes of each level on the rayon thread pool
+    ///
+    /// Leaves are derived in parallel by [`MerkleTreeBuilder::build`] when
+    /// duplicates are allowed; the levels above are always hashed in
+    /// parallel. Without the `rayon` feature this is the same as
+    /// [`ConstructionMode::Serial`].
+    Parallel,
+}
+
+impl fmt::Debug for Salting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            #[cfg(feature = "random-salt")]
+            Self::Random => f.write_str("Random"),
+            // Never print the secret
+            Self::Derived(_) => f.write_str("Derived(..)"),
+        }
+    }
+}
+
+/// Builder for [`MerkleTree`] with configurable leaf encoding
+///
+/// The built tree keeps its encoding, so [`MerkleTree::verify_proof`] takes
+/// raw data. Verifying without the tree needs the encoded leaf, e.g. with
+/// [`MerkleProof::verify_with_leaf_hash`](crate::proof::MerkleProof::verify_with_leaf_hash)
+/// and [`MerkleTreeBuilder::encode_leaf`].
+///
+/// ```rust
+/// use merkle_tree::{LeafEncoding, MerkleTreeBuilder, Sha256Hasher};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let builder = MerkleTreeBuilder::new(Sha256Hasher::new()).leaf_encoding(LeafEncoding::DoubleHash);
+/// let tree = builder.build(vec!["alice", "bob", "carol"])?;
+///
+/// let proof = tree.generate_proof(1)?;
+/// let leaf = builder.encode_leaf(b"bob");
+/// assert!(proof.verify_with_leaf_hash(tree.hasher(), &leaf, &tree.root()));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MerkleTreeBuilder<H: Hasher> {
+    hasher: H,
+    leaf_encoding: LeafEncoding,
+    salting: Salting,
+    shape: TreeShape,
+    node_domain: NodeDomain,
+    duplicates: Duplicates,
+    construction_mode: ConstructionMode,
+    max_leaves: Option<usize>,
+    max_bytes: Option<u64>,
+}
+
+impl<H: Hasher> MerkleTreeBuilder<H> {
+    /// Create a builder with the default settings of [`MerkleTree::new`]
+    pub fn new(hasher: H) -> Self {
+        Self {
+            hasher,
+            leaf_encoding: LeafEncoding::default(),
+            salting: Salting::default(),
+            shape: TreeShape::default(),
+            node_domain: NodeDomain::default(),
+            duplicates: Duplicates::default(),
+            construction_mode: ConstructionMode::default(),
+            max_leaves: None,
+            max_bytes: None,
+        }
+    }
+
+    /// Set how data items are encoded as leaves
+    pub fn leaf_encoding(mut self, leaf_encoding: LeafEncoding) -> Self {
+        self.leaf_encoding = leaf_encoding;
+        self
+    }
+
+    /// Set how leaves are salted
+    ///
+    /// Salts are kept in the built tree and returned with
+    /// [`MerkleTree::generate_salted_proof`].
+    pub fn salting(mut self, salting: Salting) -> Self {
+        self.salting = salting;
+        self
+    }
+
+    /// Set how the last node of an odd level is handled
+    pub fn shape(mut self, shape: TreeShape) -> Self {
+        self.shape = shape;
+        self
+    }
+
+    /// Set what internal node hashes commit to besides their children
+    ///
+    /// The default [`NodeDomain::None`] gives the standard roots.
+    pub fn node_domain(mut self, node_domain: NodeDomain) -> Self {
+        self.node_domain = node_domain;
+        self
+    }
+
+    /// Set how repeated data items are handled
+    pub fn duplicates(mut self, duplicates: Duplicates) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Set how hashing is scheduled; the tree is the same in every mode
+    pub fn construction_mode(mut self, construction_mode: ConstructionMode) -> Self {
+        self.construction_mode = construction_mode;
+        self
+    }
+
+    /// Limit the number of data items read
+    ///
+    /// Building fails with [`MerkleError::ResourceLimitExceeded`] as soon as
+    /// one more item is read, before it is hashed.
+    pub fn max_leaves(mut self, max_leaves: usize) -> Self {
+        self.max_leaves = Some(max_leaves);
+        self
+    }
+
+    /// Limit the total size of the data items read, in bytes
+    ///
+    /// Building fails with [`MerkleError::ResourceLimitExceeded`] as soon as
+    /// an item takes the total past the limit, before it is hashed.
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Encode a single unsalted data item the same way [`MerkleTreeBuilder::build`] does
+    pub fn encode_leaf(&self, data: &[u8]) -> Hash {
+        self.leaf_encoding.encode(&self.hasher, data)
+    }
+
+    /// Build a tree from the given data
+    ///
+    /// If the hasher provides a [`BatchHasher`](crate::hasher::BatchHasher),
+    /// unsalted [`LeafEncoding::PreHash`] and [`LeafEncoding::DoubleHash`]
+    /// leaves that allow duplicates are hashed in batches.
+    pub fn build<T: AsRef<[u8]>>(&self, data: Vec<T>) -> Result<MerkleTree<H>> {
+        let batched = self.hasher.batch().is_some()
+            && matches!(self.salting, Salting::None)
+            && self.duplicates == Duplicates::Allow
+            && matches!(
+                self.leaf_encoding,
+                LeafEncoding::PreHash | LeafEncoding::DoubleHash
+            );
+        let parallel = cfg!(feature = "rayon")
+            && self.construction_mode == ConstructionMode::Parallel
+            && self.duplicates == Duplicates::Allow;
+        if !batched && !parallel {
+            return self.build_from_iter(data);
+        }
+
+        let mut bytes = 0;
+        for (index, item) in data.iter().enumerate() {
+            self.check_limits(index, &mut bytes, item.as_ref())?;
+        }
+        if batched {
+            let mut leaves = hash_all(&self.hasher, &data)?;
+            if let LeafEncoding::DoubleHash = self.leaf_encoding {
+                leaves = hash_all(&self.hasher, &leaves)?;
+            }
+            return self.finish(leaves, Vec::new(), Vec::new());
+        }
+
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+
+            let items: Vec<&[u8]> = data.iter().map(AsRef::as_ref).collect();
+            let derived = items
+                .par_iter()
+                .with_min_len(1024)
+                .enumerate()
+                .map(|(index, item)| self.derive_leaf(index, item))
+                .collect::<Result<Vec<_>>>()?;
+            let (leaves, salts): (Vec<Hash>, Vec<Option<Vec<u8>>>) = derived.into_iter().unzip();
+            self.finish(leaves, salts.into_iter().flatten().collect(), Vec::new())
+        }
+        #[cfg(not(feature = "rayon"))]
+        unreachable!("parallel construction needs the rayon feature")
+    }
+
+    /// Build a tree from data items that are hashed as they are read
+    ///
+    /// Items are dropped as soon as their leaf is derived, so only the level
+    /// hashes needed for proofs (and the salts, if any) are ever held in
+    /// memory.
+    pub fn build_from_iter<I>(&self, data: I) -> Result<MerkleTree<H>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let data = data.into_iter();
+        let capacity = data
+            .size_hint()
+            .0
+            .min(self.max_leaves.unwrap_or(usize::MAX));
+        let mut leaves = Vec::with_capacity(capacity);
+        let mut salts = Vec::new();
+        let mut seen = HashSet::new();
+        let mut repeated = Vec::new();
+        let mut bytes = 0;
+        for (index, item) in data.enumerate() {
+            let item = item.as_ref();
+            self.check_limits(index, &mut bytes, item)?;
+            if self.duplicates != Duplicates::Allow && !seen.insert(self.encode_leaf(item)) {
+                repeated.push(index);
+                continue;
+            }
+            // Removed items leave no gap, so later leaves are salted by position
+            let (leaf, salt) = self.derive_leaf(leaves.len(), item)?;
+            leaves.push(leaf);
+            salts.extend(salt);
+        }
+
+        self.finish(leaves, salts, repeated)
+    }
+
+    /// Build a tree with one leaf per reader
+    ///
+    /// With the default settings each reader is hashed as it is read via
+    /// [`Hasher::begin_leaf`], so multi-megabyte items are never held in
+    /// memory as a whole. Salting and encodings other than
+    /// [`LeafEncoding::PreHash`] and [`LeafEncoding::DoubleHash`] need the
+    /// whole item and read it into memory first.
+    pub fn build_from_readers<I>(&self, readers: I) -> Result<MerkleTree<H>>
+    where
+        I: IntoIterator,
+        I::Item: Read,
+    {
+        let streamed = matches!(self.salting, Salting::None)
+            && matches!(
+                self.leaf_encoding,
+                LeafEncoding::PreHash | LeafEncoding::DoubleHash
+            );
+
+        let mut leaves = Vec::new();
+        let mut salts = Vec::new();
+        let mut seen = HashSet::new();
+        let mut repeated = Vec::new();
+        let mut bytes = 0;
+        let mut buffer = vec![0u8; READ_CHUNK];
+        for (index, mut reader) in readers.into_iter().enumerate() {
+            self.check_count(index)?;
+            let mut state = streamed.then(|| self.hasher.begin_leaf());
+            let mut item = Vec::new();
+            loop {
+                let read = match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(io_error(e)),
+                };
+                self.add_bytes(&mut bytes, read)?;
+                match &mut state {
+                    Some(state) => state.update(&buffer[..read]),
+                    None => item.extend_from_slice(&buffer[..read]),
+                }
+            }
+
+            if let Some(state) = state {
+                let mut leaf = state.finalize();
+                if let LeafEncoding::DoubleHash = self.leaf_encoding {
+                    leaf = self.hasher.hash(&leaf);
+                }
+                // Streamed leaves are unsalted, so they are their own encoding
+                if self.duplicates != Duplicates::Allow && !seen.insert(leaf.clone()) {
+                    repeated.push(index);
+                    continue;
+                }
+                leaves.push(leaf);
+            } else {
+                if self.duplicates != Duplicates::Allow && !seen.insert(self.encode_leaf(&item)) {
+                    repeated.push(index);
+                    continue;
+                }
+                let (leaf, salt) = self.derive_leaf(leaves.len(), &item)?;
+                leaves.push(leaf);
+                salts.extend(salt);
+            }
+        }
+
+        self.finish(leaves, salts, repeated)
+    }
+
+    /// Turn derived leaves into a tree, applying the duplicate policy
+    fn finish(
+        &self,
+        leaves: Vec<Hash>,
+        salts: Vec<Vec<u8>>,
+        repeated: Vec<usize>,
+    ) -> Result<MerkleTree<H>> {
+        if self.duplicates == Duplicates::Reject && !repeated.is_empty() {
+            return Err(MerkleError::DuplicateLeaves { indices: repeated });
+        }
+        if leaves.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+
+        // Raw or user-derived leaves need not be hasher-sized
+        if let LeafEncoding::PreHash | LeafEncoding::DoubleHash = self.leaf_encoding {
+            MerkleTree::check_leaf_sizes(&leaves, &self.hasher)?;
+        }
+
+        let tree = MerkleTree::from_shaped_leaves(
+            leaves,
+            self.hasher.clone(),
+            self.shape,
+            self.node_domain,
+            self.construction_mode,
+        )?;
+        Ok(tree
+            .with_leaf_encoding(self.leaf_encoding.clone())
+            .with_salts(salts))
+    }
+
+    /// Compute only the root of the tree [`MerkleTreeBuilder::build`] would
+    /// produce
+    ///
+    /// Keeps one pending hash per level, so memory is `O(log n)` however many
+    /// items are read. Salts are derived as usual but not kept, so leaves of a
+    /// randomly salted root can never be proven.
+    pub fn build_root<I>(&self, data: I) -> Result<RootHash>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        // Completed subtree roots with their heights, highest first
+        let mut pending: Vec<(Hash, usize)> = Vec::new();
+        let mut bytes = 0;
+        for (index, item) in data.into_iter().enumerate() {
+            self.check_limits(index, &mut bytes, item.as_ref())?;
+            let (mut node, _) = self.derive_leaf(index, item.as_ref())?;
+            let mut height = 0;
+            while let Some((left, _)) = pending.pop_if(|(_, h)| *h == height) {
+                node = self.hash_node(height + 1, &left, &node);
+                height += 1;
+            }
+            pending.push((node, height));
+        }
+
+        let (mut root, mut height) = pending.pop().ok_or(MerkleError::EmptyData)?;
+        // Close the ragged right edge, pairing lone nodes with themselves or
+        // promoting them as they are
+        while let Some((left, left_height)) = pending.pop() {
+            if self.shape == TreeShape::Padded {
+                while height < left_height {
+                    root = self.hash_node(height + 1, &root, &root);
+                    height += 1;
+                }
+            }
+            // A promoted node rises to the level of its sibling unchanged
+            height = left_height;
+            root = self.hash_node(height + 1, &left, &root);
+            height += 1;
+        }
+
+        Ok(RootHash::from(root))
+    }
+
+    /// Hash two children into their parent at `level`
+    fn hash_node(&self, level: usize, left: &[u8], right: &[u8]) -> Hash {
+        self.node_domain.hash_node(&self.hasher, level, left, right)
+    }
+
+    /// Account for the data item at `index`, failing if it exceeds a limit
+    fn check_limits(&self, index: usize, bytes: &mut u64, item: &[u8]) -> Result<()> {
+        self.check_count(index)?;
+        self.add_bytes(bytes, item.len())
+    }
+
+    /// Fail if the data item at `index` is one more than allowed
+    fn check_count(&self, index: usize) -> Result<()> {
+        if let Some(max_leaves) = self.max_leaves.filter(|&max| index >= max) {
+            return Err(MerkleError::ResourceLimitExceeded {
+                resource: "leaf count".to_string(),
+                limit: max_leaves as u64,
+            });
+        }
+        Ok(())
+    }
+
+    /// Add `len` bytes to the running total, failing if it exceeds the limit
+    fn add_bytes(&self, bytes: &mut u64, len: usize) -> Result<()> {
+        *bytes = bytes.saturating_add(len as u64);
+        if let Some(max_bytes) = self.max_bytes.filter(|&max| *bytes > max) {
+            return Err(MerkleError::ResourceLimitExceeded {
+                resource: "total data size".to_string(),
+                limit: max_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Derive the leaf and salt of the data item at `index`
+    fn derive_leaf(&self, index: usize, data: &[u8]) -> Result<(Hash, Option<Vec<u8>>)> {
+        if let Salting::None = self.salting {
+            return Ok((self.encode_leaf(data), None));
+        }
+
+        let salt = self.salting.salt_for(&self.hasher, index, data)?;
+        let salted = [salt.as_slice(), data].concat();
+        Ok((self.encode_leaf(&salted), Some(salt)))
+    }
+}
+
+fn io_error(error: io::Error) -> MerkleError {
+    MerkleError::IoError {
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Keccak256Hasher, Sha256Hasher};
+
+    #[test]
+    fn test_default_matches_new() {
+        let data = vec!["a", "b", "c"];
+        let built = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .build(data.clone())
+            .unwrap();
+        let tree = MerkleTree::new(data, Sha256Hasher::new()).unwrap();
+        assert_eq!(built.root(), tree.root());
+    }
+
+    #[test]
+    fn test_identity_encoding() {
+        let hasher = Sha256Hasher::new();
+        let leaves = vec![hasher.hash(b"a"), hasher.hash(b"b"), hasher.hash(b"c")];
+
+        let built = MerkleTreeBuilder::new(hasher.clone())
+            .leaf_encoding(LeafEncoding::Identity)
+            .build(leaves.clone())
+            .unwrap();
+        let tree = MerkleTree::from_leaves(leaves, hasher).unwrap();
+        assert_eq!(built.root(), tree.root());
+
+        // Raw leaves may have any length
+        let short = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .leaf_encoding(LeafEncoding::Identity)
+            .build(vec!["ab", "cde"])
+            .unwrap();
+        assert_eq!(short.get_leaf(1).unwrap(), b"cde");
+        let proof = short.generate_proof(1).unwrap();
+        assert!(short.verify_proof_against_root(&proof, b"cde"));
+    }
+
+    #[test]
+    fn test_double_hash_encoding() {
+        let hasher = Keccak256Hasher::new();
+        let builder =
+            MerkleTreeBuilder::new(hasher.clone()).leaf_encoding(LeafEncoding::DoubleHash);
+        let tree = builder.build(vec!["x", "y", "z"]).unwrap();
+
+        assert_eq!(*tree.get_leaf(2).unwrap(), hasher.hash(&hasher.hash(b"z")));
+
+        let proof = tree.generate_proof(2).unwrap();
+        assert!(proof.verify_with_leaf_hash(&hasher, &builder.encode_leaf(b"z"), &tree.root()));
+        assert!(!proof.verify(&hasher, b"z", &tree.root()));
+        // The tree remembers its encoding
+        assert_eq!(tree.encode_leaf(b"z"), builder.encode_leaf(b"z"));
+        assert!(tree.verify_proof(&proof, b"z", &tree.root()));
+        assert!(!tree.verify_proof(&proof, b"y", &tree.root()));
+    }
+
+    #[test]
+    fn test_custom_encoding() {
+        let hasher = Sha256Hasher::new();
+        let prefixed = hasher.clone();
+        let builder = MerkleTreeBuilder::new(hasher.clone()).leaf_encoding(LeafEncoding::custom(
+            move |data: &[u8]| prefixed.hash(&[&[0u8][..], data].concat()),
+        ));
+        let tree = builder.build(vec!["a", "b"]).unwrap();
+
+        assert_eq!(*tree.get_leaf(0).unwrap(), hasher.hash(b"\x00a"));
+        let proof = tree
+            .generate_proof_for_hash(&tree.encode_leaf(b"b"))
+            .unwrap();
+        assert_eq!(proof.leaf_index, 1);
+        assert!(tree.verify_proof_against_root(&proof, b"b"));
+        assert_eq!(
+            format!("{:?}", LeafEncoding::custom(|data: &[u8]| Hash::from(data))),
+            "Custom(..)"
+        );
+    }
+
+    #[test]
+    fn test_derived_salting() {
+        let hasher = Sha256Hasher::new();
+        let builder =
+            MerkleTreeBuilder::new(hasher.clone()).salting(Salting::Derived(b"secret".to_vec()));
+        let tree = builder.build(vec!["alice", "bob", "bob"]).unwrap();
+
+        let proof = tree.generate_salted_proof(1).unwrap();
+        assert_eq!(proof.salt.len(), 32);
+        assert!(proof.verify(&hasher, b"bob", &tree.root()));
+        assert!(!proof.verify(&hasher, b"alice", &tree.root()));
+        assert!(!proof.proof.verify(&hasher, b"bob", &tree.root()));
+
+        // Identical data gets distinct salts and therefore distinct leaves
+        assert_ne!(tree.salt(1), tree.salt(2));
+        assert_ne!(tree.get_leaf(1).unwrap(), tree.get_leaf(2).unwrap());
+
+        // Salts are reproducible from the secret
+        let again = builder.build(vec!["alice", "bob", "bob"]).unwrap();
+        assert_eq!(again.root(), tree.root());
+        assert_eq!(
+            format!("{:?}", Salting::Derived(b"secret".to_vec())),
+            "Derived(..)"
+        );
+    }
+
+    #[test]
+    fn test_salting_with_double_hash() {
+        let hasher = Keccak256Hasher::new();
+        let builder = MerkleTreeBuilder::new(hasher.clone())
+            .leaf_encoding(LeafEncoding::DoubleHash)
+            .salting(Salting::Derived(b"k".to_vec()));
+        let tree = builder.build(vec!["x", "y"]).unwrap();
+
+        let proof = tree.generate_salted_proof(0).unwrap();
+        let leaf = builder.encode_leaf(&proof.salted_data(b"x"));
+        assert!(proof
+            .proof
+            .verify_with_leaf_hash(&hasher, &leaf, &tree.root()));
+    }
+
+    #[test]
+    fn test_unsalted_tree_has_no_salts() {
+        let tree = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .build(vec!["a", "b"])
+            .unwrap();
+        assert_eq!(tree.salt(0), None);
+        assert!(matches!(
+            tree.generate_salted_proof(0),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+    }
+
+    #[cfg(feature = "random-salt")]
+    #[test]
+    fn test_random_salting() {
+        let hasher = Sha256Hasher::new();
+        let builder = MerkleTreeBuilder::new(hasher.clone()).salting(Salting::Random);
+        let first = builder.build(vec!["a", "b", "c"]).unwrap();
+        let second = builder.build(vec!["a", "b", "c"]).unwrap();
+
+        assert_ne!(first.root(), second.root());
+        let proof = first.generate_salted_proof(2).unwrap();
+        assert!(proof.verify(&hasher, b"c", &first.root()));
+    }
+
+    #[test]
+    fn test_build_from_iter() {
+        let builder = MerkleTreeBuilder::new(Sha256Hasher::new());
+        let streamed = builder
+            .build_from_iter((0..7).map(|i| format!("item_{}", i)))
+            .unwrap();
+        let data: Vec<String> = (0..7).map(|i| format!("item_{}", i)).collect();
+        let tree = builder.build(data).unwrap();
+        assert_eq!(streamed.root(), tree.root());
+        assert_eq!(streamed.leaves(), tree.leaves());
+    }
+
+    #[test]
+    fn test_build_root() {
+        let hasher = Sha256Hasher::new();
+        for len in 1..=17 {
+            let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+            let builder = MerkleTreeBuilder::new(hasher.clone());
+            let root = builder.build_root(data.iter()).unwrap();
+            assert_eq!(root, builder.build(data.clone()).unwrap().root(), "{}", len);
+
+            let salted = builder.salting(Salting::Derived(b"k".to_vec()));
+            let root = salted.build_root(data.iter()).unwrap();
+            assert_eq!(root, salted.build(data).unwrap().root(), "{}", len);
+        }
+
+        assert!(matches!(
+            MerkleTreeBuilder::new(hasher).build_root(Vec::<&[u8]>::new()),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let hasher = Sha256Hasher::new();
+        let data = vec!["alice", "bob", "alice", "carol", "bob", "alice"];
+
+        let allowed = MerkleTreeBuilder::new(hasher.clone())
+            .build(data.clone())
+            .unwrap();
+        assert_eq!(allowed.len(), 6);
+        assert_eq!(allowed.duplicate_leaves(), vec![2, 4, 5]);
+
+        let rejected = MerkleTreeBuilder::new(hasher.clone())
+            .duplicates(Duplicates::Reject)
+            .build(data.clone());
+        assert_eq!(
+            rejected.unwrap_err(),
+            MerkleError::DuplicateLeaves {
+                indices: vec![2, 4, 5]
+            }
+        );
+
+        let removed = MerkleTreeBuilder::new(hasher.clone())
+            .duplicates(Duplicates::Remove)
+            .build(data.clone())
+            .unwrap();
+        let unique = MerkleTree::new(vec!["alice", "bob", "carol"], hasher.clone()).unwrap();
+        assert_eq!(removed.root(), unique.root());
+
+        // Salts differ per item, but duplicates are still caught
+        let salted = MerkleTreeBuilder::new(hasher.clone())
+            .salting(Salting::Derived(b"k".to_vec()))
+            .duplicates(Duplicates::Reject)
+            .build(data.clone());
+        assert!(matches!(salted, Err(MerkleError::DuplicateLeaves { .. })));
+
+        let salted = MerkleTreeBuilder::new(hasher)
+            .salting(Salting::Derived(b"k".to_vec()))
+            .duplicates(Duplicates::Remove)
+            .build(data)
+            .unwrap();
+        assert_eq!(salted.len(), 3);
+        assert!(salted.generate_salted_proof(2).unwrap().verify(
+            salted.hasher(),
+            b"carol",
+            &salted.root()
+        ));
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let builder = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .max_leaves(3)
+            .max_bytes(12);
+        assert!(builder.build(vec!["aaaa", "bbbb", "cccc"]).is_ok());
+        assert!(builder.build_root(vec!["aaaa", "bbbb", "cccc"]).is_ok());
+
+        assert_eq!(
+            builder.build(vec!["a", "b", "c", "d"]).unwrap_err(),
+            MerkleError::ResourceLimitExceeded {
+                resource: "leaf count".to_string(),
+                limit: 3
+            }
+        );
+        assert!(matches!(
+            builder.build_root(vec!["aaaa", "bbbb", "ccccc"]),
+            Err(MerkleError::ResourceLimitExceeded { limit: 12, .. })
+        ));
+
+        // Reading stops at the first item over the limit
+        let mut read = 0;
+        let endless = std::iter::repeat_with(|| {
+            read += 1;
+            "x"
+        });
+        assert!(builder.build_from_iter(endless).is_err());
+        assert_eq!(read, 4);
+    }
+
+    #[test]
+    fn test_build_from_readers() {
+        let data = vec![vec![7u8; 200_000], b"small".to_vec(), Vec::new()];
+        let readers = || data.iter().map(|item| io::Cursor::new(item.clone()));
+
+        for encoding in [
+            LeafEncoding::PreHash,
+            LeafEncoding::DoubleHash,
+            LeafEncoding::Identity,
+        ] {
+            let builder = MerkleTreeBuilder::new(Sha256Hasher::new()).leaf_encoding(encoding);
+            let expected = builder.build(data.clone()).unwrap();
+            assert_eq!(builder.build_from_readers(readers()).unwrap(), expected);
+        }
+
+        let tree = MerkleTree::from_readers(readers(), Sha256Hasher::new()).unwrap();
+        assert_eq!(
+            tree,
+            MerkleTree::new(data.clone(), Sha256Hasher::new()).unwrap()
+        );
+
+        // Salted leaves need the whole item
+        let builder =
+            MerkleTreeBuilder::new(Sha256Hasher::new()).salting(Salting::Derived(b"key".to_vec()));
+        let tree = builder.build_from_readers(readers()).unwrap();
+        assert_eq!(tree.root(), builder.build(data.clone()).unwrap().root());
+
+        // Limits are enforced while reading
+        let builder = MerkleTreeBuilder::new(Sha256Hasher::new()).max_bytes(1024);
+        assert!(matches!(
+            builder.build_from_readers([io::repeat(0)]),
+            Err(MerkleError::ResourceLimitExceeded { limit: 1024, .. })
+        ));
+
+        let builder = MerkleTreeBuilder::new(Sha256Hasher::new()).duplicates(Duplicates::Reject);
+        assert_eq!(
+            builder
+                .build_from_readers([&b"a"[..], b"b", b"a"])
+                .unwrap_err(),
+            MerkleError::DuplicateLeaves { indices: vec![2] }
+        );
+        assert!(matches!(
+            MerkleTree::from_readers(Vec::<&[u8]>::new(), Sha256Hasher::new()),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_construction_modes_agree() {
+        let builders = |shape, domain, encoding: LeafEncoding, salting: Salting| {
+            [ConstructionMode::Serial, ConstructionMode::Parallel].map(|mode| {
+                MerkleTreeBuilder::new(Sha256Hasher::new())
+                    .shape(shape)
+                    .node_domain(domain)
+                    .leaf_encoding(encoding.clone())
+                    .salting(salting.clone())
+                    .construction_mode(mode)
+            })
+        };
+
+        let check = || {
+            for len in [1, 2, 3, 5, 8, 13, 1000, 2049] {
+                let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+                for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+                    for domain in [NodeDomain::None, NodeDomain::Level] {
+                        for (encoding, salting) in [
+                            (LeafEncoding::PreHash, Salting::None),
+                            (LeafEncoding::DoubleHash, Salting::None),
+                            (LeafEncoding::Identity, Salting::None),
+                            (LeafEncoding::PreHash, Salting::Derived(b"secret".to_vec())),
+                        ] {
+                            let [serial, parallel] = builders(shape, domain, encoding, salting);
+                            let serial = serial.build(data.clone()).unwrap();
+                            let parallel = parallel.build(data.clone()).unwrap();
+                            assert_eq!(serial.levels(), parallel.levels(), "{} leaves", len);
+                            assert_eq!(serial.salts(), parallel.salts());
+                        }
+                    }
+                }
+            }
+        };
+
+        check();
+        // The same roots whatever the number of threads
+        #[cfg(feature = "rayon")]
+        for threads in [1, 3, 8] {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(check);
+        }
+    }
+
+    #[test]
+    fn test_node_domain() {
+        let data: Vec<String> = (0..7).map(|i| format!("item_{}", i)).collect();
+        let standard = MerkleTree::new(data.clone(), Sha256Hasher::new()).unwrap();
+
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            let builder = MerkleTreeBuilder::new(Sha256Hasher::new())
+                .shape(shape)
+                .node_domain(NodeDomain::Level);
+            let tree = builder.build(data.clone()).unwrap();
+            assert_eq!(tree.node_domain(), NodeDomain::Level);
+            assert_ne!(tree.root(), standard.root());
+            assert_eq!(builder.build_root(data.clone()).unwrap(), tree.root());
+            assert_eq!(tree.verify_integrity(), Ok(()));
+
+            for (i, item) in data.iter().enumerate() {
+                let proof = tree.generate_proof(i).unwrap();
+                assert!(tree.verify_proof_against_root(&proof, item.as_bytes()));
+                assert!(proof.verify_leveled(
+                    tree.hasher(),
+                    item.as_bytes(),
+                    &tree.root(),
+                    data.len(),
+                    shape
+                ));
+                assert!(!proof.verify(tree.hasher(), item.as_bytes(), &tree.root()));
+                assert!(!tree.verify_proof_against_root(&proof, b"wrong"));
+            }
+
+            let mut moved = tree.generate_proof(2).unwrap();
+            moved.leaf_index = data.len();
+            assert!(matches!(
+                tree.try_verify_proof(&moved, b"item_2", &tree.root()),
+                Err(MerkleError::InvalidIndex { .. })
+            ));
+        }
+
+        // Parents of leaves are hashed at level 1
+        let hasher = Sha256Hasher::new();
+        let tree = MerkleTreeBuilder::new(hasher.clone())
+            .node_domain(NodeDomain::Level)
+            .build(vec!["a", "b"])
+            .unwrap();
+        let leaves = [hasher.hash(b"a"), hasher.hash(b"b")];
+        let expected = hasher.hash(&[&1u32.to_be_bytes()[..], &leaves[0], &leaves[1]].concat());
+        assert_eq!(tree.root().as_hash(), &expected);
+    }
+
+    #[test]
+    fn test_empty_data() {
+        let result = MerkleTreeBuilder::new(Sha256Hasher::new()).build(Vec::<&[u8]>::new());
+        assert!(matches!(result, Err(MerkleError::EmptyData)));
+    }
+}
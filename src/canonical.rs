@@ -0,0 +1,252 @@
+//! Canonical binary encoding of trees and proofs
+//!
+//! Unlike serde output, which depends on the format crate and on field names,
+//! these encodings are fixed byte layouts: every integer is big-endian and
+//! sized independently of the platform, and every artifact starts with a
+//! magic, a format version and the name of the hasher it was built with.
+//! Bytes written by one version of the crate on one platform decode the same
+//! way everywhere; the golden vectors in the tests pin the layouts down.
+//!
+//! Trees use the layout of [`MerkleTree::serialize_into`]. Proofs use:
+//!
+//! ```text
+//! magic "MRKP" | version u8 | hasher name (u8 length + bytes)
+//! leaf index u64 | step count u32
+//! per step: direction u8 (0 left, 1 right) | hash (u32 length + bytes)
+//! ```
+
+use crate::error::Result;
+use crate::hash::Hash;
+use crate::hasher::Hasher;
+use crate::merkle_tree::MerkleTree;
+use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+use crate::stream::{invalid, read_array, read_bounded_bytes, write_bytes, MAX_HASH_LEN};
+
+const PROOF_MAGIC: &[u8; 4] = b"MRKP";
+const PROOF_VERSION: u8 = 1;
+
+impl MerkleProof {
+    /// Encode the proof canonically, tagged with the hasher it belongs to
+    pub fn to_canonical_bytes<H: Hasher>(&self, hasher: &H) -> Vec<u8> {
+        let name = hasher.name().as_bytes();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(PROOF_MAGIC);
+        bytes.push(PROOF_VERSION);
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(&(self.leaf_index as u64).to_be_bytes());
+        bytes.extend_from_slice(&(self.steps.len() as u32).to_be_bytes());
+        for step in &self.steps {
+            bytes.push(match step.direction {
+                ProofDirection::Left => 0,
+                ProofDirection::Right => 1,
+            });
+            // Writing to a Vec cannot fail
+            write_bytes(&mut bytes, &step.hash).expect("write to Vec");
+        }
+        bytes
+    }
+
+    /// Decode a proof written by [`MerkleProof::to_canonical_bytes`]
+    ///
+    /// The encoded hasher name must match `hasher`, and the input must hold
+    /// exactly one proof.
+    pub fn from_canonical_bytes<H: Hasher>(bytes: &[u8], hasher: &H) -> Result<Self> {
+        let mut reader = bytes;
+        let magic: [u8; 4] = read_array(&mut reader)?;
+        if &magic != PROOF_MAGIC {
+            return Err(invalid("Not a canonical Merkle proof"));
+        }
+        let [version] = read_array(&mut reader)?;
+        if version != PROOF_VERSION {
+            return Err(invalid(&format!("Unsupported proof version {}", version)));
+        }
+        check_hasher_name(&mut reader, hasher)?;
+
+        let leaf_index = usize::try_from(u64::from_be_bytes(read_array(&mut reader)?))
+            .map_err(|_| invalid("Leaf index does not fit in memory"))?;
+        let count = u32::from_be_bytes(read_array(&mut reader)?) as usize;
+
+        // Each step takes at least five bytes, which bounds the allocation
+        let mut steps = Vec::with_capacity(count.min(reader.len() / 5));
+        for _ in 0..count {
+            let direction = match read_array(&mut reader)? {
+                [0] => ProofDirection::Left,
+                [1] => ProofDirection::Right,
+                [other] => return Err(invalid(&format!("Unknown direction {}", other))),
+            };
+            steps.push(ProofStep {
+                hash: Hash::new(read_bounded_bytes(&mut reader, MAX_HASH_LEN)?),
+                direction,
+            });
+        }
+
+        if !reader.is_empty() {
+            return Err(invalid("Trailing bytes after proof"));
+        }
+        Ok(Self::new(leaf_index, steps))
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Encode the tree canonically, as [`MerkleTree::serialize_into`] does
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // Writing to a Vec cannot fail
+        self.serialize_into(&mut bytes).expect("write to Vec");
+        bytes
+    }
+
+    /// Decode a tree written by [`MerkleTree::to_canonical_bytes`]
+    ///
+    /// Unlike [`MerkleTree::deserialize_from`], trailing bytes are rejected.
+    pub fn from_canonical_bytes(bytes: &[u8], hasher: H) -> Result<Self> {
+        let mut reader = bytes;
+        let tree = Self::deserialize_from(&mut reader, hasher)?;
+        if !reader.is_empty() {
+            return Err(invalid("Trailing bytes after tree"));
+        }
+        Ok(tree)
+    }
+}
+
+fn check_hasher_name<H: Hasher>(reader: &mut &[u8], hasher: &H) -> Result<()> {
+    let [len] = read_array(reader)?;
+    let len = len as usize;
+    if reader.len() < len {
+        return Err(invalid("Unexpected end of input"));
+    }
+    let (name, rest) = reader.split_at(len);
+    *reader = rest;
+
+    if name != hasher.name().as_bytes() {
+        return Err(invalid(&format!(
+            "Encoded with hasher {:?}, not {}",
+            String::from_utf8_lossy(name),
+            hasher.name()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MerkleTreeBuilder;
+    use crate::error::MerkleError;
+    use crate::hasher::{Blake3Hasher, Keccak256Hasher, Sha256Hasher};
+    use crate::merkle_tree::TreeShape;
+
+    fn golden_proof<H: Hasher>(hasher: H) -> String {
+        let tree = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+        hex::encode(tree.generate_proof(2).unwrap().to_canonical_bytes(&hasher))
+    }
+
+    #[test]
+    fn test_golden_proofs() {
+        assert_eq!(
+            golden_proof(Sha256Hasher::new()),
+            concat!(
+                "4d524b50",
+                "01",
+                "07",
+                "5348412d323536",
+                "0000000000000002",
+                "00000002",
+                "01",
+                "00000020",
+                "2e7d2c03a9507ae265ecf5b5356885a53393a2029d241394997265a1a25aefc6",
+                "00",
+                "00000020",
+                "e5a01fee14e0ed5c48714f22180f25ad8365b53f9779f79dc4a3d7e93963f94a",
+            )
+        );
+        assert_eq!(
+            golden_proof(Keccak256Hasher::new()),
+            concat!(
+                "4d524b50",
+                "01",
+                "0a",
+                "4b656363616b2d323536",
+                "0000000000000002",
+                "00000002",
+                "01",
+                "00000020",
+                "0b42b6393c1f53060fe3ddbfcd7aadcca894465a5a438f69c87d790b2299b9b2",
+                "00",
+                "00000020",
+                "805b21d846b189efaeb0377d6bb0d201b3872a363e607c25088f025b0c6ae1f8",
+            )
+        );
+    }
+
+    #[test]
+    fn test_golden_tree() {
+        let tree = MerkleTree::new(vec!["a", "b"], Sha256Hasher::new()).unwrap();
+        assert_eq!(
+            hex::encode(tree.to_canonical_bytes()),
+            concat!(
+                "4d524b4c",
+                "02",
+                "07",
+                "5348412d323536",
+                "00",
+                "00000002",
+                "0000000000000002",
+                "00000020",
+                "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb",
+                "00000020",
+                "3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d",
+                "0000000000000001",
+                "00000020",
+                "e5a01fee14e0ed5c48714f22180f25ad8365b53f9779f79dc4a3d7e93963f94a",
+                "0000000000000000",
+                "0000000000000000",
+            )
+        );
+
+        let restored =
+            MerkleTree::from_canonical_bytes(&tree.to_canonical_bytes(), Sha256Hasher::new())
+                .unwrap();
+        assert_eq!(restored.root(), tree.root());
+    }
+
+    #[test]
+    fn test_proof_roundtrip() {
+        let tree = MerkleTreeBuilder::new(Blake3Hasher::new())
+            .shape(TreeShape::Rfc6962)
+            .build_from_iter((0..11).map(|i| format!("item_{}", i)))
+            .unwrap();
+        for index in 0..tree.len() {
+            let proof = tree.generate_proof(index).unwrap();
+            let bytes = proof.to_canonical_bytes(tree.hasher());
+            assert_eq!(
+                MerkleProof::from_canonical_bytes(&bytes, tree.hasher()).unwrap(),
+                proof
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let hasher = Sha256Hasher::new();
+        let tree = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+        let bytes = tree.generate_proof(0).unwrap().to_canonical_bytes(&hasher);
+
+        assert!(matches!(
+            MerkleProof::from_canonical_bytes(&bytes, &Blake3Hasher::new()),
+            Err(MerkleError::SerializationError { .. })
+        ));
+        for len in 0..bytes.len() {
+            assert!(MerkleProof::from_canonical_bytes(&bytes[..len], &hasher).is_err());
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(MerkleProof::from_canonical_bytes(&trailing, &hasher).is_err());
+
+        let mut trailing = tree.to_canonical_bytes();
+        trailing.push(0);
+        assert!(MerkleTree::from_canonical_bytes(&trailing, hasher).is_err());
+    }
+}
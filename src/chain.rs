@@ -0,0 +1,260 @@
+//! Hash chains over published roots
+//!
+//! [`RootChain`] folds every root a log operator publishes into a running
+//! chain value, `c_n = H(c_{n-1} || root_n)` from an all-zero `c_0`, in the
+//! style of proof of history. The chain value at a position commits to every
+//! root up to it and their order, so clients that gossip `(position, value)`
+//! pairs find out whether they were shown the same history: a single
+//! mismatch at any position reveals an operator presenting divergent roots
+//! to different clients, without exchanging the roots themselves.
+//!
+//! A [`ChainSegment`] carries the roots between two positions, letting a
+//! client that knows the chain value at one position verify the roots that
+//! follow against an announced later value.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Running hash chain over a sequence of published roots
+///
+/// ```rust
+/// use merkle_tree::chain::RootChain;
+/// use merkle_tree::{RootHash, Sha256Hasher};
+///
+/// let hasher = Sha256Hasher::new();
+/// let mut chain = RootChain::new(hasher.clone());
+/// for i in 0..5u8 {
+///     chain.push(RootHash::new(vec![i; 32]));
+/// }
+///
+/// // A client that saw the first two roots catches up on the rest
+/// let known = chain.value(1).unwrap().clone();
+/// let segment = chain.segment(2..5).unwrap();
+/// assert!(segment.extends(1, &known));
+/// assert!(segment.verify(&hasher, &chain.head()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RootChain<H: Hasher> {
+    hasher: H,
+    roots: Vec<RootHash>,
+    /// Chain value after each root
+    values: Vec<Hash>,
+}
+
+impl<H: Hasher> RootChain<H> {
+    /// Create a chain without any roots
+    pub fn new(hasher: H) -> Self {
+        Self {
+            hasher,
+            roots: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Append a newly published root, returning the new chain value
+    pub fn push(&mut self, root: RootHash) -> Hash {
+        let value = link(&self.hasher, &self.head(), &root);
+        self.roots.push(root);
+        self.values.push(value.clone());
+        value
+    }
+
+    /// Get the chain value before any root, all zeroes
+    pub fn genesis(&self) -> Hash {
+        genesis(&self.hasher)
+    }
+
+    /// Get the chain value after the latest root, or the genesis value
+    pub fn head(&self) -> Hash {
+        self.values
+            .last()
+            .cloned()
+            .unwrap_or_else(|| self.genesis())
+    }
+
+    /// Get the chain value after the root at `index`
+    pub fn value(&self, index: usize) -> Option<&Hash> {
+        self.values.get(index)
+    }
+
+    /// Get the root at `index`
+    pub fn root(&self, index: usize) -> Option<&RootHash> {
+        self.roots.get(index)
+    }
+
+    /// Get the number of roots
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Check if no root has been published
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Get the hasher used by this chain
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Check that `value`, the chain value another client saw after the root
+    /// at `index`, matches this chain
+    ///
+    /// A mismatch means the two were shown different histories.
+    pub fn agrees_with(&self, index: usize, value: &Hash) -> bool {
+        self.value(index) == Some(value)
+    }
+
+    /// Get the roots in `range` with the chain value before them
+    pub fn segment(&self, range: Range<usize>) -> Result<ChainSegment> {
+        if range.start > range.end || range.end > self.len() {
+            return Err(MerkleError::InvalidIndex {
+                index: range.end,
+                size: self.len(),
+            });
+        }
+        let prev = match range.start {
+            0 => self.genesis(),
+            start => self.values[start - 1].clone(),
+        };
+        Ok(ChainSegment {
+            start: range.start,
+            prev,
+            roots: self.roots[range].to_vec(),
+        })
+    }
+}
+
+/// Consecutive roots of a [`RootChain`] and the chain value before them
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChainSegment {
+    /// Index of the first root
+    pub start: usize,
+    /// Chain value after the root before `start`, or the genesis value
+    pub prev: Hash,
+    /// Roots from `start` on
+    pub roots: Vec<RootHash>,
+}
+
+impl ChainSegment {
+    /// Get the chain value after every root of the segment
+    pub fn values<H: Hasher>(&self, hasher: &H) -> Vec<Hash> {
+        let mut value = self.prev.clone();
+        self.roots
+            .iter()
+            .map(|root| {
+                value = link(hasher, &value, root);
+                value.clone()
+            })
+            .collect()
+    }
+
+    /// Verify that the segment leads to `head`, the chain value after its
+    /// last root
+    ///
+    /// A segment starting at the first root must start from the genesis
+    /// value.
+    pub fn verify<H: Hasher>(&self, hasher: &H, head: &Hash) -> bool {
+        if self.start == 0 && self.prev != genesis(hasher) {
+            return false;
+        }
+        let end = self
+            .values(hasher)
+            .pop()
+            .unwrap_or_else(|| self.prev.clone());
+        end == *head
+    }
+
+    /// Check that the segment continues a chain whose value after the root
+    /// at `index` is `value`
+    pub fn extends(&self, index: usize, value: &Hash) -> bool {
+        self.start == index + 1 && self.prev == *value
+    }
+}
+
+/// `H(prev || root)`
+fn link<H: Hasher>(hasher: &H, prev: &Hash, root: &RootHash) -> Hash {
+    let mut input = Vec::with_capacity(prev.len() + root.len());
+    input.extend_from_slice(prev.as_bytes());
+    input.extend_from_slice(root.as_bytes());
+    hasher.hash(&input)
+}
+
+fn genesis<H: Hasher>(hasher: &H) -> Hash {
+    Hash::new(vec![0; hasher.output_size()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    fn root(i: u8) -> RootHash {
+        RootHash::new(vec![i; 32])
+    }
+
+    #[test]
+    fn test_chain() {
+        let hasher = Sha256Hasher::new();
+        let mut chain = RootChain::new(hasher.clone());
+        assert_eq!(chain.head(), Hash::new(vec![0; 32]));
+        let first = chain.push(root(1));
+        let expected = hasher.hash(&[vec![0; 32], vec![1; 32]].concat());
+        assert_eq!(first, expected);
+        for i in 2..10 {
+            chain.push(root(i));
+        }
+        assert_eq!(chain.len(), 9);
+        assert_eq!(chain.root(4), Some(&root(5)));
+
+        let full = chain.segment(0..9).unwrap();
+        assert!(full.verify(&hasher, &chain.head()));
+        assert_eq!(full.values(&hasher), chain.values);
+        let tail = chain.segment(4..9).unwrap();
+        assert!(tail.extends(3, chain.value(3).unwrap()));
+        assert!(tail.verify(&hasher, &chain.head()));
+        assert!(chain.segment(9..9).unwrap().verify(&hasher, &chain.head()));
+        assert!(chain.segment(5..10).is_err());
+
+        // Reordered or altered roots lead elsewhere
+        let mut swapped = tail.clone();
+        swapped.roots.swap(0, 1);
+        assert!(!swapped.verify(&hasher, &chain.head()));
+        let mut forged = full.clone();
+        forged.prev = chain.head();
+        forged.roots.clear();
+        assert!(!forged.verify(&hasher, &chain.head()));
+    }
+
+    #[test]
+    fn test_detects_divergence() {
+        let hasher = Sha256Hasher::new();
+        let mut alice = RootChain::new(hasher.clone());
+        let mut bob = RootChain::new(hasher.clone());
+        for i in 0..5 {
+            alice.push(root(i));
+            bob.push(root(i));
+        }
+        // The operator shows Bob a different root at position 5
+        alice.push(root(5));
+        bob.push(root(50));
+        for i in 6..8 {
+            alice.push(root(i));
+            bob.push(root(i));
+        }
+
+        assert!(bob.agrees_with(4, alice.value(4).unwrap()));
+        for i in 5..8 {
+            assert!(!bob.agrees_with(i, alice.value(i).unwrap()));
+        }
+        // Bob's segment does not extend what Alice knows into Alice's head
+        let segment = bob.segment(5..8).unwrap();
+        assert!(segment.extends(4, alice.value(4).unwrap()));
+        assert!(!segment.verify(&hasher, &alice.head()));
+    }
+}
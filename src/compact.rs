@@ -0,0 +1,299 @@
+//! Compact sparse Merkle trees
+//!
+//! A [`SparseMerkleTree`](crate::SparseMerkleTree) proof has one step per
+//! level, however few leaves the tree holds. [`CompactSparseTree`] instead
+//! collapses every subtree holding a single key into that key's leaf, placed
+//! at the shallowest depth where no other key shares its path. Proofs then
+//! have about `log2(n)` siblings for `n` keys, whether keys are 256-bit
+//! hashes or longer, rather than one per key bit.
+//!
+//! Keys are hashed with the tree's hasher and nodes hashed as in
+//! [`UrkelTree`], so a [`CompactSparseTree`] is its in-memory counterpart:
+//! the same keys and values give the same root, and proofs are
+//! [`UrkelProof`]s covering both membership and non-membership.
+
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+#[cfg(doc)]
+use crate::urkel::UrkelTree;
+use crate::urkel::{bit, empty_hash, internal_hash, leaf_hash, UrkelProof, UrkelTerminal};
+use std::collections::{BTreeMap, HashMap};
+
+/// In-memory sparse Merkle tree with single-key subtrees collapsed
+///
+/// ```rust
+/// use merkle_tree::{CompactSparseTree, Sha256Hasher};
+///
+/// let mut tree = CompactSparseTree::new(Sha256Hasher::new());
+/// tree.insert(b"alice", b"100");
+/// tree.insert(b"bob", b"42");
+/// let root = tree.root();
+///
+/// // Two keys split at the first bit where their hashes differ
+/// let proof = tree.prove(b"alice");
+/// assert!(proof.siblings.len() <= 2);
+/// assert!(proof.verify(tree.hasher(), &root, b"alice", Some(b"100")));
+/// assert!(tree.prove(b"carol").verify(tree.hasher(), &root, b"carol", None));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompactSparseTree<H: Hasher> {
+    /// Key hash to value and value hash, ordered as the leaves of the trie
+    entries: BTreeMap<Hash, (Vec<u8>, Hash)>,
+    /// Cached internal nodes, by depth and the lowest key below them
+    nodes: HashMap<(usize, Hash), Hash>,
+    hasher: H,
+}
+
+impl<H: Hasher> CompactSparseTree<H> {
+    /// Create an empty tree, whose root is all zeroes
+    pub fn new(hasher: H) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            nodes: HashMap::new(),
+            hasher,
+        }
+    }
+
+    /// Insert or replace the value stored under `key`
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) {
+        let key = self.hasher.hash(key);
+        let value_hash = self.hasher.hash(value);
+        self.invalidate(&key);
+        self.entries.insert(key, (value.to_vec(), value_hash));
+    }
+
+    /// Remove `key`, returning whether it was stored
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        let key = self.hasher.hash(key);
+        let removed = self.entries.remove(&key).is_some();
+        if removed {
+            self.invalidate(&key);
+        }
+        removed
+    }
+
+    /// Get the value stored under `key`
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries
+            .get(&self.hasher.hash(key))
+            .map(|(value, _)| value.as_slice())
+    }
+
+    /// Check if `key` is stored
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Get the number of stored keys
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the tree holds no keys
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the hasher used by this tree
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the root hash
+    pub fn root(&mut self) -> RootHash {
+        let any_key = empty_hash(&self.hasher);
+        RootHash::from(self.node_hash(&any_key, 0))
+    }
+
+    /// Generate a membership or non-membership proof for `key`
+    ///
+    /// The proof has one sibling per level down to where `key`'s path ends:
+    /// at its own leaf, at the leaf of another key sharing the path, or at an
+    /// empty subtree.
+    pub fn prove(&mut self, key: &[u8]) -> UrkelProof {
+        let key = self.hasher.hash(key);
+        let mut siblings = Vec::new();
+        loop {
+            let depth = siblings.len();
+            let terminal = match self.subtree(&key, depth) {
+                Subtree::Empty => UrkelTerminal::Empty,
+                Subtree::Leaf(found, value_hash) if *found == key => UrkelTerminal::Exists {
+                    value_hash: value_hash.clone(),
+                },
+                Subtree::Leaf(found, value_hash) => UrkelTerminal::Collision {
+                    key: found.clone(),
+                    value_hash: value_hash.clone(),
+                },
+                Subtree::Internal => {
+                    let sibling = with_bit(&key, depth, !bit(&key, depth));
+                    siblings.push(self.node_hash(&sibling, depth + 1));
+                    continue;
+                }
+            };
+            return UrkelProof { siblings, terminal };
+        }
+    }
+
+    /// Hash of the node at `depth` on the path of `key`
+    fn node_hash(&mut self, key: &Hash, depth: usize) -> Hash {
+        match self.subtree(key, depth) {
+            Subtree::Empty => empty_hash(&self.hasher),
+            Subtree::Leaf(key, value_hash) => leaf_hash(&self.hasher, key, value_hash),
+            Subtree::Internal => {
+                let id = (depth, lowest_key(key, depth));
+                if let Some(hash) = self.nodes.get(&id) {
+                    return hash.clone();
+                }
+                let left = self.node_hash(&with_bit(key, depth, false), depth + 1);
+                let right = self.node_hash(&with_bit(key, depth, true), depth + 1);
+                let hash = internal_hash(&self.hasher, &left, &right);
+                self.nodes.insert(id, hash.clone());
+                hash
+            }
+        }
+    }
+
+    /// What the node at `depth` on the path of `key` holds
+    fn subtree(&self, key: &Hash, depth: usize) -> Subtree<'_> {
+        let mut below = self
+            .entries
+            .range(lowest_key(key, depth)..=highest_key(key, depth));
+        match (below.next(), below.next()) {
+            (None, _) => Subtree::Empty,
+            (Some((key, (_, value_hash))), None) => Subtree::Leaf(key, value_hash),
+            _ => Subtree::Internal,
+        }
+    }
+
+    /// Drop the cached nodes on the path of `key`, the only ones it changes
+    fn invalidate(&mut self, key: &Hash) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        for depth in 0..=key.len() * 8 {
+            self.nodes.remove(&(depth, lowest_key(key, depth)));
+        }
+    }
+}
+
+/// Contents of a subtree
+enum Subtree<'a> {
+    Empty,
+    /// A single key, with its value hash
+    Leaf(&'a Hash, &'a Hash),
+    Internal,
+}
+
+/// `key` with the bits from `depth` on cleared
+pub(crate) fn lowest_key(key: &Hash, depth: usize) -> Hash {
+    mask_from(key, depth, 0x00)
+}
+
+/// `key` with the bits from `depth` on set
+pub(crate) fn highest_key(key: &Hash, depth: usize) -> Hash {
+    mask_from(key, depth, 0xff)
+}
+
+fn mask_from(key: &Hash, depth: usize, fill: u8) -> Hash {
+    let mut bytes = key.to_vec();
+    for (i, byte) in bytes.iter_mut().enumerate().skip(depth / 8) {
+        let kept = if i == depth / 8 {
+            0xffu8.checked_shl(8 - (depth % 8) as u32).unwrap_or(0)
+        } else {
+            0
+        };
+        *byte = *byte & kept | fill & !kept;
+    }
+    Hash::new(bytes)
+}
+
+/// `key` with bit `depth` set to `value`
+pub(crate) fn with_bit(key: &Hash, depth: usize, value: bool) -> Hash {
+    let mut bytes = key.to_vec();
+    let mask = 0x80 >> (depth % 8);
+    if value {
+        bytes[depth / 8] |= mask;
+    } else {
+        bytes[depth / 8] &= !mask;
+    }
+    Hash::new(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+    use crate::urkel::UrkelTree;
+
+    #[test]
+    fn test_matches_urkel() {
+        let hasher = Sha256Hasher::new();
+        let path = std::env::temp_dir().join(format!("merkle-compact-{}", std::process::id()));
+        let mut urkel = UrkelTree::open(&path, hasher.clone()).unwrap();
+        let mut tree = CompactSparseTree::new(hasher);
+        assert_eq!(tree.root(), urkel.root());
+
+        for i in 0..50u32 {
+            let key = format!("key_{}", i);
+            tree.insert(key.as_bytes(), &i.to_be_bytes());
+            urkel.insert(key.as_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        assert_eq!(tree.root(), urkel.root());
+        for i in (0..50u32).step_by(3) {
+            let key = format!("key_{}", i);
+            assert!(tree.remove(key.as_bytes()));
+            assert!(urkel.remove(key.as_bytes()).unwrap());
+        }
+        assert!(!tree.remove(b"key_0"));
+        assert_eq!(tree.root(), urkel.root());
+
+        // Updates after the root was cached only change their own path
+        tree.insert(b"key_1", b"updated");
+        urkel.insert(b"key_1", b"updated").unwrap();
+        assert_eq!(tree.root(), urkel.root());
+        assert_eq!(tree.prove(b"key_1"), urkel.prove(b"key_1").unwrap());
+        assert_eq!(tree.prove(b"key_3"), urkel.prove(b"key_3").unwrap());
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_proofs() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = CompactSparseTree::new(hasher.clone());
+        tree.insert(b"alice", b"100");
+        tree.insert(b"bob", b"42");
+        let root = tree.root();
+
+        // Two keys need at most as many steps as their hashes share bits,
+        // nowhere near the 256 of a full-depth proof
+        let proof = tree.prove(b"alice");
+        assert!(proof.siblings.len() < 16);
+        assert!(proof.verify(&hasher, &root, b"alice", Some(b"100")));
+        assert!(!proof.verify(&hasher, &root, b"alice", Some(b"101")));
+        assert!(tree.prove(b"carol").verify(&hasher, &root, b"carol", None));
+
+        let count = 1000;
+        for i in 0..count {
+            tree.insert(format!("key_{}", i).as_bytes(), b"value");
+        }
+        assert_eq!(tree.len(), count + 2);
+        assert_eq!(tree.get(b"bob"), Some(b"42".as_slice()));
+        let root = tree.root();
+        let longest = (0..count)
+            .map(|i| {
+                let key = format!("key_{}", i);
+                let proof = tree.prove(key.as_bytes());
+                assert!(proof.verify(&hasher, &root, key.as_bytes(), Some(b"value")));
+                proof.siblings.len()
+            })
+            .max()
+            .unwrap();
+        // log2(1002) is about 10; random keys add a few levels at most
+        assert!(longest < 32, "longest proof has {} steps", longest);
+
+        let absent = tree.prove(b"missing");
+        assert!(absent.verify(&hasher, &root, b"missing", None));
+        assert!(!absent.verify(&hasher, &root, b"missing", Some(b"value")));
+    }
+}
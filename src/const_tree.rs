@@ -0,0 +1,239 @@
+//! Roots of small fixed trees computed at compile time
+//!
+//! [`sha256_root`] is a `const fn` giving the same root as a [`MerkleTree`]
+//! built with [`Sha256Hasher`] and the default leaf encoding and node
+//! domain, so firmware can embed the expected root of a fixed leaf set, e.g.
+//! a list of feature flags, and check it at runtime with the regular tree
+//! and proof code.
+//!
+//! ```rust
+//! use merkle_tree::const_tree::sha256_root;
+//! use merkle_tree::{MerkleTree, Sha256Hasher, TreeShape};
+//!
+//! const FLAGS: [&[u8]; 3] = [b"fast-boot", b"secure-update", b"telemetry"];
+//! const FLAGS_ROOT: [u8; 32] = sha256_root(&FLAGS, TreeShape::Padded);
+//!
+//! let tree = MerkleTree::new(FLAGS.to_vec(), Sha256Hasher::new()).unwrap();
+//! assert_eq!(tree.root().as_bytes(), &FLAGS_ROOT);
+//! ```
+//!
+//! [`MerkleTree`]: crate::MerkleTree
+//! [`Sha256Hasher`]: crate::Sha256Hasher
+
+use crate::merkle_tree::TreeShape;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 of `data`, usable in constant expressions
+///
+/// Gives the same digest as [`Sha256Hasher::hash`](crate::Hasher::hash) for
+/// [`Sha256Hasher`](crate::Sha256Hasher), only slower, so it is meant for
+/// constants rather than runtime hashing.
+pub const fn sha256(data: &[u8]) -> [u8; 32] {
+    sha256_parts(data, &[])
+}
+
+/// SHA-256 root of a tree over `leaves`, usable in constant expressions
+///
+/// Leaves are hashed once and pairs as `H(left || right)`, matching
+/// [`MerkleTree::new`](crate::MerkleTree::new) for
+/// [`TreeShape::Padded`] and the builder with [`TreeShape::Rfc6962`]. An
+/// empty list has the root of [`MerkleTree::empty`](crate::MerkleTree::empty),
+/// `H("")`.
+pub const fn sha256_root<const N: usize>(leaves: &[&[u8]; N], shape: TreeShape) -> [u8; 32] {
+    if N == 0 {
+        return sha256(&[]);
+    }
+
+    let mut nodes = [[0u8; 32]; N];
+    let mut i = 0;
+    while i < N {
+        nodes[i] = sha256(leaves[i]);
+        i += 1;
+    }
+
+    let mut width = N;
+    while width > 1 {
+        let mut parent = 0;
+        while 2 * parent < width {
+            let left = nodes[2 * parent];
+            nodes[parent] = if 2 * parent + 1 < width {
+                sha256_parts(&left, &nodes[2 * parent + 1])
+            } else {
+                match shape {
+                    TreeShape::Padded => sha256_parts(&left, &left),
+                    TreeShape::Rfc6962 => left,
+                }
+            };
+            parent += 1;
+        }
+        width = parent;
+    }
+    nodes[0]
+}
+
+/// SHA-256 of `first || second` without concatenating them
+const fn sha256_parts(first: &[u8], second: &[u8]) -> [u8; 32] {
+    let len = first.len() + second.len();
+    // Message, a 0x80 byte, zero padding and the 8-byte bit length
+    let blocks = (len + 9).div_ceil(64);
+
+    let mut state = H0;
+    let mut block = 0;
+    while block < blocks {
+        let mut w = [0u32; 64];
+        let mut t = 0;
+        while t < 16 {
+            let mut word = 0u32;
+            let mut b = 0;
+            while b < 4 {
+                let i = block * 64 + t * 4 + b;
+                let byte = if i < len {
+                    byte_at(first, second, i)
+                } else if i == len {
+                    0x80
+                } else if i >= blocks * 64 - 8 {
+                    ((len as u64 * 8) >> ((blocks * 64 - 1 - i) * 8)) as u8
+                } else {
+                    0
+                };
+                word = word << 8 | byte as u32;
+                b += 1;
+            }
+            w[t] = word;
+            t += 1;
+        }
+        while t < 64 {
+            let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+            let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+            w[t] = w[t - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[t - 7])
+                .wrapping_add(s1);
+            t += 1;
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        t = 0;
+        while t < 64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[t])
+                .wrapping_add(w[t]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+            t += 1;
+        }
+
+        let added = [a, b, c, d, e, f, g, h];
+        let mut i = 0;
+        while i < 8 {
+            state[i] = state[i].wrapping_add(added[i]);
+            i += 1;
+        }
+        block += 1;
+    }
+
+    let mut digest = [0u8; 32];
+    let mut i = 0;
+    while i < 8 {
+        let bytes = state[i].to_be_bytes();
+        digest[4 * i] = bytes[0];
+        digest[4 * i + 1] = bytes[1];
+        digest[4 * i + 2] = bytes[2];
+        digest[4 * i + 3] = bytes[3];
+        i += 1;
+    }
+    digest
+}
+
+/// Byte `i` of `first || second`
+const fn byte_at(first: &[u8], second: &[u8], i: usize) -> u8 {
+    if i < first.len() {
+        first[i]
+    } else {
+        second[i - first.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MerkleTreeBuilder;
+    use crate::hasher::{Hasher, Sha256Hasher};
+    use crate::merkle_tree::MerkleTree;
+
+    #[test]
+    fn test_sha256() {
+        let hasher = Sha256Hasher::new();
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        for len in [0, 1, 3, 55, 56, 63, 64, 65, 119, 120, 128, 300] {
+            assert_eq!(
+                sha256(&data[..len]),
+                hasher.hash(&data[..len]).as_bytes(),
+                "length {}",
+                len
+            );
+        }
+
+        const ABC: [u8; 32] = sha256(b"abc");
+        assert_eq!(
+            hex::encode(ABC),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_sha256_root() {
+        const LEAVES: [&[u8]; 9] = [b"a", b"b", b"c", b"d", b"e", b"f", b"g", b"h", b"i"];
+        const ROOT_5: [u8; 32] = sha256_root(&[b"a", b"b", b"c", b"d", b"e"], TreeShape::Padded);
+
+        let tree = MerkleTree::new(LEAVES[..5].to_vec(), Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root().as_bytes(), &ROOT_5);
+
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            macro_rules! check {
+                ($($n:literal)*) => {$(
+                    let leaves: &[&[u8]; $n] = LEAVES[..$n].try_into().unwrap();
+                    let tree = MerkleTreeBuilder::new(Sha256Hasher::new())
+                        .shape(shape)
+                        .build(leaves.to_vec())
+                        .unwrap();
+                    assert_eq!(tree.root().as_bytes(), &sha256_root(leaves, shape));
+                )*};
+            }
+            check!(1 2 3 4 5 6 7 8 9);
+        }
+
+        let empty = MerkleTree::empty(Sha256Hasher::new());
+        assert_eq!(
+            empty.root().as_bytes(),
+            &sha256_root(&[], TreeShape::Padded)
+        );
+    }
+}
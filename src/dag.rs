@@ -0,0 +1,646 @@
+//! Content-addressed Merkle DAGs
+//!
+//! A [`DagNode`] holds a data payload and named links to other nodes, and is
+//! addressed by the [`Cid`] of its encoding: a [`Multihash`] tagging the
+//! digest with the hash function that produced it, plus a codec saying how
+//! to read the bytes. Nodes encode as IPFS DAG-PB and CIDs are version 1, so
+//! a node built here with [`SHA2_256`] has the CID `ipfs` gives the same
+//! node. Raw blocks use the [`RAW`] codec.
+//!
+//! [`DagStore`] keeps blocks by CID in memory, checking each against its CID
+//! on the way in, and resolves `/`-separated paths through named links.
+//!
+//! ```rust
+//! use merkle_tree::dag::{DagNode, DagStore, SHA2_256};
+//! use merkle_tree::Sha256Hasher;
+//!
+//! # fn main() -> merkle_tree::Result<()> {
+//! let mut store = DagStore::new(Sha256Hasher::new(), SHA2_256);
+//! let readme = store.put_raw(b"hello")?;
+//!
+//! let mut dir = DagNode::new(Vec::new());
+//! dir.add_link("README", readme.clone(), 5);
+//! let root = store.put(&dir)?;
+//!
+//! assert!(root.to_string().starts_with("bafybei"));
+//! assert_eq!(store.resolve(&root, "README")?, Some(readme));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::{MerkleError, Result};
+use crate::hasher::Hasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Multihash code of SHA-256, for [`Sha256Hasher`](crate::Sha256Hasher)
+pub const SHA2_256: u64 = 0x12;
+/// Multihash code of SHA3-256, for [`Sha3Hasher`](crate::Sha3Hasher)
+pub const SHA3_256: u64 = 0x16;
+/// Multihash code of Keccak-256, for
+/// [`Keccak256Hasher`](crate::Keccak256Hasher)
+pub const KECCAK_256: u64 = 0x1b;
+/// Multihash code of 32-byte BLAKE3, for [`Blake3Hasher`](crate::Blake3Hasher)
+pub const BLAKE3: u64 = 0x1e;
+
+/// Codec of DAG-PB nodes
+pub const DAG_PB: u64 = 0x70;
+/// Codec of raw blocks
+pub const RAW: u64 = 0x55;
+
+/// Largest digest a multihash may carry
+const MAX_DIGEST_LEN: usize = 1024;
+
+/// A digest tagged with the code of the hash function that produced it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Multihash {
+    code: u64,
+    digest: Vec<u8>,
+}
+
+impl Multihash {
+    /// Wrap a digest made by the hash function with multihash `code`
+    pub fn new(code: u64, digest: Vec<u8>) -> Result<Self> {
+        if digest.len() > MAX_DIGEST_LEN {
+            return Err(MerkleError::ResourceLimitExceeded {
+                resource: "Multihash digest length".to_string(),
+                limit: MAX_DIGEST_LEN as u64,
+            });
+        }
+        Ok(Self { code, digest })
+    }
+
+    /// Hash `data` with `hasher`, whose multihash code is `code`
+    ///
+    /// The code is not checked against the hasher: a tagged or
+    /// domain-separated hasher reports its inner hash's name but produces
+    /// different digests, so only the caller knows which code applies.
+    pub fn digest<H: Hasher>(code: u64, hasher: &H, data: &[u8]) -> Result<Self> {
+        Self::new(code, hasher.hash(data).into_bytes())
+    }
+
+    /// Get the hash function code
+    pub fn code(&self) -> u64 {
+        self.code
+    }
+
+    /// Get the digest
+    pub fn digest_bytes(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// Encode as `varint(code) || varint(length) || digest`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.digest.len() + 4);
+        self.write_to(&mut out);
+        out
+    }
+
+    /// Decode from the encoding of [`Multihash::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut input = bytes;
+        let hash = Self::read_from(&mut input)?;
+        expect_end(input, "multihash")?;
+        Ok(hash)
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        put_uvarint(out, self.code);
+        put_uvarint(out, self.digest.len() as u64);
+        out.extend_from_slice(&self.digest);
+    }
+
+    fn read_from(input: &mut &[u8]) -> Result<Self> {
+        let code = take_uvarint(input)?;
+        let len = take_uvarint(input)?;
+        if len > MAX_DIGEST_LEN as u64 {
+            return Err(MerkleError::ResourceLimitExceeded {
+                resource: "Multihash digest length".to_string(),
+                limit: MAX_DIGEST_LEN as u64,
+            });
+        }
+        let digest = take_bytes(input, len as usize)?.to_vec();
+        Ok(Self { code, digest })
+    }
+}
+
+/// Content identifier: a codec and the multihash of a block, CID version 1
+///
+/// Displays and parses as lowercase base32 with the `b` multibase prefix,
+/// the default text form of version 1 CIDs.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Cid {
+    codec: u64,
+    hash: Multihash,
+}
+
+impl Cid {
+    /// Address a block read with `codec` by its multihash
+    pub fn new(codec: u64, hash: Multihash) -> Self {
+        Self { codec, hash }
+    }
+
+    /// Get the codec of the addressed block
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+
+    /// Get the multihash of the addressed block
+    pub fn hash(&self) -> &Multihash {
+        &self.hash
+    }
+
+    /// Check that `block` is the block this CID addresses
+    ///
+    /// `hasher` must be the hash function of this CID's multihash code.
+    pub fn matches<H: Hasher>(&self, hasher: &H, block: &[u8]) -> bool {
+        hasher.hash(block).as_bytes() == self.hash.digest_bytes()
+    }
+
+    /// Encode as `varint(1) || varint(codec) || multihash`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.hash.digest.len() + 8);
+        put_uvarint(&mut out, 1);
+        put_uvarint(&mut out, self.codec);
+        self.hash.write_to(&mut out);
+        out
+    }
+
+    /// Decode from the encoding of [`Cid::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut input = bytes;
+        let version = take_uvarint(&mut input)?;
+        if version != 1 {
+            return Err(invalid(format!("Unsupported CID version {}", version)));
+        }
+        let codec = take_uvarint(&mut input)?;
+        let hash = Multihash::read_from(&mut input)?;
+        expect_end(input, "CID")?;
+        Ok(Self { codec, hash })
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", base32_encode(&self.to_bytes()))
+    }
+}
+
+impl fmt::Debug for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cid({})", self)
+    }
+}
+
+impl FromStr for Cid {
+    type Err = MerkleError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(encoded) = s.strip_prefix('b') else {
+            return Err(invalid("Expected a base32 CID with the 'b' prefix"));
+        };
+        Self::from_bytes(&base32_decode(encoded)?)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Cid {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Cid {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Named link from a DAG node to another block
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DagLink {
+    /// Name the link is resolved by in paths
+    pub name: String,
+    /// CID of the linked block
+    pub cid: Cid,
+    /// Total size of the linked block and everything it links to
+    pub size: u64,
+}
+
+/// DAG node: a data payload and links to other blocks
+///
+/// Links keep their insertion order, which is part of the encoding and so
+/// of the CID. An empty payload is left out of the encoding, as `ipfs` does
+/// for directories.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DagNode {
+    /// Links to other blocks
+    pub links: Vec<DagLink>,
+    /// Payload of the node
+    pub data: Vec<u8>,
+}
+
+impl DagNode {
+    /// Create a node with `data` and no links
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            links: Vec::new(),
+            data,
+        }
+    }
+
+    /// Add a link to `cid` named `name`, whose total size is `size`
+    pub fn add_link(&mut self, name: impl Into<String>, cid: Cid, size: u64) {
+        self.links.push(DagLink {
+            name: name.into(),
+            cid,
+            size,
+        });
+    }
+
+    /// Find the first link named `name`
+    pub fn link(&self, name: &str) -> Option<&DagLink> {
+        self.links.iter().find(|link| link.name == name)
+    }
+
+    /// Total size of the node and everything it links to, the size a link
+    /// to it should carry
+    pub fn total_size(&self) -> u64 {
+        let linked: u64 = self.links.iter().map(|link| link.size).sum();
+        self.encode().len() as u64 + linked
+    }
+
+    /// Encode as a DAG-PB `PBNode` message
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for link in &self.links {
+            let mut encoded = Vec::new();
+            put_field(&mut encoded, 1, &link.cid.to_bytes());
+            put_field(&mut encoded, 2, link.name.as_bytes());
+            put_uvarint(&mut encoded, 3 << 3);
+            put_uvarint(&mut encoded, link.size);
+            put_field(&mut out, 2, &encoded);
+        }
+        if !self.data.is_empty() {
+            put_field(&mut out, 1, &self.data);
+        }
+        out
+    }
+
+    /// Decode a DAG-PB `PBNode` message
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut input = bytes;
+        let mut node = Self::default();
+        while !input.is_empty() {
+            match take_field(&mut input)? {
+                (1, Field::Bytes(data)) => node.data = data.to_vec(),
+                (2, Field::Bytes(mut link)) if node.data.is_empty() => {
+                    node.links.push(decode_link(&mut link)?)
+                }
+                (2, _) => return Err(invalid("Links must come before the data")),
+                (number, _) => return Err(invalid(format!("Unexpected PBNode field {}", number))),
+            }
+        }
+        Ok(node)
+    }
+
+    /// Get the CID of the node, hashing with `hasher` under multihash `code`
+    pub fn cid<H: Hasher>(&self, hasher: &H, code: u64) -> Result<Cid> {
+        Ok(Cid::new(
+            DAG_PB,
+            Multihash::digest(code, hasher, &self.encode())?,
+        ))
+    }
+}
+
+/// In-memory store of DAG nodes and raw blocks keyed by CID
+#[derive(Debug, Clone)]
+pub struct DagStore<H: Hasher> {
+    blocks: HashMap<Cid, Vec<u8>>,
+    hasher: H,
+    code: u64,
+}
+
+impl<H: Hasher> DagStore<H> {
+    /// Create an empty store hashing with `hasher`, whose multihash code is
+    /// `code`
+    pub fn new(hasher: H, code: u64) -> Self {
+        Self {
+            blocks: HashMap::new(),
+            hasher,
+            code,
+        }
+    }
+
+    /// Store a node, returning its CID
+    pub fn put(&mut self, node: &DagNode) -> Result<Cid> {
+        self.put_block(DAG_PB, node.encode())
+    }
+
+    /// Store a raw block, returning its CID
+    pub fn put_raw(&mut self, data: &[u8]) -> Result<Cid> {
+        self.put_block(RAW, data.to_vec())
+    }
+
+    /// Store a block fetched from elsewhere under `cid`
+    ///
+    /// Fails if the block does not hash to the CID or the CID uses another
+    /// hash function than the store, so a store only ever holds blocks its
+    /// CIDs vouch for. DAG-PB blocks must also decode as nodes.
+    pub fn insert(&mut self, cid: Cid, block: Vec<u8>) -> Result<()> {
+        if cid.hash().code() != self.code || !cid.matches(&self.hasher, &block) {
+            return Err(MerkleError::IntegrityError {
+                path: cid.to_string(),
+                reason: "Block does not match its CID".to_string(),
+            });
+        }
+        if cid.codec() == DAG_PB {
+            DagNode::decode(&block)?;
+        }
+        self.blocks.insert(cid, block);
+        Ok(())
+    }
+
+    /// Get the bytes of the block addressed by `cid`
+    pub fn get_block(&self, cid: &Cid) -> Option<&[u8]> {
+        self.blocks.get(cid).map(Vec::as_slice)
+    }
+
+    /// Get the node addressed by `cid`
+    ///
+    /// Returns `None` if it is not stored, and fails if `cid` addresses a
+    /// block of another codec.
+    pub fn get(&self, cid: &Cid) -> Result<Option<DagNode>> {
+        if cid.codec() != DAG_PB {
+            return Err(invalid(format!("CID {} is not a DAG-PB node", cid)));
+        }
+        self.get_block(cid).map(DagNode::decode).transpose()
+    }
+
+    /// Follow the named links of `path`, separated by `/`, from `root`
+    ///
+    /// Returns `None` if a name along the way has no link or a node on the
+    /// path is not stored.
+    pub fn resolve(&self, root: &Cid, path: &str) -> Result<Option<Cid>> {
+        let mut cid = root.clone();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let Some(node) = self.get(&cid)? else {
+                return Ok(None);
+            };
+            let Some(link) = node.link(name) else {
+                return Ok(None);
+            };
+            cid = link.cid.clone();
+        }
+        Ok(Some(cid))
+    }
+
+    /// Check if `cid` is stored
+    pub fn contains(&self, cid: &Cid) -> bool {
+        self.blocks.contains_key(cid)
+    }
+
+    /// Get the number of stored blocks
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Check if the store holds no blocks
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Get the hasher used by this store
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    fn put_block(&mut self, codec: u64, block: Vec<u8>) -> Result<Cid> {
+        let cid = Cid::new(codec, Multihash::digest(self.code, &self.hasher, &block)?);
+        self.blocks.insert(cid.clone(), block);
+        Ok(cid)
+    }
+}
+
+fn decode_link(input: &mut &[u8]) -> Result<DagLink> {
+    let (mut cid, mut name, mut size) = (None, String::new(), 0);
+    while !input.is_empty() {
+        match take_field(input)? {
+            (1, Field::Bytes(bytes)) => cid = Some(Cid::from_bytes(bytes)?),
+            (2, Field::Bytes(bytes)) => {
+                name = String::from_utf8(bytes.to_vec())
+                    .map_err(|_| invalid("Link name is not UTF-8"))?
+            }
+            (3, Field::Varint(value)) => size = value,
+            (number, _) => return Err(invalid(format!("Unexpected PBLink field {}", number))),
+        }
+    }
+    let cid = cid.ok_or_else(|| invalid("Link has no CID"))?;
+    Ok(DagLink { name, cid, size })
+}
+
+/// Value of a protobuf field
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn take_field<'a>(input: &mut &'a [u8]) -> Result<(u64, Field<'a>)> {
+    let key = take_uvarint(input)?;
+    let field = match key & 7 {
+        0 => Field::Varint(take_uvarint(input)?),
+        2 => {
+            let len = take_uvarint(input)?;
+            let len = usize::try_from(len).map_err(|_| invalid("Field is too long"))?;
+            Field::Bytes(take_bytes(input, len)?)
+        }
+        wire_type => return Err(invalid(format!("Unexpected wire type {}", wire_type))),
+    };
+    Ok((key >> 3, field))
+}
+
+fn put_field(out: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+    put_uvarint(out, number << 3 | 2);
+    put_uvarint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn put_uvarint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Read an unsigned varint of at most 10 bytes, as multiformats and
+/// protobuf write them
+fn take_uvarint(input: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for (i, &byte) in input.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(invalid("Truncated or overlong varint"))
+}
+
+fn take_bytes<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(invalid("Unexpected end of input"));
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+fn expect_end(input: &[u8], what: &str) -> Result<()> {
+    if !input.is_empty() {
+        return Err(invalid(format!("Trailing bytes after {}", what)));
+    }
+    Ok(())
+}
+
+fn invalid(message: impl Into<String>) -> MerkleError {
+    MerkleError::SerializationError {
+        message: message.into(),
+    }
+}
+
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// RFC 4648 base32, lowercase and unpadded
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = buffer << 8 | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let value = BASE32
+            .iter()
+            .position(|&b| b == c)
+            .ok_or_else(|| invalid(format!("Invalid base32 character {:?}", c as char)))?;
+        buffer = buffer << 5 | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Blake3Hasher, Sha256Hasher};
+
+    #[test]
+    fn test_cids() {
+        let hasher = Sha256Hasher::new();
+        // CIDs `ipfs` gives the empty raw block and the empty directory
+        let empty = Cid::new(RAW, Multihash::digest(SHA2_256, &hasher, b"").unwrap());
+        assert_eq!(
+            empty.to_string(),
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+        let dir = DagNode::new(vec![0x08, 0x01]);
+        assert_eq!(
+            dir.cid(&hasher, SHA2_256).unwrap().to_string(),
+            "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354"
+        );
+
+        let parsed: Cid = empty.to_string().parse().unwrap();
+        assert_eq!(parsed, empty);
+        assert_eq!(Cid::from_bytes(&empty.to_bytes()).unwrap(), empty);
+        assert_eq!(
+            Multihash::from_bytes(&empty.hash().to_bytes()).unwrap(),
+            *empty.hash()
+        );
+        assert!("Qmabc".parse::<Cid>().is_err());
+        assert!("b!!".parse::<Cid>().is_err());
+        let mut truncated = empty.to_bytes();
+        truncated.pop();
+        assert!(Cid::from_bytes(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_nodes() {
+        let hasher = Blake3Hasher::new();
+        let mut store = DagStore::new(hasher.clone(), BLAKE3);
+        let file = store.put_raw(b"file contents").unwrap();
+        let mut sub = DagNode::new(Vec::new());
+        sub.add_link("file.txt", file.clone(), 13);
+        let sub_cid = store.put(&sub).unwrap();
+        let mut root = DagNode::new(b"metadata".to_vec());
+        root.add_link("docs", sub_cid.clone(), sub.total_size());
+        let root_cid = store.put(&root).unwrap();
+
+        assert_eq!(store.len(), 3);
+        assert_eq!(root_cid.hash().code(), BLAKE3);
+        assert_eq!(DagNode::decode(&root.encode()).unwrap(), root);
+        assert_eq!(store.get(&root_cid).unwrap(), Some(root.clone()));
+        assert_eq!(store.get_block(&file), Some(b"file contents".as_slice()));
+        assert!(store.get(&file).is_err());
+
+        assert_eq!(
+            store.resolve(&root_cid, "docs/file.txt").unwrap(),
+            Some(file)
+        );
+        assert_eq!(
+            store.resolve(&root_cid, "/").unwrap(),
+            Some(root_cid.clone())
+        );
+        assert_eq!(store.resolve(&root_cid, "docs/missing").unwrap(), None);
+
+        // Changing a leaf changes every CID above it
+        let mut other = store.clone();
+        let changed = other.put_raw(b"other contents").unwrap();
+        sub.links[0].cid = changed;
+        root.links[0].cid = other.put(&sub).unwrap();
+        assert_ne!(other.put(&root).unwrap(), root_cid);
+
+        // Blocks from elsewhere are checked against their CID
+        let mut copy = DagStore::new(hasher, BLAKE3);
+        // `root` now links to the changed subtree, so no longer matches
+        assert!(copy.insert(root_cid.clone(), root.encode()).is_err());
+        copy.insert(sub_cid.clone(), store.get_block(&sub_cid).unwrap().to_vec())
+            .unwrap();
+        assert!(copy.contains(&sub_cid));
+        let sha = Cid::new(
+            RAW,
+            Multihash::digest(SHA2_256, &Sha256Hasher::new(), b"x").unwrap(),
+        );
+        assert!(copy.insert(sha, b"x".to_vec()).is_err());
+
+        assert!(DagNode::decode(&[0x0a, 0x01, 0x00, 0x12, 0x00]).is_err());
+        assert!(DagNode::decode(&[0x1a, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Trees committed in epochs
+//!
+//! [`EpochedTree`] buffers appended leaves until [`EpochedTree::commit`]
+//! seals them into a [`MerkleTree`] of their own, the epoch. Epoch roots are
+//! appended to a [`MerkleLog`], whose root commits to every sealed leaf, so a
+//! leaf is proven by its path within its epoch chained with the epoch's path
+//! in the log, and consecutive top-level roots are linked by consistency
+//! proofs.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::log::{ConsistencyProof, MerkleLog};
+use crate::merkle_tree::MerkleTree;
+use crate::proof::MerkleProof;
+
+/// Append-only tree sealed into epochs
+#[derive(Debug)]
+pub struct EpochedTree<H: Hasher> {
+    hasher: H,
+    /// Leaves appended since the last commit
+    pending: Vec<Hash>,
+    /// Sealed epochs, oldest first
+    epochs: Vec<MerkleTree<H>>,
+    /// Log over the epoch roots
+    top: MerkleLog<H>,
+}
+
+impl<H: Hasher> EpochedTree<H> {
+    /// Create a tree without any epochs
+    pub fn new(hasher: H) -> Self {
+        Self {
+            top: MerkleLog::new(hasher.clone()),
+            hasher,
+            pending: Vec::new(),
+            epochs: Vec::new(),
+        }
+    }
+
+    /// Hash and buffer a data item
+    ///
+    /// Returns the epoch the leaf will be sealed into and its index within
+    /// that epoch.
+    pub fn append(&mut self, data: &[u8]) -> (usize, usize) {
+        let leaf = self.hasher.hash(data);
+        self.append_leaf(leaf)
+    }
+
+    /// Buffer a pre-hashed leaf
+    pub fn append_leaf(&mut self, leaf: Hash) -> (usize, usize) {
+        self.pending.push(leaf);
+        (self.epochs.len(), self.pending.len() - 1)
+    }
+
+    /// Seal the buffered leaves into a new epoch
+    ///
+    /// Returns the root of the new epoch. Fails with
+    /// [`MerkleError::EmptyData`] if nothing was appended since the last
+    /// commit.
+    pub fn commit(&mut self) -> Result<RootHash> {
+        if self.pending.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+
+        let leaves = std::mem::take(&mut self.pending);
+        let epoch = MerkleTree::from_leaves(leaves, self.hasher.clone())?;
+        let root = epoch.root();
+        self.top.append_leaf(root.as_hash().clone())?;
+        self.epochs.push(epoch);
+        Ok(root)
+    }
+
+    /// Get the root over all sealed epochs
+    ///
+    /// Buffered leaves are not covered until they are committed. Before the
+    /// first commit this is the empty log root [`Hasher::empty_root`].
+    pub fn root(&self) -> RootHash {
+        self.top.root()
+    }
+
+    /// Get the number of sealed epochs
+    pub fn epochs(&self) -> usize {
+        self.epochs.len()
+    }
+
+    /// Get the number of leaves waiting for the next commit
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Get the hasher used by the tree
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get a sealed epoch
+    pub fn epoch(&self, epoch: usize) -> Result<&MerkleTree<H>> {
+        self.epochs.get(epoch).ok_or(MerkleError::InvalidIndex {
+            index: epoch,
+            size: self.epochs.len(),
+        })
+    }
+
+    /// Get the root of a sealed epoch
+    pub fn epoch_root(&self, epoch: usize) -> Result<RootHash> {
+        Ok(self.epoch(epoch)?.root())
+    }
+
+    /// Prove a sealed leaf against [`EpochedTree::root`]
+    ///
+    /// The proof's `leaf_index` is the index within the epoch.
+    pub fn generate_proof(&self, epoch: usize, index: usize) -> Result<MerkleProof> {
+        let inner = self.epoch(epoch)?.generate_proof(index)?;
+        let outer = self.top.prove_inclusion(epoch, self.epochs.len())?;
+        // Epochs differ in size, so the chained global position means nothing
+        let mut proof = inner.chain(outer)?;
+        proof.leaf_index = index;
+        Ok(proof)
+    }
+
+    /// Prove that the root after `old_epochs` epochs is a prefix of the
+    /// current root
+    pub fn prove_consistency(&self, old_epochs: usize) -> Result<ConsistencyProof> {
+        self.top.prove_consistency(old_epochs, self.epochs.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    #[test]
+    fn test_commit_and_prove() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = EpochedTree::new(hasher.clone());
+        assert_eq!(tree.root(), RootHash::from(hasher.empty_root()));
+        assert_eq!(tree.commit(), Err(MerkleError::EmptyData));
+
+        let mut sealed = Vec::new();
+        for epoch in 0..4 {
+            for i in 0..=epoch {
+                let item = format!("epoch_{}_item_{}", epoch, i);
+                assert_eq!(tree.append(item.as_bytes()), (epoch, i));
+                sealed.push((epoch, i, item));
+            }
+            assert_eq!(tree.pending(), epoch + 1);
+
+            let old_root = tree.root();
+            let epoch_root = tree.commit().unwrap();
+            assert_eq!(tree.epoch_root(epoch).unwrap(), epoch_root);
+            assert_eq!(tree.pending(), 0);
+            if epoch > 0 {
+                let proof = tree.prove_consistency(epoch).unwrap();
+                assert!(proof.verify(&hasher, &old_root, &tree.root()));
+            }
+        }
+        assert_eq!(tree.epochs(), 4);
+
+        // Every sealed leaf is proven against the latest root
+        let root = tree.root();
+        for (epoch, index, item) in &sealed {
+            let proof = tree.generate_proof(*epoch, *index).unwrap();
+            assert_eq!(proof.leaf_index, *index);
+            assert!(proof.verify(&hasher, item.as_bytes(), &root));
+            assert!(!proof.verify(&hasher, b"wrong", &root));
+        }
+
+        // Buffered leaves are not part of the root until committed
+        tree.append(b"late");
+        assert_eq!(tree.root(), root);
+        assert!(matches!(
+            tree.generate_proof(4, 0),
+            Err(MerkleError::InvalidIndex { index: 4, size: 4 })
+        ));
+    }
+}
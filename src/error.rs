@@ -2,6 +2,7 @@ use thiserror::Error;
 
 /// Error types for Merkle tree operations
 #[derive(Error, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error), uniffi(flat_error))]
 pub enum MerkleError {
     #[error("Empty data provided")]
     EmptyData,
@@ -20,6 +21,37 @@ pub enum MerkleError {
 
     #[error("Tree construction failed: {reason}")]
     TreeConstructionError { reason: String },
+
+    #[error("I/O error: {message}")]
+    IoError { message: String },
+
+    #[error("Sync failed: {reason}")]
+    SyncError { reason: String },
+
+    #[error("Leaf {index} is {actual} bytes, expected a {expected}-byte hash")]
+    HashSizeMismatch {
+        index: usize,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("Corrupt node at {path}: {reason}")]
+    IntegrityError { path: String, reason: String },
+
+    #[error("Duplicate leaves at indices {indices:?}")]
+    DuplicateLeaves { indices: Vec<usize> },
+
+    #[error("Resource limit exceeded: {resource} exceeds the limit of {limit}")]
+    ResourceLimitExceeded { resource: String, limit: u64 },
+
+    #[error("Root mismatch: expected {expected}, found {actual}")]
+    RootMismatch { expected: String, actual: String },
+
+    #[error("Invalid transaction state: {reason}")]
+    TransactionState { reason: String },
+
+    #[error("Unsupported operation: {reason}")]
+    UnsupportedOperation { reason: String },
 }
 
 /// Result type for Merkle tree operations
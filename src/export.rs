@@ -0,0 +1,183 @@
+//! Bulk export of proofs for distribution to claimants
+//!
+//! Proofs are generated and written one leaf at a time, so exporting a tree
+//! with hundreds of thousands of leaves never holds more than one proof in
+//! memory. Wrap files in a [`std::io::BufWriter`] for best throughput.
+
+use crate::error::{MerkleError, Result};
+use crate::hasher::Hasher;
+use crate::merkle_tree::MerkleTree;
+use crate::proof::{MerkleProof, ProofDirection};
+use std::io::Write;
+
+/// Output format for [`MerkleTree::export_proofs`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofFormat {
+    /// One JSON object per line:
+    /// `{"index":0,"leaf":"<hex>","proof":[{"direction":"Right","hash":"<hex>"}]}`
+    JsonLines,
+    /// Header `index,leaf,proof` followed by one row per leaf, where the proof
+    /// is a `;`-separated list of `L:<hex>` / `R:<hex>` steps
+    Csv,
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Write the proof of every leaf to `writer`, in leaf order
+    pub fn export_proofs<W: Write>(&self, mut writer: W, format: ProofFormat) -> Result<()> {
+        if format == ProofFormat::Csv {
+            writeln!(writer, "index,leaf,proof").map_err(io_error)?;
+        }
+
+        for index in 0..self.len() {
+            let proof = self.generate_proof(index)?;
+            let leaf = self.get_leaf(index)?.to_hex();
+
+            match format {
+                ProofFormat::JsonLines => write_json_line(&mut writer, &leaf, &proof),
+                ProofFormat::Csv => write_csv_row(&mut writer, &leaf, &proof),
+            }
+            .map_err(io_error)?;
+        }
+
+        writer.flush().map_err(io_error)
+    }
+}
+
+fn write_json_line<W: Write>(
+    writer: &mut W,
+    leaf: &str,
+    proof: &MerkleProof,
+) -> std::io::Result<()> {
+    // Only integers and hex strings are written, so nothing needs escaping
+    write!(
+        writer,
+        r#"{{"index":{},"leaf":"{}","proof":["#,
+        proof.leaf_index, leaf
+    )?;
+    for (i, step) in proof.steps.iter().enumerate() {
+        let direction = match step.direction {
+            ProofDirection::Left => "Left",
+            ProofDirection::Right => "Right",
+        };
+        let separator = if i == 0 { "" } else { "," };
+        write!(
+            writer,
+            r#"{}{{"direction":"{}","hash":"{}"}}"#,
+            separator,
+            direction,
+            step.hash.to_hex()
+        )?;
+    }
+    writeln!(writer, "]}}")
+}
+
+fn write_csv_row<W: Write>(writer: &mut W, leaf: &str, proof: &MerkleProof) -> std::io::Result<()> {
+    write!(writer, "{},{},", proof.leaf_index, leaf)?;
+    for (i, step) in proof.steps.iter().enumerate() {
+        let direction = match step.direction {
+            ProofDirection::Left => "L",
+            ProofDirection::Right => "R",
+        };
+        let separator = if i == 0 { "" } else { ";" };
+        write!(writer, "{}{}:{}", separator, direction, step.hash.to_hex())?;
+    }
+    writeln!(writer)
+}
+
+fn io_error(e: std::io::Error) -> MerkleError {
+    MerkleError::IoError {
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::Hash;
+    use crate::hasher::Sha256Hasher;
+    use crate::proof::ProofStep;
+
+    fn tree() -> MerkleTree<Sha256Hasher> {
+        MerkleTree::new(vec!["a", "b", "c", "d", "e"], Sha256Hasher::new()).unwrap()
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let tree = tree();
+        let mut out = Vec::new();
+        tree.export_proofs(&mut out, ProofFormat::Csv).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("index,leaf,proof"));
+
+        for (index, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.split(',').collect();
+            assert_eq!(fields[0], index.to_string());
+            assert_eq!(fields[1], tree.get_leaf(index).unwrap().to_hex());
+
+            let steps = fields[2]
+                .split(';')
+                .map(|step| {
+                    let (direction, hash) = step.split_once(':').unwrap();
+                    ProofStep {
+                        hash: Hash::from_hex(hash).unwrap(),
+                        direction: if direction == "L" {
+                            ProofDirection::Left
+                        } else {
+                            ProofDirection::Right
+                        },
+                    }
+                })
+                .collect();
+            let proof = MerkleProof::new(index, steps);
+            assert_eq!(proof, tree.generate_proof(index).unwrap());
+        }
+        assert_eq!(text.lines().count(), tree.len() + 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_export_json_lines() {
+        let tree = tree();
+        let mut out = Vec::new();
+        tree.export_proofs(&mut out, ProofFormat::JsonLines)
+            .unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), tree.len());
+
+        for (index, line) in text.lines().enumerate() {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(value["index"], index);
+            assert_eq!(value["leaf"], tree.get_leaf(index).unwrap().to_hex());
+
+            // The proof array deserializes straight into proof steps
+            let steps: Vec<ProofStep> = serde_json::from_value(value["proof"].clone()).unwrap();
+            assert_eq!(steps, tree.generate_proof(index).unwrap().steps);
+        }
+    }
+
+    #[test]
+    fn test_export_write_error() {
+        struct Full;
+
+        impl Write for Full {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "disk full",
+                ))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        assert!(matches!(
+            tree().export_proofs(Full, ProofFormat::JsonLines),
+            Err(MerkleError::IoError { .. })
+        ));
+    }
+}
@@ -0,0 +1,632 @@
+//! External-memory construction for trees larger than RAM
+//!
+//! [`ExternalTreeBuilder`] writes leaf hashes to disk as they are pushed and
+//! then hashes the tree one level at a time, streaming each level from its
+//! file into the next, so only the read and write buffers are held in memory.
+//! The result is a [`DiskTree`] with one file per level, `level-0.bin` holding
+//! the leaves, each a flat array of `hasher.output_size()`-byte hashes. Proofs
+//! are read from it with one seek per level.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Default size of the read and write buffers, in bytes
+pub const DEFAULT_BUFFER_SIZE: usize = 1 << 20;
+
+/// Builds a [`DiskTree`] in a directory without holding its levels in memory
+///
+/// Existing level files in the directory are overwritten.
+///
+/// ```rust
+/// use merkle_tree::{ExternalTreeBuilder, Sha256Hasher};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// # let dir = std::env::temp_dir().join(format!("merkle-doc-{}", std::process::id()));
+/// let mut builder = ExternalTreeBuilder::new(Sha256Hasher::new(), &dir);
+/// for i in 0..1000u32 {
+///     builder.push(&i.to_be_bytes())?;
+/// }
+/// let tree = builder.finish()?;
+///
+/// let proof = tree.generate_proof(42)?;
+/// assert!(proof.verify(tree.hasher(), &42u32.to_be_bytes(), &tree.root()));
+/// # std::fs::remove_dir_all(&dir).ok();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ExternalTreeBuilder<H: Hasher> {
+    hasher: H,
+    dir: PathBuf,
+    buffer_size: usize,
+    leaves: Option<BufWriter<File>>,
+    len: usize,
+}
+
+impl<H: Hasher> ExternalTreeBuilder<H> {
+    /// Create a builder writing its level files to `dir`
+    pub fn new(hasher: H, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            hasher,
+            dir: dir.into(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            leaves: None,
+            len: 0,
+        }
+    }
+
+    /// Set the size of each read and write buffer, in bytes
+    ///
+    /// Must be set before the first leaf is pushed.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// Hash a data item and append it as a leaf
+    pub fn push(&mut self, data: &[u8]) -> Result<()> {
+        let leaf = self.hasher.hash(data);
+        self.write_leaf(&leaf)
+    }
+
+    /// Append a pre-hashed leaf, which must be `hasher.output_size()` bytes
+    pub fn push_leaf(&mut self, leaf: &Hash) -> Result<()> {
+        let expected = self.hasher.output_size();
+        if leaf.len() != expected {
+            return Err(MerkleError::HashSizeMismatch {
+                index: self.len,
+                expected,
+                actual: leaf.len(),
+            });
+        }
+        self.write_leaf(leaf)
+    }
+
+    /// Get the number of leaves pushed so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if no leaves have been pushed
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Hash the remaining levels and return the finished tree
+    pub fn finish(mut self) -> Result<DiskTree<H>> {
+        let mut leaves = self.leaves.take().ok_or(MerkleError::EmptyData)?;
+        leaves.flush().map_err(io_error)?;
+        drop(leaves);
+
+        let hash_size = self.hasher.output_size();
+        let mut level = 0;
+        let mut len = self.len;
+        let mut left = vec![0u8; hash_size];
+        let mut right = vec![0u8; hash_size];
+
+        while len > 1 {
+            let input = File::open(level_path(&self.dir, level)).map_err(io_error)?;
+            let mut reader = BufReader::with_capacity(self.buffer_size, input);
+            let mut writer = self.create_level(level + 1)?;
+
+            for pair in 0..len.div_ceil(2) {
+                reader.read_exact(&mut left).map_err(io_error)?;
+                if 2 * pair + 1 < len {
+                    reader.read_exact(&mut right).map_err(io_error)?;
+                } else {
+                    // Odd number of nodes - duplicate the last one
+                    right.copy_from_slice(&left);
+                }
+                let parent = self
+                    .hasher
+                    .hash_pair(&Hash::from(left.as_slice()), &Hash::from(right.as_slice()));
+                writer.write_all(&parent).map_err(io_error)?;
+            }
+
+            writer.flush().map_err(io_error)?;
+            level += 1;
+            len = len.div_ceil(2);
+        }
+
+        DiskTree::open(self.dir, self.hasher)
+    }
+
+    fn write_leaf(&mut self, leaf: &Hash) -> Result<()> {
+        if self.leaves.is_none() {
+            fs::create_dir_all(&self.dir).map_err(io_error)?;
+            self.leaves = Some(self.create_level(0)?);
+        }
+        if let Some(writer) = &mut self.leaves {
+            writer.write_all(leaf).map_err(io_error)?;
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    fn create_level(&self, level: usize) -> Result<BufWriter<File>> {
+        let file = File::create(level_path(&self.dir, level)).map_err(io_error)?;
+        Ok(BufWriter::with_capacity(self.buffer_size, file))
+    }
+}
+
+/// Builds a [`DiskTree`] from sorted leaf data, dropping duplicates as they
+/// arrive and writing an index map alongside
+///
+/// Pushed items must be in ascending byte order. An item equal to the one
+/// before it is skipped, so a sorted dump with repeats needs no separate
+/// deduplication pass, and each distinct item is written to the index map
+/// with its leaf index:
+///
+/// ```text
+/// per leaf: data length u32 | data | leaf index u64
+/// ```
+///
+/// with integers big-endian. Entries come in leaf order, which is also byte
+/// order, and are read back with [`IndexMapReader`].
+///
+/// ```rust
+/// use merkle_tree::{ExternalTreeBuilder, IndexMapReader, Sha256Hasher, SortedDedupBuilder};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// # let dir = std::env::temp_dir().join(format!("merkle-dedup-doc-{}", std::process::id()));
+/// let builder = ExternalTreeBuilder::new(Sha256Hasher::new(), &dir);
+/// let mut dedup = SortedDedupBuilder::new(builder, Vec::new());
+/// for item in ["apple", "apple", "banana", "cherry", "cherry"] {
+///     dedup.push(item.as_bytes())?;
+/// }
+/// assert_eq!(dedup.duplicates(), 2);
+/// let (tree, index_map) = dedup.finish()?;
+/// assert_eq!(tree.len(), 3);
+///
+/// let entries = IndexMapReader::new(index_map.as_slice()).collect::<Result<Vec<_>, _>>()?;
+/// assert_eq!(entries[1], (b"banana".to_vec(), 1));
+/// # std::fs::remove_dir_all(&dir).ok();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SortedDedupBuilder<H: Hasher, W: Write> {
+    builder: ExternalTreeBuilder<H>,
+    index_map: W,
+    last: Option<Vec<u8>>,
+    duplicates: u64,
+}
+
+impl<H: Hasher, W: Write> SortedDedupBuilder<H, W> {
+    /// Feed `builder` and write the index map to `index_map`
+    ///
+    /// Wrap files in a [`BufWriter`] for best throughput.
+    pub fn new(builder: ExternalTreeBuilder<H>, index_map: W) -> Self {
+        Self {
+            builder,
+            index_map,
+            last: None,
+            duplicates: 0,
+        }
+    }
+
+    /// Append `data` as a leaf unless it repeats the previous item
+    ///
+    /// Returns whether a leaf was added. Fails if `data` sorts before the
+    /// previous item.
+    pub fn push(&mut self, data: &[u8]) -> Result<bool> {
+        if let Some(last) = &self.last {
+            match data.cmp(last.as_slice()) {
+                Ordering::Equal => {
+                    self.duplicates += 1;
+                    return Ok(false);
+                }
+                Ordering::Less => {
+                    return Err(MerkleError::TreeConstructionError {
+                        reason: format!(
+                            "Item after leaf {} is out of order",
+                            self.builder.len() - 1
+                        ),
+                    })
+                }
+                Ordering::Greater => {}
+            }
+        }
+
+        let len = u32::try_from(data.len()).map_err(|_| MerkleError::SerializationError {
+            message: format!("Item of {} bytes is too large", data.len()),
+        })?;
+        let index = self.builder.len() as u64;
+        self.index_map
+            .write_all(&len.to_be_bytes())
+            .and_then(|()| self.index_map.write_all(data))
+            .and_then(|()| self.index_map.write_all(&index.to_be_bytes()))
+            .map_err(io_error)?;
+        self.builder.push(data)?;
+
+        let last = self.last.get_or_insert_with(Vec::new);
+        last.clear();
+        last.extend_from_slice(data);
+        Ok(true)
+    }
+
+    /// Get the number of distinct leaves pushed so far
+    pub fn len(&self) -> usize {
+        self.builder.len()
+    }
+
+    /// Check if no leaves have been pushed
+    pub fn is_empty(&self) -> bool {
+        self.builder.is_empty()
+    }
+
+    /// Get the number of items skipped as duplicates
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Finish the tree and return it with the flushed index map writer
+    pub fn finish(mut self) -> Result<(DiskTree<H>, W)> {
+        self.index_map.flush().map_err(io_error)?;
+        let tree = self.builder.finish()?;
+        Ok((tree, self.index_map))
+    }
+}
+
+/// Reads the `(data, leaf index)` entries of an index map written by
+/// [`SortedDedupBuilder`]
+#[derive(Debug)]
+pub struct IndexMapReader<R> {
+    reader: R,
+}
+
+impl<R: Read> IndexMapReader<R> {
+    /// Read entries from `reader`; wrap files in a [`BufReader`]
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<(Vec<u8>, u64)>> {
+        let mut len = [0u8; 4];
+        // Input may end cleanly only between entries
+        let first = loop {
+            match self.reader.read(&mut len[..1]) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(io_error(e)),
+            }
+        };
+        if first == 0 {
+            return Ok(None);
+        }
+        self.reader.read_exact(&mut len[1..]).map_err(io_error)?;
+
+        let len = u32::from_be_bytes(len) as u64;
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(len)
+            .read_to_end(&mut data)
+            .map_err(io_error)?;
+        if data.len() as u64 != len {
+            return Err(MerkleError::SerializationError {
+                message: "Index map ends inside an entry".to_string(),
+            });
+        }
+        let mut index = [0u8; 8];
+        self.reader.read_exact(&mut index).map_err(io_error)?;
+        Ok(Some((data, u64::from_be_bytes(index))))
+    }
+}
+
+impl<R: Read> Iterator for IndexMapReader<R> {
+    type Item = Result<(Vec<u8>, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+/// Merkle tree whose levels live in files, as written by
+/// [`ExternalTreeBuilder`]
+#[derive(Debug, Clone)]
+pub struct DiskTree<H: Hasher> {
+    hasher: H,
+    dir: PathBuf,
+    /// Number of hashes on each level, leaves first
+    lens: Vec<usize>,
+    root: RootHash,
+}
+
+impl<H: Hasher> DiskTree<H> {
+    /// Open a tree previously written to `dir`
+    ///
+    /// Checks that every level file has the size implied by the leaf count,
+    /// but does not rehash the levels.
+    pub fn open(dir: impl Into<PathBuf>, hasher: H) -> Result<Self> {
+        let dir = dir.into();
+        let hash_size = hasher.output_size() as u64;
+
+        let mut lens = Vec::new();
+        loop {
+            let level = lens.len();
+            let bytes = fs::metadata(level_path(&dir, level))
+                .map_err(io_error)?
+                .len();
+            if bytes % hash_size != 0 {
+                return Err(MerkleError::TreeConstructionError {
+                    reason: format!("Level {} is not a whole number of hashes", level),
+                });
+            }
+
+            let len = usize::try_from(bytes / hash_size).map_err(|_| {
+                MerkleError::TreeConstructionError {
+                    reason: format!("Level {} does not fit in memory", level),
+                }
+            })?;
+            if len == 0 {
+                return Err(MerkleError::EmptyData);
+            }
+            let expected = lens.last().map_or(len, |&below: &usize| below.div_ceil(2));
+            if len != expected {
+                return Err(MerkleError::TreeConstructionError {
+                    reason: format!("Level {} has {} hashes, expected {}", level, len, expected),
+                });
+            }
+
+            lens.push(len);
+            if len == 1 {
+                break;
+            }
+        }
+
+        let mut tree = Self {
+            hasher,
+            dir,
+            lens,
+            root: RootHash::default(),
+        };
+        tree.root = RootHash::from(tree.read_hash(tree.lens.len() - 1, 0)?);
+        Ok(tree)
+    }
+
+    /// Get the root hash of the tree
+    pub fn root(&self) -> RootHash {
+        self.root.clone()
+    }
+
+    /// Get the number of leaves in the tree
+    pub fn len(&self) -> usize {
+        self.lens[0]
+    }
+
+    /// Check if the tree is empty
+    pub fn is_empty(&self) -> bool {
+        self.lens[0] == 0
+    }
+
+    /// Get the hasher used by this tree
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the directory holding the level files
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Read the leaf hash at the given index
+    pub fn get_leaf(&self, index: usize) -> Result<Hash> {
+        if index >= self.len() {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len(),
+            });
+        }
+        self.read_hash(0, index)
+    }
+
+    /// Generate a Merkle proof for the leaf at the given index
+    pub fn generate_proof(&self, index: usize) -> Result<MerkleProof> {
+        if index >= self.len() {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len(),
+            });
+        }
+
+        let mut steps = Vec::with_capacity(self.lens.len() - 1);
+        let mut position = index;
+        for (level, &len) in self.lens[..self.lens.len() - 1].iter().enumerate() {
+            let step = if position.is_multiple_of(2) {
+                // The last node of an odd level is paired with itself
+                ProofStep {
+                    hash: self.read_hash(level, (position + 1).min(len - 1))?,
+                    direction: ProofDirection::Right,
+                }
+            } else {
+                ProofStep {
+                    hash: self.read_hash(level, position - 1)?,
+                    direction: ProofDirection::Left,
+                }
+            };
+            steps.push(step);
+            position /= 2;
+        }
+
+        Ok(MerkleProof::new(index, steps))
+    }
+
+    fn read_hash(&self, level: usize, index: usize) -> Result<Hash> {
+        let hash_size = self.hasher.output_size();
+        let mut file = File::open(level_path(&self.dir, level)).map_err(io_error)?;
+        file.seek(SeekFrom::Start((index * hash_size) as u64))
+            .map_err(io_error)?;
+
+        let mut hash = vec![0u8; hash_size];
+        file.read_exact(&mut hash).map_err(io_error)?;
+        Ok(Hash::new(hash))
+    }
+}
+
+fn level_path(dir: &Path, level: usize) -> PathBuf {
+    dir.join(format!("level-{}.bin", level))
+}
+
+fn io_error(e: std::io::Error) -> MerkleError {
+    MerkleError::IoError {
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+    use crate::merkle_tree::MerkleTree;
+
+    /// Fresh directory under the system temp dir, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "merkle-external-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::remove_dir_all(&dir).ok();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn test_matches_merkle_tree() {
+        for len in 1..=17 {
+            let dir = TempDir::new(&format!("matches-{}", len));
+            let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+
+            // A tiny buffer forces many refills while streaming levels
+            let mut builder = ExternalTreeBuilder::new(Sha256Hasher::new(), &dir.0).buffer_size(16);
+            for item in &data {
+                builder.push(item.as_bytes()).unwrap();
+            }
+            assert_eq!(builder.len(), len);
+            let tree = builder.finish().unwrap();
+
+            let reference = MerkleTree::new(data, Sha256Hasher::new()).unwrap();
+            assert_eq!(tree.root(), reference.root());
+            assert_eq!(tree.len(), len);
+            for index in 0..len {
+                assert_eq!(
+                    tree.generate_proof(index).unwrap(),
+                    reference.generate_proof(index).unwrap()
+                );
+                assert_eq!(
+                    &tree.get_leaf(index).unwrap(),
+                    reference.get_leaf(index).unwrap()
+                );
+            }
+            assert!(matches!(
+                tree.generate_proof(len),
+                Err(MerkleError::InvalidIndex { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = TempDir::new("reopen");
+        let hasher = Sha256Hasher::new();
+        let mut builder = ExternalTreeBuilder::new(hasher.clone(), &dir.0);
+        for i in 0..5u8 {
+            builder.push_leaf(&hasher.hash(&[i])).unwrap();
+        }
+        let tree = builder.finish().unwrap();
+
+        let reopened = DiskTree::open(&dir.0, hasher.clone()).unwrap();
+        assert_eq!(reopened.root(), tree.root());
+        assert_eq!(reopened.len(), 5);
+
+        // A truncated level no longer matches the leaf count
+        let path = level_path(&dir.0, 1);
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..32]).unwrap();
+        assert!(matches!(
+            DiskTree::open(&dir.0, hasher),
+            Err(MerkleError::TreeConstructionError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sorted_dedup() {
+        let dir = TempDir::new("dedup");
+        let items = ["a", "a", "a", "b", "c", "c", "d", "e", "e"];
+        let builder = ExternalTreeBuilder::new(Sha256Hasher::new(), &dir.0).buffer_size(16);
+        let mut dedup = SortedDedupBuilder::new(builder, Vec::new());
+        let added: Vec<bool> = items
+            .iter()
+            .map(|item| dedup.push(item.as_bytes()).unwrap())
+            .collect();
+        assert_eq!(
+            added,
+            [true, false, false, true, true, false, true, true, false]
+        );
+        assert_eq!(dedup.len(), 5);
+        assert_eq!(dedup.duplicates(), 4);
+
+        // Out of order input is refused and leaves the builder usable
+        assert!(matches!(
+            dedup.push(b"b"),
+            Err(MerkleError::TreeConstructionError { .. })
+        ));
+        assert!(dedup.push(b"f").unwrap());
+
+        let (tree, index_map) = dedup.finish().unwrap();
+        let distinct = vec!["a", "b", "c", "d", "e", "f"];
+        let reference = MerkleTree::new(distinct.clone(), Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), reference.root());
+
+        let entries: Vec<(Vec<u8>, u64)> = IndexMapReader::new(index_map.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        let expected: Vec<(Vec<u8>, u64)> = distinct
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (item.as_bytes().to_vec(), i as u64))
+            .collect();
+        assert_eq!(entries, expected);
+
+        // A map cut inside an entry is reported, not silently shortened
+        let cut = &index_map[..index_map.len() - 3];
+        let result: Result<Vec<_>> = IndexMapReader::new(cut).collect();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let dir = TempDir::new("errors");
+        let builder = ExternalTreeBuilder::new(Sha256Hasher::new(), &dir.0);
+        assert!(matches!(builder.finish(), Err(MerkleError::EmptyData)));
+
+        let mut builder = ExternalTreeBuilder::new(Sha256Hasher::new(), &dir.0);
+        assert_eq!(
+            builder.push_leaf(&Hash::from([0u8; 20])),
+            Err(MerkleError::HashSizeMismatch {
+                index: 0,
+                expected: 32,
+                actual: 20
+            })
+        );
+        assert!(matches!(
+            DiskTree::open(&dir.0, Sha256Hasher::new()),
+            Err(MerkleError::IoError { .. })
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+//! UniFFI bindings for Swift and Kotlin
+//!
+//! Generic hashers cannot cross the FFI boundary, so the bindings select one
+//! with [`HashAlgorithm`](crate::hasher::HashAlgorithm). Hashes and data are plain byte arrays on the
+//! foreign side. Generate the bindings with the `uniffi-bindgen` binary:
+//!
+//! ```text
+//! cargo build --release --features uniffi
+//! cargo run --features uniffi-cli --bin uniffi-bindgen -- generate \
+//!     --library target/release/libmerkle_tree.so --language swift --out-dir out
+//! ```
+
+use crate::error::Result;
+use crate::hash::{Hash, RootHash};
+use crate::hasher::{HashAlgorithm, Hasher};
+use crate::merkle_tree::MerkleTree;
+use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+use std::sync::Arc;
+
+/// Proof step as seen from foreign code
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct FfiProofStep {
+    pub hash: Vec<u8>,
+    pub direction: ProofDirection,
+}
+
+/// Inclusion proof as seen from foreign code
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct FfiProof {
+    pub leaf_index: u64,
+    pub steps: Vec<FfiProofStep>,
+}
+
+impl From<MerkleProof> for FfiProof {
+    fn from(proof: MerkleProof) -> Self {
+        Self {
+            leaf_index: proof.leaf_index as u64,
+            steps: proof
+                .steps
+                .into_iter()
+                .map(|step| FfiProofStep {
+                    hash: step.hash.into_bytes(),
+                    direction: step.direction,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<FfiProof> for MerkleProof {
+    fn from(proof: FfiProof) -> Self {
+        MerkleProof::new(
+            proof.leaf_index as usize,
+            proof
+                .steps
+                .into_iter()
+                .map(|step| ProofStep {
+                    hash: Hash::new(step.hash),
+                    direction: step.direction,
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Merkle tree handle for foreign code
+#[derive(Debug, uniffi::Object)]
+pub struct FfiMerkleTree {
+    tree: MerkleTree<HashAlgorithm>,
+}
+
+#[uniffi::export]
+impl FfiMerkleTree {
+    /// Build a tree by hashing each data item
+    #[uniffi::constructor]
+    pub fn new(data: Vec<Vec<u8>>, algorithm: HashAlgorithm) -> Result<Arc<Self>> {
+        let tree = MerkleTree::new(data, algorithm)?;
+        Ok(Arc::new(Self { tree }))
+    }
+
+    /// Build a tree from pre-hashed leaves
+    #[uniffi::constructor]
+    pub fn from_leaves(leaves: Vec<Vec<u8>>, algorithm: HashAlgorithm) -> Result<Arc<Self>> {
+        let leaves = leaves.into_iter().map(Hash::new).collect();
+        let tree = MerkleTree::from_leaves(leaves, algorithm)?;
+        Ok(Arc::new(Self { tree }))
+    }
+
+    /// Get the root hash
+    pub fn root(&self) -> Vec<u8> {
+        self.tree.root().to_vec()
+    }
+
+    /// Get the number of leaves
+    pub fn len(&self) -> u64 {
+        self.tree.len() as u64
+    }
+
+    /// Check if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Get a leaf hash
+    pub fn leaf(&self, index: u64) -> Result<Vec<u8>> {
+        Ok(self.tree.get_leaf(index as usize)?.to_vec())
+    }
+
+    /// Generate the inclusion proof of a leaf
+    pub fn generate_proof(&self, index: u64) -> Result<FfiProof> {
+        Ok(self.tree.generate_proof(index as usize)?.into())
+    }
+}
+
+/// Hash data with the given algorithm
+#[uniffi::export]
+pub fn hash_data(algorithm: HashAlgorithm, data: Vec<u8>) -> Vec<u8> {
+    algorithm.hash(&data).into_bytes()
+}
+
+/// Verify that `proof` includes `data` under `root`
+#[uniffi::export]
+pub fn verify_proof(
+    algorithm: HashAlgorithm,
+    proof: FfiProof,
+    data: Vec<u8>,
+    root: Vec<u8>,
+) -> bool {
+    MerkleProof::from(proof).verify(&algorithm, &data, &RootHash::from(root))
+}
+
+/// Parse a proof from its hex text form, `index:N, steps:[L:<hex>, ...]`
+#[uniffi::export]
+pub fn proof_from_hex(text: String) -> Result<FfiProof> {
+    Ok(MerkleProof::from_hex(&text)?.into())
+}
+
+/// Format a proof in its hex text form
+#[uniffi::export]
+pub fn proof_to_hex(proof: FfiProof) -> String {
+    MerkleProof::from(proof).to_hex()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::MerkleError;
+    use crate::hasher::{Keccak256Hasher, Sha256Hasher, SortedPairHasher};
+
+    #[test]
+    fn test_matches_native_trees() {
+        let data: Vec<Vec<u8>> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+
+        let tree = FfiMerkleTree::new(data.clone(), HashAlgorithm::Sha256).unwrap();
+        let native = MerkleTree::new(data.clone(), Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), native.root().to_vec());
+
+        let tree = FfiMerkleTree::new(data.clone(), HashAlgorithm::SortedKeccak256).unwrap();
+        let native =
+            MerkleTree::new(data.clone(), SortedPairHasher::new(Keccak256Hasher::new())).unwrap();
+        assert_eq!(tree.root(), native.root().to_vec());
+        assert_eq!(tree.len(), 5);
+    }
+
+    #[test]
+    fn test_proofs() {
+        let data: Vec<Vec<u8>> = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
+        let tree = FfiMerkleTree::new(data, HashAlgorithm::Blake3).unwrap();
+        let proof = tree.generate_proof(2).unwrap();
+
+        assert!(verify_proof(
+            HashAlgorithm::Blake3,
+            proof.clone(),
+            b"c".to_vec(),
+            tree.root()
+        ));
+        assert!(!verify_proof(
+            HashAlgorithm::Blake3,
+            proof.clone(),
+            b"a".to_vec(),
+            tree.root()
+        ));
+        assert!(!verify_proof(
+            HashAlgorithm::Sha256,
+            proof.clone(),
+            b"c".to_vec(),
+            tree.root()
+        ));
+
+        let text = proof_to_hex(proof.clone());
+        assert_eq!(proof_from_hex(text).unwrap(), proof);
+
+        assert!(matches!(
+            tree.generate_proof(3),
+            Err(MerkleError::InvalidIndex { .. })
+        ));
+    }
+}
@@ -0,0 +1,326 @@
+//! Merkle trees over the fixed-size chunks of a file
+//!
+//! [`FileTree`] splits content into chunks of a fixed size, the last one
+//! possibly shorter, and builds a [`MerkleTree`] over them with one leaf
+//! `H(chunk)` per chunk. Its root also commits to the file length, so a
+//! [`ByteRangeProof`] pins down exactly which bytes a range covers and a
+//! client can check the response to an HTTP range request against the
+//! published root alone: it widens the range to chunk boundaries with
+//! [`ByteRangeProof::fetch_range`], downloads those bytes from any mirror
+//! and verifies them with the proof.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::RootHash;
+use crate::hasher::Hasher;
+use crate::merkle_tree::{MerkleTree, TreeShape};
+use crate::proof::MerkleProof;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::ops::Range;
+
+/// Merkle tree over the chunks of a file
+///
+/// ```rust
+/// use merkle_tree::file::FileTree;
+/// use merkle_tree::Sha256Hasher;
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let content: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+/// let hasher = Sha256Hasher::new();
+/// let file = FileTree::new(&content, 1024, hasher.clone())?;
+/// let root = file.root();
+///
+/// // Client side: fetch the chunk-aligned range, then verify it
+/// let proof = file.prove_byte_range(3000, 500)?;
+/// let fetch = proof.fetch_range();
+/// let chunks = &content[fetch.start as usize..fetch.end as usize];
+/// assert!(proof.verify(&hasher, &root, chunks));
+/// assert_eq!(proof.extract(chunks), Some(&content[3000..3500]));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileTree<H: Hasher> {
+    tree: MerkleTree<H>,
+    chunk_size: u64,
+    len: u64,
+}
+
+impl<H: Hasher> FileTree<H> {
+    /// Build the tree over `content` split into `chunk_size`-byte chunks
+    pub fn new(content: &[u8], chunk_size: usize, hasher: H) -> Result<Self> {
+        Self::from_reader(content, chunk_size, hasher)
+    }
+
+    /// Build the tree over everything `reader` yields, one chunk at a time,
+    /// so the file never needs to fit in memory
+    pub fn from_reader<R: Read>(mut reader: R, chunk_size: usize, hasher: H) -> Result<Self> {
+        if chunk_size == 0 {
+            return Err(MerkleError::TreeConstructionError {
+                reason: "Chunk size must be at least 1".to_string(),
+            });
+        }
+        let mut leaves = Vec::new();
+        let mut len = 0u64;
+        let mut chunk = Vec::with_capacity(chunk_size);
+        loop {
+            chunk.clear();
+            (&mut reader)
+                .take(chunk_size as u64)
+                .read_to_end(&mut chunk)
+                .map_err(|e| MerkleError::IoError {
+                    message: e.to_string(),
+                })?;
+            if chunk.is_empty() {
+                break;
+            }
+            len += chunk.len() as u64;
+            leaves.push(hasher.hash(&chunk));
+            if chunk.len() < chunk_size {
+                break;
+            }
+        }
+
+        let tree = if leaves.is_empty() {
+            MerkleTree::empty(hasher)
+        } else {
+            MerkleTree::from_leaves(leaves, hasher)?
+        };
+        Ok(Self {
+            tree,
+            chunk_size: chunk_size as u64,
+            len,
+        })
+    }
+
+    /// Get the root, committing to the file length and its chunks
+    pub fn root(&self) -> RootHash {
+        file_root(self.tree.hasher(), self.len, &self.tree.root())
+    }
+
+    /// Get the file length in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Check if the file is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the chunk size in bytes
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Get the number of chunks
+    pub fn chunk_count(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Get the tree over the chunks, whose root [`FileTree::root`] wraps
+    pub fn tree(&self) -> &MerkleTree<H> {
+        &self.tree
+    }
+
+    /// Prove the `len` bytes from `offset`
+    ///
+    /// The proof covers every chunk the range touches; the client fetches
+    /// them whole, see [`ByteRangeProof::fetch_range`].
+    pub fn prove_byte_range(&self, offset: u64, len: u64) -> Result<ByteRangeProof> {
+        let chunks = chunk_range(offset, len, self.len, self.chunk_size)?;
+        let proofs = chunks
+            .map(|index| self.tree.generate_proof(index))
+            .collect::<Result<_>>()?;
+        Ok(ByteRangeProof {
+            offset,
+            len,
+            file_len: self.len,
+            chunk_size: self.chunk_size,
+            proofs,
+        })
+    }
+}
+
+/// Proof of a byte range of a [`FileTree`], with a proof per covering chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ByteRangeProof {
+    /// Offset of the first proven byte
+    pub offset: u64,
+    /// Number of proven bytes
+    pub len: u64,
+    /// Length of the whole file
+    pub file_len: u64,
+    /// Size of every chunk but the last
+    pub chunk_size: u64,
+    /// Proofs of the covering chunks, in order
+    pub proofs: Vec<MerkleProof>,
+}
+
+impl ByteRangeProof {
+    /// Get the indices of the chunks covering the range
+    pub fn chunks(&self) -> Range<usize> {
+        chunk_range(self.offset, self.len, self.file_len, self.chunk_size).unwrap_or(0..0)
+    }
+
+    /// Get the chunk-aligned byte range to fetch, e.g. for an HTTP `Range`
+    /// header, whose bytes [`ByteRangeProof::verify`] expects
+    pub fn fetch_range(&self) -> Range<u64> {
+        let chunks = self.chunks();
+        let start = chunks.start as u64 * self.chunk_size;
+        let end = (chunks.end as u64 * self.chunk_size).min(self.file_len);
+        start..end
+    }
+
+    /// Verify `chunks`, the bytes of [`ByteRangeProof::fetch_range`], against
+    /// the root of a [`FileTree`]
+    ///
+    /// Checks each chunk's proof and position within a file of `file_len`
+    /// bytes, and that the root commits to that length, so the bytes, their
+    /// offsets and the file length are all authenticated.
+    pub fn verify<H: Hasher>(&self, hasher: &H, root: &RootHash, chunks: &[u8]) -> bool {
+        let Ok(indices) = chunk_range(self.offset, self.len, self.file_len, self.chunk_size) else {
+            return false;
+        };
+        let fetch = self.fetch_range();
+        if self.proofs.len() != indices.len() || chunks.len() as u64 != fetch.end - fetch.start {
+            return false;
+        }
+
+        let count = self.file_len.div_ceil(self.chunk_size) as usize;
+        let mut tree_root = None;
+        for ((index, proof), chunk) in indices
+            .zip(&self.proofs)
+            .zip(chunks.chunks(self.chunk_size as usize))
+        {
+            if proof.leaf_index != index || proof.check_position(count, TreeShape::Padded).is_err()
+            {
+                return false;
+            }
+            let computed = proof.compute_root(hasher, &hasher.hash(chunk));
+            if tree_root.get_or_insert_with(|| computed.clone()) != &computed {
+                return false;
+            }
+        }
+        tree_root.is_some_and(|tree_root| file_root(hasher, self.file_len, &tree_root) == *root)
+    }
+
+    /// Cut the proven range out of `chunks`, the bytes of
+    /// [`ByteRangeProof::fetch_range`]
+    ///
+    /// Returns `None` if `chunks` has the wrong length. Only meaningful once
+    /// [`ByteRangeProof::verify`] has passed.
+    pub fn extract<'a>(&self, chunks: &'a [u8]) -> Option<&'a [u8]> {
+        let fetch = self.fetch_range();
+        if chunks.len() as u64 != fetch.end - fetch.start || self.chunks().is_empty() {
+            return None;
+        }
+        let from = (self.offset - fetch.start) as usize;
+        chunks.get(from..from + self.len as usize)
+    }
+}
+
+/// `H(file_len || tree_root)`, with the length as 8 big-endian bytes
+fn file_root<H: Hasher>(hasher: &H, file_len: u64, tree_root: &RootHash) -> RootHash {
+    let mut input = file_len.to_be_bytes().to_vec();
+    input.extend_from_slice(tree_root.as_bytes());
+    RootHash::from(hasher.hash(&input))
+}
+
+/// Indices of the chunks holding `len` bytes from `offset`
+fn chunk_range(offset: u64, len: u64, file_len: u64, chunk_size: u64) -> Result<Range<usize>> {
+    if len == 0 {
+        return Err(MerkleError::EmptyData);
+    }
+    if chunk_size == 0 {
+        return Err(MerkleError::InvalidProof {
+            reason: "Chunk size must be at least 1".to_string(),
+        });
+    }
+    let Some(end) = offset.checked_add(len).filter(|&end| end <= file_len) else {
+        return Err(MerkleError::InvalidIndex {
+            index: offset.saturating_add(len) as usize,
+            size: file_len as usize,
+        });
+    };
+    Ok((offset / chunk_size) as usize..end.div_ceil(chunk_size) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn fetch<'a>(content: &'a [u8], proof: &ByteRangeProof) -> &'a [u8] {
+        let range = proof.fetch_range();
+        &content[range.start as usize..range.end as usize]
+    }
+
+    #[test]
+    fn test_byte_ranges() {
+        let hasher = Sha256Hasher::new();
+        let data = content(10_500);
+        let file = FileTree::new(&data, 1000, hasher.clone()).unwrap();
+        assert_eq!(file.chunk_count(), 11);
+        let root = file.root();
+
+        for (offset, len) in [(0, 1), (999, 2), (2500, 3000), (10_000, 500), (0, 10_500)] {
+            let proof = file.prove_byte_range(offset, len).unwrap();
+            let chunks = fetch(&data, &proof);
+            assert!(proof.verify(&hasher, &root, chunks), "{}+{}", offset, len);
+            let range = offset as usize..(offset + len) as usize;
+            assert_eq!(proof.extract(chunks), Some(&data[range]));
+        }
+        let proof = file.prove_byte_range(999, 2).unwrap();
+        assert_eq!(proof.chunks(), 0..2);
+        assert_eq!(proof.fetch_range(), 0..2000);
+        assert_eq!(
+            file.prove_byte_range(10_000, 500).unwrap().fetch_range(),
+            10_000..10_500
+        );
+
+        assert!(file.prove_byte_range(10_000, 501).is_err());
+        assert!(file.prove_byte_range(5, 0).is_err());
+        assert!(FileTree::new(&data, 0, hasher).is_err());
+    }
+
+    #[test]
+    fn test_rejects_tampering() {
+        let hasher = Sha256Hasher::new();
+        let data = content(5_000);
+        let file = FileTree::from_reader(data.as_slice(), 1024, hasher.clone()).unwrap();
+        let root = file.root();
+        let proof = file.prove_byte_range(1500, 1000).unwrap();
+        let chunks = fetch(&data, &proof).to_vec();
+        assert!(proof.verify(&hasher, &root, &chunks));
+
+        let mut tampered = chunks.clone();
+        tampered[700] ^= 1;
+        assert!(!proof.verify(&hasher, &root, &tampered));
+        assert!(!proof.verify(&hasher, &root, &chunks[1..]));
+
+        // Shifting the range or claiming another length breaks the proof
+        let mut shifted = proof.clone();
+        shifted.offset += 1024;
+        assert!(!shifted.verify(&hasher, &root, &data[2048..4096]));
+        let mut longer = proof.clone();
+        longer.file_len += 1;
+        assert!(!longer.verify(&hasher, &root, &chunks));
+
+        // The same chunks in a file of another length have another root
+        let other = FileTree::new(&data[..4999], 1024, hasher.clone()).unwrap();
+        assert_eq!(other.tree().get_leaf(1), file.tree().get_leaf(1));
+        assert_ne!(other.root(), root);
+        assert!(!proof.verify(&hasher, &other.root(), &chunks));
+
+        let empty = FileTree::new(&[], 1024, hasher).unwrap();
+        assert!(empty.is_empty());
+        assert!(empty.prove_byte_range(0, 1).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Typed digests
+//!
+//! [`Hash`] wraps any digest produced by a [`Hasher`](crate::hasher::Hasher),
+//! while [`RootHash`] is reserved for tree roots so that roots, leaf hashes
+//! and raw data cannot be mixed up by accident. Both compare in constant time,
+//! display as lowercase hex and serialize as hex strings.
+//!
+//! Digest bytes are reference counted, so cloning a hash, and with it a tree
+//! or a proof, never copies them.
+
+use crate::error::{MerkleError, Result};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Digest produced by a hash function
+#[derive(Clone, Default)]
+pub struct Hash(Arc<[u8]>);
+
+/// Root hash of a Merkle tree
+#[derive(Clone, Default)]
+pub struct RootHash(Hash);
+
+impl Hash {
+    /// Wrap raw digest bytes
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes.into())
+    }
+
+    /// Get the digest bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Copy out the digest bytes
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+impl RootHash {
+    /// Wrap raw root bytes
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(Hash::new(bytes))
+    }
+
+    /// Get the root bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    /// Copy out the root bytes
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0.into_bytes()
+    }
+
+    /// View the root as a plain hash, e.g. to use it as a leaf of a parent tree
+    pub fn as_hash(&self) -> &Hash {
+        &self.0
+    }
+
+    /// Convert the root into a plain hash
+    pub fn into_hash(self) -> Hash {
+        self.0
+    }
+}
+
+impl From<Hash> for RootHash {
+    fn from(hash: Hash) -> Self {
+        Self(hash)
+    }
+}
+
+impl From<RootHash> for Hash {
+    fn from(root: RootHash) -> Self {
+        root.0
+    }
+}
+
+/// Compare two byte strings without short-circuiting on the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+/// Parse lowercase or uppercase hex, with or without a `0x` prefix
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    hex::decode(digits).map_err(|e| MerkleError::SerializationError {
+        message: format!("Invalid hex digest {:?}: {}", s, e),
+    })
+}
+
+macro_rules! impl_digest_traits {
+    ($name:ident) => {
+        impl $name {
+            /// Encode as lowercase hex without a prefix
+            pub fn to_hex(&self) -> String {
+                hex::encode(self.as_bytes())
+            }
+
+            /// Decode from hex, accepting an optional `0x` prefix
+            pub fn from_hex(s: &str) -> Result<Self> {
+                parse_hex(s).map(Self::new)
+            }
+        }
+
+        impl Deref for $name {
+            type Target = [u8];
+
+            fn deref(&self) -> &[u8] {
+                self.as_bytes()
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                self.as_bytes()
+            }
+        }
+
+        impl From<Vec<u8>> for $name {
+            fn from(bytes: Vec<u8>) -> Self {
+                Self::new(bytes)
+            }
+        }
+
+        impl From<&[u8]> for $name {
+            fn from(bytes: &[u8]) -> Self {
+                Self::new(bytes.to_vec())
+            }
+        }
+
+        impl<const N: usize> From<[u8; N]> for $name {
+            fn from(bytes: [u8; N]) -> Self {
+                Self::new(bytes.to_vec())
+            }
+        }
+
+        impl From<$name> for Vec<u8> {
+            fn from(hash: $name) -> Self {
+                hash.into_bytes()
+            }
+        }
+
+        impl PartialEq for $name {
+            fn eq(&self, other: &Self) -> bool {
+                constant_time_eq(self.as_bytes(), other.as_bytes())
+            }
+        }
+
+        impl Eq for $name {}
+
+        impl PartialEq<[u8]> for $name {
+            fn eq(&self, other: &[u8]) -> bool {
+                constant_time_eq(self.as_bytes(), other)
+            }
+        }
+
+        impl PartialEq<&[u8]> for $name {
+            fn eq(&self, other: &&[u8]) -> bool {
+                constant_time_eq(self.as_bytes(), other)
+            }
+        }
+
+        impl PartialEq<Vec<u8>> for $name {
+            fn eq(&self, other: &Vec<u8>) -> bool {
+                constant_time_eq(self.as_bytes(), other)
+            }
+        }
+
+        impl<const N: usize> PartialEq<[u8; N]> for $name {
+            fn eq(&self, other: &[u8; N]) -> bool {
+                constant_time_eq(self.as_bytes(), other)
+            }
+        }
+
+        impl PartialOrd for $name {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for $name {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.as_bytes().cmp(other.as_bytes())
+            }
+        }
+
+        impl std::hash::Hash for $name {
+            fn hash<S: std::hash::Hasher>(&self, state: &mut S) {
+                self.as_bytes().hash(state);
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.to_hex())
+            }
+        }
+
+        impl fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if f.alternate() {
+                    f.write_str("0x")?;
+                }
+                f.write_str(&self.to_hex())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self.to_hex())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = MerkleError;
+
+            fn from_str(s: &str) -> Result<Self> {
+                Self::from_hex(s)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_hex())
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> std::result::Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                Self::from_hex(&s).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+impl_digest_traits!(Hash);
+impl_digest_traits!(RootHash);
+
+/// Wipes the digest bytes if this is their last owner and clears the hash
+///
+/// Bytes still shared with clones are left for the last clone to wipe.
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for Hash {
+    fn zeroize(&mut self) {
+        let mut bytes = std::mem::take(&mut self.0);
+        if let Some(bytes) = Arc::get_mut(&mut bytes) {
+            bytes.zeroize();
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for RootHash {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Hasher, Sha256Hasher};
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let hash = Sha256Hasher::new().hash(b"hello");
+        let text = hash.to_string();
+        assert_eq!(text.len(), 64);
+        assert_eq!(text.parse::<Hash>().unwrap(), hash);
+        assert_eq!(Hash::from_hex(&format!("0x{}", text)).unwrap(), hash);
+        assert_eq!(format!("{:#x}", hash), format!("0x{}", text));
+    }
+
+    #[test]
+    fn test_invalid_hex() {
+        assert!(matches!(
+            "xyz".parse::<Hash>(),
+            Err(MerkleError::SerializationError { .. })
+        ));
+        assert!("abc".parse::<RootHash>().is_err());
+    }
+
+    #[test]
+    fn test_equality() {
+        let a = Hash::from([1u8, 2, 3]);
+        assert_eq!(a, Hash::new(vec![1, 2, 3]));
+        assert_ne!(a, Hash::new(vec![1, 2, 4]));
+        assert_ne!(a, Hash::new(vec![1, 2]));
+        assert_eq!(a, [1u8, 2, 3]);
+        assert_eq!(a, vec![1u8, 2, 3]);
+        assert_eq!(a, &[1u8, 2, 3][..]);
+    }
+
+    #[test]
+    fn test_root_conversions() {
+        let hash = Hash::from([9u8; 32]);
+        let root = RootHash::from(hash.clone());
+        assert_eq!(root.as_hash(), &hash);
+        assert_eq!(root.as_bytes(), hash.as_bytes());
+        assert_eq!(Hash::from(root), hash);
+    }
+
+    #[test]
+    fn test_usable_as_map_key() {
+        use std::collections::HashSet;
+
+        let mut set = HashSet::new();
+        set.insert(Hash::from([1u8; 4]));
+        set.insert(Hash::from([1u8; 4]));
+        set.insert(Hash::from([2u8; 4]));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_clone_shares_bytes() {
+        let hash = Sha256Hasher::new().hash(b"hello");
+        let copy = hash.clone();
+        assert_eq!(copy.as_ptr(), hash.as_ptr());
+        assert_eq!(copy.into_bytes(), hash.as_bytes());
+    }
+
+    #[test]
+    fn test_debug_format() {
+        let root = RootHash::from([0xabu8, 0xcd]);
+        assert_eq!(format!("{:?}", root), "RootHash(abcd)");
+    }
+}
@@ -1,21 +1,71 @@
 use crate::error::{MerkleError, Result};
+use crate::hash::Hash;
 use blake3;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use sha2::digest::generic_array::GenericArray;
 use sha2::{Digest, Sha256};
-use sha3::Sha3_256;
+use sha3::digest::typenum::Unsigned;
+use sha3::digest::{self, ExtendableOutput};
+use sha3::{Keccak256, Sha3_256, Shake128, Shake256};
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
 
 /// Trait for hash functions used in Merkle trees
 pub trait Hasher: Clone + Send + Sync {
     /// Hash a single input
-    fn hash(&self, data: &[u8]) -> Vec<u8>;
+    fn hash(&self, data: &[u8]) -> Hash;
     
     /// Hash two inputs together (for internal nodes)
-    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
         let mut combined = Vec::with_capacity(left.len() + right.len());
         combined.extend_from_slice(left);
         combined.extend_from_slice(right);
         self.hash(&combined)
     }
     
+    /// Hash two inputs together, writing the digest into `out`
+    ///
+    /// `out` must be exactly `output_size()` bytes long. The built-in hashers
+    /// override this to hash without allocating; the default copies the
+    /// result of [`Hasher::hash_pair`]. Proof verification only calls this
+    /// once `hash_pair` has been seen to return `output_size()` bytes.
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        out.copy_from_slice(&self.hash_pair(left, right));
+    }
+
+    /// Start hashing a leaf incrementally
+    ///
+    /// Feeding the state the pieces of `data` gives `hash(data)`. The
+    /// built-in hashers override this to hash in constant memory; the default
+    /// buffers the input and calls [`Hasher::hash`] at the end.
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(BufferedLeaf {
+            hasher: self,
+            data: Vec::new(),
+        })
+    }
+
+    /// Get the root of a tree with no leaves
+    ///
+    /// RFC 6962 defines this as the digest of the empty string, which is what
+    /// the default `hash(&[])` gives for hashers that do not prefix their
+    /// input. Hashers that domain-separate leaves override it so that the
+    /// empty root is not the hash of an empty leaf.
+    fn empty_root(&self) -> Hash {
+        self.hash(&[])
+    }
+
+    /// Get a bulk implementation of [`Hasher::hash`], if there is one
+    ///
+    /// Tree construction hashes all leaves in a single
+    /// [`BatchHasher::hash_many`] call when this returns `Some`, which lets a
+    /// GPU or FPGA backend take over leaf hashing. The default is `None`.
+    fn batch(&self) -> Option<&dyn BatchHasher> {
+        None
+    }
+
     /// Get the output size of the hash function
     fn output_size(&self) -> usize;
     
@@ -23,8 +73,196 @@ pub trait Hasher: Clone + Send + Sync {
     fn name(&self) -> &'static str;
 }
 
+/// Hash function whose output length is fixed by its type
+///
+/// Structures keyed by hash, such as [`MerkleMap`](crate::map::MerkleMap),
+/// take their depth from [`FixedOutputHasher::OUTPUT_SIZE`], so it is settled
+/// at compile time and always covers the whole key hash. Hashers whose length
+/// is chosen at runtime, like [`XofHasher`] or [`HashAlgorithm`], do not
+/// implement it:
+///
+/// ```rust,compile_fail
+/// use merkle_tree::map::MerkleMap;
+/// use merkle_tree::HashAlgorithm;
+///
+/// let map = MerkleMap::new(HashAlgorithm::Sha256);
+/// ```
+pub trait FixedOutputHasher: Hasher {
+    /// Output length in bytes, equal to [`Hasher::output_size`]
+    const OUTPUT_SIZE: usize;
+}
+
+/// Hash function that hashes many inputs in one call, see [`Hasher::batch`]
+///
+/// ```rust
+/// use merkle_tree::{BatchHasher, Hash, Hasher, MerkleTree, Sha256Hasher};
+///
+/// #[derive(Clone)]
+/// struct Accelerated;
+///
+/// impl BatchHasher for Accelerated {
+///     fn hash_many(&self, inputs: &[&[u8]]) -> Vec<Hash> {
+///         // Hand the whole batch to the device here
+///         inputs.iter().map(|input| Sha256Hasher.hash(input)).collect()
+///     }
+/// }
+///
+/// impl Hasher for Accelerated {
+///     fn hash(&self, data: &[u8]) -> Hash {
+///         Sha256Hasher.hash(data)
+///     }
+///
+///     fn batch(&self) -> Option<&dyn BatchHasher> {
+///         Some(self)
+///     }
+///
+///     fn output_size(&self) -> usize {
+///         32
+///     }
+///
+///     fn name(&self) -> &'static str {
+///         "SHA-256"
+///     }
+/// }
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let tree = MerkleTree::new(vec!["a", "b", "c"], Accelerated)?;
+/// let reference = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new())?;
+/// assert_eq!(tree.root(), reference.root());
+/// # Ok(())
+/// # }
+/// ```
+pub trait BatchHasher {
+    /// Hash every input, returning the digests in input order
+    ///
+    /// Each digest must equal [`Hasher::hash`] of the same input.
+    fn hash_many(&self, inputs: &[&[u8]]) -> Vec<Hash>;
+}
+
+/// Hash every item, in one batch if the hasher supports it
+pub(crate) fn hash_all<H: Hasher, T: AsRef<[u8]>>(hasher: &H, data: &[T]) -> Result<Vec<Hash>> {
+    let Some(batch) = hasher.batch() else {
+        return Ok(data.iter().map(|item| hasher.hash(item.as_ref())).collect());
+    };
+
+    let inputs: Vec<&[u8]> = data.iter().map(AsRef::as_ref).collect();
+    let hashes = batch.hash_many(&inputs);
+    if hashes.len() != inputs.len() {
+        return Err(MerkleError::HashError {
+            message: format!(
+                "Batch hasher returned {} digests for {} inputs",
+                hashes.len(),
+                inputs.len()
+            ),
+        });
+    }
+    Ok(hashes)
+}
+
+/// Incremental hash state for a single leaf, see [`Hasher::begin_leaf`]
+pub trait LeafHasher {
+    /// Feed the next piece of the leaf
+    fn update(&mut self, data: &[u8]);
+
+    /// Finish the leaf hash
+    fn finalize(self: Box<Self>) -> Hash;
+}
+
+/// Leaf state that collects the input for a one-shot hash
+struct BufferedLeaf<'a, H> {
+    hasher: &'a H,
+    data: Vec<u8>,
+}
+
+impl<H: Hasher> LeafHasher for BufferedLeaf<'_, H> {
+    fn update(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        self.hasher.hash(&self.data)
+    }
+}
+
+/// Leaf state of a RustCrypto digest
+struct DigestLeaf<D>(D);
+
+impl<D: Digest> LeafHasher for DigestLeaf<D> {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(&mut self.0, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        Hash::new(self.0.finalize().to_vec())
+    }
+}
+
+impl LeafHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        Hash::new(blake3::Hasher::finalize(&self).as_bytes().to_vec())
+    }
+}
+
+/// Leaf state of an extendable-output function
+struct XofLeaf<X> {
+    state: X,
+    output_len: usize,
+}
+
+impl<X: ExtendableOutput + digest::Update> LeafHasher for XofLeaf<X> {
+    fn update(&mut self, data: &[u8]) {
+        digest::Update::update(&mut self.state, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        let mut out = vec![0u8; self.output_len];
+        self.state.finalize_xof_into(&mut out);
+        Hash::new(out)
+    }
+}
+
+/// Leaf state of BLAKE3 in extendable-output mode
+struct Blake3XofLeaf {
+    state: blake3::Hasher,
+    output_len: usize,
+}
+
+impl LeafHasher for Blake3XofLeaf {
+    fn update(&mut self, data: &[u8]) {
+        self.state.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        let mut out = vec![0u8; self.output_len];
+        self.state.finalize_xof().fill(&mut out);
+        Hash::new(out)
+    }
+}
+
+/// Hash the concatenation of `parts` with `X`, filling `out`
+fn xof_into<X: ExtendableOutput + digest::Update + Default>(parts: &[&[u8]], out: &mut [u8]) {
+    let mut xof = X::default();
+    for part in parts {
+        digest::Update::update(&mut xof, part);
+    }
+    xof.finalize_xof_into(out);
+}
+
+/// Hash `left || right` with `D` without concatenating the inputs
+fn digest_pair_into<D: Digest>(left: &[u8], right: &[u8], out: &mut [u8]) {
+    D::new()
+        .chain_update(left)
+        .chain_update(right)
+        .finalize_into(GenericArray::from_mut_slice(out));
+}
+
 /// SHA-256 hasher implementation
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Sha256Hasher;
 
 impl Sha256Hasher {
@@ -39,13 +277,31 @@ impl Default for Sha256Hasher {
     }
 }
 
+impl FixedOutputHasher for Sha256Hasher {
+    const OUTPUT_SIZE: usize = 32;
+}
+
 impl Hasher for Sha256Hasher {
-    fn hash(&self, data: &[u8]) -> Vec<u8> {
+    fn hash(&self, data: &[u8]) -> Hash {
         let mut hasher = Sha256::new();
         hasher.update(data);
-        hasher.finalize().to_vec()
+        Hash::new(hasher.finalize().to_vec())
     }
     
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut out = vec![0u8; 32];
+        self.hash_pair_into(left, right, &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        digest_pair_into::<Sha256>(left, right, out);
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(DigestLeaf(Sha256::new()))
+    }
+
     fn output_size(&self) -> usize {
         32 // SHA-256 produces 32-byte hashes
     }
@@ -57,6 +313,7 @@ impl Hasher for Sha256Hasher {
 
 /// SHA-3 hasher implementation
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Sha3Hasher;
 
 impl Sha3Hasher {
@@ -71,13 +328,31 @@ impl Default for Sha3Hasher {
     }
 }
 
+impl FixedOutputHasher for Sha3Hasher {
+    const OUTPUT_SIZE: usize = 32;
+}
+
 impl Hasher for Sha3Hasher {
-    fn hash(&self, data: &[u8]) -> Vec<u8> {
+    fn hash(&self, data: &[u8]) -> Hash {
         let mut hasher = Sha3_256::new();
         hasher.update(data);
-        hasher.finalize().to_vec()
+        Hash::new(hasher.finalize().to_vec())
     }
     
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut out = vec![0u8; 32];
+        self.hash_pair_into(left, right, &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        digest_pair_into::<Sha3_256>(left, right, out);
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(DigestLeaf(Sha3_256::new()))
+    }
+
     fn output_size(&self) -> usize {
         32 // SHA3-256 produces 32-byte hashes
     }
@@ -89,6 +364,7 @@ impl Hasher for Sha3Hasher {
 
 /// BLAKE3 hasher implementation
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Blake3Hasher;
 
 impl Blake3Hasher {
@@ -103,11 +379,32 @@ impl Default for Blake3Hasher {
     }
 }
 
+impl FixedOutputHasher for Blake3Hasher {
+    const OUTPUT_SIZE: usize = 32;
+}
+
 impl Hasher for Blake3Hasher {
-    fn hash(&self, data: &[u8]) -> Vec<u8> {
-        blake3::hash(data).as_bytes().to_vec()
+    fn hash(&self, data: &[u8]) -> Hash {
+        Hash::new(blake3::hash(data).as_bytes().to_vec())
     }
     
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut out = vec![0u8; 32];
+        self.hash_pair_into(left, right, &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(left);
+        hasher.update(right);
+        out.copy_from_slice(hasher.finalize().as_bytes());
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(blake3::Hasher::new())
+    }
+
     fn output_size(&self) -> usize {
         32 // BLAKE3 produces 32-byte hashes
     }
@@ -117,6 +414,594 @@ impl Hasher for Blake3Hasher {
     }
 }
 
+/// Keccak-256 hasher implementation, as used by Ethereum
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Keccak256Hasher;
+
+impl Keccak256Hasher {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for Keccak256Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FixedOutputHasher for Keccak256Hasher {
+    const OUTPUT_SIZE: usize = 32;
+}
+
+impl Hasher for Keccak256Hasher {
+    fn hash(&self, data: &[u8]) -> Hash {
+        let mut hasher = Keccak256::new();
+        hasher.update(data);
+        Hash::new(hasher.finalize().to_vec())
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut out = vec![0u8; 32];
+        self.hash_pair_into(left, right, &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        digest_pair_into::<Keccak256>(left, right, out);
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(DigestLeaf(Keccak256::new()))
+    }
+
+    fn output_size(&self) -> usize {
+        32 // Keccak-256 produces 32-byte hashes
+    }
+
+    fn name(&self) -> &'static str {
+        "Keccak-256"
+    }
+}
+
+/// SHA-256 with the RFC 6962 leaf (`0x00`) and node (`0x01`) prefixes
+///
+/// Together with [`TreeShape::Rfc6962`](crate::TreeShape::Rfc6962) this
+/// gives the roots of Certificate Transparency and trillian logs, and of
+/// CometBFT's simple Merkle trees. The empty root is `SHA-256("")`.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rfc6962Hasher;
+
+impl Rfc6962Hasher {
+    const LEAF_PREFIX: u8 = 0x00;
+    const NODE_PREFIX: u8 = 0x01;
+
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl FixedOutputHasher for Rfc6962Hasher {
+    const OUTPUT_SIZE: usize = 32;
+}
+
+impl Hasher for Rfc6962Hasher {
+    fn hash(&self, data: &[u8]) -> Hash {
+        let digest = Sha256::new()
+            .chain_update([Self::LEAF_PREFIX])
+            .chain_update(data)
+            .finalize();
+        Hash::new(digest.to_vec())
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut out = vec![0u8; 32];
+        self.hash_pair_into(left, right, &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        Sha256::new()
+            .chain_update([Self::NODE_PREFIX])
+            .chain_update(left)
+            .chain_update(right)
+            .finalize_into(GenericArray::from_mut_slice(out));
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(DigestLeaf(Sha256::new().chain_update([Self::LEAF_PREFIX])))
+    }
+
+    fn empty_root(&self) -> Hash {
+        Hash::new(Sha256::digest([]).to_vec())
+    }
+
+    fn output_size(&self) -> usize {
+        32
+    }
+
+    fn name(&self) -> &'static str {
+        "RFC6962-SHA-256"
+    }
+}
+
+/// Hasher for any RustCrypto digest, e.g. RIPEMD-160 or Whirlpool
+///
+/// `D` is any type implementing [`Digest`] from version 0.10 of the `digest`
+/// crate. [`Hasher::name`] defaults to the Rust type name of `D`; use
+/// [`DigestHasher::with_name`] to set a shorter one.
+pub struct DigestHasher<D> {
+    name: &'static str,
+    digest: PhantomData<fn() -> D>,
+}
+
+impl<D: Digest> DigestHasher<D> {
+    pub fn new() -> Self {
+        Self::with_name(std::any::type_name::<D>())
+    }
+
+    /// Create a hasher reporting `name` as its name
+    pub fn with_name(name: &'static str) -> Self {
+        Self {
+            name,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D: Digest> Default for DigestHasher<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> Clone for DigestHasher<D> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> fmt::Debug for DigestHasher<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DigestHasher").field(&self.name).finish()
+    }
+}
+
+impl<D: Digest + 'static> FixedOutputHasher for DigestHasher<D> {
+    const OUTPUT_SIZE: usize = <D as digest::OutputSizeUser>::OutputSize::USIZE;
+}
+
+impl<D: Digest + 'static> Hasher for DigestHasher<D> {
+    fn hash(&self, data: &[u8]) -> Hash {
+        Hash::new(D::digest(data).to_vec())
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut out = vec![0u8; self.output_size()];
+        self.hash_pair_into(left, right, &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        digest_pair_into::<D>(left, right, out);
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(DigestLeaf(D::new()))
+    }
+
+    fn output_size(&self) -> usize {
+        <D as Digest>::output_size()
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Hasher for an extendable-output function with a chosen output length
+///
+/// `X` is any type implementing [`ExtendableOutput`] from version 0.10 of the
+/// `digest` crate, such as [`Shake256`]. Every leaf and node hash is
+/// `output_len` bytes long, so trees and proofs built with it carry hashes of
+/// that size throughout:
+///
+/// ```rust
+/// use merkle_tree::{Hasher, MerkleTree, XofHasher};
+///
+/// // SHAKE256 with 64-byte (512-bit) commitments
+/// let hasher = XofHasher::shake256(64);
+/// let tree = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+/// assert_eq!(tree.root().len(), 64);
+///
+/// let proof = tree.generate_proof(1).unwrap();
+/// assert!(proof.steps.iter().all(|step| step.hash.len() == 64));
+/// assert!(proof.verify(&hasher, b"b", &tree.root()));
+/// ```
+///
+/// [`Hasher::name`] does not include the length. A tree read back with a
+/// hasher of another length fails [`MerkleTree::verify_integrity`], and
+/// [`SerializedTree`] and [`MerkleProof::verify_streaming`] reject its hashes
+/// outright.
+///
+/// [`MerkleTree::verify_integrity`]: crate::MerkleTree::verify_integrity
+/// [`SerializedTree`]: crate::SerializedTree
+/// [`MerkleProof::verify_streaming`]: crate::MerkleProof::verify_streaming
+pub struct XofHasher<X> {
+    output_len: usize,
+    name: &'static str,
+    xof: PhantomData<fn() -> X>,
+}
+
+impl<X> XofHasher<X> {
+    /// Create a hasher giving `output_len`-byte hashes
+    ///
+    /// Its name is the Rust type name of `X`.
+    pub fn new(output_len: usize) -> Self {
+        Self::with_name(output_len, std::any::type_name::<X>())
+    }
+
+    /// Create a hasher giving `output_len`-byte hashes and reporting `name`
+    /// as its name
+    pub fn with_name(output_len: usize, name: &'static str) -> Self {
+        Self {
+            output_len,
+            name,
+            xof: PhantomData,
+        }
+    }
+}
+
+impl XofHasher<Shake128> {
+    /// SHAKE128 with `output_len`-byte hashes
+    pub fn shake128(output_len: usize) -> Self {
+        Self::with_name(output_len, "SHAKE128")
+    }
+}
+
+impl XofHasher<Shake256> {
+    /// SHAKE256 with `output_len`-byte hashes
+    pub fn shake256(output_len: usize) -> Self {
+        Self::with_name(output_len, "SHAKE256")
+    }
+}
+
+impl<X> Clone for XofHasher<X> {
+    fn clone(&self) -> Self {
+        Self::with_name(self.output_len, self.name)
+    }
+}
+
+impl<X> fmt::Debug for XofHasher<X> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XofHasher")
+            .field("name", &self.name)
+            .field("output_len", &self.output_len)
+            .finish()
+    }
+}
+
+impl<X: ExtendableOutput + digest::Update + Default + 'static> Hasher for XofHasher<X> {
+    fn hash(&self, data: &[u8]) -> Hash {
+        let mut out = vec![0u8; self.output_len];
+        xof_into::<X>(&[data], &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut out = vec![0u8; self.output_len];
+        self.hash_pair_into(left, right, &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        xof_into::<X>(&[left, right], out);
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(XofLeaf {
+            state: X::default(),
+            output_len: self.output_len,
+        })
+    }
+
+    fn output_size(&self) -> usize {
+        self.output_len
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// BLAKE3 in extendable-output mode with a chosen output length
+///
+/// With a 32-byte output this hashes exactly like [`Blake3Hasher`], under
+/// another name.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Blake3XofHasher {
+    output_len: usize,
+}
+
+impl Blake3XofHasher {
+    /// Create a hasher giving `output_len`-byte hashes
+    pub fn new(output_len: usize) -> Self {
+        Self { output_len }
+    }
+}
+
+impl Hasher for Blake3XofHasher {
+    fn hash(&self, data: &[u8]) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(data);
+        let mut out = vec![0u8; self.output_len];
+        hasher.finalize_xof().fill(&mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut out = vec![0u8; self.output_len];
+        self.hash_pair_into(left, right, &mut out);
+        Hash::new(out)
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize_xof().fill(out);
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        Box::new(Blake3XofLeaf {
+            state: blake3::Hasher::new(),
+            output_len: self.output_len,
+        })
+    }
+
+    fn output_size(&self) -> usize {
+        self.output_len
+    }
+
+    fn name(&self) -> &'static str {
+        "BLAKE3-XOF"
+    }
+}
+
+/// Hasher wrapper that sorts each pair before hashing it
+///
+/// This makes `hash_pair` commutative, matching the sorted-pair convention of
+/// OpenZeppelin's `MerkleProof` library when wrapping [`Keccak256Hasher`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SortedPairHasher<H: Hasher> {
+    inner: H,
+}
+
+impl<H: Hasher> SortedPairHasher<H> {
+    pub fn new(inner: H) -> Self {
+        Self { inner }
+    }
+
+    /// Get the wrapped hasher
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+}
+
+impl<H: Hasher + Default> Default for SortedPairHasher<H> {
+    fn default() -> Self {
+        Self::new(H::default())
+    }
+}
+
+impl<H: FixedOutputHasher> FixedOutputHasher for SortedPairHasher<H> {
+    const OUTPUT_SIZE: usize = H::OUTPUT_SIZE;
+}
+
+impl<H: Hasher> Hasher for SortedPairHasher<H> {
+    fn hash(&self, data: &[u8]) -> Hash {
+        self.inner.hash(data)
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        if left <= right {
+            self.inner.hash_pair(left, right)
+        } else {
+            self.inner.hash_pair(right, left)
+        }
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        if left <= right {
+            self.inner.hash_pair_into(left, right, out)
+        } else {
+            self.inner.hash_pair_into(right, left, out)
+        }
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        self.inner.begin_leaf()
+    }
+
+    fn empty_root(&self) -> Hash {
+        self.inner.empty_root()
+    }
+
+    fn batch(&self) -> Option<&dyn BatchHasher> {
+        self.inner.batch()
+    }
+
+    fn output_size(&self) -> usize {
+        self.inner.output_size()
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+}
+
+/// Hasher wrapper that prefixes every input with a tag, as in BIP 340
+///
+/// [`TaggedHasher::new`] uses the BIP 340 prefix `H(tag) || H(tag)`, so
+/// wrapping [`Sha256Hasher`] gives `tagged_hash(tag, x)` for leaves and for
+/// `left || right` pairs. Use [`TaggedHasher::with_prefix`] for other
+/// schemes.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TaggedHasher<H: Hasher> {
+    inner: H,
+    prefix: Vec<u8>,
+}
+
+impl<H: Hasher> TaggedHasher<H> {
+    pub fn new(inner: H, tag: &[u8]) -> Self {
+        let tag_hash = inner.hash(tag);
+        let prefix = [tag_hash.as_bytes(), tag_hash.as_bytes()].concat();
+        Self::with_prefix(inner, prefix)
+    }
+
+    /// Create a hasher prefixing every input with `prefix` as given
+    pub fn with_prefix(inner: H, prefix: Vec<u8>) -> Self {
+        Self { inner, prefix }
+    }
+
+    /// Get the wrapped hasher
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// Get the bytes every input is prefixed with
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+}
+
+impl<H: FixedOutputHasher> FixedOutputHasher for TaggedHasher<H> {
+    const OUTPUT_SIZE: usize = H::OUTPUT_SIZE;
+}
+
+impl<H: Hasher> Hasher for TaggedHasher<H> {
+    fn hash(&self, data: &[u8]) -> Hash {
+        let mut state = self.begin_leaf();
+        state.update(data);
+        state.finalize()
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        let mut state = self.begin_leaf();
+        state.update(left);
+        state.update(right);
+        state.finalize()
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        let mut state = self.inner.begin_leaf();
+        state.update(&self.prefix);
+        state
+    }
+
+    fn output_size(&self) -> usize {
+        self.inner.output_size()
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+}
+
+/// Hash function chosen at runtime, for bindings and configuration
+///
+/// Parses from names such as `sha256`, `keccak256` or `sorted-keccak256`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha3,
+    Blake3,
+    Keccak256,
+    /// Keccak-256 with sorted pairs, as used by OpenZeppelin
+    SortedKeccak256,
+}
+
+impl Hasher for HashAlgorithm {
+    fn hash(&self, data: &[u8]) -> Hash {
+        match self {
+            Self::Sha256 => Sha256Hasher.hash(data),
+            Self::Sha3 => Sha3Hasher.hash(data),
+            Self::Blake3 => Blake3Hasher.hash(data),
+            Self::Keccak256 | Self::SortedKeccak256 => Keccak256Hasher.hash(data),
+        }
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Hash {
+        match self {
+            Self::Sha256 => Sha256Hasher.hash_pair(left, right),
+            Self::Sha3 => Sha3Hasher.hash_pair(left, right),
+            Self::Blake3 => Blake3Hasher.hash_pair(left, right),
+            Self::Keccak256 => Keccak256Hasher.hash_pair(left, right),
+            Self::SortedKeccak256 => SortedPairHasher::new(Keccak256Hasher).hash_pair(left, right),
+        }
+    }
+
+    fn hash_pair_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) {
+        match self {
+            Self::Sha256 => Sha256Hasher.hash_pair_into(left, right, out),
+            Self::Sha3 => Sha3Hasher.hash_pair_into(left, right, out),
+            Self::Blake3 => Blake3Hasher.hash_pair_into(left, right, out),
+            Self::Keccak256 => Keccak256Hasher.hash_pair_into(left, right, out),
+            Self::SortedKeccak256 => {
+                SortedPairHasher::new(Keccak256Hasher).hash_pair_into(left, right, out)
+            }
+        }
+    }
+
+    fn begin_leaf(&self) -> Box<dyn LeafHasher + '_> {
+        match self {
+            Self::Sha256 => Sha256Hasher.begin_leaf(),
+            Self::Sha3 => Sha3Hasher.begin_leaf(),
+            Self::Blake3 => Blake3Hasher.begin_leaf(),
+            Self::Keccak256 | Self::SortedKeccak256 => Keccak256Hasher.begin_leaf(),
+        }
+    }
+
+    fn output_size(&self) -> usize {
+        32
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => Sha256Hasher.name(),
+            Self::Sha3 => Sha3Hasher.name(),
+            Self::Blake3 => Blake3Hasher.name(),
+            Self::Keccak256 | Self::SortedKeccak256 => Keccak256Hasher.name(),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = MerkleError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Ok(Self::Sha256),
+            "sha3" | "sha3-256" => Ok(Self::Sha3),
+            "blake3" => Ok(Self::Blake3),
+            "keccak256" | "keccak-256" => Ok(Self::Keccak256),
+            "sorted-keccak256" | "sorted-keccak-256" => Ok(Self::SortedKeccak256),
+            _ => Err(MerkleError::HashError {
+                message: format!("Unknown hash algorithm {}", s),
+            }),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,6 +1039,59 @@ mod tests {
         assert_eq!(hasher.name(), "BLAKE3");
     }
 
+    #[test]
+    fn test_keccak256_hasher() {
+        let hasher = Keccak256Hasher::new();
+        assert_eq!(
+            hex::encode(hasher.hash(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        assert_eq!(hasher.output_size(), 32);
+        assert_eq!(hasher.name(), "Keccak-256");
+    }
+
+    #[test]
+    fn test_rfc6962_hasher() {
+        // Test vectors from trillian's rfc6962 package
+        let hasher = Rfc6962Hasher::new();
+        assert_eq!(
+            hasher.empty_root().to_hex(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hasher.hash(b"").to_hex(),
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d"
+        );
+        assert_eq!(
+            hasher.hash(b"L123456").to_hex(),
+            "395aa064aa4c29f7010acfe3f25db9485bbd4b91897b6ad7ad547639252b4d56"
+        );
+        assert_eq!(
+            hasher.hash_pair(b"N123", b"N456").to_hex(),
+            "aa217fe888e47007fa15edab33c2b492a722cb106c64667fc2b044444de66bbb"
+        );
+
+        let mut leaf = hasher.begin_leaf();
+        leaf.update(b"L123");
+        leaf.update(b"456");
+        assert_eq!(leaf.finalize(), hasher.hash(b"L123456"));
+        assert_eq!(hasher.name(), "RFC6962-SHA-256");
+    }
+
+    #[test]
+    fn test_sorted_pair_hasher() {
+        let hasher = SortedPairHasher::new(Keccak256Hasher::new());
+        let a = hasher.hash(b"a");
+        let b = hasher.hash(b"b");
+        assert_eq!(hasher.hash_pair(&a, &b), hasher.hash_pair(&b, &a));
+
+        let (low, high) = if a < b { (&a, &b) } else { (&b, &a) };
+        assert_eq!(
+            hasher.hash_pair(&a, &b),
+            Keccak256Hasher::new().hash_pair(low, high)
+        );
+    }
+
     #[test]
     fn test_hash_pair() {
         let hasher = Sha256Hasher::new();
@@ -163,6 +1101,33 @@ mod tests {
         assert_eq!(combined.len(), 32);
     }
 
+    #[test]
+    fn test_hash_pair_into() {
+        fn check<H: Hasher>(hasher: H) {
+            let concatenated = hasher.hash(b"leftright");
+            assert_eq!(hasher.hash_pair(b"left", b"right"), concatenated);
+
+            let mut out = vec![0u8; hasher.output_size()];
+            hasher.hash_pair_into(b"left", b"right", &mut out);
+            assert_eq!(concatenated, out);
+        }
+
+        check(Sha256Hasher::new());
+        check(Sha3Hasher::new());
+        check(Blake3Hasher::new());
+        check(Keccak256Hasher::new());
+        check(DigestHasher::<ripemd::Ripemd160>::new());
+        check(TaggedHasher::new(Keccak256Hasher::new(), b"tag"));
+        check(HashAlgorithm::Sha3);
+        check(XofHasher::shake128(64));
+        check(Blake3XofHasher::new(64));
+
+        let sorted = SortedPairHasher::new(Keccak256Hasher::new());
+        let mut out = vec![0u8; 32];
+        sorted.hash_pair_into(b"b", b"a", &mut out);
+        assert_eq!(sorted.hash_pair(b"a", b"b"), out);
+    }
+
     #[test]
     fn test_different_hashers_produce_different_results() {
         let data = b"test data";
@@ -175,4 +1140,201 @@ mod tests {
         assert_ne!(sha256_hash, blake3_hash);
         assert_ne!(sha3_hash, blake3_hash);
     }
+
+    #[test]
+    fn test_hash_algorithm() {
+        let data = b"hello";
+        assert_eq!(HashAlgorithm::Sha256.hash(data), Sha256Hasher::new().hash(data));
+        assert_eq!(HashAlgorithm::Blake3.hash(data), Blake3Hasher::new().hash(data));
+
+        let (a, b) = (b"b".as_slice(), b"a".as_slice());
+        let sorted = SortedPairHasher::new(Keccak256Hasher::new());
+        assert_eq!(HashAlgorithm::SortedKeccak256.hash_pair(a, b), sorted.hash_pair(a, b));
+        assert_ne!(HashAlgorithm::Keccak256.hash_pair(a, b), sorted.hash_pair(a, b));
+
+        assert_eq!("Keccak-256".parse(), Ok(HashAlgorithm::Keccak256));
+        assert_eq!("sorted-keccak256".parse(), Ok(HashAlgorithm::SortedKeccak256));
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_digest_hasher() {
+        let hasher = DigestHasher::<Sha256>::new();
+        assert_eq!(hasher.hash(b"hello"), Sha256Hasher.hash(b"hello"));
+        assert_eq!(
+            hasher.hash_pair(b"left", b"right"),
+            Sha256Hasher.hash_pair(b"left", b"right")
+        );
+        assert_eq!(hasher.name(), std::any::type_name::<Sha256>());
+
+        let hasher = DigestHasher::<ripemd::Ripemd160>::with_name("RIPEMD-160");
+        assert_eq!(hasher.output_size(), 20);
+        assert_eq!(hasher.name(), "RIPEMD-160");
+        assert_eq!(
+            hasher.hash(b"abc").to_hex(),
+            "8eb208f7e05d987a9b044a8e98c6b087f15a0bfc"
+        );
+        assert_eq!(hasher.hash_pair(b"a", b"bc"), hasher.hash(b"abc"));
+    }
+
+    #[test]
+    fn test_xof_hasher() {
+        let hasher = XofHasher::shake256(64);
+        assert_eq!(hasher.output_size(), 64);
+        assert_eq!(hasher.name(), "SHAKE256");
+        assert_eq!(
+            hasher.hash(b"").to_hex(),
+            "46b9dd2b0ba88d13233b3feb743eeb243fcd52ea62b81b82b50c27646ed5762f\
+             d75dc4ddd8c0f200cb05019d67b592f6fc821c49479ab48640292eacb3b7c4be"
+        );
+        assert_eq!(
+            XofHasher::shake128(32).hash(b"").to_hex(),
+            "7f9c2ba4e88f827d616045507605853ed73b8093f6efbc88eb1a6eacfa66ef26"
+        );
+
+        // A shorter output is a prefix of a longer one
+        let short = XofHasher::<Shake256>::new(20);
+        assert_eq!(short.hash(b"abc").as_bytes(), &hasher.hash(b"abc")[..20]);
+        assert_eq!(short.name(), std::any::type_name::<Shake256>());
+
+        let blake3 = Blake3XofHasher::new(64);
+        assert_eq!(blake3.hash(b"abc").len(), 64);
+        assert_eq!(
+            &blake3.hash(b"abc")[..32],
+            Blake3Hasher.hash(b"abc").as_bytes()
+        );
+        assert_eq!(
+            Blake3XofHasher::new(32).hash_pair(b"l", b"r"),
+            Blake3Hasher.hash_pair(b"l", b"r")
+        );
+    }
+
+    #[test]
+    fn test_tagged_hasher() {
+        let hasher = TaggedHasher::new(Sha256Hasher::new(), b"BIP0340/challenge");
+        let tag = Sha256::digest(b"BIP0340/challenge");
+        let expected = Sha256::new()
+            .chain_update(tag)
+            .chain_update(tag)
+            .chain_update(b"message")
+            .finalize();
+        assert_eq!(hasher.hash(b"message").as_bytes(), &expected[..]);
+        assert_eq!(hasher.hash_pair(b"mess", b"age"), hasher.hash(b"message"));
+        assert_eq!(hasher.prefix().len(), 64);
+        assert_eq!(hasher.name(), "SHA-256");
+
+        // Different tags separate otherwise equal inputs
+        let other = TaggedHasher::new(Sha256Hasher::new(), b"BIP0340/aux");
+        assert_ne!(other.hash(b"message"), hasher.hash(b"message"));
+        assert_ne!(hasher.hash(b"message"), Sha256Hasher.hash(b"message"));
+
+        let prefixed = TaggedHasher::with_prefix(Sha256Hasher::new(), b"\x00".to_vec());
+        assert_eq!(prefixed.hash(b"leaf"), Sha256Hasher.hash(b"\x00leaf"));
+    }
+
+    #[test]
+    fn test_begin_leaf() {
+        fn check<H: Hasher>(hasher: H) {
+            let mut state = hasher.begin_leaf();
+            for piece in [b"hel".as_slice(), b"", b"lo world"] {
+                state.update(piece);
+            }
+            assert_eq!(state.finalize(), hasher.hash(b"hello world"), "{}", hasher.name());
+        }
+
+        /// Relies on the buffering default
+        #[derive(Clone)]
+        struct Plain;
+
+        impl Hasher for Plain {
+            fn hash(&self, data: &[u8]) -> Hash {
+                Sha256Hasher.hash(data)
+            }
+
+            fn output_size(&self) -> usize {
+                32
+            }
+
+            fn name(&self) -> &'static str {
+                "plain"
+            }
+        }
+
+        check(Sha256Hasher::new());
+        check(Sha3Hasher::new());
+        check(Blake3Hasher::new());
+        check(Keccak256Hasher::new());
+        check(SortedPairHasher::new(Keccak256Hasher::new()));
+        check(HashAlgorithm::Blake3);
+        check(XofHasher::shake256(64));
+        check(Blake3XofHasher::new(48));
+        check(Plain);
+    }
+
+    #[test]
+    fn test_batch_hasher() {
+        use crate::builder::{LeafEncoding, MerkleTreeBuilder, Salting};
+        use crate::merkle_tree::MerkleTree;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        /// Counts the batches it is handed
+        #[derive(Clone, Default)]
+        struct Counting(Arc<AtomicUsize>);
+
+        impl BatchHasher for Counting {
+            fn hash_many(&self, inputs: &[&[u8]]) -> Vec<Hash> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                inputs.iter().map(|input| Sha256Hasher.hash(input)).collect()
+            }
+        }
+
+        impl Hasher for Counting {
+            fn hash(&self, data: &[u8]) -> Hash {
+                Sha256Hasher.hash(data)
+            }
+
+            fn batch(&self) -> Option<&dyn BatchHasher> {
+                Some(self)
+            }
+
+            fn output_size(&self) -> usize {
+                32
+            }
+
+            fn name(&self) -> &'static str {
+                "SHA-256"
+            }
+        }
+
+        let data = vec!["a", "b", "c", "d", "e"];
+        let hasher = Counting::default();
+        let tree = MerkleTree::new(data.clone(), hasher.clone()).unwrap();
+        let reference = MerkleTree::new(data.clone(), Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), reference.root());
+        assert_eq!(hasher.0.load(Ordering::SeqCst), 1);
+
+        let double = MerkleTreeBuilder::new(hasher.clone())
+            .leaf_encoding(LeafEncoding::DoubleHash)
+            .build(data.clone())
+            .unwrap();
+        let reference = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .leaf_encoding(LeafEncoding::DoubleHash)
+            .build(data.clone())
+            .unwrap();
+        assert_eq!(double.root(), reference.root());
+        assert_eq!(hasher.0.load(Ordering::SeqCst), 3);
+
+        // Wrappers that keep leaf hashing unchanged pass the batch through
+        let sorted = SortedPairHasher::new(hasher.clone());
+        MerkleTree::new(data.clone(), sorted).unwrap();
+        assert_eq!(hasher.0.load(Ordering::SeqCst), 4);
+
+        // Salted leaves are derived one at a time
+        MerkleTreeBuilder::new(hasher.clone())
+            .salting(Salting::Derived(b"secret".to_vec()))
+            .build(data)
+            .unwrap();
+        assert_eq!(hasher.0.load(Ordering::SeqCst), 4);
+    }
 }
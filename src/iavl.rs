@@ -0,0 +1,1126 @@
+//! Versioned IAVL+ tree, as used by the Cosmos SDK
+//!
+//! [`IavlTree`] is an AVL tree that keeps keys in order and stores values only
+//! in its leaves. Each inner node holds the smallest key of its right subtree.
+//! Updates copy the path they touch, so every saved version keeps its own root
+//! and shares unchanged subtrees with the others.
+//!
+//! Nodes hash as IAVL does: a leaf is `H(height || size || version ||
+//! len(key) || key || len(H(value)) || H(value))` and an inner node
+//! `H(height || size || version || len(left) || left || len(right) || right)`,
+//! with height, size and version as signed varints and lengths as unsigned
+//! varints. With [`Sha256Hasher`](crate::Sha256Hasher) the roots and proofs
+//! match the ICS-23 IAVL spec: [`ExistenceProof`] and [`NonExistenceProof`]
+//! mirror the ICS-23 messages of the same names.
+//! A [`RangeProof`] shows that a set of entries is every entry of a key range.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct Node {
+    key: Vec<u8>,
+    /// Value of a leaf, empty for inner nodes
+    value: Vec<u8>,
+    version: u64,
+    height: u8,
+    size: u64,
+    hash: Hash,
+    /// Children of an inner node, `None` for leaves
+    children: Option<(Arc<Node>, Arc<Node>)>,
+}
+
+impl Node {
+    fn leaf<H: Hasher>(hasher: &H, key: Vec<u8>, value: Vec<u8>, version: u64) -> Arc<Self> {
+        let mut input = header(0, 1, version);
+        length_prefixed(&mut input, &key);
+        length_prefixed(&mut input, &hasher.hash(&value));
+        Arc::new(Self {
+            hash: hasher.hash(&input),
+            key,
+            value,
+            version,
+            height: 0,
+            size: 1,
+            children: None,
+        })
+    }
+
+    fn inner<H: Hasher>(
+        hasher: &H,
+        key: Vec<u8>,
+        left: Arc<Node>,
+        right: Arc<Node>,
+        version: u64,
+    ) -> Arc<Self> {
+        let height = left.height.max(right.height) + 1;
+        let size = left.size + right.size;
+        let mut input = header(height, size, version);
+        length_prefixed(&mut input, &left.hash);
+        length_prefixed(&mut input, &right.hash);
+        Arc::new(Self {
+            hash: hasher.hash(&input),
+            key,
+            value: Vec::new(),
+            version,
+            height,
+            size,
+            children: Some((left, right)),
+        })
+    }
+
+    fn balance_factor(&self) -> i16 {
+        match &self.children {
+            Some((left, right)) => left.height as i16 - right.height as i16,
+            None => 0,
+        }
+    }
+}
+
+/// Leaf hashing of an [`ExistenceProof`], as in ICS-23's `LeafOp`
+///
+/// The hash function, SHA-256 prehashing of the value and varint length
+/// prefixes of the IAVL spec are implied; only the prefix varies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LeafOp {
+    /// Height, size and version of the leaf
+    pub prefix: Vec<u8>,
+}
+
+/// One step from a child to its parent, as in ICS-23's `InnerOp`
+///
+/// The parent hash is `H(prefix || child || suffix)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InnerOp {
+    pub prefix: Vec<u8>,
+    pub suffix: Vec<u8>,
+}
+
+impl InnerOp {
+    /// Whether the child is the right child of the parent
+    fn child_is_right(&self) -> bool {
+        self.suffix.is_empty()
+    }
+}
+
+/// Proof that a key is stored with a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExistenceProof {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub leaf: LeafOp,
+    /// Steps from the leaf up to the root
+    pub path: Vec<InnerOp>,
+}
+
+impl ExistenceProof {
+    /// Recompute the root hash from the leaf and path
+    pub fn calculate_root<H: Hasher>(&self, hasher: &H) -> Result<RootHash> {
+        // Leaves encode height 0 as a zero byte, inner nodes never do, which
+        // keeps a leaf from passing as an inner node and the other way around
+        if self.leaf.prefix.first() != Some(&0) {
+            return Err(MerkleError::InvalidProof {
+                reason: "Leaf prefix does not start with height 0".to_string(),
+            });
+        }
+
+        let mut input = self.leaf.prefix.clone();
+        length_prefixed(&mut input, &self.key);
+        length_prefixed(&mut input, &hasher.hash(&self.value));
+        let mut hash = hasher.hash(&input);
+
+        for op in &self.path {
+            if op.prefix.first().is_none_or(|&height| height == 0) {
+                return Err(MerkleError::InvalidProof {
+                    reason: "Inner prefix does not start with a height above 0".to_string(),
+                });
+            }
+            let mut input = Vec::with_capacity(op.prefix.len() + hash.len() + op.suffix.len());
+            input.extend_from_slice(&op.prefix);
+            input.extend_from_slice(&hash);
+            input.extend_from_slice(&op.suffix);
+            hash = hasher.hash(&input);
+        }
+
+        Ok(RootHash::from(hash))
+    }
+
+    /// Verify that `key` is stored with `value` under `root`
+    pub fn verify<H: Hasher>(&self, hasher: &H, root: &RootHash, key: &[u8], value: &[u8]) -> bool {
+        self.key == key
+            && self.value == value
+            && self
+                .calculate_root(hasher)
+                .is_ok_and(|computed| computed == *root)
+    }
+
+    /// Whether the leaf is the leftmost one of the tree
+    fn is_leftmost(&self) -> bool {
+        self.path.iter().all(|op| !op.child_is_right())
+    }
+
+    /// Whether the leaf is the rightmost one of the tree
+    fn is_rightmost(&self) -> bool {
+        self.path.iter().all(InnerOp::child_is_right)
+    }
+}
+
+/// Proof that a key is absent, by the existence of its neighbours
+///
+/// `left` is the greatest key below the missing one and `right` the smallest
+/// above it; either is `None` when the key is past that end of the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NonExistenceProof {
+    pub key: Vec<u8>,
+    pub left: Option<ExistenceProof>,
+    pub right: Option<ExistenceProof>,
+}
+
+impl NonExistenceProof {
+    /// Verify that `key` is absent from the tree under `root`
+    pub fn verify<H: Hasher>(&self, hasher: &H, root: &RootHash, key: &[u8]) -> bool {
+        if self.key != key {
+            return false;
+        }
+
+        let verifies = |proof: &ExistenceProof| {
+            proof
+                .calculate_root(hasher)
+                .is_ok_and(|computed| computed == *root)
+        };
+
+        match (&self.left, &self.right) {
+            (None, None) => *root == empty_root(hasher),
+            (Some(left), None) => {
+                verifies(left) && left.key.as_slice() < key && left.is_rightmost()
+            }
+            (None, Some(right)) => {
+                verifies(right) && key < right.key.as_slice() && right.is_leftmost()
+            }
+            (Some(left), Some(right)) => {
+                verifies(left)
+                    && verifies(right)
+                    && left.key.as_slice() < key
+                    && key < right.key.as_slice()
+                    && are_neighbours(left, right)
+            }
+        }
+    }
+}
+
+/// Proof that a set of entries is every entry of a key range
+///
+/// Each entry is proven to exist, and so are the entries just outside the
+/// range. Since consecutive proofs must be neighbours in the tree, no key in
+/// the range can have been left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RangeProof {
+    /// Entry with the greatest key below the range, if any
+    pub left: Option<ExistenceProof>,
+    /// Entries in the range, in key order
+    pub entries: Vec<ExistenceProof>,
+    /// Entry with the smallest key at or above the range end, if any
+    pub right: Option<ExistenceProof>,
+}
+
+impl RangeProof {
+    /// Get the proven keys and values in the range, in order
+    pub fn entries(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.key.as_slice(), entry.value.as_slice()))
+    }
+
+    /// Verify that the entries are all entries in `start..end` under `root`
+    ///
+    /// `None` leaves that end of the range open, as in [`IavlTree::range`].
+    pub fn verify<H: Hasher>(
+        &self,
+        hasher: &H,
+        root: &RootHash,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> bool {
+        let in_range =
+            |key: &[u8]| start.is_none_or(|start| start <= key) && end.is_none_or(|end| key < end);
+        let bounds_hold = self.entries.iter().all(|entry| in_range(&entry.key))
+            && match (&self.left, start) {
+                (Some(left), Some(start)) => left.key.as_slice() < start,
+                (Some(_), None) => false,
+                (None, _) => true,
+            }
+            && match (&self.right, end) {
+                (Some(right), Some(end)) => end <= right.key.as_slice(),
+                (Some(_), None) => false,
+                (None, _) => true,
+            };
+        if !bounds_hold {
+            return false;
+        }
+
+        let chain: Vec<&ExistenceProof> = self
+            .left
+            .iter()
+            .chain(&self.entries)
+            .chain(&self.right)
+            .collect();
+        let (Some(first), Some(last)) = (chain.first(), chain.last()) else {
+            return *root == empty_root(hasher);
+        };
+
+        chain.iter().all(|proof| {
+            proof
+                .calculate_root(hasher)
+                .is_ok_and(|computed| computed == *root)
+        }) && chain
+            .windows(2)
+            .all(|pair| pair[0].key < pair[1].key && are_neighbours(pair[0], pair[1]))
+            && (self.left.is_some() || first.is_leftmost())
+            && (self.right.is_some() || last.is_rightmost())
+    }
+}
+
+/// Check that two verified leaves of the same tree are adjacent
+///
+/// Their paths share every node above the one where they split. There the
+/// left leaf goes left and the right one right; below it the left leaf keeps
+/// to the right edge of its subtree and the right leaf to the left edge.
+fn are_neighbours(left: &ExistenceProof, right: &ExistenceProof) -> bool {
+    let mut left_ops = left.path.iter().rev();
+    let mut right_ops = right.path.iter().rev();
+    loop {
+        match (left_ops.next(), right_ops.next()) {
+            (Some(l), Some(r)) if l == r => continue,
+            (Some(l), Some(r)) => {
+                return !l.child_is_right()
+                    && r.child_is_right()
+                    && r.prefix.starts_with(&l.prefix)
+                    && left_ops.all(InnerOp::child_is_right)
+                    && right_ops.all(|op| !op.child_is_right());
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Versioned, balanced Merkle tree with ordered keys
+///
+/// Changes apply to a working tree until [`save_version`](Self::save_version)
+/// records it as the next version. Saved versions stay readable until they
+/// are deleted.
+///
+/// ```rust
+/// use merkle_tree::{IavlTree, Sha256Hasher};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let mut tree = IavlTree::new(Sha256Hasher::new());
+/// tree.set(b"alice", b"100");
+/// tree.set(b"bob", b"42");
+/// let (root, version) = tree.save_version();
+/// assert_eq!(version, 1);
+///
+/// let proof = tree.prove_existence(b"alice")?;
+/// assert!(proof.verify(tree.hasher(), &root, b"alice", b"100"));
+/// let proof = tree.prove_non_existence(b"carol")?;
+/// assert!(proof.verify(tree.hasher(), &root, b"carol"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct IavlTree<H: Hasher> {
+    hasher: H,
+    root: Option<Arc<Node>>,
+    /// Last saved version, 0 before the first save
+    version: u64,
+    versions: BTreeMap<u64, Option<Arc<Node>>>,
+}
+
+impl<H: Hasher> IavlTree<H> {
+    /// Create an empty tree
+    pub fn new(hasher: H) -> Self {
+        Self {
+            hasher,
+            root: None,
+            version: 0,
+            versions: BTreeMap::new(),
+        }
+    }
+
+    /// Get the hasher used by this tree
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the last saved version, 0 before the first save
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Get the saved versions that are still available, in order
+    pub fn versions(&self) -> Vec<u64> {
+        self.versions.keys().copied().collect()
+    }
+
+    /// Get the root hash of the working tree
+    pub fn root(&self) -> RootHash {
+        node_root(&self.hasher, self.root.as_ref())
+    }
+
+    /// Get the number of keys in the working tree
+    pub fn len(&self) -> usize {
+        self.root.as_ref().map_or(0, |root| root.size as usize)
+    }
+
+    /// Check if the working tree is empty
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Get the value stored under `key` in the working tree
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        get(self.root.as_ref(), key)
+    }
+
+    /// Insert or replace the value stored under `key`
+    ///
+    /// Returns whether an existing value was replaced.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> bool {
+        let version = self.version + 1;
+        let (root, updated) = match self.root.take() {
+            None => (
+                Node::leaf(&self.hasher, key.to_vec(), value.to_vec(), version),
+                false,
+            ),
+            Some(root) => self.insert(&root, key, value, version),
+        };
+        self.root = Some(root);
+        updated
+    }
+
+    /// Remove `key`, returning its value if it was stored
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let root = self.root.clone()?;
+        let (root, _, value) = self.delete(&root, key, self.version + 1)?;
+        self.root = root;
+        Some(value)
+    }
+
+    /// Get the keys and values in `start..end` of the working tree, in order
+    ///
+    /// `None` leaves that end of the range open.
+    pub fn range(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        if let Some(root) = &self.root {
+            collect_range(root, start, end, &mut entries);
+        }
+        entries
+    }
+
+    /// Record the working tree as the next version
+    ///
+    /// Returns its root hash and version number.
+    pub fn save_version(&mut self) -> (RootHash, u64) {
+        self.version += 1;
+        self.versions.insert(self.version, self.root.clone());
+        (self.root(), self.version)
+    }
+
+    /// Drop a saved version, freeing the nodes no other version shares
+    pub fn delete_version(&mut self, version: u64) -> Result<()> {
+        self.versions
+            .remove(&version)
+            .map(drop)
+            .ok_or_else(|| self.missing_version(version))
+    }
+
+    /// Drop every saved version older than `version`
+    ///
+    /// Nodes are shared between versions by reference count, so a node is
+    /// freed once no retained version, nor the working tree, reaches it.
+    /// Returns the number of nodes freed. Releasing them iteratively also
+    /// keeps dropping a long chain of versions off the call stack.
+    pub fn prune_versions_older_than(&mut self, version: u64) -> usize {
+        let retained = self.versions.split_off(&version);
+        let pruned = std::mem::replace(&mut self.versions, retained);
+
+        let mut freed = 0;
+        let mut pending: Vec<Arc<Node>> = pruned.into_values().flatten().collect();
+        while let Some(node) = pending.pop() {
+            // Still reachable from another version if this is not the last
+            // reference
+            if let Ok(mut node) = Arc::try_unwrap(node) {
+                freed += 1;
+                if let Some((left, right)) = node.children.take() {
+                    pending.push(left);
+                    pending.push(right);
+                }
+            }
+        }
+        freed
+    }
+
+    /// Get the root hash of a saved version
+    pub fn version_root(&self, version: u64) -> Result<RootHash> {
+        let root = self.saved(version)?;
+        Ok(node_root(&self.hasher, root))
+    }
+
+    /// Get the value stored under `key` in a saved version
+    pub fn get_versioned(&self, key: &[u8], version: u64) -> Result<Option<&[u8]>> {
+        Ok(get(self.saved(version)?, key))
+    }
+
+    /// Prove that `key` is stored in the working tree
+    pub fn prove_existence(&self, key: &[u8]) -> Result<ExistenceProof> {
+        prove_existence(self.root.as_ref(), key)
+    }
+
+    /// Prove that `key` is absent from the working tree
+    pub fn prove_non_existence(&self, key: &[u8]) -> Result<NonExistenceProof> {
+        prove_non_existence(self.root.as_ref(), key)
+    }
+
+    /// Prove that `key` is stored in a saved version
+    pub fn prove_existence_versioned(&self, key: &[u8], version: u64) -> Result<ExistenceProof> {
+        prove_existence(self.saved(version)?, key)
+    }
+
+    /// Prove that `key` is absent from a saved version
+    pub fn prove_non_existence_versioned(
+        &self,
+        key: &[u8],
+        version: u64,
+    ) -> Result<NonExistenceProof> {
+        prove_non_existence(self.saved(version)?, key)
+    }
+
+    /// Prove that the entries in `start..end` of the working tree are
+    /// complete
+    pub fn prove_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<RangeProof> {
+        prove_range(self.root.as_ref(), start, end)
+    }
+
+    /// Prove that the entries in `start..end` of a saved version are complete
+    pub fn prove_range_versioned(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        version: u64,
+    ) -> Result<RangeProof> {
+        prove_range(self.saved(version)?, start, end)
+    }
+
+    /// Error for a version that was never saved or has been deleted
+    fn missing_version(&self, version: u64) -> MerkleError {
+        MerkleError::InvalidIndex {
+            index: version as usize,
+            size: self.version as usize + 1,
+        }
+    }
+
+    fn saved(&self, version: u64) -> Result<Option<&Arc<Node>>> {
+        self.versions
+            .get(&version)
+            .map(Option::as_ref)
+            .ok_or_else(|| self.missing_version(version))
+    }
+
+    fn insert(
+        &self,
+        node: &Arc<Node>,
+        key: &[u8],
+        value: &[u8],
+        version: u64,
+    ) -> (Arc<Node>, bool) {
+        let hasher = &self.hasher;
+        let Some((left, right)) = &node.children else {
+            let leaf = Node::leaf(hasher, key.to_vec(), value.to_vec(), version);
+            return match key.cmp(&node.key) {
+                Ordering::Less => (
+                    Node::inner(hasher, node.key.clone(), leaf, node.clone(), version),
+                    false,
+                ),
+                Ordering::Greater => (
+                    Node::inner(hasher, key.to_vec(), node.clone(), leaf, version),
+                    false,
+                ),
+                Ordering::Equal => (leaf, true),
+            };
+        };
+
+        let (left, right, updated) = if key < node.key.as_slice() {
+            let (left, updated) = self.insert(left, key, value, version);
+            (left, right.clone(), updated)
+        } else {
+            let (right, updated) = self.insert(right, key, value, version);
+            (left.clone(), right, updated)
+        };
+
+        let node = Node::inner(hasher, node.key.clone(), left, right, version);
+        if updated {
+            // The shape is unchanged, so no rebalancing is needed
+            (node, true)
+        } else {
+            (self.rebalance(node, version), false)
+        }
+    }
+
+    /// Remove `key` below `node`
+    ///
+    /// Returns the new subtree, the new smallest key of the subtree if it
+    /// changed, and the removed value; `None` if the key is not there.
+    #[allow(clippy::type_complexity)]
+    fn delete(
+        &self,
+        node: &Arc<Node>,
+        key: &[u8],
+        version: u64,
+    ) -> Option<(Option<Arc<Node>>, Option<Vec<u8>>, Vec<u8>)> {
+        let Some((left, right)) = &node.children else {
+            return (node.key == key).then(|| (None, None, node.value.clone()));
+        };
+
+        if key < node.key.as_slice() {
+            let (new_left, new_key, value) = self.delete(left, key, version)?;
+            let Some(new_left) = new_left else {
+                // The right subtree's smallest key is this node's key
+                return Some((Some(right.clone()), Some(node.key.clone()), value));
+            };
+            let node = Node::inner(
+                &self.hasher,
+                node.key.clone(),
+                new_left,
+                right.clone(),
+                version,
+            );
+            Some((Some(self.rebalance(node, version)), new_key, value))
+        } else {
+            let (new_right, new_key, value) = self.delete(right, key, version)?;
+            let Some(new_right) = new_right else {
+                return Some((Some(left.clone()), None, value));
+            };
+            let key = new_key.unwrap_or_else(|| node.key.clone());
+            let node = Node::inner(&self.hasher, key, left.clone(), new_right, version);
+            Some((Some(self.rebalance(node, version)), None, value))
+        }
+    }
+
+    fn rebalance(&self, node: Arc<Node>, version: u64) -> Arc<Node> {
+        let Some((left, right)) = &node.children else {
+            return node;
+        };
+
+        match node.balance_factor() {
+            2.. if left.balance_factor() >= 0 => self.rotate_right(&node, version),
+            2.. => {
+                let left = self.rotate_left(left, version);
+                let node =
+                    Node::inner(&self.hasher, node.key.clone(), left, right.clone(), version);
+                self.rotate_right(&node, version)
+            }
+            ..=-2 if right.balance_factor() <= 0 => self.rotate_left(&node, version),
+            ..=-2 => {
+                let right = self.rotate_right(right, version);
+                let node =
+                    Node::inner(&self.hasher, node.key.clone(), left.clone(), right, version);
+                self.rotate_left(&node, version)
+            }
+            _ => node,
+        }
+    }
+
+    fn rotate_right(&self, node: &Arc<Node>, version: u64) -> Arc<Node> {
+        let (left, right) = node.children.as_ref().expect("rotating a leaf");
+        let (left_left, left_right) = left.children.as_ref().expect("rotating onto a leaf");
+        let lowered = Node::inner(
+            &self.hasher,
+            node.key.clone(),
+            left_right.clone(),
+            right.clone(),
+            version,
+        );
+        Node::inner(
+            &self.hasher,
+            left.key.clone(),
+            left_left.clone(),
+            lowered,
+            version,
+        )
+    }
+
+    fn rotate_left(&self, node: &Arc<Node>, version: u64) -> Arc<Node> {
+        let (left, right) = node.children.as_ref().expect("rotating a leaf");
+        let (right_left, right_right) = right.children.as_ref().expect("rotating onto a leaf");
+        let lowered = Node::inner(
+            &self.hasher,
+            node.key.clone(),
+            left.clone(),
+            right_left.clone(),
+            version,
+        );
+        Node::inner(
+            &self.hasher,
+            right.key.clone(),
+            lowered,
+            right_right.clone(),
+            version,
+        )
+    }
+}
+
+fn get<'a>(root: Option<&'a Arc<Node>>, key: &[u8]) -> Option<&'a [u8]> {
+    let mut node = root?;
+    while let Some((left, right)) = &node.children {
+        node = if key < node.key.as_slice() {
+            left
+        } else {
+            right
+        };
+    }
+    (node.key == key).then_some(node.value.as_slice())
+}
+
+fn collect_range(
+    node: &Arc<Node>,
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+    entries: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) {
+    match &node.children {
+        None => {
+            let key = node.key.as_slice();
+            if start.is_none_or(|start| start <= key) && end.is_none_or(|end| key < end) {
+                entries.push((node.key.clone(), node.value.clone()));
+            }
+        }
+        Some((left, right)) => {
+            // Left holds keys below this node's key, right the rest
+            if start.is_none_or(|start| start < node.key.as_slice()) {
+                collect_range(left, start, end, entries);
+            }
+            if end.is_none_or(|end| node.key.as_slice() < end) {
+                collect_range(right, start, end, entries);
+            }
+        }
+    }
+}
+
+fn prove_existence(root: Option<&Arc<Node>>, key: &[u8]) -> Result<ExistenceProof> {
+    let missing = || MerkleError::InvalidProof {
+        reason: format!("Key {} is not in the tree", hex::encode(key)),
+    };
+
+    let mut node = root.ok_or_else(missing)?;
+    let mut path = Vec::new();
+    while let Some((left, right)) = &node.children {
+        let mut prefix = header(node.height, node.size, node.version);
+        let mut suffix = Vec::new();
+        node = if key < node.key.as_slice() {
+            put_uvarint(&mut prefix, left.hash.len() as u64);
+            length_prefixed(&mut suffix, &right.hash);
+            left
+        } else {
+            length_prefixed(&mut prefix, &left.hash);
+            put_uvarint(&mut prefix, right.hash.len() as u64);
+            right
+        };
+        path.push(InnerOp { prefix, suffix });
+    }
+
+    if node.key != key {
+        return Err(missing());
+    }
+    path.reverse();
+    Ok(ExistenceProof {
+        key: node.key.clone(),
+        value: node.value.clone(),
+        leaf: LeafOp {
+            prefix: header(0, 1, node.version),
+        },
+        path,
+    })
+}
+
+fn prove_non_existence(root: Option<&Arc<Node>>, key: &[u8]) -> Result<NonExistenceProof> {
+    if get(root, key).is_some() {
+        return Err(MerkleError::InvalidProof {
+            reason: format!("Key {} is in the tree", hex::encode(key)),
+        });
+    }
+
+    let prove = |node: &Arc<Node>| prove_existence(root, &node.key);
+    Ok(NonExistenceProof {
+        key: key.to_vec(),
+        left: root
+            .and_then(|root| last_before(root, key))
+            .map(prove)
+            .transpose()?,
+        right: root
+            .and_then(|root| first_from(root, key))
+            .map(prove)
+            .transpose()?,
+    })
+}
+
+fn prove_range(
+    root: Option<&Arc<Node>>,
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+) -> Result<RangeProof> {
+    let prove = |node: &Arc<Node>| prove_existence(root, &node.key);
+    let mut entries = Vec::new();
+    if let Some(root) = root {
+        collect_range(root, start, end, &mut entries);
+    }
+
+    Ok(RangeProof {
+        left: match (root, start) {
+            (Some(root), Some(start)) => last_before(root, start).map(prove).transpose()?,
+            _ => None,
+        },
+        entries: entries
+            .iter()
+            .map(|(key, _)| prove_existence(root, key))
+            .collect::<Result<_>>()?,
+        right: match (root, end) {
+            (Some(root), Some(end)) => first_from(root, end).map(prove).transpose()?,
+            _ => None,
+        },
+    })
+}
+
+/// Leaf with the greatest key below `key`
+fn last_before<'a>(root: &'a Arc<Node>, key: &[u8]) -> Option<&'a Arc<Node>> {
+    let mut node = root;
+    let mut fallback = None;
+    while let Some((left, right)) = &node.children {
+        if key <= node.key.as_slice() {
+            node = left;
+        } else {
+            fallback = Some(left);
+            node = right;
+        }
+    }
+    if node.key.as_slice() < key {
+        return Some(node);
+    }
+
+    let mut node = fallback?;
+    while let Some((_, right)) = &node.children {
+        node = right;
+    }
+    Some(node)
+}
+
+/// Leaf with the smallest key at or above `key`
+fn first_from<'a>(root: &'a Arc<Node>, key: &[u8]) -> Option<&'a Arc<Node>> {
+    let mut node = root;
+    let mut fallback = None;
+    while let Some((left, right)) = &node.children {
+        if key < node.key.as_slice() {
+            fallback = Some(right);
+            node = left;
+        } else {
+            node = right;
+        }
+    }
+    if node.key.as_slice() >= key {
+        return Some(node);
+    }
+
+    let mut node = fallback?;
+    while let Some((left, _)) = &node.children {
+        node = left;
+    }
+    Some(node)
+}
+
+fn node_root<H: Hasher>(hasher: &H, root: Option<&Arc<Node>>) -> RootHash {
+    match root {
+        Some(root) => RootHash::from(root.hash.clone()),
+        None => empty_root(hasher),
+    }
+}
+
+/// Root of an empty tree, the hash of no input
+fn empty_root<H: Hasher>(hasher: &H) -> RootHash {
+    RootHash::from(hasher.empty_root())
+}
+
+/// Height, size and version as signed varints
+fn header(height: u8, size: u64, version: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    put_varint(&mut out, height as i64);
+    put_varint(&mut out, size as i64);
+    put_varint(&mut out, version as i64);
+    out
+}
+
+fn length_prefixed(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_uvarint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Zigzag varint, as Go's `binary.PutVarint`
+fn put_varint(out: &mut Vec<u8>, value: i64) {
+    put_uvarint(out, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn put_uvarint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("key_{:04}", i).into_bytes()
+    }
+
+    /// Check the AVL and IAVL+ invariants, returning the subtree's keys
+    fn check(node: &Arc<Node>) -> Vec<Vec<u8>> {
+        let Some((left, right)) = &node.children else {
+            return vec![node.key.clone()];
+        };
+        assert!(node.balance_factor().abs() <= 1);
+        assert_eq!(node.height, left.height.max(right.height) + 1);
+        assert_eq!(node.size, left.size + right.size);
+
+        let mut keys = check(left);
+        let right_keys = check(right);
+        assert_eq!(right_keys[0], node.key);
+        assert!(keys.last().unwrap() < &node.key);
+        keys.extend(right_keys);
+        keys
+    }
+
+    #[test]
+    fn test_set_get_remove() {
+        let mut tree = IavlTree::new(Sha256Hasher::new());
+        for i in (0..200).rev().step_by(3).chain((0..200).step_by(3)) {
+            tree.set(&key(i), &i.to_be_bytes());
+        }
+        assert!(tree.set(&key(3), b"updated"));
+        check(tree.root.as_ref().unwrap());
+        assert_eq!(tree.get(&key(3)), Some(b"updated".as_slice()));
+        assert_eq!(tree.get(&key(5)), None);
+
+        let len = tree.len();
+        for i in (0..200).step_by(6) {
+            assert!(tree.remove(&key(i)).is_some());
+            check(tree.root.as_ref().unwrap());
+        }
+        assert_eq!(tree.remove(&key(0)), None);
+        assert_eq!(tree.len(), len - (0..200).step_by(6).count());
+    }
+
+    #[test]
+    fn test_root_matches_rebuilt_tree() {
+        // The same sequence of operations always gives the same root
+        let build = || {
+            let mut tree = IavlTree::new(Sha256Hasher::new());
+            for i in 0..50 {
+                tree.set(&key(i), b"v");
+            }
+            for i in 10..20 {
+                tree.remove(&key(i));
+            }
+            tree.save_version().0
+        };
+        assert_eq!(build(), build());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut tree = IavlTree::new(Sha256Hasher::new());
+        for i in 0..30 {
+            tree.set(&key(i), &i.to_be_bytes());
+        }
+
+        let entries = tree.range(Some(&key(10)), Some(&key(15)));
+        let keys: Vec<Vec<u8>> = entries.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, (10..15).map(key).collect::<Vec<_>>());
+        assert_eq!(tree.range(None, Some(&key(3))).len(), 3);
+        assert_eq!(tree.range(Some(&key(27)), None).len(), 3);
+        assert_eq!(tree.range(None, None).len(), 30);
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut tree = IavlTree::new(Sha256Hasher::new());
+        tree.set(b"a", b"1");
+        let (root1, version1) = tree.save_version();
+        tree.set(b"a", b"2");
+        tree.set(b"b", b"3");
+        let (root2, version2) = tree.save_version();
+        assert_ne!(root1, root2);
+
+        assert_eq!(
+            tree.get_versioned(b"a", version1).unwrap(),
+            Some(b"1".as_slice())
+        );
+        assert_eq!(tree.get_versioned(b"b", version1).unwrap(), None);
+        assert_eq!(tree.version_root(version1).unwrap(), root1);
+
+        let proof = tree.prove_existence_versioned(b"a", version1).unwrap();
+        assert!(proof.verify(tree.hasher(), &root1, b"a", b"1"));
+        assert!(!proof.verify(tree.hasher(), &root2, b"a", b"1"));
+
+        tree.delete_version(version1).unwrap();
+        assert_eq!(tree.versions(), vec![version2]);
+        assert!(matches!(
+            tree.get_versioned(b"a", version1),
+            Err(MerkleError::InvalidIndex { .. })
+        ));
+    }
+
+    #[test]
+    fn test_prune_versions() {
+        let key = |i: u32| i.to_be_bytes().to_vec();
+        let mut tree = IavlTree::new(Sha256Hasher::new());
+        for i in 0..16 {
+            tree.set(&key(i), b"v1");
+        }
+        let (_, version1) = tree.save_version();
+        tree.set(&key(3), b"v2");
+        let (_, version2) = tree.save_version();
+        tree.set(&key(12), b"v3");
+        let (root3, version3) = tree.save_version();
+
+        // Only the nodes on the paths rewritten since are freed
+        let freed = tree.prune_versions_older_than(version2);
+        assert!(freed > 0 && freed < 31, "freed {} nodes", freed);
+        assert_eq!(tree.versions(), vec![version2, version3]);
+        assert!(tree.get_versioned(&key(3), version1).is_err());
+        assert_eq!(
+            tree.get_versioned(&key(3), version2).unwrap(),
+            Some(b"v2".as_slice())
+        );
+
+        // Nodes the working tree still holds survive pruning every version
+        assert!(tree.prune_versions_older_than(version3 + 1) > 0);
+        assert!(tree.versions().is_empty());
+        assert_eq!(tree.root(), root3);
+        assert_eq!(tree.get(&key(5)), Some(b"v1".as_slice()));
+        assert_eq!(tree.prune_versions_older_than(u64::MAX), 0);
+
+        // With nothing else holding them every node of a version is freed
+        let mut tree = IavlTree::new(Sha256Hasher::new());
+        for i in 0..16 {
+            tree.set(&key(i), b"v1");
+        }
+        tree.save_version();
+        tree.root = None;
+        assert_eq!(tree.prune_versions_older_than(2), 31);
+    }
+
+    #[test]
+    fn test_proofs() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = IavlTree::new(hasher.clone());
+        let proof = tree.prove_non_existence(b"x").unwrap();
+        assert!(proof.verify(&hasher, &tree.root(), b"x"));
+
+        for i in (0..40).step_by(2) {
+            tree.set(&key(i), &i.to_be_bytes());
+        }
+        let (root, _) = tree.save_version();
+
+        for i in 0..41 {
+            if i % 2 == 0 && i < 40 {
+                let proof = tree.prove_existence(&key(i)).unwrap();
+                assert!(proof.verify(&hasher, &root, &key(i), &i.to_be_bytes()));
+                assert!(!proof.verify(&hasher, &root, &key(i), b"other"));
+                assert!(tree.prove_non_existence(&key(i)).is_err());
+            } else {
+                let proof = tree.prove_non_existence(&key(i)).unwrap();
+                assert!(proof.verify(&hasher, &root, &key(i)));
+                assert!(tree.prove_existence(&key(i)).is_err());
+            }
+        }
+
+        // Neighbours that skip a key do not prove it absent
+        let proof = NonExistenceProof {
+            key: key(3),
+            left: Some(tree.prove_existence(&key(0)).unwrap()),
+            right: Some(tree.prove_existence(&key(4)).unwrap()),
+        };
+        assert!(!proof.verify(&hasher, &root, &key(3)));
+        let proof = NonExistenceProof {
+            key: key(41),
+            left: Some(tree.prove_existence(&key(36)).unwrap()),
+            right: None,
+        };
+        assert!(!proof.verify(&hasher, &root, &key(41)));
+    }
+
+    #[test]
+    fn test_range_proof() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = IavlTree::new(hasher.clone());
+        let proof = tree.prove_range(None, None).unwrap();
+        assert!(proof.verify(&hasher, &tree.root(), None, None));
+
+        for i in (0..60).step_by(2) {
+            tree.set(&key(i), &i.to_be_bytes());
+        }
+        let root = tree.root();
+
+        let bounds = [
+            (Some(key(10)), Some(key(20))),
+            (Some(key(11)), Some(key(19))),
+            (None, Some(key(7))),
+            (Some(key(51)), None),
+            (Some(key(13)), Some(key(14))),
+            (None, None),
+        ];
+        for (start, end) in &bounds {
+            let (start, end) = (start.as_deref(), end.as_deref());
+            let proof = tree.prove_range(start, end).unwrap();
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = proof
+                .entries()
+                .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                .collect();
+            assert_eq!(entries, tree.range(start, end));
+            assert!(proof.verify(&hasher, &root, start, end));
+        }
+
+        // Dropping an entry, or claiming a wider range, is caught
+        let mut proof = tree.prove_range(Some(&key(10)), Some(&key(20))).unwrap();
+        assert!(!proof.verify(&hasher, &root, Some(&key(10)), Some(&key(30))));
+        assert!(!proof.verify(&hasher, &root, Some(&key(0)), Some(&key(20))));
+        proof.entries.remove(2);
+        assert!(!proof.verify(&hasher, &root, Some(&key(10)), Some(&key(20))));
+
+        let mut proof = tree.prove_range(None, Some(&key(7))).unwrap();
+        proof.entries.remove(0);
+        assert!(!proof.verify(&hasher, &root, None, Some(&key(7))));
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut out = Vec::new();
+        put_varint(&mut out, 0);
+        put_varint(&mut out, 1);
+        put_varint(&mut out, -1);
+        put_varint(&mut out, 64);
+        assert_eq!(out, vec![0x00, 0x02, 0x01, 0x80, 0x01]);
+    }
+}
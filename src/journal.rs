@@ -0,0 +1,438 @@
+//! Write-ahead log for sparse Merkle tree mutations
+//!
+//! [`JournaledSparseTree`] records every update and removal of a
+//! [`SparseMerkleTree`] in an append-only journal file. [`commit`] appends the
+//! buffered operations followed by a checkpoint naming the resulting root,
+//! checksummed so a torn write is recognized, and syncs the file. On open the
+//! journal is replayed up to its last valid checkpoint and the rebuilt root
+//! is checked against the checkpointed one before the tree is handed out, so
+//! a crashed process recovers exactly the last committed state or fails with
+//! [`MerkleError::IntegrityError`].
+//!
+//! Records, with integers big-endian:
+//!
+//! ```text
+//! update:     0x01 | index u64 | leaf hash
+//! remove:     0x02 | index u64
+//! checkpoint: 0x03 | root | H(0x03 || root)
+//! ```
+//!
+//! [`commit`]: JournaledSparseTree::commit
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::proof::MerkleProof;
+use crate::sparse::SparseMerkleTree;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const TAG_UPDATE: u8 = 1;
+const TAG_REMOVE: u8 = 2;
+const TAG_CHECKPOINT: u8 = 3;
+
+/// Journaled operation read back from the file
+enum Op {
+    Update(u64, Hash),
+    Remove(u64),
+}
+
+/// Sparse Merkle tree whose mutations are journaled to a file
+///
+/// ```rust
+/// use merkle_tree::journal::JournaledSparseTree;
+/// use merkle_tree::Sha256Hasher;
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// # let path = std::env::temp_dir().join(format!("merkle-journal-doc-{}", std::process::id()));
+/// let mut tree = JournaledSparseTree::open(&path, 16, Sha256Hasher::new())?;
+/// tree.update(7, b"alice")?;
+/// let root = tree.commit()?;
+/// drop(tree);
+///
+/// let mut tree = JournaledSparseTree::open(&path, 16, Sha256Hasher::new())?;
+/// assert_eq!(tree.root(), &root);
+/// # std::fs::remove_file(&path).ok();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct JournaledSparseTree<H: Hasher> {
+    tree: SparseMerkleTree<H>,
+    path: PathBuf,
+    file: File,
+    /// Length of the file up to the last checkpoint
+    committed: u64,
+    /// Records of operations since the last commit
+    pending: Vec<u8>,
+    /// Operations since the last commit
+    uncommitted: usize,
+    /// Commit automatically after this many operations
+    checkpoint_interval: Option<usize>,
+}
+
+impl<H: Hasher> JournaledSparseTree<H> {
+    /// Open the journal at `path` for a tree of the given depth, creating an
+    /// empty one if the file is missing
+    ///
+    /// Operations after the last checkpoint, left behind by a crash or never
+    /// committed, are truncated away. Fails with
+    /// [`MerkleError::IntegrityError`] if the replayed tree does not have the
+    /// checkpointed root.
+    pub fn open(path: impl Into<PathBuf>, depth: u8, hasher: H) -> Result<Self> {
+        let path = path.into();
+        let mut tree = SparseMerkleTree::new(depth, hasher)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+
+        let (ops, root, committed) = scan(tree.hasher(), &file)?;
+        for op in ops {
+            match op {
+                Op::Update(index, leaf) => tree.insert_leaf(index, leaf)?,
+                Op::Remove(index) => {
+                    tree.remove(index)?;
+                }
+            }
+        }
+        if let Some(root) = root {
+            if *tree.root().as_hash() != root {
+                return Err(MerkleError::IntegrityError {
+                    path: path.display().to_string(),
+                    reason: "replayed root does not match the last checkpoint".to_string(),
+                });
+            }
+        }
+        file.set_len(committed).map_err(io_error)?;
+
+        Ok(Self {
+            tree,
+            path,
+            file,
+            committed,
+            pending: Vec::new(),
+            uncommitted: 0,
+            checkpoint_interval: None,
+        })
+    }
+
+    /// Commit automatically after every `operations` updates and removals
+    pub fn checkpoint_every(mut self, operations: usize) -> Self {
+        self.checkpoint_interval = Some(operations.max(1));
+        self
+    }
+
+    /// Insert or update a leaf, journaling the change
+    pub fn update(&mut self, index: u64, value: &[u8]) -> Result<()> {
+        let leaf = self.tree.leaf_hash(index, value);
+        self.tree.insert_leaf(index, leaf.clone())?;
+
+        self.pending.push(TAG_UPDATE);
+        self.pending.extend_from_slice(&index.to_be_bytes());
+        self.pending.extend_from_slice(&leaf);
+        self.operation_done()
+    }
+
+    /// Remove a leaf, journaling the change
+    pub fn remove(&mut self, index: u64) -> Result<bool> {
+        if !self.tree.remove(index)? {
+            return Ok(false);
+        }
+
+        self.pending.push(TAG_REMOVE);
+        self.pending.extend_from_slice(&index.to_be_bytes());
+        self.operation_done()?;
+        Ok(true)
+    }
+
+    /// Write buffered operations and a checkpoint naming the current root
+    ///
+    /// Returns the committed root. The file is synced before returning.
+    pub fn commit(&mut self) -> Result<RootHash> {
+        let root = self.tree.root().clone();
+        self.pending
+            .extend_from_slice(&checkpoint_record(self.tree.hasher(), &root));
+
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.committed))
+            .map_err(io_error)?;
+        file.write_all(&self.pending).map_err(io_error)?;
+        file.sync_data().map_err(io_error)?;
+
+        self.committed += self.pending.len() as u64;
+        self.pending.clear();
+        self.uncommitted = 0;
+        Ok(root)
+    }
+
+    /// Rewrite the journal as one update per leaf and a checkpoint
+    ///
+    /// The records are written to a temporary file next to the journal,
+    /// which then replaces it. This also commits the current root.
+    pub fn compact(&mut self) -> Result<()> {
+        let mut tmp_name = self.path.clone().into_os_string();
+        tmp_name.push(".compact");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let mut records = Vec::new();
+        for (index, leaf) in self.tree.leaves() {
+            records.push(TAG_UPDATE);
+            records.extend_from_slice(&index.to_be_bytes());
+            records.extend_from_slice(leaf);
+        }
+        let root = self.tree.root().clone();
+        records.extend_from_slice(&checkpoint_record(self.tree.hasher(), &root));
+
+        let mut tmp = File::create(&tmp_path).map_err(io_error)?;
+        tmp.write_all(&records).map_err(io_error)?;
+        tmp.sync_data().map_err(io_error)?;
+        drop(tmp);
+        fs::rename(&tmp_path, &self.path).map_err(io_error)?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .map_err(io_error)?;
+        self.committed = records.len() as u64;
+        self.pending.clear();
+        self.uncommitted = 0;
+        Ok(())
+    }
+
+    /// Get the root hash, including uncommitted operations
+    pub fn root(&mut self) -> &RootHash {
+        self.tree.root()
+    }
+
+    /// Generate a Merkle proof for the given index
+    pub fn generate_proof(&mut self, index: u64) -> Result<MerkleProof> {
+        self.tree.generate_proof(index)
+    }
+
+    /// Get the journaled tree
+    pub fn tree(&self) -> &SparseMerkleTree<H> {
+        &self.tree
+    }
+
+    /// Get the path of the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the size of the journal, including uncommitted records
+    pub fn journal_size(&self) -> u64 {
+        self.committed + self.pending.len() as u64
+    }
+
+    fn operation_done(&mut self) -> Result<()> {
+        self.uncommitted += 1;
+        if self
+            .checkpoint_interval
+            .is_some_and(|interval| self.uncommitted >= interval)
+        {
+            self.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the operations up to the last valid checkpoint
+///
+/// Returns the operations, the checkpointed root and the length of the file
+/// up to that checkpoint.
+fn scan<H: Hasher>(hasher: &H, file: &File) -> Result<(Vec<Op>, Option<Hash>, u64)> {
+    let hash_size = hasher.output_size();
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0)).map_err(io_error)?;
+
+    let mut ops = Vec::new();
+    let mut committed_ops = 0;
+    let mut root = None;
+    let mut committed = 0;
+    let mut pos = 0;
+    loop {
+        // A record cut short is the torn tail of an interrupted write
+        let record = match read_record(&mut reader, hash_size) {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(io_error(e)),
+        };
+        pos += record.len() as u64;
+
+        let index = || u64::from_be_bytes(record[1..9].try_into().unwrap());
+        match record[0] {
+            TAG_UPDATE => ops.push(Op::Update(index(), Hash::new(record[9..].to_vec()))),
+            TAG_REMOVE => ops.push(Op::Remove(index())),
+            _ => {
+                let body = &record[..1 + hash_size];
+                if hasher.hash(body).as_ref() != &record[1 + hash_size..] {
+                    break;
+                }
+                root = Some(Hash::new(body[1..].to_vec()));
+                committed_ops = ops.len();
+                committed = pos;
+            }
+        }
+    }
+
+    ops.truncate(committed_ops);
+    Ok((ops, root, committed))
+}
+
+/// Read one whole record, or `None` at the end of the file or at an unknown
+/// tag
+fn read_record<R: Read>(reader: &mut R, hash_size: usize) -> std::io::Result<Option<Vec<u8>>> {
+    let mut tag = [0u8; 1];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+
+    let len = match tag[0] {
+        TAG_UPDATE => 8 + hash_size,
+        TAG_REMOVE => 8,
+        TAG_CHECKPOINT => 2 * hash_size,
+        _ => return Ok(None),
+    };
+    let mut record = vec![0u8; 1 + len];
+    record[0] = tag[0];
+    reader.read_exact(&mut record[1..])?;
+    Ok(Some(record))
+}
+
+/// Checkpoint naming `root`, followed by a checksum of the record
+fn checkpoint_record<H: Hasher>(hasher: &H, root: &RootHash) -> Vec<u8> {
+    let mut record = vec![TAG_CHECKPOINT];
+    record.extend_from_slice(root.as_hash());
+    let checksum = hasher.hash(&record);
+    record.extend_from_slice(&checksum);
+    record
+}
+
+fn io_error(e: std::io::Error) -> MerkleError {
+    MerkleError::IoError {
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    /// Fresh file path under the system temp dir, removed on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "merkle-journal-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::remove_file(&path).ok();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn test_commit_and_recover() {
+        let file = TempFile::new("recover");
+        let mut tree = JournaledSparseTree::open(&file.0, 16, Sha256Hasher::new()).unwrap();
+        for i in 0..20u64 {
+            tree.update(i * 31, &i.to_be_bytes()).unwrap();
+        }
+        assert!(tree.remove(31).unwrap());
+        assert!(!tree.remove(32).unwrap());
+        let committed = tree.commit().unwrap();
+
+        // Uncommitted operations are lost on reopen
+        tree.update(1, b"lost").unwrap();
+        drop(tree);
+        let mut tree = JournaledSparseTree::open(&file.0, 16, Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), &committed);
+        assert!(!tree.tree().contains(31));
+        assert!(!tree.tree().contains(1));
+
+        // So is a torn tail after the last checkpoint
+        tree.update(1, b"torn").unwrap();
+        tree.commit().unwrap();
+        let size = tree.journal_size();
+        drop(tree);
+        let mut bytes = fs::read(&file.0).unwrap();
+        bytes.truncate(size as usize - 1);
+        fs::write(&file.0, &bytes).unwrap();
+        let mut tree = JournaledSparseTree::open(&file.0, 16, Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), &committed);
+
+        // The recovered tree proves against the committed root
+        let proof = tree.generate_proof(62).unwrap();
+        assert!(tree
+            .tree()
+            .clone()
+            .verify_proof(&proof, 62, &2u64.to_be_bytes()));
+    }
+
+    #[test]
+    fn test_rejects_mismatched_root() {
+        let file = TempFile::new("mismatch");
+        let mut tree = JournaledSparseTree::open(&file.0, 16, Sha256Hasher::new()).unwrap();
+        tree.update(5, b"value").unwrap();
+        tree.commit().unwrap();
+        drop(tree);
+
+        // Corrupt the journaled leaf; the checkpoint is still intact
+        let mut bytes = fs::read(&file.0).unwrap();
+        bytes[9] ^= 1;
+        fs::write(&file.0, &bytes).unwrap();
+        assert!(matches!(
+            JournaledSparseTree::open(&file.0, 16, Sha256Hasher::new()),
+            Err(MerkleError::IntegrityError { .. })
+        ));
+
+        // As does replaying into a tree of another depth
+        bytes[9] ^= 1;
+        fs::write(&file.0, &bytes).unwrap();
+        assert!(JournaledSparseTree::open(&file.0, 16, Sha256Hasher::new()).is_ok());
+        assert!(matches!(
+            JournaledSparseTree::open(&file.0, 8, Sha256Hasher::new()),
+            Err(MerkleError::IntegrityError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_checkpoint_interval_and_compact() {
+        let file = TempFile::new("compact");
+        let mut tree = JournaledSparseTree::open(&file.0, 16, Sha256Hasher::new())
+            .unwrap()
+            .checkpoint_every(10);
+        for round in 0..5u32 {
+            for i in 0..10u64 {
+                tree.update(i, &round.to_be_bytes()).unwrap();
+            }
+        }
+        // Every tenth operation committed
+        assert_eq!(tree.pending.len(), 0);
+        let root = tree.root().clone();
+        let size = tree.journal_size();
+
+        tree.compact().unwrap();
+        assert!(tree.journal_size() < size);
+        drop(tree);
+        let mut tree = JournaledSparseTree::open(&file.0, 16, Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), &root);
+        assert_eq!(tree.tree().len(), 10);
+    }
+}
@@ -0,0 +1,188 @@
+//! Canonical byte encoding of structured leaves
+//!
+//! Hand-written `to_bytes` functions tend to drift: a reordered field or a
+//! switch from little- to big-endian silently changes every root. A
+//! [`MerkleLeaf`] type encodes itself by fixed rules instead:
+//!
+//! - integers are big-endian and fixed width, with `usize` and `isize`
+//!   widened to 64 bits
+//! - `bool` is one byte, `0` or `1`
+//! - strings, byte strings, slices and vectors are prefixed with their
+//!   length as a big-endian `u32`
+//! - arrays are their elements, as their length is part of the type
+//! - `Option` is a `0` byte, or a `1` byte followed by the value
+//! - structs deriving `MerkleLeaf` (with the `derive` feature) are their
+//!   fields in declaration order
+//!
+//! ```rust
+//! # #[cfg(feature = "derive")]
+//! # fn main() -> merkle_tree::Result<()> {
+//! use merkle_tree::{MerkleLeaf, MerkleTree, Sha256Hasher};
+//!
+//! #[derive(MerkleLeaf)]
+//! struct Claim {
+//!     account: [u8; 20],
+//!     amount: u128,
+//! }
+//!
+//! let claims = vec![
+//!     Claim { account: [1; 20], amount: 100 },
+//!     Claim { account: [2; 20], amount: 250 },
+//! ];
+//! let data: Vec<Vec<u8>> = claims.iter().map(MerkleLeaf::to_leaf_bytes).collect();
+//! let tree = MerkleTree::new(data, Sha256Hasher::new())?;
+//!
+//! let proof = tree.generate_proof(1)?;
+//! assert!(proof.verify(tree.hasher(), &claims[1].to_leaf_bytes(), &tree.root()));
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "derive"))]
+//! # fn main() {}
+//! ```
+
+use crate::hash::Hash;
+
+/// Value with a canonical byte encoding for use as tree input
+pub trait MerkleLeaf {
+    /// Append the encoding of the value to `out`
+    fn encode_leaf(&self, out: &mut Vec<u8>);
+
+    /// Encode the value on its own
+    fn to_leaf_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_leaf(&mut out);
+        out
+    }
+}
+
+macro_rules! impl_int {
+    ($($ty:ty),*) => {
+        $(
+            impl MerkleLeaf for $ty {
+                fn encode_leaf(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl MerkleLeaf for usize {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode_leaf(out);
+    }
+}
+
+impl MerkleLeaf for isize {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        (*self as i64).encode_leaf(out);
+    }
+}
+
+impl MerkleLeaf for bool {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+/// Write a big-endian `u32` length prefix
+///
+/// Panics if `len` does not fit, as a truncated prefix would make the
+/// encoding ambiguous.
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    let len = u32::try_from(len).expect("leaf field longer than u32::MAX");
+    out.extend_from_slice(&len.to_be_bytes());
+}
+
+impl MerkleLeaf for str {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl MerkleLeaf for String {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        self.as_str().encode_leaf(out);
+    }
+}
+
+impl<T: MerkleLeaf> MerkleLeaf for [T] {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        for item in self {
+            item.encode_leaf(out);
+        }
+    }
+}
+
+impl<T: MerkleLeaf> MerkleLeaf for Vec<T> {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_leaf(out);
+    }
+}
+
+impl<T: MerkleLeaf, const N: usize> MerkleLeaf for [T; N] {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.encode_leaf(out);
+        }
+    }
+}
+
+impl<T: MerkleLeaf> MerkleLeaf for Option<T> {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode_leaf(out);
+            }
+        }
+    }
+}
+
+impl<T: MerkleLeaf + ?Sized> MerkleLeaf for &T {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        (**self).encode_leaf(out);
+    }
+}
+
+impl MerkleLeaf for Hash {
+    fn encode_leaf(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_leaf(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_primitive_encodings() {
+        assert_eq!(0x0102u16.to_leaf_bytes(), [1, 2]);
+        assert_eq!((-1i8).to_leaf_bytes(), [0xff]);
+        assert_eq!(7usize.to_leaf_bytes(), 7u64.to_leaf_bytes());
+        assert_eq!(true.to_leaf_bytes(), [1]);
+        assert_eq!("ab".to_leaf_bytes(), [0, 0, 0, 2, b'a', b'b']);
+        assert_eq!(String::from("ab").to_leaf_bytes(), "ab".to_leaf_bytes());
+        assert_eq!(vec![1u8, 2].to_leaf_bytes(), [0, 0, 0, 2, 1, 2]);
+        assert_eq!([1u8, 2].to_leaf_bytes(), [1, 2]);
+        assert_eq!(None::<u8>.to_leaf_bytes(), [0]);
+        assert_eq!(Some(3u8).to_leaf_bytes(), [1, 3]);
+        assert_eq!(Hash::from([9u8, 9]).to_leaf_bytes(), [0, 0, 0, 2, 9, 9]);
+    }
+
+    #[test]
+    fn test_length_prefixes_keep_fields_apart() {
+        let split = |a: &str, b: &str| {
+            let mut out = Vec::new();
+            a.encode_leaf(&mut out);
+            b.encode_leaf(&mut out);
+            out
+        };
+        assert_ne!(split("ab", "c"), split("a", "bc"));
+    }
+}
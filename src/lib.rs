@@ -5,8 +5,9 @@
 //!
 //! ## Features
 //!
-//! - Generic hash function support (SHA-256, SHA-3, BLAKE3, etc.)
+//! - Generic hash function support (SHA-256, SHA-3, BLAKE3, Keccak-256, etc.)
 //! - Efficient proof generation and verification
+//! - OpenZeppelin-compatible multiproofs
 //! - Binary and sparse Merkle tree implementations
 //! - Serialization support with serde
 //! - Comprehensive error handling
@@ -17,7 +18,8 @@
 //! ```rust
 //! use merkle_tree::{MerkleTree, Sha256Hasher};
 //!
-//! let data = vec![b"hello", b"world", b"merkle", b"tree"];
+//! # fn main() -> merkle_tree::Result<()> {
+//! let data: Vec<&[u8]> = vec![b"hello", b"world", b"merkle", b"tree"];
 //! let tree = MerkleTree::new(data, Sha256Hasher::new())?;
 //! let root = tree.root();
 //!
@@ -25,20 +27,108 @@
 //! let proof = tree.generate_proof(0)?;
 //!
 //! // Verify the proof
-//! assert!(tree.verify_proof(&proof, b"hello", root));
+//! assert!(tree.verify_proof(&proof, b"hello", &root));
+//! # Ok(())
+//! # }
 //! ```
 
+pub mod abi;
+pub mod accumulator;
+pub mod airdrop;
+pub mod augmented;
+pub mod bao;
+pub mod builder;
+pub mod canonical;
+pub mod chain;
+pub mod compact;
+pub mod const_tree;
+pub mod dag;
+pub mod epoch;
 pub mod error;
+pub mod export;
+pub mod external;
+pub mod file;
+#[cfg(feature = "uniffi")]
+pub mod ffi;
+pub mod hash;
 pub mod hasher;
+pub mod iavl;
+pub mod journal;
+pub mod leaf;
+pub mod log;
+pub mod map;
 pub mod merkle_tree;
+pub mod migration;
+pub mod multiproof;
+pub mod namespace;
+#[cfg(feature = "napi")]
+pub mod node;
 pub mod proof;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "sled")]
+pub mod sled_tree;
 pub mod sparse;
+pub mod sth;
+pub mod stream;
+pub mod substrate;
+pub mod sync;
+pub mod tendermint;
+pub mod testing;
+pub mod urkel;
 
+pub use abi::{AbiEncoding, AbiLeaf};
+pub use accumulator::{AppendUpdate, MerkleAccumulator, Witness};
+pub use airdrop::Airdrop;
+pub use augmented::{AugmentedProof, AugmentedTree, NodeCombiner, SumCombiner, SumNode};
+pub use builder::{
+    ConstructionMode, Duplicates, LeafEncoder, LeafEncoding, MerkleTreeBuilder, Salting,
+};
+pub use compact::CompactSparseTree;
+pub use dag::{Cid, DagLink, DagNode, DagStore, Multihash};
+pub use epoch::EpochedTree;
 pub use error::{MerkleError, Result};
-pub use hasher::{Blake3Hasher, Hasher, Sha256Hasher, Sha3Hasher};
-pub use merkle_tree::MerkleTree;
-pub use proof::{MerkleProof, ProofDirection};
-pub use sparse::SparseMerkleTree;
+pub use export::ProofFormat;
+pub use external::{DiskTree, ExternalTreeBuilder, IndexMapReader, SortedDedupBuilder};
+pub use file::{ByteRangeProof, FileTree};
+pub use hash::{Hash, RootHash};
+pub use hasher::{
+    BatchHasher, Blake3Hasher, Blake3XofHasher, DigestHasher, FixedOutputHasher, HashAlgorithm,
+    Hasher, Keccak256Hasher, LeafHasher, Rfc6962Hasher, Sha256Hasher, Sha3Hasher,
+    SortedPairHasher, TaggedHasher, XofHasher,
+};
+pub use iavl::{ExistenceProof, IavlTree, InnerOp, LeafOp, NonExistenceProof, RangeProof};
+pub use journal::JournaledSparseTree;
+pub use leaf::MerkleLeaf;
+#[cfg(feature = "derive")]
+pub use merkle_tree_derive::MerkleLeaf;
+pub use log::{ConsistencyProof, LogStore, MerkleLog};
+pub use map::{MapProof, MerkleMap};
+pub use merkle_tree::{MerkleTree, NodeDomain, TreeShape};
+pub use migration::HashTransition;
+pub use multiproof::MultiProof;
+pub use namespace::NamespacedTree;
+pub use proof::{
+    AnnotatedProof, MerkleProof, MerkleProofRef, ProofCost, ProofDirection, ProofStepRef,
+    SaltedProof, TypedProof,
+};
+#[cfg(feature = "rayon")]
+pub use proof::{verify_proofs_parallel, VerificationReport};
+#[cfg(feature = "sled")]
+pub use sled_tree::SledTree;
+pub use sparse::{EmptyProof, SparseLeafEncoding, SparseMerkleTree, SparseProof, TransitionProof};
+pub use sth::{SignedTreeHead, Signer, SplitView, TreeHead, Verifier};
+pub use stream::SerializedTree;
+#[cfg(feature = "blake2")]
+pub use substrate::Blake2_256Hasher;
+pub use substrate::SubstrateProof;
+pub use tendermint::TendermintProof;
+pub use urkel::{UrkelProof, UrkelTerminal, UrkelTree};
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
 
 #[cfg(test)]
 mod tests {
@@ -48,6 +138,6 @@ mod tests {
     fn test_basic_functionality() {
         let data = vec![b"hello", b"world"];
         let tree = MerkleTree::new(data, Sha256Hasher::new()).unwrap();
-        assert!(tree.root().len() > 0);
+        assert!(!tree.root().is_empty());
     }
 }
@@ -0,0 +1,526 @@
+//! Append-only transparency log
+//!
+//! [`MerkleLog`] keeps every leaf together with the roots of all complete
+//! subtrees, so appending costs `O(log n)` hashes and inclusion and
+//! consistency proofs can be produced for any earlier size without rebuilding
+//! the tree. The tree shape and proof algorithms follow RFC 6962: the left
+//! subtree always holds the largest power of two of leaves, and nothing is
+//! duplicated.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+use crate::sth::{SignedTreeHead, Signer};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Persistence hook for the leaves of a [`MerkleLog`]
+///
+/// Only leaves are stored; interior nodes are recomputed when the log is
+/// reopened with [`MerkleLog::open`].
+pub trait LogStore {
+    /// Durably record the leaf at `index` before it is added to the log
+    fn persist_leaf(&mut self, index: usize, leaf: &Hash) -> Result<()>;
+
+    /// Load all previously persisted leaves in index order
+    fn load_leaves(&mut self) -> Result<Vec<Hash>>;
+}
+
+/// Store that keeps nothing, for logs that live only in memory
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoStore;
+
+impl LogStore for NoStore {
+    fn persist_leaf(&mut self, _index: usize, _leaf: &Hash) -> Result<()> {
+        Ok(())
+    }
+
+    fn load_leaves(&mut self) -> Result<Vec<Hash>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Proof that a log of `old_size` leaves is a prefix of one of `new_size`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConsistencyProof {
+    pub old_size: usize,
+    pub new_size: usize,
+    pub hashes: Vec<Hash>,
+}
+
+impl ConsistencyProof {
+    /// Verify the proof against the roots of both log sizes
+    ///
+    /// Implements the verification algorithm of RFC 9162, section 2.1.4.2.
+    pub fn verify<H: Hasher>(&self, hasher: &H, old_root: &RootHash, new_root: &RootHash) -> bool {
+        let (old_size, new_size) = (self.old_size, self.new_size);
+
+        if old_size == 0 || old_size > new_size {
+            return false;
+        }
+        if old_size == new_size {
+            return self.hashes.is_empty() && old_root == new_root;
+        }
+
+        self.fold(hasher, old_root)
+            .is_some_and(|(fr, sr)| fr == *old_root.as_hash() && sr == *new_root.as_hash())
+    }
+
+    /// Get the root at `old_size` that the proof implies for a log whose
+    /// root at `new_size` is `new_root`
+    ///
+    /// If the proof leads to `new_root`, the log behind that root had this
+    /// root at `old_size`, so a different old root signed by the same
+    /// operator is evidence of a split view. Returns `None` if the proof does
+    /// not lead to `new_root`, and when `old_size` is a power of two, as the
+    /// proof then leaves the old root out.
+    pub fn implied_old_root<H: Hasher>(&self, hasher: &H, new_root: &RootHash) -> Option<RootHash> {
+        if self.old_size == 0 || self.old_size >= self.new_size || self.old_size.is_power_of_two() {
+            return None;
+        }
+        // The old root only enters the path for power-of-two sizes
+        let (fr, sr) = self.fold(hasher, new_root)?;
+        (sr == *new_root.as_hash()).then(|| RootHash::from(fr))
+    }
+
+    /// Recompute the old and new roots from the proof, for
+    /// `0 < old_size < new_size`
+    ///
+    /// `old_root` starts the path when `old_size` is a power of two.
+    fn fold<H: Hasher>(&self, hasher: &H, old_root: &RootHash) -> Option<(Hash, Hash)> {
+        let (old_size, new_size) = (self.old_size, self.new_size);
+
+        let mut path: Vec<&Hash> = self.hashes.iter().collect();
+        if old_size.is_power_of_two() {
+            path.insert(0, old_root.as_hash());
+        }
+
+        let (first, rest) = path.split_first()?;
+
+        let mut fn_ = old_size - 1;
+        let mut sn = new_size - 1;
+        while fn_ & 1 == 1 {
+            fn_ >>= 1;
+            sn >>= 1;
+        }
+
+        let mut fr = (*first).clone();
+        let mut sr = (*first).clone();
+
+        for &c in rest {
+            if sn == 0 {
+                return None;
+            }
+            if fn_ & 1 == 1 || fn_ == sn {
+                fr = hasher.hash_pair(c, &fr);
+                sr = hasher.hash_pair(c, &sr);
+                while fn_ & 1 == 0 && fn_ != 0 {
+                    fn_ >>= 1;
+                    sn >>= 1;
+                }
+            } else {
+                sr = hasher.hash_pair(&sr, c);
+            }
+            fn_ >>= 1;
+            sn >>= 1;
+        }
+
+        (sn == 0).then_some((fr, sr))
+    }
+}
+
+/// Append-only Merkle log with inclusion and consistency proofs
+#[derive(Debug)]
+pub struct MerkleLog<H: Hasher, S: LogStore = NoStore> {
+    hasher: H,
+    store: S,
+    /// Roots of complete subtrees per level; level 0 holds the leaves
+    levels: Vec<Vec<Hash>>,
+}
+
+impl<H: Hasher> MerkleLog<H> {
+    /// Create an empty in-memory log
+    pub fn new(hasher: H) -> Self {
+        Self {
+            hasher,
+            store: NoStore,
+            levels: vec![Vec::new()],
+        }
+    }
+}
+
+impl<H: Hasher, S: LogStore> MerkleLog<H, S> {
+    /// Open a log backed by `store`, replaying the leaves it already holds
+    pub fn open(hasher: H, mut store: S) -> Result<Self> {
+        let leaves = store.load_leaves()?;
+        let mut log = Self {
+            hasher,
+            store,
+            levels: vec![Vec::new()],
+        };
+        for leaf in leaves {
+            log.push(leaf);
+        }
+        Ok(log)
+    }
+
+    /// Hash and append a data item
+    ///
+    /// Returns the index of the new leaf and its inclusion proof against the
+    /// new root.
+    pub fn append(&mut self, data: &[u8]) -> Result<(usize, MerkleProof)> {
+        let leaf = self.hasher.hash(data);
+        self.append_leaf(leaf)
+    }
+
+    /// Append a pre-hashed leaf
+    pub fn append_leaf(&mut self, leaf: Hash) -> Result<(usize, MerkleProof)> {
+        let index = self.len();
+        self.store.persist_leaf(index, &leaf)?;
+        self.push(leaf);
+
+        let proof = self.prove_inclusion(index, index + 1)?;
+        Ok((index, proof))
+    }
+
+    /// Get the number of leaves in the log
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Check if the log is empty
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Get the leaf hash at the given index
+    pub fn get_leaf(&self, index: usize) -> Result<&Hash> {
+        self.levels[0].get(index).ok_or(MerkleError::InvalidIndex {
+            index,
+            size: self.len(),
+        })
+    }
+
+    /// Get the hasher used by the log
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the current root hash
+    pub fn root(&self) -> RootHash {
+        self.root_at(self.len())
+            .expect("current size is always in range")
+    }
+
+    /// Get the root hash the log had at `size` leaves
+    ///
+    /// The root of the empty log is [`Hasher::empty_root`].
+    pub fn root_at(&self, size: usize) -> Result<RootHash> {
+        self.check_size(size)?;
+        if size == 0 {
+            return Ok(RootHash::from(self.hasher.empty_root()));
+        }
+        Ok(RootHash::from(self.subtree_root(0, size)))
+    }
+
+    /// Prove that the leaf at `index` is included in the log at `at_size`
+    pub fn prove_inclusion(&self, index: usize, at_size: usize) -> Result<MerkleProof> {
+        self.check_size(at_size)?;
+        if index >= at_size {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: at_size,
+            });
+        }
+
+        let mut steps = Vec::new();
+        self.inclusion_path(index, 0, at_size, &mut steps);
+        Ok(MerkleProof::new(index, steps))
+    }
+
+    /// Prove that the log at `old_size` is a prefix of the log at `new_size`
+    pub fn prove_consistency(&self, old_size: usize, new_size: usize) -> Result<ConsistencyProof> {
+        self.check_size(new_size)?;
+        if old_size == 0 || old_size > new_size {
+            return Err(MerkleError::InvalidProof {
+                reason: format!(
+                    "Cannot prove consistency from size {} to {}",
+                    old_size, new_size
+                ),
+            });
+        }
+
+        let mut hashes = Vec::new();
+        self.subproof(old_size, 0, new_size, true, &mut hashes);
+        Ok(ConsistencyProof {
+            old_size,
+            new_size,
+            hashes,
+        })
+    }
+
+    /// Sign the current size and root
+    pub fn signed_head<T: Signer>(&self, signer: &T) -> Result<SignedTreeHead> {
+        SignedTreeHead::sign(signer, self.len() as u64, self.root())
+    }
+
+    /// Add a leaf and the complete subtrees it finishes
+    fn push(&mut self, leaf: Hash) {
+        self.levels[0].push(leaf);
+
+        let mut level = 0;
+        while self.levels[level].len().is_multiple_of(2) {
+            let nodes = &self.levels[level];
+            let parent = self
+                .hasher
+                .hash_pair(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+
+            if self.levels.len() == level + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[level + 1].push(parent);
+            level += 1;
+        }
+    }
+
+    fn check_size(&self, size: usize) -> Result<()> {
+        if size > self.len() {
+            return Err(MerkleError::InvalidIndex {
+                index: size,
+                size: self.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Root of the leaves in `start..end`, where the range is one produced by
+    /// the RFC 6962 recursion
+    fn subtree_root(&self, start: usize, end: usize) -> Hash {
+        let n = end - start;
+        if n.is_power_of_two() && start.is_multiple_of(n) {
+            return self.levels[n.trailing_zeros() as usize][start / n].clone();
+        }
+
+        let k = split_point(n);
+        self.hasher.hash_pair(
+            &self.subtree_root(start, start + k),
+            &self.subtree_root(start + k, end),
+        )
+    }
+
+    /// RFC 6962 `PATH(m, D[start:end])`, collected from the leaf upwards
+    fn inclusion_path(&self, index: usize, start: usize, end: usize, steps: &mut Vec<ProofStep>) {
+        let n = end - start;
+        if n <= 1 {
+            return;
+        }
+
+        let k = split_point(n);
+        if index < start + k {
+            self.inclusion_path(index, start, start + k, steps);
+            steps.push(ProofStep {
+                hash: self.subtree_root(start + k, end),
+                direction: ProofDirection::Right,
+            });
+        } else {
+            self.inclusion_path(index, start + k, end, steps);
+            steps.push(ProofStep {
+                hash: self.subtree_root(start, start + k),
+                direction: ProofDirection::Left,
+            });
+        }
+    }
+
+    /// RFC 6962 `SUBPROOF(m, D[start:end], b)`
+    fn subproof(&self, m: usize, start: usize, end: usize, complete: bool, out: &mut Vec<Hash>) {
+        let n = end - start;
+        if m == n {
+            if !complete {
+                out.push(self.subtree_root(start, end));
+            }
+            return;
+        }
+
+        let k = split_point(n);
+        if m <= k {
+            self.subproof(m, start, start + k, complete, out);
+            out.push(self.subtree_root(start + k, end));
+        } else {
+            self.subproof(m - k, start + k, end, false, out);
+            out.push(self.subtree_root(start, start + k));
+        }
+    }
+}
+
+/// Largest power of two strictly smaller than `n`, for `n > 1`
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accumulator::MerkleAccumulator;
+    use crate::hasher::{Rfc6962Hasher, Sha256Hasher};
+
+    fn log_of(len: usize) -> MerkleLog<Sha256Hasher> {
+        let mut log = MerkleLog::new(Sha256Hasher::new());
+        for i in 0..len {
+            log.append(format!("entry_{}", i).as_bytes()).unwrap();
+        }
+        log
+    }
+
+    #[test]
+    fn test_rfc6962_roots() {
+        // Test vectors from the certificate-transparency reference implementation
+        let leaves: [&[u8]; 8] = [
+            b"",
+            b"\x00",
+            b"\x10",
+            b"\x20\x21",
+            b"\x30\x31",
+            b"\x40\x41\x42\x43",
+            b"\x50\x51\x52\x53\x54\x55\x56\x57",
+            b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+        ];
+        let roots = [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+
+        let mut log = MerkleLog::new(Rfc6962Hasher::new());
+        for (leaf, root) in leaves.iter().zip(roots) {
+            log.append(leaf).unwrap();
+            assert_eq!(log.root().to_hex(), root);
+        }
+    }
+
+    #[test]
+    fn test_matches_accumulator() {
+        let hasher = Sha256Hasher::new();
+        let mut log = MerkleLog::new(hasher.clone());
+        let mut acc = MerkleAccumulator::new(hasher);
+
+        for i in 0..20u32 {
+            log.append(&i.to_be_bytes()).unwrap();
+            acc.append(&i.to_be_bytes());
+            assert_eq!(log.root(), acc.root());
+        }
+    }
+
+    #[test]
+    fn test_inclusion_proofs() {
+        let log = log_of(13);
+        let hasher = log.hasher().clone();
+
+        for size in 1..=13 {
+            let root = log.root_at(size).unwrap();
+            for index in 0..size {
+                let proof = log.prove_inclusion(index, size).unwrap();
+                let data = format!("entry_{}", index);
+                assert!(proof.verify(&hasher, data.as_bytes(), &root));
+            }
+        }
+
+        assert!(log.prove_inclusion(5, 5).is_err());
+        assert!(log.prove_inclusion(0, 14).is_err());
+    }
+
+    #[test]
+    fn test_append_returns_valid_proof() {
+        let mut log = MerkleLog::new(Sha256Hasher::new());
+        for i in 0..9u32 {
+            let (index, proof) = log.append(&i.to_be_bytes()).unwrap();
+            assert_eq!(index, i as usize);
+            assert!(proof.verify(log.hasher(), &i.to_be_bytes(), &log.root()));
+        }
+    }
+
+    #[test]
+    fn test_consistency_proofs() {
+        let log = log_of(17);
+        let hasher = log.hasher().clone();
+
+        for new_size in 1..=17 {
+            let new_root = log.root_at(new_size).unwrap();
+            for old_size in 1..=new_size {
+                let old_root = log.root_at(old_size).unwrap();
+                let proof = log.prove_consistency(old_size, new_size).unwrap();
+                assert!(
+                    proof.verify(&hasher, &old_root, &new_root),
+                    "{} -> {}",
+                    old_size,
+                    new_size
+                );
+
+                if old_size < new_size {
+                    let forged = RootHash::from(hasher.hash(b"forged"));
+                    assert!(!proof.verify(&hasher, &forged, &new_root));
+                    assert!(!proof.verify(&hasher, &old_root, &forged));
+                }
+            }
+        }
+
+        assert!(log.prove_consistency(0, 3).is_err());
+        assert!(log.prove_consistency(4, 3).is_err());
+        assert!(log.prove_consistency(3, 18).is_err());
+    }
+
+    #[test]
+    fn test_forked_log_is_inconsistent() {
+        // A fork of the log must not be provable as an extension
+        let log = log_of(8);
+        let mut fork = MerkleLog::new(Sha256Hasher::new());
+        for i in 0..8 {
+            let entry = if i == 2 {
+                "fork".to_string()
+            } else {
+                format!("entry_{}", i)
+            };
+            fork.append(entry.as_bytes()).unwrap();
+        }
+
+        let proof = fork.prove_consistency(3, 8).unwrap();
+        assert!(!proof.verify(log.hasher(), &log.root_at(3).unwrap(), &fork.root()));
+    }
+
+    #[test]
+    fn test_store_replay() {
+        #[derive(Default)]
+        struct Memory(Vec<Hash>);
+
+        impl LogStore for &mut Memory {
+            fn persist_leaf(&mut self, index: usize, leaf: &Hash) -> Result<()> {
+                assert_eq!(index, self.0.len());
+                self.0.push(leaf.clone());
+                Ok(())
+            }
+
+            fn load_leaves(&mut self) -> Result<Vec<Hash>> {
+                Ok(self.0.clone())
+            }
+        }
+
+        let mut memory = Memory::default();
+        let root = {
+            let mut log = MerkleLog::open(Sha256Hasher::new(), &mut memory).unwrap();
+            for i in 0..6u32 {
+                log.append(&i.to_be_bytes()).unwrap();
+            }
+            log.root()
+        };
+
+        let reopened = MerkleLog::open(Sha256Hasher::new(), &mut memory).unwrap();
+        assert_eq!(reopened.len(), 6);
+        assert_eq!(reopened.root(), root);
+    }
+}
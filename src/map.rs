@@ -0,0 +1,322 @@
+//! Key-hashed sparse Merkle maps
+//!
+//! [`MerkleMap`] stores each value at the leaf its key hashes to, in a sparse
+//! Merkle tree with one level per bit of the key hash. The depth is not a
+//! parameter but [`MerkleMap::DEPTH`], derived from
+//! [`FixedOutputHasher::OUTPUT_SIZE`]: a `MerkleMap<Sha256Hasher>` always has
+//! 256 levels, a map over a 64-byte digest 512, and every bit of a key hash
+//! picks a side. Mapping key hashes onto the `u64` indices of a
+//! [`SparseMerkleTree`](crate::SparseMerkleTree) would instead keep only the
+//! first bits of each hash, so distinct keys could share a leaf.
+//!
+//! Leaves are `H(value)`, empty leaves all zeroes and internal nodes
+//! [`Hasher::hash_pair`] of their children, as in a sparse Merkle tree. A
+//! [`MapProof`] covers membership and non-membership alike and omits siblings
+//! that are empty subtrees.
+
+use crate::compact::{highest_key, lowest_key, with_bit};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::{FixedOutputHasher, Hasher};
+use crate::urkel::bit;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Sparse Merkle tree keyed by the full hash of each key
+///
+/// ```rust
+/// use merkle_tree::map::MerkleMap;
+/// use merkle_tree::Sha256Hasher;
+///
+/// let mut map = MerkleMap::new(Sha256Hasher::new());
+/// assert_eq!(map.depth(), 256);
+/// map.insert(b"alice", b"100");
+/// let root = map.root();
+///
+/// let proof = map.prove(b"alice");
+/// assert!(proof.verify(map.hasher(), &root, b"alice", Some(b"100")));
+/// assert!(map.prove(b"bob").verify(map.hasher(), &root, b"bob", None));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MerkleMap<H: FixedOutputHasher> {
+    /// Key hash to value and value hash
+    entries: BTreeMap<Hash, (Vec<u8>, Hash)>,
+    /// Cached nodes above more than one key, by depth and the lowest key
+    /// below them
+    nodes: HashMap<(usize, Hash), Hash>,
+    /// Hash of an empty subtree by height, from the leaf up to the root
+    empty: Vec<Hash>,
+    hasher: H,
+}
+
+impl<H: FixedOutputHasher> MerkleMap<H> {
+    /// Number of levels below the root, one per bit of the key hash
+    pub const DEPTH: usize = H::OUTPUT_SIZE * 8;
+
+    /// Create an empty map
+    pub fn new(hasher: H) -> Self {
+        debug_assert_eq!(hasher.output_size(), H::OUTPUT_SIZE);
+        Self {
+            entries: BTreeMap::new(),
+            nodes: HashMap::new(),
+            empty: empty_hashes(&hasher),
+            hasher,
+        }
+    }
+
+    /// Insert or replace the value stored under `key`
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) {
+        let key = self.hasher.hash(key);
+        let value_hash = self.hasher.hash(value);
+        self.invalidate(&key);
+        self.entries.insert(key, (value.to_vec(), value_hash));
+    }
+
+    /// Remove `key`, returning whether it was stored
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        let key = self.hasher.hash(key);
+        let removed = self.entries.remove(&key).is_some();
+        if removed {
+            self.invalidate(&key);
+        }
+        removed
+    }
+
+    /// Get the value stored under `key`
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries
+            .get(&self.hasher.hash(key))
+            .map(|(value, _)| value.as_slice())
+    }
+
+    /// Check if `key` is stored
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Get the number of stored keys
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the map holds no keys
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the depth of the tree, [`MerkleMap::DEPTH`]
+    pub fn depth(&self) -> usize {
+        Self::DEPTH
+    }
+
+    /// Get the hasher used by this map
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the root hash
+    pub fn root(&mut self) -> RootHash {
+        let any_key = Hash::new(vec![0u8; H::OUTPUT_SIZE]);
+        RootHash::from(self.node_hash(&any_key, 0))
+    }
+
+    /// Generate a membership or non-membership proof for `key`
+    pub fn prove(&mut self, key: &[u8]) -> MapProof {
+        let key = self.hasher.hash(key);
+        let siblings = (0..Self::DEPTH)
+            .rev()
+            .map(|depth| {
+                let sibling = with_bit(&key, depth, !bit(&key, depth));
+                match self.subtree(&sibling, depth + 1) {
+                    Subtree::Empty => None,
+                    _ => Some(self.node_hash(&sibling, depth + 1)),
+                }
+            })
+            .collect();
+        MapProof { siblings }
+    }
+
+    /// Hash of the node at `depth` on the path of `key`
+    fn node_hash(&mut self, key: &Hash, depth: usize) -> Hash {
+        match self.subtree(key, depth) {
+            Subtree::Empty => self.empty[Self::DEPTH - depth].clone(),
+            Subtree::Leaf(key, value_hash) => {
+                // A lone key climbs through empty siblings only
+                (depth..Self::DEPTH)
+                    .rev()
+                    .fold(value_hash.clone(), |hash, below| {
+                        let sibling = &self.empty[Self::DEPTH - 1 - below];
+                        parent(&self.hasher, key, below, &hash, sibling)
+                    })
+            }
+            Subtree::Internal => {
+                let id = (depth, lowest_key(key, depth));
+                if let Some(hash) = self.nodes.get(&id) {
+                    return hash.clone();
+                }
+                let left = self.node_hash(&with_bit(key, depth, false), depth + 1);
+                let right = self.node_hash(&with_bit(key, depth, true), depth + 1);
+                let hash = self.hasher.hash_pair(&left, &right);
+                self.nodes.insert(id, hash.clone());
+                hash
+            }
+        }
+    }
+
+    /// What the node at `depth` on the path of `key` holds
+    fn subtree(&self, key: &Hash, depth: usize) -> Subtree<'_> {
+        let mut below = self
+            .entries
+            .range(lowest_key(key, depth)..=highest_key(key, depth));
+        match (below.next(), below.next()) {
+            (None, _) => Subtree::Empty,
+            (Some((key, (_, value_hash))), None) => Subtree::Leaf(key, value_hash),
+            _ => Subtree::Internal,
+        }
+    }
+
+    /// Drop the cached nodes on the path of `key`, the only ones it changes
+    fn invalidate(&mut self, key: &Hash) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        for depth in 0..=Self::DEPTH {
+            self.nodes.remove(&(depth, lowest_key(key, depth)));
+        }
+    }
+}
+
+/// Contents of a subtree
+enum Subtree<'a> {
+    Empty,
+    /// A single key, with its value hash
+    Leaf(&'a Hash, &'a Hash),
+    Internal,
+}
+
+/// Membership or non-membership proof for a key of a [`MerkleMap`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MapProof {
+    /// Sibling hashes from the leaf level upwards, `None` for empty subtrees
+    pub siblings: Vec<Option<Hash>>,
+}
+
+impl MapProof {
+    /// Verify that `key` holds `value` under `root`, or is absent if `value`
+    /// is `None`
+    ///
+    /// The proof must have exactly [`MerkleMap::DEPTH`] siblings for `H`.
+    pub fn verify<H: FixedOutputHasher>(
+        &self,
+        hasher: &H,
+        root: &RootHash,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> bool {
+        let depth = MerkleMap::<H>::DEPTH;
+        if self.siblings.len() != depth {
+            return false;
+        }
+        let key = hasher.hash(key);
+        let empty = empty_hashes(hasher);
+        let leaf = value.map_or_else(|| empty[0].clone(), |value| hasher.hash(value));
+        let computed = self
+            .siblings
+            .iter()
+            .enumerate()
+            .fold(leaf, |hash, (level, sibling)| {
+                let sibling = sibling.as_ref().unwrap_or(&empty[level]);
+                parent(hasher, &key, depth - 1 - level, &hash, sibling)
+            });
+        RootHash::from(computed) == *root
+    }
+}
+
+/// Parent of `child`, the node below `depth` on the path of `key`
+fn parent<H: Hasher>(hasher: &H, key: &Hash, depth: usize, child: &Hash, sibling: &Hash) -> Hash {
+    if bit(key, depth) {
+        hasher.hash_pair(sibling, child)
+    } else {
+        hasher.hash_pair(child, sibling)
+    }
+}
+
+/// Hash of an empty subtree at each height, from the all-zero leaf up
+fn empty_hashes<H: FixedOutputHasher>(hasher: &H) -> Vec<Hash> {
+    let mut hashes = vec![Hash::new(vec![0u8; H::OUTPUT_SIZE])];
+    for height in 0..MerkleMap::<H>::DEPTH {
+        let below = &hashes[height];
+        hashes.push(hasher.hash_pair(below, below));
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{DigestHasher, Sha256Hasher};
+    use sha2::Sha512;
+
+    #[test]
+    fn test_depth_follows_hasher() {
+        assert_eq!(MerkleMap::<Sha256Hasher>::DEPTH, 256);
+        assert_eq!(MerkleMap::<DigestHasher<Sha512>>::DEPTH, 512);
+
+        let hasher = DigestHasher::<Sha512>::new();
+        let mut map = MerkleMap::new(hasher.clone());
+        assert_eq!(map.root().as_bytes(), empty_hashes(&hasher)[512].as_bytes());
+        map.insert(b"key", b"value");
+        let root = map.root();
+        let proof = map.prove(b"key");
+        assert_eq!(proof.siblings.len(), 512);
+        assert!(proof.siblings.iter().all(Option::is_none));
+        assert!(proof.verify(&hasher, &root, b"key", Some(b"value")));
+
+        // A proof cut to another depth is rejected
+        let mut short = proof.clone();
+        short.siblings.truncate(256);
+        assert!(!short.verify(&hasher, &root, b"key", Some(b"value")));
+    }
+
+    #[test]
+    fn test_proofs() {
+        let hasher = Sha256Hasher::new();
+        let mut map = MerkleMap::new(hasher.clone());
+        let empty_root = map.root();
+        for i in 0..100u32 {
+            map.insert(format!("key_{}", i).as_bytes(), &i.to_be_bytes());
+        }
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(b"key_7"), Some(7u32.to_be_bytes().as_slice()));
+        let root = map.root();
+
+        for i in (0..100u32).step_by(7) {
+            let key = format!("key_{}", i);
+            let proof = map.prove(key.as_bytes());
+            assert!(proof.verify(&hasher, &root, key.as_bytes(), Some(&i.to_be_bytes())));
+            assert!(!proof.verify(&hasher, &root, key.as_bytes(), None));
+            assert!(!proof.verify(&hasher, &root, b"key_100", Some(&i.to_be_bytes())));
+        }
+        let absent = map.prove(b"missing");
+        assert!(absent.verify(&hasher, &root, b"missing", None));
+        assert!(!absent.verify(&hasher, &root, b"missing", Some(b"")));
+
+        // Updates after the root was cached give the same root as a fresh map
+        map.insert(b"key_3", b"updated");
+        assert!(map.remove(b"key_4"));
+        assert!(!map.remove(b"key_4"));
+        let mut fresh = MerkleMap::new(hasher.clone());
+        for i in (0..100u32).rev().filter(|&i| i != 4) {
+            fresh.insert(format!("key_{}", i).as_bytes(), &i.to_be_bytes());
+        }
+        fresh.insert(b"key_3", b"updated");
+        assert_eq!(map.root(), fresh.root());
+
+        for i in 0..100u32 {
+            map.remove(format!("key_{}", i).as_bytes());
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.root(), empty_root);
+    }
+}
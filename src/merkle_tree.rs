@@ -1,146 +1,1085 @@
+use crate::builder::{ConstructionMode, LeafEncoding};
 use crate::error::{MerkleError, Result};
-use crate::hasher::Hasher;
-use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::{hash_all, Hasher};
+use crate::multiproof::MultiProof;
+use crate::proof::{
+    path_steps, AnnotatedProof, MerkleProof, MerkleProofRef, ProofCost, ProofDirection, ProofStep,
+    ProofStepRef, SaltedProof, TypedProof,
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
 
-/// A node in the Merkle tree
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// How the last node of a level with an odd number of nodes is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-struct MerkleNode {
-    hash: Vec<u8>,
-    left: Option<Box<MerkleNode>>,
-    right: Option<Box<MerkleNode>>,
+pub enum TreeShape {
+    /// Pair the last node with a copy of itself
+    #[default]
+    Padded,
+    /// Promote the last node to the next level unchanged
+    ///
+    /// This gives the RFC 6962 shape used by Certificate Transparency and
+    /// Trillian, where the left subtree always holds the largest power of two
+    /// of leaves. Both shapes agree whenever the leaf count is a power of two.
+    Rfc6962,
 }
 
-impl MerkleNode {
-    fn new_leaf(hash: Vec<u8>) -> Self {
-        Self {
-            hash,
-            left: None,
-            right: None,
-        }
-    }
+/// What internal node hashes commit to besides their two children
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NodeDomain {
+    /// `hash_pair(left, right)`
+    #[default]
+    None,
+    /// `hash_pair(level || left, right)`, i.e. `H(level || left || right)`
+    /// for hashers that do not customize `hash_pair`
+    ///
+    /// `level` is the level of the parent as a big-endian `u32`, counting
+    /// the leaves as level 0, so identical subtrees at different heights
+    /// hash differently. Proofs from such trees verify with
+    /// [`MerkleTree::verify_proof`] or [`MerkleProof::verify_leveled`].
+    Level,
+}
 
-    fn new_internal(hash: Vec<u8>, left: MerkleNode, right: MerkleNode) -> Self {
-        Self {
-            hash,
-            left: Some(Box::new(left)),
-            right: Some(Box::new(right)),
+impl NodeDomain {
+    /// Hash two children into their parent at `level`
+    pub fn hash_node<H: Hasher>(self, hasher: &H, level: usize, left: &[u8], right: &[u8]) -> Hash {
+        match self {
+            Self::None => hasher.hash_pair(left, right),
+            Self::Level => {
+                let mut prefixed = Vec::with_capacity(4 + left.len());
+                prefixed.extend_from_slice(&(level as u32).to_be_bytes());
+                prefixed.extend_from_slice(left);
+                hasher.hash_pair(&prefixed, right)
+            }
         }
     }
-
-    fn is_leaf(&self) -> bool {
-        self.left.is_none() && self.right.is_none()
-    }
 }
 
 /// Binary Merkle tree implementation
+///
+/// Node hashes are kept in one array per level, so the sibling and parent of
+/// a node are found by index arithmetic rather than by walking the tree.
+///
+/// Deserialized trees are checked with [`MerkleTree::verify_integrity`], so
+/// tampered input is rejected instead of yielding bogus proofs.
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        try_from = "UncheckedTree<H>",
+        bound(deserialize = "H: Deserialize<'de>")
+    )
+)]
 pub struct MerkleTree<H: Hasher> {
-    root: Option<MerkleNode>,
-    leaves: Vec<Vec<u8>>,
+    /// Node hashes per level, leaves first and root last
+    ///
+    /// Padding nodes created by duplicating the last node of an odd level are
+    /// not stored; each level has `ceil(len / 2^level)` entries.
+    levels: Vec<Vec<Hash>>,
     hasher: H,
+    #[cfg_attr(feature = "serde", serde(default))]
+    shape: TreeShape,
+    #[cfg_attr(feature = "serde", serde(default))]
+    domain: NodeDomain,
+    /// How data items were turned into leaves, see [`MerkleTree::encode_leaf`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    leaf_encoding: LeafEncoding,
+    /// Child trees whose roots form the leaves, when built via `from_subtrees`
+    #[cfg_attr(feature = "serde", serde(default))]
+    subtrees: Vec<MerkleTree<H>>,
+    /// Per-leaf salts, when built with [`Salting`](crate::builder::Salting)
+    #[cfg_attr(feature = "serde", serde(default))]
+    salts: Vec<Vec<u8>>,
+    /// Unhashed caller metadata by leaf index, see [`MerkleTree::annotate`]
+    #[cfg_attr(feature = "serde", serde(default))]
+    annotations: BTreeMap<usize, Vec<u8>>,
+    /// Position of the first leaf with each hash, when built via
+    /// [`MerkleTree::with_leaf_index`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    leaf_positions: HashMap<Hash, usize>,
+    /// Leaf hashes back to back, built on the first call to
+    /// [`MerkleTree::packed_leaves`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    packed_leaves: Option<Vec<u8>>,
+}
+
+/// Serialized form of a [`MerkleTree`] before its hashes are checked
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(bound = "H: Deserialize<'de>")]
+struct UncheckedTree<H> {
+    levels: Vec<Vec<Hash>>,
+    hasher: H,
+    #[serde(default)]
+    shape: TreeShape,
+    #[serde(default)]
+    domain: NodeDomain,
+    #[serde(default)]
+    subtrees: Vec<UncheckedTree<H>>,
+    #[serde(default)]
+    salts: Vec<Vec<u8>>,
+    #[serde(default)]
+    annotations: BTreeMap<usize, Vec<u8>>,
+}
+
+#[cfg(feature = "serde")]
+impl<H: Hasher> TryFrom<UncheckedTree<H>> for MerkleTree<H> {
+    type Error = MerkleError;
+
+    fn try_from(raw: UncheckedTree<H>) -> Result<Self> {
+        let subtrees = raw
+            .subtrees
+            .into_iter()
+            .map(Self::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        let tree = Self::from_levels(raw.levels, raw.hasher, raw.shape, raw.domain, subtrees)?;
+        if !raw.salts.is_empty() && raw.salts.len() != tree.len() {
+            return Err(MerkleError::IntegrityError {
+                path: "root".to_string(),
+                reason: "salt count does not match leaf count".to_string(),
+            });
+        }
+        if let Some(index) = raw.annotations.keys().find(|&&index| index >= tree.len()) {
+            return Err(MerkleError::IntegrityError {
+                path: "root".to_string(),
+                reason: format!("annotation for missing leaf {}", index),
+            });
+        }
+        tree.verify_integrity()?;
+        Ok(tree.with_salts(raw.salts).with_annotations(raw.annotations))
+    }
 }
 
 impl<H: Hasher> MerkleTree<H> {
     /// Create a new Merkle tree from the given data
+    ///
+    /// Leaves are hashed in one batch if the hasher provides a
+    /// [`BatchHasher`](crate::hasher::BatchHasher).
     pub fn new<T: AsRef<[u8]>>(data: Vec<T>, hasher: H) -> Result<Self> {
         if data.is_empty() {
             return Err(MerkleError::EmptyData);
         }
 
-        let leaves: Vec<Vec<u8>> = data.iter().map(|d| hasher.hash(d.as_ref())).collect();
-        let root = Self::build_tree(&leaves, &hasher)?;
+        let leaves = hash_all(&hasher, &data)?;
+        let levels = Self::build_levels(
+            leaves,
+            &hasher,
+            TreeShape::Padded,
+            NodeDomain::None,
+            ConstructionMode::Serial,
+        );
 
         Ok(Self {
-            root: Some(root),
+            levels,
+            hasher,
+            shape: TreeShape::Padded,
+            domain: NodeDomain::None,
+            leaf_encoding: LeafEncoding::PreHash,
+            subtrees: Vec::new(),
+            salts: Vec::new(),
+            annotations: BTreeMap::new(),
+            leaf_positions: HashMap::new(),
+            packed_leaves: None,
+        })
+    }
+
+    /// Create a tree without any leaves
+    ///
+    /// Its root is [`Hasher::empty_root`], the hash of the empty string that
+    /// RFC 6962 defines for an empty log. No proofs can be generated until leaves are added with
+    /// [`MerkleTree::push`].
+    pub fn empty(hasher: H) -> Self {
+        Self {
+            levels: Vec::new(),
+            hasher,
+            shape: TreeShape::Padded,
+            domain: NodeDomain::None,
+            leaf_encoding: LeafEncoding::PreHash,
+            subtrees: Vec::new(),
+            salts: Vec::new(),
+            annotations: BTreeMap::new(),
+            leaf_positions: HashMap::new(),
+            packed_leaves: None,
+        }
+    }
+
+    /// Create a new Merkle tree, hashing the data on the rayon thread pool
+    ///
+    /// Produces the same tree as [`MerkleTree::new`], see
+    /// [`ConstructionMode::Parallel`]. Items are hashed in chunks of at least
+    /// 1024 so that small leaves are not dominated by scheduling overhead.
+    #[cfg(feature = "rayon")]
+    pub fn par_new<T: AsRef<[u8]> + Sync>(data: Vec<T>, hasher: H) -> Result<Self>
+    where
+        H: Sync,
+    {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+
+        let leaves: Vec<Hash> = data
+            .par_iter()
+            .with_min_len(1024)
+            .map(|d| hasher.hash(d.as_ref()))
+            .collect();
+        let levels = Self::build_levels(
             leaves,
+            &hasher,
+            TreeShape::Padded,
+            NodeDomain::None,
+            ConstructionMode::Parallel,
+        );
+
+        Ok(Self {
+            levels,
             hasher,
+            shape: TreeShape::Padded,
+            domain: NodeDomain::None,
+            leaf_encoding: LeafEncoding::PreHash,
+            subtrees: Vec::new(),
+            salts: Vec::new(),
+            annotations: BTreeMap::new(),
+            leaf_positions: HashMap::new(),
+            packed_leaves: None,
         })
     }
 
+    /// Create a new Merkle tree with one leaf per reader
+    ///
+    /// Produces the same tree as [`MerkleTree::new`] over the readers'
+    /// contents, but hashes each one as it is read, so large items never
+    /// need to fit in memory.
+    pub fn from_readers<I>(readers: I, hasher: H) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: std::io::Read,
+    {
+        crate::builder::MerkleTreeBuilder::new(hasher).build_from_readers(readers)
+    }
+
     /// Create a new Merkle tree from pre-hashed leaves
-    pub fn from_leaves(leaves: Vec<Vec<u8>>, hasher: H) -> Result<Self> {
+    ///
+    /// Every leaf must be exactly `hasher.output_size()` bytes long, otherwise
+    /// [`MerkleError::HashSizeMismatch`] is returned. Use
+    /// [`MerkleTree::from_leaves_unchecked`] for intentionally variable-length
+    /// leaves.
+    pub fn from_leaves(leaves: Vec<Hash>, hasher: H) -> Result<Self> {
+        Self::check_leaf_sizes(&leaves, &hasher)?;
+        Self::from_leaves_unchecked(leaves, hasher)
+    }
+
+    /// Check that every leaf is a `hasher.output_size()`-byte digest
+    pub(crate) fn check_leaf_sizes(leaves: &[Hash], hasher: &H) -> Result<()> {
+        let expected = hasher.output_size();
+        if let Some((index, leaf)) = leaves
+            .iter()
+            .enumerate()
+            .find(|(_, leaf)| leaf.len() != expected)
+        {
+            return Err(MerkleError::HashSizeMismatch {
+                index,
+                expected,
+                actual: leaf.len(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Create a new Merkle tree from leaves of any length
+    ///
+    /// Trees built from leaves that are not hasher-sized digests will not
+    /// match other Merkle tree implementations.
+    pub fn from_leaves_unchecked(leaves: Vec<Hash>, hasher: H) -> Result<Self> {
+        Self::from_shaped_leaves(
+            leaves,
+            hasher,
+            TreeShape::Padded,
+            NodeDomain::None,
+            ConstructionMode::Serial,
+        )
+    }
+
+    /// Create a tree of the given shape and node domain from leaves of any
+    /// length
+    pub(crate) fn from_shaped_leaves(
+        leaves: Vec<Hash>,
+        hasher: H,
+        shape: TreeShape,
+        domain: NodeDomain,
+        mode: ConstructionMode,
+    ) -> Result<Self> {
         if leaves.is_empty() {
             return Err(MerkleError::EmptyData);
         }
 
-        let root = Self::build_tree(&leaves, &hasher)?;
+        let levels = Self::build_levels(leaves, &hasher, shape, domain, mode);
 
         Ok(Self {
-            root: Some(root),
+            levels,
+            hasher,
+            shape,
+            domain,
+            leaf_encoding: LeafEncoding::PreHash,
+            subtrees: Vec::new(),
+            salts: Vec::new(),
+            annotations: BTreeMap::new(),
+            leaf_positions: HashMap::new(),
+            packed_leaves: None,
+        })
+    }
+
+    /// Create a parent tree whose leaves are the roots of the given child trees
+    ///
+    /// The child trees are kept so that proofs spanning both levels can be
+    /// produced with [`MerkleTree::generate_nested_proof`]. All children must
+    /// use the same hash function.
+    pub fn from_subtrees(subtrees: Vec<MerkleTree<H>>) -> Result<Self> {
+        let first = subtrees.first().ok_or(MerkleError::EmptyData)?;
+        let hasher = first.hasher.clone();
+
+        if let Some(other) = subtrees
+            .iter()
+            .find(|tree| tree.hasher.name() != hasher.name())
+        {
+            return Err(MerkleError::TreeConstructionError {
+                reason: format!(
+                    "Subtree hasher mismatch: expected {}, found {}",
+                    hasher.name(),
+                    other.hasher.name()
+                ),
+            });
+        }
+
+        let leaves: Vec<Hash> = subtrees
+            .iter()
+            .map(|tree| tree.root().into_hash())
+            .collect();
+        let levels = Self::build_levels(
             leaves,
+            &hasher,
+            TreeShape::Padded,
+            NodeDomain::None,
+            ConstructionMode::Serial,
+        );
+
+        Ok(Self {
+            levels,
             hasher,
+            shape: TreeShape::Padded,
+            domain: NodeDomain::None,
+            leaf_encoding: LeafEncoding::PreHash,
+            subtrees,
+            salts: Vec::new(),
+            annotations: BTreeMap::new(),
+            leaf_positions: HashMap::new(),
+            packed_leaves: None,
         })
     }
 
+    /// Append `data` as a new leaf, encoded like the tree's other leaves
+    ///
+    /// Only the nodes on the path from the new leaf to the root are
+    /// rehashed, so the tree matches one built over all leaves at once.
+    pub fn push(&mut self, data: &[u8]) -> Result<()> {
+        let leaf = self.encode_leaf(data);
+        self.push_leaf(leaf)
+    }
+
+    /// Append an already hashed leaf
+    ///
+    /// Fails with [`MerkleError::UnsupportedOperation`] for salted trees and
+    /// trees built from subtrees, whose leaves cannot be extended one hash at
+    /// a time.
+    pub fn push_leaf(&mut self, leaf: Hash) -> Result<()> {
+        if !self.salts.is_empty() || !self.subtrees.is_empty() {
+            return Err(MerkleError::UnsupportedOperation {
+                reason: "Cannot push onto a salted tree or a tree of subtrees".to_string(),
+            });
+        }
+
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        let mut index = self.len();
+        if !self.leaf_positions.is_empty() {
+            self.leaf_positions.entry(leaf.clone()).or_insert(index);
+        }
+        self.levels[0].push(leaf);
+        self.packed_leaves = None;
+
+        // Rehash the new leaf's ancestors, adding a level when the old root
+        // gains a sibling
+        let mut level = 0;
+        while self.levels[level].len() > 1 {
+            let children = &self.levels[level];
+            let left = &children[index & !1];
+            let parent = match (children.get(index | 1), self.shape) {
+                (Some(right), _) => self.domain.hash_node(&self.hasher, level + 1, left, right),
+                (None, TreeShape::Padded) => {
+                    self.domain.hash_node(&self.hasher, level + 1, left, left)
+                }
+                (None, TreeShape::Rfc6962) => left.clone(),
+            };
+
+            index /= 2;
+            level += 1;
+            if self.levels.len() == level {
+                self.levels.push(Vec::new());
+            }
+            let parents = &mut self.levels[level];
+            if index < parents.len() {
+                parents[index] = parent;
+            } else {
+                parents.push(parent);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Get the root hash of the tree
-    pub fn root(&self) -> &[u8] {
-        self.root.as_ref().map(|r| r.hash.as_slice()).unwrap_or(&[])
+    ///
+    /// The root of an empty tree is the hash of the empty string.
+    pub fn root(&self) -> RootHash {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .map(|root| RootHash::from(root.clone()))
+            .unwrap_or_else(|| RootHash::from(self.hasher.empty_root()))
+    }
+
+    /// Consume the tree and return only its root hash
+    ///
+    /// With the `zeroize` feature enabled every other hash held by the tree is
+    /// wiped from memory before it is freed.
+    pub fn into_root(mut self) -> RootHash {
+        self.levels
+            .last_mut()
+            .and_then(|level| level.first_mut())
+            .map(|root| RootHash::from(std::mem::take(root)))
+            .unwrap_or_else(|| RootHash::from(self.hasher.empty_root()))
     }
 
     /// Get the number of leaves in the tree
     pub fn len(&self) -> usize {
-        self.leaves.len()
+        self.leaves().len()
     }
 
     /// Check if the tree is empty
     pub fn is_empty(&self) -> bool {
-        self.leaves.is_empty()
+        self.leaves().is_empty()
+    }
+
+    /// Get the number of levels above the leaves
+    ///
+    /// A single leaf is its own root, at height 0, as is an empty tree.
+    pub fn height(&self) -> usize {
+        self.levels.len().saturating_sub(1)
+    }
+
+    /// Get the number of steps in the proof of the leaf at `index`
+    ///
+    /// This is the height of the tree, less the levels where an RFC 6962
+    /// shaped tree promotes the leaf's ancestor without a sibling.
+    pub fn expected_proof_len(&self, index: usize) -> Result<usize> {
+        if index >= self.len() {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len(),
+            });
+        }
+        Ok(path_steps(index, self.len(), self.shape).len())
     }
 
     /// Get the leaf hash at the given index
-    pub fn get_leaf(&self, index: usize) -> Result<&[u8]> {
-        self.leaves
-            .get(index)
-            .map(|leaf| leaf.as_slice())
+    pub fn get_leaf(&self, index: usize) -> Result<&Hash> {
+        self.leaves().get(index).ok_or(MerkleError::InvalidIndex {
+            index,
+            size: self.len(),
+        })
+    }
+
+    /// Index the leaves by hash, so [`MerkleTree::position_of`] and
+    /// [`MerkleTree::generate_proof_for_hash`] avoid scanning every leaf
+    pub fn with_leaf_index(mut self) -> Self {
+        let mut positions = HashMap::with_capacity(self.len());
+        for (index, leaf) in self.leaves().iter().enumerate() {
+            positions.entry(leaf.clone()).or_insert(index);
+        }
+        self.leaf_positions = positions;
+        self
+    }
+
+    /// Check if the leaves were indexed with [`MerkleTree::with_leaf_index`]
+    pub(crate) fn has_leaf_index(&self) -> bool {
+        !self.leaf_positions.is_empty()
+    }
+
+    /// Find the position of the first leaf with the given hash
+    pub fn position_of(&self, leaf_hash: &Hash) -> Option<usize> {
+        if !self.leaf_positions.is_empty() {
+            return self.leaf_positions.get(leaf_hash).copied();
+        }
+        self.leaves().iter().position(|leaf| leaf == leaf_hash)
+    }
+
+    /// Generate a proof for the first leaf with the given hash
+    pub fn generate_proof_for_hash(&self, leaf_hash: &Hash) -> Result<MerkleProof> {
+        let index = self
+            .position_of(leaf_hash)
+            .ok_or_else(|| MerkleError::InvalidProof {
+                reason: format!("Leaf {} is not in the tree", leaf_hash),
+            })?;
+        self.generate_proof(index)
+    }
+
+    /// Get the hash of the node at `index` within `level`
+    ///
+    /// Level 0 holds the leaves and level `stats().tree_height` the root.
+    /// Padding nodes that duplicate the last node of an odd level are not
+    /// addressable.
+    pub fn node_hash(&self, level: usize, index: usize) -> Result<&Hash> {
+        let nodes = self.level(level)?;
+        nodes.get(index).ok_or(MerkleError::InvalidIndex {
+            index,
+            size: nodes.len(),
+        })
+    }
+
+    /// Get the hashes of every node on `level`, leaves being level 0
+    ///
+    /// This is what a replica serves for top-down sync, see
+    /// [`SyncSession`](crate::sync::SyncSession). Returns an empty list for
+    /// a level above the root.
+    pub fn level_digests(&self, level: usize) -> Vec<Hash> {
+        self.level(level).map(<[Hash]>::to_vec).unwrap_or_default()
+    }
+
+    /// Get the number of nodes on `level`, leaves being level 0
+    pub fn level_len(&self, level: usize) -> Result<usize> {
+        self.level(level).map(<[Hash]>::len)
+    }
+
+    fn level(&self, level: usize) -> Result<&[Hash]> {
+        self.levels
+            .get(level)
+            .map(Vec::as_slice)
             .ok_or(MerkleError::InvalidIndex {
-                index,
-                size: self.leaves.len(),
+                index: level,
+                size: self.levels.len(),
             })
     }
 
     /// Generate a Merkle proof for the leaf at the given index
+    ///
+    /// Takes one sibling lookup per level: the sibling of node `i` is node
+    /// `i ^ 1` and its parent is node `i / 2` of the level above.
     pub fn generate_proof(&self, index: usize) -> Result<MerkleProof> {
-        if index >= self.leaves.len() {
+        let steps = self
+            .proof_siblings(index)?
+            .map(|(hash, direction)| ProofStep {
+                hash: hash.clone(),
+                direction,
+            })
+            .collect();
+
+        Ok(MerkleProof::new(index, steps))
+    }
+
+    /// Get the size and verification cost of the proof for the leaf at the
+    /// given index without generating it
+    ///
+    /// Matches [`MerkleTree::generate_proof`]; verifying hashes the leaf data
+    /// once and every step once.
+    pub fn proof_cost(&self, index: usize) -> Result<ProofCost> {
+        let steps = self.expected_proof_len(index)?;
+        Ok(ProofCost {
+            steps,
+            bytes: steps * self.hasher.output_size(),
+            hashes_to_verify: steps + 1,
+        })
+    }
+
+    /// Generate a proof that only verifies with this tree's hasher type,
+    /// see [`TypedProof`]
+    pub fn generate_typed_proof(&self, index: usize) -> Result<TypedProof<H>> {
+        Ok(self.generate_proof(index)?.assume_hasher())
+    }
+
+    /// Generate a proof that borrows its sibling hashes from the tree
+    pub fn generate_proof_ref(&self, index: usize) -> Result<MerkleProofRef<'_>> {
+        let steps = self
+            .proof_siblings(index)?
+            .map(|(hash, direction)| ProofStepRef { hash, direction })
+            .collect();
+
+        Ok(MerkleProofRef {
+            leaf_index: index,
+            steps,
+        })
+    }
+
+    /// Sibling hashes on the path from a leaf to the root
+    fn proof_siblings(
+        &self,
+        index: usize,
+    ) -> Result<impl Iterator<Item = (&Hash, ProofDirection)> + '_> {
+        if index >= self.len() {
             return Err(MerkleError::InvalidIndex {
                 index,
-                size: self.leaves.len(),
+                size: self.len(),
             });
         }
 
-        let root = self
-            .root
-            .as_ref()
-            .ok_or(MerkleError::TreeConstructionError {
-                reason: "Tree has no root".to_string(),
+        let levels = &self.levels[..self.levels.len() - 1];
+        let shape = self.shape;
+        Ok(levels
+            .iter()
+            .enumerate()
+            .filter_map(move |(height, level)| {
+                let position = index >> height;
+                if position.is_multiple_of(2) {
+                    // The last node of an odd level is paired with itself, or
+                    // promoted without a sibling
+                    let sibling = match (level.get(position + 1), shape) {
+                        (Some(sibling), _) => sibling,
+                        (None, TreeShape::Padded) => &level[position],
+                        (None, TreeShape::Rfc6962) => return None,
+                    };
+                    Some((sibling, ProofDirection::Right))
+                } else {
+                    Some((&level[position - 1], ProofDirection::Left))
+                }
+            }))
+    }
+
+    /// Get the salt mixed into the leaf at `index`, if the tree is salted
+    pub fn salt(&self, index: usize) -> Option<&[u8]> {
+        self.salts.get(index).map(Vec::as_slice)
+    }
+
+    /// Generate a proof for a salted leaf together with its salt
+    pub fn generate_salted_proof(&self, index: usize) -> Result<SaltedProof> {
+        let proof = self.generate_proof(index)?;
+        let salt = self.salt(index).ok_or(MerkleError::InvalidProof {
+            reason: "Tree was built without salts".to_string(),
+        })?;
+
+        Ok(SaltedProof::new(proof, salt.to_vec()))
+    }
+
+    /// Attach `annotation` to the leaf at `index`, replacing any previous one
+    ///
+    /// Annotations are small caller metadata, such as a database row ID,
+    /// kept next to the leaf and handed out with
+    /// [`MerkleTree::generate_annotated_proof`]. They are not hashed, so the
+    /// root does not change and proofs do not authenticate them. Serde keeps
+    /// them; [`MerkleTree::serialize_into`] does not.
+    pub fn annotate(&mut self, index: usize, annotation: Vec<u8>) -> Result<()> {
+        if index >= self.len() {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len(),
+            });
+        }
+        self.annotations.insert(index, annotation);
+        Ok(())
+    }
+
+    /// Get the annotation of the leaf at `index`
+    pub fn annotation(&self, index: usize) -> Option<&[u8]> {
+        self.annotations.get(&index).map(Vec::as_slice)
+    }
+
+    /// Remove the annotation of the leaf at `index`, returning it
+    pub fn remove_annotation(&mut self, index: usize) -> Option<Vec<u8>> {
+        self.annotations.remove(&index)
+    }
+
+    /// Generate a proof for the leaf at `index` together with its annotation
+    pub fn generate_annotated_proof(&self, index: usize) -> Result<AnnotatedProof> {
+        let proof = self.generate_proof(index)?;
+        let annotation = self.annotation(index).map(<[u8]>::to_vec);
+        Ok(AnnotatedProof::new(proof, annotation))
+    }
+
+    /// Attach the annotations of another tree over the same leaves
+    pub(crate) fn with_annotations(mut self, annotations: BTreeMap<usize, Vec<u8>>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Record how the builder encoded data items as leaves
+    pub(crate) fn with_leaf_encoding(mut self, leaf_encoding: LeafEncoding) -> Self {
+        self.leaf_encoding = leaf_encoding;
+        self
+    }
+
+    /// Get how data items are encoded as leaves
+    ///
+    /// This is [`LeafEncoding::PreHash`] unless the tree was built with
+    /// [`MerkleTreeBuilder::leaf_encoding`](crate::builder::MerkleTreeBuilder::leaf_encoding).
+    /// The encoding is not serialized, so deserialized trees report
+    /// `PreHash` again.
+    pub fn leaf_encoding(&self) -> &LeafEncoding {
+        &self.leaf_encoding
+    }
+
+    /// Encode an unsalted data item as a leaf of this tree
+    ///
+    /// Pass the result to [`MerkleTree::generate_proof_for_hash`] to prove
+    /// a data item by value.
+    pub fn encode_leaf(&self, data: &[u8]) -> Hash {
+        self.leaf_encoding.encode(&self.hasher, data)
+    }
+
+    /// Attach the salts used to derive the leaves
+    pub(crate) fn with_salts(mut self, salts: Vec<Vec<u8>>) -> Self {
+        self.salts = salts;
+        self
+    }
+
+    /// Get the annotations of all annotated leaves
+    pub(crate) fn annotations(&self) -> &BTreeMap<usize, Vec<u8>> {
+        &self.annotations
+    }
+
+    /// Get the salts of all leaves, empty if the tree is unsalted
+    pub(crate) fn salts(&self) -> &[Vec<u8>] {
+        &self.salts
+    }
+
+    /// Reassemble a tree from already computed level hashes, leaves first,
+    /// in the layout returned by `levels`
+    ///
+    /// No hashing is done; use [`MerkleTree::verify_integrity`] to check that
+    /// the hashes are consistent.
+    pub(crate) fn from_levels(
+        levels: Vec<Vec<Hash>>,
+        hasher: H,
+        shape: TreeShape,
+        domain: NodeDomain,
+        subtrees: Vec<MerkleTree<H>>,
+    ) -> Result<Self> {
+        // An empty tree has no levels at all
+        if levels.is_empty() && subtrees.is_empty() {
+            let mut tree = Self::empty(hasher);
+            tree.shape = shape;
+            tree.domain = domain;
+            return Ok(tree);
+        }
+
+        let leaf_count = levels.first().map_or(0, Vec::len);
+        if leaf_count == 0 {
+            return Err(MerkleError::EmptyData);
+        }
+
+        let lens = Self::level_lens(leaf_count);
+        if levels.len() != lens.len() {
+            return Err(MerkleError::TreeConstructionError {
+                reason: format!(
+                    "Tree has {} levels, expected {}",
+                    levels.len(),
+                    lens.len()
+                ),
+            });
+        }
+        if let Some((level, (hashes, expected))) = levels
+            .iter()
+            .zip(&lens)
+            .enumerate()
+            .find(|(_, (hashes, &expected))| hashes.len() != expected)
+        {
+            return Err(MerkleError::TreeConstructionError {
+                reason: format!(
+                    "Level {} has {} hashes, expected {}",
+                    level,
+                    hashes.len(),
+                    expected
+                ),
+            });
+        }
+
+        Ok(Self {
+            levels,
+            hasher,
+            shape,
+            domain,
+            leaf_encoding: LeafEncoding::PreHash,
+            subtrees,
+            salts: Vec::new(),
+            annotations: BTreeMap::new(),
+            leaf_positions: HashMap::new(),
+            packed_leaves: None,
+        })
+    }
+
+    /// Generate a proof from a leaf of a child tree up to this tree's root
+    ///
+    /// The returned proof first walks from the leaf to the child root and then
+    /// from the child root to the parent root, so it verifies directly against
+    /// [`MerkleTree::root`]. Its `leaf_index` is the leaf's global position,
+    /// see [`MerkleProof::chain`], which fails for children whose proofs have
+    /// promoted RFC 6962 steps.
+    ///
+    /// The path is longer than this tree's own proofs, so
+    /// [`MerkleTree::verify_proof`] rejects it. Verify it with
+    /// [`MerkleProof::verify`], or with [`MerkleProof::verify_indexed`] when
+    /// all children have the same height.
+    pub fn generate_nested_proof(
+        &self,
+        child_index: usize,
+        leaf_index: usize,
+    ) -> Result<MerkleProof> {
+        let child = self
+            .subtrees
+            .get(child_index)
+            .ok_or(MerkleError::InvalidIndex {
+                index: child_index,
+                size: self.subtrees.len(),
             })?;
 
-        let mut steps = Vec::new();
-        self.collect_proof_steps(root, index, 0, self.leaves.len(), &mut steps)?;
+        let inner = child.generate_proof(leaf_index)?;
+        let outer = self.generate_proof(child_index)?;
+        inner.chain(outer)
+    }
 
-        // Reverse the steps since we collected them from root to leaf,
-        // but verification needs them from leaf to root
-        steps.reverse();
+    /// Generate a multiproof covering the leaves at the given indices
+    ///
+    /// See [`MultiProof`] for the encoding; the tree must use a commutative
+    /// pair hash for the result to verify.
+    pub fn generate_multiproof(&self, indices: &[usize]) -> Result<MultiProof> {
+        let mut known = indices.to_vec();
+        known.sort_unstable();
 
-        Ok(MerkleProof::new(index, steps))
+        if let Some(&index) = known.iter().find(|&&index| index >= self.len()) {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len(),
+            });
+        }
+        if known.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(MerkleError::InvalidProof {
+                reason: "Cannot prove a duplicated index".to_string(),
+            });
+        }
+        if known.is_empty() {
+            return Ok(MultiProof::new(
+                Vec::new(),
+                vec![self.root().into_hash()],
+                Vec::new(),
+            ));
+        }
+
+        let levels = self.levels();
+        let leaves = known
+            .iter()
+            .map(|&index| self.leaves()[index].clone())
+            .collect();
+        let mut proof = Vec::new();
+        let mut proof_flags = Vec::new();
+
+        for nodes in &levels[..levels.len() - 1] {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut i = 0;
+
+            while i < known.len() {
+                let index = known[i];
+                if index.is_multiple_of(2) && known.get(i + 1) == Some(&(index + 1)) {
+                    proof_flags.push(true);
+                    i += 2;
+                } else {
+                    // A missing right sibling means the node was paired with itself
+                    let sibling = match (nodes.get(index ^ 1), self.shape) {
+                        (Some(sibling), _) => sibling,
+                        (None, TreeShape::Padded) => &nodes[index],
+                        (None, TreeShape::Rfc6962) => {
+                            return Err(MerkleError::InvalidProof {
+                                reason: "Multiproofs cannot skip a promoted node".to_string(),
+                            })
+                        }
+                    };
+                    proof.push(sibling.clone());
+                    proof_flags.push(false);
+                    i += 1;
+                }
+                parents.push(index / 2);
+            }
+
+            known = parents;
+        }
+
+        Ok(MultiProof::new(leaves, proof, proof_flags))
+    }
+
+    /// Get the size and verification cost of the multiproof for the given
+    /// indices without generating it
+    ///
+    /// Fails like [`MerkleTree::generate_multiproof`]. The proof carries the
+    /// leaf hashes and the siblings; verifying computes one hash per flag.
+    pub fn multiproof_cost(&self, indices: &[usize]) -> Result<ProofCost> {
+        let mut known = indices.to_vec();
+        known.sort_unstable();
+        if let Some(&index) = known.iter().find(|&&index| index >= self.len()) {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len(),
+            });
+        }
+        if known.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(MerkleError::InvalidProof {
+                reason: "Cannot prove a duplicated index".to_string(),
+            });
+        }
+        let hash_size = self.hasher.output_size();
+        if known.is_empty() {
+            return Ok(ProofCost {
+                steps: 0,
+                bytes: hash_size,
+                hashes_to_verify: 0,
+            });
+        }
+
+        let (mut siblings, mut flags) = (0, 0);
+        let mut width = self.len();
+        while width > 1 {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut i = 0;
+            while i < known.len() {
+                let index = known[i];
+                if index.is_multiple_of(2) && known.get(i + 1) == Some(&(index + 1)) {
+                    i += 2;
+                } else if (index ^ 1) < width || self.shape == TreeShape::Padded {
+                    siblings += 1;
+                    i += 1;
+                } else {
+                    return Err(MerkleError::InvalidProof {
+                        reason: "Multiproofs cannot skip a promoted node".to_string(),
+                    });
+                }
+                flags += 1;
+                parents.push(index / 2);
+            }
+            known = parents;
+            width = width.div_ceil(2);
+        }
+
+        Ok(ProofCost {
+            steps: flags,
+            bytes: (indices.len() + siblings) * hash_size,
+            hashes_to_verify: flags,
+        })
+    }
+
+    /// Check that a proof fits this tree's size and shape
+    ///
+    /// Fails with [`MerkleError::InvalidIndex`] if `leaf_index` is out of
+    /// range and with [`MerkleError::InvalidProof`] if the steps do not
+    /// follow the path from that leaf to the root.
+    pub fn check_proof(&self, proof: &MerkleProof) -> Result<()> {
+        proof.check_position(self.len(), self.shape)
     }
 
     /// Verify a Merkle proof for the given leaf data
-    pub fn verify_proof(&self, proof: &MerkleProof, leaf_data: &[u8], root: &[u8]) -> bool {
-        proof.verify(&self.hasher, leaf_data, root)
+    ///
+    /// The data is encoded with the tree's [`LeafEncoding`]. Proofs that do
+    /// not fit this tree are rejected before any hashing; use
+    /// [`MerkleTree::try_verify_proof`] to learn why.
+    pub fn verify_proof(&self, proof: &MerkleProof, leaf_data: &[u8], root: &RootHash) -> bool {
+        self.try_verify_proof(proof, leaf_data, root)
+            .unwrap_or(false)
+    }
+
+    /// Verify a Merkle proof for the given leaf data, reporting proofs that
+    /// do not fit this tree
+    ///
+    /// Fails with the error of [`MerkleTree::check_proof`] if the proof's
+    /// index or steps do not match this tree, and otherwise returns whether
+    /// the proof leads to `root`.
+    pub fn try_verify_proof(
+        &self,
+        proof: &MerkleProof,
+        leaf_data: &[u8],
+        root: &RootHash,
+    ) -> Result<bool> {
+        self.check_proof(proof)?;
+        let leaf_hash = self.encode_leaf(leaf_data);
+        Ok(match self.domain {
+            NodeDomain::None => proof.verify_with_leaf_hash(&self.hasher, &leaf_hash, root),
+            NodeDomain::Level => proof.verify_leveled_with_leaf_hash(
+                &self.hasher,
+                &leaf_hash,
+                root,
+                self.len(),
+                self.shape,
+            ),
+        })
     }
 
     /// Verify a Merkle proof against this tree's root
     pub fn verify_proof_against_root(&self, proof: &MerkleProof, leaf_data: &[u8]) -> bool {
-        self.verify_proof(proof, leaf_data, self.root())
+        self.verify_proof(proof, leaf_data, &self.root())
     }
 
     /// Get all leaf hashes
-    pub fn leaves(&self) -> &[Vec<u8>] {
-        &self.leaves
+    pub fn leaves(&self) -> &[Hash] {
+        self.levels.first().map_or(&[], Vec::as_slice)
+    }
+
+    /// Get all leaf hashes packed back to back in one buffer
+    ///
+    /// Leaf `i` is at `i * hasher.output_size()`, so the buffer can be handed
+    /// to a GPU, a file or a socket without touching each leaf. It is built
+    /// on the first call and kept until the tree changes, making later calls
+    /// O(1); like [`SparseMerkleTree::root`](crate::SparseMerkleTree::root)
+    /// this takes `&mut self` to fill the cache. Returns `None` if some leaf
+    /// is not a `hasher.output_size()`-byte digest, see
+    /// [`MerkleTree::from_leaves_unchecked`].
+    pub fn packed_leaves(&mut self) -> Option<&[u8]> {
+        if self.packed_leaves.is_none() {
+            let stride = self.hasher.output_size();
+            if self.leaves().iter().any(|leaf| leaf.len() != stride) {
+                return None;
+            }
+
+            let mut packed = Vec::with_capacity(self.len() * stride);
+            for leaf in self.leaves() {
+                packed.extend_from_slice(leaf);
+            }
+            self.packed_leaves = Some(packed);
+        }
+        self.packed_leaves.as_deref()
+    }
+
+    /// Get all leaf hashes as fixed-size arrays
+    ///
+    /// A view of [`MerkleTree::packed_leaves`]; returns `None` unless `N` is
+    /// the hasher's output size.
+    pub fn leaf_arrays<const N: usize>(&mut self) -> Option<&[[u8; N]]> {
+        if N != self.hasher.output_size() {
+            return None;
+        }
+        self.packed_leaves().map(|packed| packed.as_chunks::<N>().0)
+    }
+
+    /// Get the indices of leaves equal to an earlier leaf
+    pub fn duplicate_leaves(&self) -> Vec<usize> {
+        let mut seen = std::collections::HashSet::new();
+        self.leaves()
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| !seen.insert(*leaf))
+            .map(|(index, _)| index)
+            .collect()
     }
 
     /// Get the hasher used by this tree
@@ -148,92 +1087,155 @@ impl<H: Hasher> MerkleTree<H> {
         &self.hasher
     }
 
-    /// Build the tree from leaf hashes
-    fn build_tree(leaves: &[Vec<u8>], hasher: &H) -> Result<MerkleNode> {
-        if leaves.is_empty() {
-            return Err(MerkleError::EmptyData);
-        }
+    /// Get the shape the tree was built with
+    pub fn shape(&self) -> TreeShape {
+        self.shape
+    }
 
-        if leaves.len() == 1 {
-            return Ok(MerkleNode::new_leaf(leaves[0].clone()));
-        }
+    /// Get what internal node hashes commit to besides their children
+    pub fn node_domain(&self) -> NodeDomain {
+        self.domain
+    }
 
-        let mut current_level: Vec<MerkleNode> = leaves
-            .iter()
-            .map(|leaf| MerkleNode::new_leaf(leaf.clone()))
-            .collect();
+    /// Get the child trees this tree was built from, if any
+    pub fn subtrees(&self) -> &[MerkleTree<H>] {
+        &self.subtrees
+    }
 
-        while current_level.len() > 1 {
-            let mut next_level = Vec::new();
+    /// Hash every level of the tree from its leaves up to the root
+    ///
+    /// Each parent depends only on its two children, so hashing the pairs of
+    /// a level on several threads gives the same levels as hashing them in
+    /// order.
+    fn build_levels(
+        leaves: Vec<Hash>,
+        hasher: &H,
+        shape: TreeShape,
+        domain: NodeDomain,
+        mode: ConstructionMode,
+    ) -> Vec<Vec<Hash>> {
+        let mut levels = vec![leaves];
+
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let height = levels.len();
+            let parent = |pair: &[Hash]| match pair {
+                [left, right] => domain.hash_node(hasher, height, left, right),
+                // Odd number of nodes - duplicate or promote the last one
+                [last] => match shape {
+                    TreeShape::Padded => domain.hash_node(hasher, height, last, last),
+                    TreeShape::Rfc6962 => last.clone(),
+                },
+                _ => unreachable!(),
+            };
 
-            for chunk in current_level.chunks(2) {
-                if chunk.len() == 2 {
-                    let left = chunk[0].clone();
-                    let right = chunk[1].clone();
-                    let combined_hash = hasher.hash_pair(&left.hash, &right.hash);
-                    next_level.push(MerkleNode::new_internal(combined_hash, left, right));
-                } else {
-                    // Odd number of nodes - duplicate the last one
-                    let node = chunk[0].clone();
-                    let duplicated_hash = hasher.hash_pair(&node.hash, &node.hash);
-                    next_level.push(MerkleNode::new_internal(
-                        duplicated_hash,
-                        node.clone(),
-                        node,
-                    ));
+            let parents = match mode {
+                #[cfg(feature = "rayon")]
+                ConstructionMode::Parallel => {
+                    level.par_chunks(2).with_min_len(1024).map(parent).collect()
                 }
+                _ => level.chunks(2).map(parent).collect(),
+            };
+            levels.push(parents);
+        }
+
+        levels
+    }
+
+    /// Check that every stored node hash matches the hashes of its children
+    ///
+    /// Walks the tree depth-first, left before right, and reports the first
+    /// node that does not match as a path from the root such as `root/L/R`.
+    /// Child trees from [`MerkleTree::from_subtrees`] are audited recursively,
+    /// so a tree loaded from untrusted storage can be validated before
+    /// generating proofs from it.
+    pub fn verify_integrity(&self) -> Result<()> {
+        let corrupt = |path: &str, reason: &str| MerkleError::IntegrityError {
+            path: path.to_string(),
+            reason: reason.to_string(),
+        };
+
+        if !self.levels.is_empty() {
+            if self.is_empty() {
+                return Err(corrupt("root", "tree has a root but no leaves"));
+            }
+
+            let lens = Self::level_lens(self.len());
+            if self.levels.len() != lens.len() {
+                return Err(corrupt("root", "level count does not match leaf count"));
+            }
+            if let Some(level) = (0..lens.len()).find(|&i| self.levels[i].len() != lens[i]) {
+                return Err(corrupt(
+                    "root",
+                    &format!("level {} has the wrong number of nodes", level),
+                ));
             }
 
-            current_level = next_level;
+            let mut path = String::from("root");
+            self.check_node(self.height(), 0, &mut path)?;
         }
 
-        Ok(current_level.into_iter().next().unwrap())
+        if self.subtrees.is_empty() {
+            return Ok(());
+        }
+        if self.subtrees.len() != self.len() {
+            return Err(corrupt("root", "subtree count does not match leaf count"));
+        }
+        for (i, (subtree, leaf)) in self.subtrees.iter().zip(self.leaves()).enumerate() {
+            let prefix = format!("subtree[{}]", i);
+            if subtree.root().as_hash() != leaf {
+                return Err(corrupt(&prefix, "subtree root does not match its leaf"));
+            }
+            subtree.verify_integrity().map_err(|e| match e {
+                MerkleError::IntegrityError { path, reason } => MerkleError::IntegrityError {
+                    path: format!("{}/{}", prefix, path),
+                    reason,
+                },
+                other => other,
+            })?;
+        }
+
+        Ok(())
     }
 
-    /// Collect proof steps by traversing the tree
-    fn collect_proof_steps(
-        &self,
-        node: &MerkleNode,
-        target_index: usize,
-        start_index: usize,
-        range_size: usize,
-        steps: &mut Vec<ProofStep>,
-    ) -> Result<()> {
-        if node.is_leaf() {
+    /// Recursively check the node at `index` within `level`
+    fn check_node(&self, level: usize, index: usize, path: &mut String) -> Result<()> {
+        if level == 0 {
             return Ok(());
         }
 
-        let left_node = node.left.as_ref().unwrap();
-        let right_node = node.right.as_ref().unwrap();
-
-        let mid = start_index + (range_size + 1) / 2;
+        let children = &self.levels[level - 1];
+        let left = 2 * index;
+        let right = left + 1;
+        // An odd level pads its last node by pairing it with itself, or
+        // promotes it unchanged
+        let expected = match (children.get(right), self.shape) {
+            (Some(right_hash), _) => {
+                self.domain
+                    .hash_node(&self.hasher, level, &children[left], right_hash)
+            }
+            (None, TreeShape::Padded) => {
+                self.domain
+                    .hash_node(&self.hasher, level, &children[left], &children[left])
+            }
+            (None, TreeShape::Rfc6962) => children[left].clone(),
+        };
 
-        if target_index < mid {
-            // Target is in left subtree, add right sibling to proof
-            steps.push(ProofStep {
-                hash: right_node.hash.clone(),
-                direction: ProofDirection::Right,
-            });
-            self.collect_proof_steps(
-                left_node,
-                target_index,
-                start_index,
-                mid - start_index,
-                steps,
-            )?;
-        } else {
-            // Target is in right subtree, add left sibling to proof
-            steps.push(ProofStep {
-                hash: left_node.hash.clone(),
-                direction: ProofDirection::Left,
+        if self.levels[level][index] != expected {
+            return Err(MerkleError::IntegrityError {
+                path: path.clone(),
+                reason: "hash does not match its children".to_string(),
             });
-            self.collect_proof_steps(
-                right_node,
-                target_index,
-                mid,
-                range_size - (mid - start_index),
-                steps,
-            )?;
+        }
+
+        let len = path.len();
+        path.push_str("/L");
+        self.check_node(level - 1, left, path)?;
+        path.truncate(len);
+
+        if right < children.len() {
+            path.push_str("/R");
+            self.check_node(level - 1, right, path)?;
+            path.truncate(len);
         }
 
         Ok(())
@@ -241,29 +1243,96 @@ impl<H: Hasher> MerkleTree<H> {
 
     /// Get tree statistics for debugging
     pub fn stats(&self) -> TreeStats {
+        let level_sizes: Vec<usize> = self.levels.iter().map(Vec::len).collect();
+        // Every level below the root with an odd number of nodes pads or
+        // promotes its last node
+        let odd_levels = level_sizes
+            .iter()
+            .rev()
+            .skip(1)
+            .filter(|&&len| len % 2 == 1)
+            .count();
+        let (padding_nodes, promoted_nodes) = match self.shape {
+            TreeShape::Padded => (odd_levels, 0),
+            TreeShape::Rfc6962 => (0, odd_levels),
+        };
+
         TreeStats {
-            leaf_count: self.leaves.len(),
-            tree_height: self.calculate_height(),
+            leaf_count: self.len(),
+            tree_height: self.height(),
             hasher_name: self.hasher.name().to_string(),
-            root_hash: hex::encode(self.root()),
+            root_hash: self.root().to_hex(),
+            level_sizes,
+            padding_nodes,
+            promoted_nodes,
         }
     }
 
-    /// Calculate the height of the tree
-    fn calculate_height(&self) -> usize {
-        if self.leaves.is_empty() {
-            return 0;
+    /// Number of nodes on each level of a tree over `leaf_count` leaves
+    pub(crate) fn level_lens(leaf_count: usize) -> Vec<usize> {
+        let mut lens = vec![leaf_count];
+        let mut len = leaf_count;
+
+        while len > 1 {
+            len = len.div_ceil(2);
+            lens.push(len);
         }
 
-        let mut height = 0;
-        let mut nodes = self.leaves.len();
+        lens
+    }
+
+    /// Get the hashes of every level of the tree, leaves first
+    pub(crate) fn levels(&self) -> &[Vec<Hash>] {
+        &self.levels
+    }
+}
 
-        while nodes > 1 {
-            nodes = (nodes + 1) / 2;
-            height += 1;
-        }
+/// Trees are equal when they use the same hash function and have the same
+/// leaf count and root
+///
+/// Nothing else is compared: trees that differ only in their subtrees, salts
+/// or annotations compare equal. The leaf count is part of the comparison
+/// because duplicating the last leaf of an odd level gives e.g. `[a, b, c]`
+/// and `[a, b, c, c]` the same root.
+impl<H: Hasher> PartialEq for MerkleTree<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hasher.name() == other.hasher.name()
+            && self.len() == other.len()
+            && self.root() == other.root()
+    }
+}
+
+impl<H: Hasher> Eq for MerkleTree<H> {}
 
-        height
+impl<H: Hasher> std::hash::Hash for MerkleTree<H> {
+    fn hash<S: std::hash::Hasher>(&self, state: &mut S) {
+        self.hasher.name().hash(state);
+        self.len().hash(state);
+        self.root().hash(state);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<H: Hasher> MerkleTree<H> {
+    /// Wipe all leaf and node hashes held by the tree
+    ///
+    /// The levels keep their shape with every hash emptied, so a scrubbed
+    /// tree reports an empty root rather than the root of an empty tree.
+    fn scrub(&mut self) {
+        // Release the shared leaf bytes held by the index so they can be wiped
+        self.leaf_positions.clear();
+        self.packed_leaves.zeroize();
+        self.levels.iter_mut().flatten().for_each(Zeroize::zeroize);
+        self.salts.zeroize();
+        self.annotations.values_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<H: Hasher> Drop for MerkleTree<H> {
+    fn drop(&mut self) {
+        // Child trees scrub themselves when they are dropped
+        self.scrub();
     }
 }
 
@@ -275,11 +1344,29 @@ pub struct TreeStats {
     pub tree_height: usize,
     pub hasher_name: String,
     pub root_hash: String,
+    /// Number of stored nodes on each level, leaves first and the root last
+    pub level_sizes: Vec<usize>,
+    /// Nodes paired with themselves to complete an odd level
+    pub padding_nodes: usize,
+    /// Nodes moved up unchanged from an odd level, in RFC 6962 shaped trees
+    pub promoted_nodes: usize,
+}
+
+impl TreeStats {
+    /// Get the share of the leaf slots of a complete tree of this height
+    /// that hold a leaf
+    pub fn fill_factor(&self) -> f64 {
+        if self.leaf_count == 0 {
+            return 0.0;
+        }
+        self.leaf_count as f64 / 2f64.powi(self.tree_height as i32)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::builder::{MerkleTreeBuilder, Salting};
     use crate::hasher::{Blake3Hasher, Hasher, Sha256Hasher, Sha3Hasher};
 
     #[test]
@@ -288,7 +1375,7 @@ mod tests {
         let tree = MerkleTree::new(data, Sha256Hasher::new()).unwrap();
         assert_eq!(tree.len(), 1);
         assert!(!tree.is_empty());
-        assert!(tree.root().len() > 0);
+        assert!(!tree.root().is_empty());
     }
 
     #[test]
@@ -393,7 +1480,33 @@ mod tests {
         assert_eq!(tree.len(), 3);
 
         let proof = tree.generate_proof(1).unwrap();
-        assert!(proof.verify_with_leaf_hash(tree.hasher(), tree.get_leaf(1).unwrap(), tree.root()));
+        assert!(proof.verify_with_leaf_hash(
+            tree.hasher(),
+            tree.get_leaf(1).unwrap(),
+            &tree.root()
+        ));
+    }
+
+    #[test]
+    fn test_from_leaves_size_mismatch() {
+        let hasher = Sha256Hasher::new();
+        let leaves = vec![hasher.hash(b"a"), Hash::from([1u8; 20]), hasher.hash(b"c")];
+
+        assert_eq!(
+            MerkleTree::from_leaves(leaves.clone(), hasher.clone()).unwrap_err(),
+            MerkleError::HashSizeMismatch {
+                index: 1,
+                expected: 32,
+                actual: 20
+            }
+        );
+
+        let tree = MerkleTree::from_leaves_unchecked(leaves, hasher).unwrap();
+        assert_eq!(tree.len(), 3);
+        assert!(matches!(
+            MerkleTree::from_leaves_unchecked(Vec::new(), Sha256Hasher::new()),
+            Err(MerkleError::EmptyData)
+        ));
     }
 
     #[test]
@@ -411,6 +1524,74 @@ mod tests {
         assert_eq!(stats.tree_height, 2);
         assert_eq!(stats.hasher_name, "SHA-256");
         assert!(!stats.root_hash.is_empty());
+        assert_eq!(stats.level_sizes, vec![4, 2, 1]);
+        assert_eq!((stats.padding_nodes, stats.promoted_nodes), (0, 0));
+        assert_eq!(stats.fill_factor(), 1.0);
+
+        let data = vec!["a", "b", "c", "d", "e"];
+        let stats = MerkleTree::new(data.clone(), Sha256Hasher::new())
+            .unwrap()
+            .stats();
+        assert_eq!(stats.level_sizes, vec![5, 3, 2, 1]);
+        assert_eq!((stats.padding_nodes, stats.promoted_nodes), (2, 0));
+        assert_eq!(stats.fill_factor(), 5.0 / 8.0);
+
+        let stats = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .shape(TreeShape::Rfc6962)
+            .build(data)
+            .unwrap()
+            .stats();
+        assert_eq!((stats.padding_nodes, stats.promoted_nodes), (0, 2));
+        assert_eq!(
+            MerkleTree::empty(Sha256Hasher::new()).stats().fill_factor(),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_height_and_proof_len() {
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            for len in 1..=17 {
+                let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+                let tree = MerkleTreeBuilder::new(Sha256Hasher::new())
+                    .shape(shape)
+                    .build(data)
+                    .unwrap();
+                assert_eq!(tree.height(), tree.stats().tree_height);
+                for index in 0..len {
+                    assert_eq!(
+                        tree.expected_proof_len(index).unwrap(),
+                        tree.generate_proof(index).unwrap().len()
+                    );
+                }
+                assert!(tree.expected_proof_len(len).is_err());
+
+                for index in 0..len {
+                    let cost = tree.proof_cost(index).unwrap();
+                    let proof = tree.generate_proof(index).unwrap();
+                    assert_eq!(cost.steps, proof.len());
+                    assert_eq!(cost.bytes, proof.len() * 32);
+                    assert_eq!(cost.hashes_to_verify, proof.len() + 1);
+                }
+                assert!(tree.proof_cost(len).is_err());
+                assert!(tree.multiproof_cost(&[len - 1, len - 1]).is_err());
+                let indices: Vec<usize> = (0..len).step_by(3).collect();
+                match (
+                    tree.multiproof_cost(&indices),
+                    tree.generate_multiproof(&indices),
+                ) {
+                    (Ok(cost), Ok(multiproof)) => {
+                        assert_eq!(cost.steps, multiproof.proof_flags.len());
+                        assert_eq!(cost.hashes_to_verify, multiproof.proof_flags.len());
+                        let carried = multiproof.leaves.len() + multiproof.proof.len();
+                        assert_eq!(cost.bytes, carried * 32);
+                    }
+                    (Err(_), Err(_)) => {}
+                    (cost, multiproof) => panic!("{:?} vs {:?}", cost, multiproof),
+                }
+            }
+        }
+        assert_eq!(MerkleTree::empty(Sha256Hasher::new()).height(), 0);
     }
 
     #[test]
@@ -430,6 +1611,606 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_proof_per_level() {
+        for len in 1..=17 {
+            let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+            let tree = MerkleTree::new(data.clone(), Sha256Hasher::new()).unwrap();
+            let height = tree.stats().tree_height;
+
+            for (index, item) in data.iter().enumerate() {
+                let proof = tree.generate_proof(index).unwrap();
+                assert_eq!(proof.len(), height);
+                assert!(tree.verify_proof_against_root(&proof, item.as_bytes()));
+            }
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_new() {
+        for len in [1, 2, 5, 3000] {
+            let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+            let parallel = MerkleTree::par_new(data.clone(), Sha256Hasher::new()).unwrap();
+            let sequential = MerkleTree::new(data, Sha256Hasher::new()).unwrap();
+            assert_eq!(parallel.levels(), sequential.levels());
+        }
+
+        let empty: Vec<&[u8]> = Vec::new();
+        assert!(matches!(
+            MerkleTree::par_new(empty, Sha256Hasher::new()),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_checks_hashes() {
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let json = serde_json::to_value(&tree).unwrap();
+        let restored: MerkleTree<Sha256Hasher> = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(restored.root(), tree.root());
+
+        let parse = |value: serde_json::Value| {
+            serde_json::from_value::<MerkleTree<Sha256Hasher>>(value).unwrap_err()
+        };
+
+        // A swapped leaf no longer matches the stored parents
+        let mut tampered = json.clone();
+        tampered["levels"][0][0] = json["levels"][0][1].clone();
+        assert!(parse(tampered).to_string().contains("root/L"));
+
+        // A forged root cannot be paired with honest leaves
+        let mut tampered = json.clone();
+        tampered["levels"][2][0] = json["levels"][1][0].clone();
+        assert!(parse(tampered).to_string().contains("root"));
+
+        let mut tampered = json.clone();
+        tampered["levels"].as_array_mut().unwrap().pop();
+        assert!(parse(tampered).to_string().contains("levels"));
+
+        let mut tampered = json.clone();
+        tampered["salts"] = serde_json::json!([[1]]);
+        assert!(parse(tampered).to_string().contains("salt count"));
+
+        let mut tampered = json;
+        tampered["annotations"] = serde_json::json!({ "3": [1] });
+        assert!(parse(tampered).to_string().contains("annotation"));
+    }
+
+    #[test]
+    fn test_annotations() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+        let root = tree.root();
+        tree.annotate(1, b"row 42".to_vec()).unwrap();
+        assert!(tree.annotate(3, b"row 43".to_vec()).is_err());
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.annotation(1), Some(b"row 42".as_slice()));
+        assert_eq!(tree.annotation(0), None);
+
+        let proof = tree.generate_annotated_proof(1).unwrap();
+        assert_eq!(proof.annotation.as_deref(), Some(b"row 42".as_slice()));
+        assert_eq!(proof.proof, tree.generate_proof(1).unwrap());
+        assert!(proof.verify(&hasher, b"b", &root));
+        assert_eq!(tree.generate_annotated_proof(2).unwrap().annotation, None);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&tree).unwrap();
+            let restored: MerkleTree<Sha256Hasher> = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored.annotation(1), Some(b"row 42".as_slice()));
+        }
+
+        assert_eq!(tree.remove_annotation(1), Some(b"row 42".to_vec()));
+        assert_eq!(tree.annotation(1), None);
+    }
+
+    #[test]
+    fn test_generate_proof_for_hash() {
+        let hasher = Sha256Hasher::new();
+        let leaves: Vec<Hash> = ["a", "b", "c", "b"]
+            .iter()
+            .map(|item| hasher.hash(item.as_bytes()))
+            .collect();
+        let tree = MerkleTree::from_leaves(leaves.clone(), hasher.clone()).unwrap();
+        let indexed = tree.clone().with_leaf_index();
+
+        for tree in [&tree, &indexed] {
+            assert_eq!(tree.position_of(&leaves[2]), Some(2));
+            // Repeated leaves resolve to their first position
+            assert_eq!(tree.position_of(&leaves[3]), Some(1));
+
+            let proof = tree.generate_proof_for_hash(&leaves[2]).unwrap();
+            assert_eq!(proof, tree.generate_proof(2).unwrap());
+            assert!(proof.verify_with_leaf_hash(&hasher, &leaves[2], &tree.root()));
+
+            let missing = hasher.hash(b"z");
+            assert_eq!(tree.position_of(&missing), None);
+            assert!(matches!(
+                tree.generate_proof_for_hash(&missing),
+                Err(MerkleError::InvalidProof { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_verify_proof_checks_shape() {
+        let data: Vec<String> = (0..6).map(|i| format!("item_{}", i)).collect();
+        let tree = MerkleTree::new(data, Sha256Hasher::new()).unwrap();
+        let proof = tree.generate_proof(4).unwrap();
+        assert!(tree.check_proof(&proof).is_ok());
+
+        let mut moved = proof.clone();
+        moved.leaf_index = 6;
+        assert!(matches!(
+            tree.check_proof(&moved),
+            Err(MerkleError::InvalidIndex { index: 6, size: 6 })
+        ));
+        assert!(!tree.verify_proof_against_root(&moved, b"item_4"));
+
+        let mut short = proof.clone();
+        short.steps.pop();
+        assert!(matches!(
+            tree.check_proof(&short),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+        assert!(!tree.verify_proof_against_root(&short, b"item_4"));
+
+        let mut relabeled = proof.clone();
+        relabeled.leaf_index = 5;
+        assert!(relabeled.verify(tree.hasher(), b"item_4", &tree.root()));
+        assert!(!tree.verify_proof_against_root(&relabeled, b"item_4"));
+
+        let root = tree.root();
+        assert_eq!(tree.try_verify_proof(&proof, b"item_4", &root), Ok(true));
+        assert_eq!(tree.try_verify_proof(&proof, b"item_5", &root), Ok(false));
+        assert!(matches!(
+            tree.try_verify_proof(&moved, b"item_4", &root),
+            Err(MerkleError::InvalidIndex { index: 6, size: 6 })
+        ));
+        assert!(matches!(
+            tree.try_verify_proof(&short, b"item_4", &root),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_subtrees() {
+        let days: Vec<MerkleTree<Sha256Hasher>> = (0..3)
+            .map(|day| {
+                let data: Vec<Vec<u8>> = (0..5)
+                    .map(|i| format!("day_{}_event_{}", day, i).into_bytes())
+                    .collect();
+                MerkleTree::new(data, Sha256Hasher::new()).unwrap()
+            })
+            .collect();
+        let day_roots: Vec<Hash> = days.iter().map(|tree| tree.root().into_hash()).collect();
+
+        let month = MerkleTree::from_subtrees(days).unwrap();
+        assert_eq!(month.len(), 3);
+        assert_eq!(month.subtrees().len(), 3);
+        assert_eq!(month.leaves(), day_roots.as_slice());
+
+        for day in 0..3 {
+            for i in 0..5 {
+                let proof = month.generate_nested_proof(day, i).unwrap();
+                let leaf_data = format!("day_{}_event_{}", day, i).into_bytes();
+                assert_eq!(proof.leaf_index, day * 8 + i);
+                assert!(proof.verify(month.hasher(), &leaf_data, &month.root()));
+                assert!(proof.verify_indexed(
+                    month.hasher(),
+                    &leaf_data,
+                    &month.root(),
+                    3 * 8,
+                    TreeShape::Padded
+                ));
+                assert!(!proof.verify(month.hasher(), b"wrong", &month.root()));
+                // The path runs through a day tree, so it is no proof of a
+                // leaf of the month tree itself
+                assert!(month.check_proof(&proof).is_err());
+                assert!(!month.verify_proof_against_root(&proof, &leaf_data));
+                assert!(month
+                    .try_verify_proof(&proof, &leaf_data, &month.root())
+                    .is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_nested_proof_errors() {
+        let child = MerkleTree::new(vec![b"a", b"b"], Sha256Hasher::new()).unwrap();
+        let parent = MerkleTree::from_subtrees(vec![child]).unwrap();
+
+        assert!(matches!(
+            parent.generate_nested_proof(1, 0),
+            Err(MerkleError::InvalidIndex { index: 1, size: 1 })
+        ));
+        assert!(matches!(
+            parent.generate_nested_proof(0, 2),
+            Err(MerkleError::InvalidIndex { index: 2, size: 2 })
+        ));
+
+        // Trees not built from subtrees have no children to descend into
+        let flat = MerkleTree::new(vec![b"a", b"b"], Sha256Hasher::new()).unwrap();
+        assert!(flat.generate_nested_proof(0, 0).is_err());
+    }
+
+    #[test]
+    fn test_empty_tree() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = MerkleTree::empty(hasher.clone());
+        assert!(tree.is_empty());
+        assert_eq!(
+            tree.root().to_hex(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        // Leaf prefixes do not apply to the empty root
+        assert_eq!(
+            MerkleTree::empty(crate::hasher::Rfc6962Hasher::new()).root(),
+            tree.root()
+        );
+        assert!(matches!(
+            tree.generate_proof(0),
+            Err(MerkleError::InvalidIndex { index: 0, size: 0 })
+        ));
+        assert!(tree.verify_integrity().is_ok());
+        assert_eq!(tree.stats().tree_height, 0);
+
+        // Pushing from empty gives the same tree as building at once
+        let data: Vec<String> = (0..17).map(|i| format!("item_{}", i)).collect();
+        for (len, item) in data.iter().enumerate() {
+            tree.push(item.as_bytes()).unwrap();
+            let built = MerkleTree::new(data[..=len].to_vec(), hasher.clone()).unwrap();
+            assert_eq!(tree.levels(), built.levels());
+        }
+        assert!(tree.verify_integrity().is_ok());
+    }
+
+    #[test]
+    fn test_push() {
+        let data: Vec<String> = (0..17).map(|i| format!("item_{}", i)).collect();
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            for domain in [NodeDomain::None, NodeDomain::Level] {
+                let builder = MerkleTreeBuilder::new(Sha256Hasher::new())
+                    .shape(shape)
+                    .node_domain(domain);
+                let mut tree = builder.build(data[..1].to_vec()).unwrap();
+                for len in 2..=data.len() {
+                    tree.push(data[len - 1].as_bytes()).unwrap();
+                    let built = builder.build(data[..len].to_vec()).unwrap();
+                    assert_eq!(tree.levels(), built.levels());
+                }
+            }
+        }
+
+        // Pushed leaves are encoded like the built ones
+        for encoding in [LeafEncoding::DoubleHash, LeafEncoding::Identity] {
+            let builder = MerkleTreeBuilder::new(Sha256Hasher::new()).leaf_encoding(encoding);
+            let mut tree = builder.build(data[..3].to_vec()).unwrap();
+            tree.push(data[3].as_bytes()).unwrap();
+            assert_eq!(
+                tree.root(),
+                builder.build(data[..4].to_vec()).unwrap().root()
+            );
+        }
+
+        // The leaf index follows pushed leaves
+        let mut tree = MerkleTree::new(vec!["a", "b"], Sha256Hasher::new())
+            .unwrap()
+            .with_leaf_index();
+        tree.push(b"c").unwrap();
+        assert_eq!(tree.position_of(&Sha256Hasher::new().hash(b"c")), Some(2));
+
+        let mut salted = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .salting(Salting::Derived(b"secret".to_vec()))
+            .build(vec!["a", "b"])
+            .unwrap();
+        assert!(matches!(
+            salted.push(b"c"),
+            Err(MerkleError::UnsupportedOperation { .. })
+        ));
+        let child = MerkleTree::new(vec!["a", "b"], Sha256Hasher::new()).unwrap();
+        let mut parent = MerkleTree::from_subtrees(vec![child]).unwrap();
+        assert!(matches!(
+            parent.push(b"c"),
+            Err(MerkleError::UnsupportedOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_packed_leaves() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+        let packed = tree.packed_leaves().unwrap();
+        assert_eq!(packed.len(), 3 * 32);
+        assert_eq!(&packed[32..64], hasher.hash(b"b").as_bytes());
+
+        let arrays = tree.leaf_arrays::<32>().unwrap();
+        assert_eq!(arrays.len(), 3);
+        assert_eq!(&arrays[2][..], hasher.hash(b"c").as_bytes());
+        assert!(tree.leaf_arrays::<20>().is_none());
+
+        // Pushing rebuilds the buffer on the next call
+        tree.push(b"d").unwrap();
+        assert_eq!(tree.packed_leaves().unwrap().len(), 4 * 32);
+        assert_eq!(
+            MerkleTree::empty(hasher.clone()).packed_leaves(),
+            Some(&[][..])
+        );
+
+        let uneven = vec![hasher.hash(b"a"), Hash::new(vec![1, 2, 3])];
+        let mut tree = MerkleTree::from_leaves_unchecked(uneven, hasher).unwrap();
+        assert!(tree.packed_leaves().is_none());
+    }
+
+    #[test]
+    fn test_from_subtrees_errors() {
+        let empty: Vec<MerkleTree<Sha256Hasher>> = vec![];
+        assert!(matches!(
+            MerkleTree::from_subtrees(empty),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_clone_shares_hashes() {
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let copy = tree.clone();
+        assert_eq!(copy.leaves()[2].as_ptr(), tree.leaves()[2].as_ptr());
+
+        let proof = tree.generate_proof(0).unwrap();
+        assert_eq!(proof.steps[0].hash.as_ptr(), tree.leaves()[1].as_ptr());
+    }
+
+    #[test]
+    fn test_generate_proof_ref() {
+        let tree = MerkleTree::new(vec!["a", "b", "c", "d", "e"], Sha256Hasher::new()).unwrap();
+        for (index, item) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            let borrowed = tree.generate_proof_ref(index).unwrap();
+            assert!(borrowed.verify(tree.hasher(), item.as_bytes(), &tree.root()));
+            assert_eq!(borrowed.to_proof(), tree.generate_proof(index).unwrap());
+        }
+        assert!(matches!(
+            tree.generate_proof_ref(5),
+            Err(MerkleError::InvalidIndex { .. })
+        ));
+    }
+
+    #[test]
+    fn test_node_hash() {
+        let hasher = Sha256Hasher::new();
+        let tree = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+
+        assert_eq!(tree.level_len(0), Ok(3));
+        assert_eq!(tree.level_len(1), Ok(2));
+        assert_eq!(tree.level_len(2), Ok(1));
+        assert!(matches!(
+            tree.level_len(3),
+            Err(MerkleError::InvalidIndex { index: 3, size: 3 })
+        ));
+
+        let leaf = tree.node_hash(0, 2).unwrap();
+        assert_eq!(tree.node_hash(1, 1).unwrap(), &hasher.hash_pair(leaf, leaf));
+        assert_eq!(tree.node_hash(2, 0).unwrap(), tree.root().as_hash());
+        assert!(matches!(
+            tree.node_hash(1, 2),
+            Err(MerkleError::InvalidIndex { index: 2, size: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_equality_by_root() {
+        use crate::hasher::HashAlgorithm;
+        use std::collections::HashSet;
+
+        let hasher = Sha256Hasher::new();
+        let tree = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+        let leaves = vec![hasher.hash(b"a"), hasher.hash(b"b"), hasher.hash(b"c")];
+        let rebuilt = MerkleTree::from_leaves(leaves, hasher.clone()).unwrap();
+        assert_eq!(tree, rebuilt);
+
+        // Same root, different leaf count
+        let padded = MerkleTree::new(vec!["a", "b", "c", "c"], hasher).unwrap();
+        assert_eq!(padded.root(), tree.root());
+        assert_ne!(padded, tree);
+
+        let set: HashSet<_> = [tree.clone(), rebuilt, padded].into_iter().collect();
+        assert_eq!(set.len(), 2);
+
+        let sha256 = MerkleTree::new(vec!["a"], HashAlgorithm::Sha256).unwrap();
+        let mut sha3 = sha256.clone();
+        sha3.hasher = HashAlgorithm::Sha3;
+        assert_ne!(sha256, sha3);
+    }
+
+    #[test]
+    fn test_equality_ignores_metadata() {
+        use crate::builder::{MerkleTreeBuilder, Salting};
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash as _, Hasher as _};
+
+        fn digest(tree: &MerkleTree<Sha256Hasher>) -> u64 {
+            let mut state = DefaultHasher::new();
+            tree.hash(&mut state);
+            state.finish()
+        }
+
+        let hasher = Sha256Hasher::new();
+        let salted = MerkleTreeBuilder::new(hasher.clone())
+            .salting(Salting::Derived(b"secret".to_vec()))
+            .build(vec!["a", "b", "c"])
+            .unwrap();
+        let mut plain = MerkleTree::from_leaves(salted.leaves().to_vec(), hasher.clone()).unwrap();
+        assert!(salted.salt(0).is_some() && plain.salt(0).is_none());
+        plain.annotate(1, b"note".to_vec()).unwrap();
+        assert_eq!(salted, plain);
+        assert_eq!(digest(&salted), digest(&plain));
+
+        let children = vec![
+            MerkleTree::new(vec!["a", "b"], hasher.clone()).unwrap(),
+            MerkleTree::new(vec!["c"], hasher.clone()).unwrap(),
+        ];
+        let roots = children
+            .iter()
+            .map(|child| child.root().as_hash().clone())
+            .collect();
+        let nested = MerkleTree::from_subtrees(children).unwrap();
+        let flat = MerkleTree::from_leaves(roots, hasher).unwrap();
+        assert_eq!(nested, flat);
+        assert_eq!(digest(&nested), digest(&flat));
+    }
+
+    #[test]
+    fn test_into_root() {
+        let tree = MerkleTree::new(vec![b"a", b"b", b"c"], Sha256Hasher::new()).unwrap();
+        let root = tree.root();
+        assert_eq!(tree.into_root(), root);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_scrub_wipes_hashes() {
+        let mut tree = MerkleTree::new(vec![b"a", b"b", b"c"], Sha256Hasher::new()).unwrap();
+        tree.scrub();
+
+        assert_eq!(tree.len(), 3);
+        assert!(tree.root().is_empty());
+        assert_ne!(tree.root(), MerkleTree::empty(Sha256Hasher::new()).root());
+        assert!(tree
+            .levels()
+            .iter()
+            .flatten()
+            .all(|hash| hash.is_empty()));
+    }
+
+    #[test]
+    fn test_rfc6962_shape() {
+        use crate::builder::MerkleTreeBuilder;
+        use crate::log::MerkleLog;
+
+        let hasher = Sha256Hasher::new();
+        let builder = MerkleTreeBuilder::new(hasher.clone()).shape(TreeShape::Rfc6962);
+        let mut log = MerkleLog::new(hasher.clone());
+        for len in 1..=17 {
+            let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+            log.append(data[len - 1].as_bytes()).unwrap();
+
+            let tree = builder.build(data.clone()).unwrap();
+            assert_eq!(tree.shape(), TreeShape::Rfc6962);
+            assert_eq!(tree.root(), log.root());
+            assert_eq!(builder.build_root(&data).unwrap(), log.root());
+            assert_eq!(tree.verify_integrity(), Ok(()));
+
+            for (index, item) in data.iter().enumerate() {
+                let proof = tree.generate_proof(index).unwrap();
+                assert_eq!(proof, log.prove_inclusion(index, len).unwrap());
+                assert!(tree.verify_proof_against_root(&proof, item.as_bytes()));
+            }
+
+            // Only sizes that are not a power of two differ from the padded shape
+            let padded = MerkleTree::new(data, hasher.clone()).unwrap();
+            assert_eq!(padded.root() == tree.root(), len.is_power_of_two());
+        }
+
+        let tree = builder.build(vec!["a", "b", "c"]).unwrap();
+        assert!(tree.generate_multiproof(&[0, 1]).is_ok());
+        assert!(tree.generate_multiproof(&[2]).is_err());
+    }
+
+    #[test]
+    fn test_rfc6962_shape_golden_roots() {
+        use crate::builder::MerkleTreeBuilder;
+        use crate::hasher::Rfc6962Hasher;
+
+        // Test vectors from the certificate-transparency reference
+        // implementation, also used by trillian
+        let leaves: [&[u8]; 8] = [
+            b"",
+            b"\x00",
+            b"\x10",
+            b"\x20\x21",
+            b"\x30\x31",
+            b"\x40\x41\x42\x43",
+            b"\x50\x51\x52\x53\x54\x55\x56\x57",
+            b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+        ];
+        let roots = [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+
+        let builder = MerkleTreeBuilder::new(Rfc6962Hasher::new()).shape(TreeShape::Rfc6962);
+        for (len, root) in (1..=leaves.len()).zip(roots) {
+            let tree = builder.build(leaves[..len].to_vec()).unwrap();
+            assert_eq!(tree.root().to_hex(), root);
+        }
+        assert_eq!(
+            MerkleTree::empty(Rfc6962Hasher::new()).root().to_hex(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_verify_integrity() {
+        for len in 1..=9 {
+            let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+            let tree = MerkleTree::new(data, Sha256Hasher::new()).unwrap();
+            assert_eq!(tree.verify_integrity(), Ok(()));
+        }
+
+        let children = vec![
+            MerkleTree::new(vec!["a", "b"], Sha256Hasher::new()).unwrap(),
+            MerkleTree::new(vec!["c", "d", "e"], Sha256Hasher::new()).unwrap(),
+        ];
+        let parent = MerkleTree::from_subtrees(children).unwrap();
+        assert_eq!(parent.verify_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_integrity_detects_corruption() {
+        let hasher = Sha256Hasher::new();
+        let tree = MerkleTree::new(vec!["a", "b", "c", "d", "e"], hasher.clone()).unwrap();
+
+        let mut corrupted = tree.clone();
+        corrupted.levels[1][1] = hasher.hash(b"rot");
+        assert!(matches!(
+            corrupted.verify_integrity(),
+            Err(MerkleError::IntegrityError { path, .. }) if path == "root/L"
+        ));
+
+        let mut corrupted = tree.clone();
+        corrupted.levels[0][4] = hasher.hash(b"rot");
+        assert!(matches!(
+            corrupted.verify_integrity(),
+            Err(MerkleError::IntegrityError { path, .. }) if path == "root/R/L"
+        ));
+
+        let mut corrupted = tree.clone();
+        corrupted.levels.pop();
+        assert!(matches!(
+            corrupted.verify_integrity(),
+            Err(MerkleError::IntegrityError { .. })
+        ));
+
+        let mut children = vec![
+            MerkleTree::new(vec!["a", "b"], hasher.clone()).unwrap(),
+            MerkleTree::new(vec!["c", "d"], hasher.clone()).unwrap(),
+        ];
+        children[1].levels[0][1] = hasher.hash(b"rot");
+        let parent = MerkleTree::from_subtrees(children).unwrap();
+        assert!(matches!(
+            parent.verify_integrity(),
+            Err(MerkleError::IntegrityError { path, .. }) if path == "subtree[1]/root"
+        ));
+    }
+
     #[test]
     fn test_proof_serialization() {
         let data = vec![b"hello", b"world"];
@@ -0,0 +1,301 @@
+//! Moving trees from one hash function to another
+//!
+//! [`MerkleTree::rehash_with`] rebuilds a tree under a new hasher from the
+//! original data, keeping its shape, node domain, salts, annotations and leaf
+//! index, and [`HashTransition`] holds the old and new trees side by side so
+//! both roots can be published and either kind of proof checked while
+//! clients cut over.
+
+use crate::builder::{ConstructionMode, LeafEncoding};
+use crate::error::{MerkleError, Result};
+use crate::hash::RootHash;
+use crate::hasher::Hasher;
+use crate::merkle_tree::MerkleTree;
+use crate::proof::MerkleProof;
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Rebuild the tree under `new_hasher` from the data it was built from
+    ///
+    /// `data` must hold the original items in leaf order; each is checked
+    /// against its leaf under the current hasher first, so data that drifted
+    /// from the tree is caught instead of migrated. Leaves are `H(data)`, or
+    /// `H(salt || data)` for salted trees, as [`MerkleTree::new`] and the
+    /// default builder derive them; see [`MerkleTree::rehash_with_encoding`]
+    /// for other encodings. Salts are carried over unchanged.
+    ///
+    /// ```rust
+    /// use merkle_tree::{Blake3Hasher, MerkleTree, Sha256Hasher};
+    ///
+    /// # fn main() -> merkle_tree::Result<()> {
+    /// let data = vec!["a", "b", "c"];
+    /// let tree = MerkleTree::new(data.clone(), Sha256Hasher::new())?;
+    /// let migrated = tree.rehash_with(Blake3Hasher::new(), &data)?;
+    /// assert_eq!(migrated.root(), MerkleTree::new(data, Blake3Hasher::new())?.root());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rehash_with<H2: Hasher, T: AsRef<[u8]>>(
+        &self,
+        new_hasher: H2,
+        data: &[T],
+    ) -> Result<MerkleTree<H2>> {
+        self.rehash_with_encoding(new_hasher, data, &LeafEncoding::PreHash)
+    }
+
+    /// Rebuild the tree under `new_hasher`, deriving leaves with `encoding`
+    ///
+    /// The encoding is applied with the current hasher to check the data and
+    /// with `new_hasher` to derive the new leaves. A
+    /// [`LeafEncoding::Custom`] closure does not see the hasher, so its
+    /// leaves come out unchanged. Trees built from subtrees are rebuilt one
+    /// child at a time and joined with [`MerkleTree::from_subtrees`] instead.
+    pub fn rehash_with_encoding<H2: Hasher, T: AsRef<[u8]>>(
+        &self,
+        new_hasher: H2,
+        data: &[T],
+        encoding: &LeafEncoding,
+    ) -> Result<MerkleTree<H2>> {
+        if !self.subtrees().is_empty() {
+            return Err(MerkleError::TreeConstructionError {
+                reason: "Trees built from subtrees are rehashed child by child".to_string(),
+            });
+        }
+        if data.len() != self.len() {
+            return Err(MerkleError::TreeConstructionError {
+                reason: format!("Got {} items for {} leaves", data.len(), self.len()),
+            });
+        }
+
+        let salts = self.salts();
+        let mut leaves = Vec::with_capacity(data.len());
+        for (index, (item, leaf)) in data.iter().zip(self.leaves()).enumerate() {
+            let salted;
+            let input = match salts.get(index) {
+                Some(salt) => {
+                    salted = [salt.as_slice(), item.as_ref()].concat();
+                    salted.as_slice()
+                }
+                None => item.as_ref(),
+            };
+            if encoding.encode(self.hasher(), input) != *leaf {
+                return Err(MerkleError::TreeConstructionError {
+                    reason: format!("Item {} does not match its leaf", index),
+                });
+            }
+            leaves.push(encoding.encode(&new_hasher, input));
+        }
+
+        let tree = MerkleTree::from_shaped_leaves(
+            leaves,
+            new_hasher,
+            self.shape(),
+            self.node_domain(),
+            ConstructionMode::Serial,
+        )?
+        .with_salts(salts.to_vec())
+        .with_annotations(self.annotations().clone());
+        Ok(if self.has_leaf_index() {
+            tree.with_leaf_index()
+        } else {
+            tree
+        })
+    }
+}
+
+/// The same leaves under an old and a new hash function, for a cutover
+///
+/// Publish both roots while clients move over, answer each with proofs from
+/// the tree matching the root it trusts, then keep only the new tree with
+/// [`HashTransition::finish`].
+///
+/// ```rust
+/// use merkle_tree::{Blake3Hasher, HashTransition, MerkleTree, Sha256Hasher};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let data = vec!["a", "b", "c"];
+/// let old = MerkleTree::new(data.clone(), Sha256Hasher::new())?;
+/// let transition = HashTransition::begin(old, Blake3Hasher::new(), &data)?;
+///
+/// let (old_root, new_root) = transition.roots();
+/// let (old_proof, new_proof) = transition.generate_proofs(1)?;
+/// assert!(transition.verify(&old_proof, b"b", &old_root));
+/// assert!(transition.verify(&new_proof, b"b", &new_root));
+///
+/// let migrated = transition.finish();
+/// assert_eq!(migrated.root(), new_root);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HashTransition<Old: Hasher, New: Hasher> {
+    old: MerkleTree<Old>,
+    new: MerkleTree<New>,
+}
+
+impl<Old: Hasher, New: Hasher> HashTransition<Old, New> {
+    /// Start a transition by rehashing `old` under `new_hasher`, see
+    /// [`MerkleTree::rehash_with`]
+    pub fn begin<T: AsRef<[u8]>>(
+        old: MerkleTree<Old>,
+        new_hasher: New,
+        data: &[T],
+    ) -> Result<Self> {
+        let new = old.rehash_with(new_hasher, data)?;
+        Ok(Self { old, new })
+    }
+
+    /// Pair two trees built separately, which must have the same number of
+    /// leaves
+    pub fn from_trees(old: MerkleTree<Old>, new: MerkleTree<New>) -> Result<Self> {
+        if old.len() != new.len() {
+            return Err(MerkleError::TreeConstructionError {
+                reason: format!("Old tree has {} leaves, new tree {}", old.len(), new.len()),
+            });
+        }
+        Ok(Self { old, new })
+    }
+
+    /// Get the root under the old hasher
+    pub fn old_root(&self) -> RootHash {
+        self.old.root()
+    }
+
+    /// Get the root under the new hasher
+    pub fn new_root(&self) -> RootHash {
+        self.new.root()
+    }
+
+    /// Get the old and new roots
+    pub fn roots(&self) -> (RootHash, RootHash) {
+        (self.old_root(), self.new_root())
+    }
+
+    /// Get the tree under the old hasher
+    pub fn old_tree(&self) -> &MerkleTree<Old> {
+        &self.old
+    }
+
+    /// Get the tree under the new hasher
+    pub fn new_tree(&self) -> &MerkleTree<New> {
+        &self.new
+    }
+
+    /// Generate proofs for the leaf at `index` in the old and new trees
+    pub fn generate_proofs(&self, index: usize) -> Result<(MerkleProof, MerkleProof)> {
+        Ok((
+            self.old.generate_proof(index)?,
+            self.new.generate_proof(index)?,
+        ))
+    }
+
+    /// Verify a proof from either tree against the root it was issued for
+    ///
+    /// The root picks the hasher: the old one for the old root, the new one
+    /// for the new root. Any other root is rejected.
+    pub fn verify(&self, proof: &MerkleProof, leaf_data: &[u8], root: &RootHash) -> bool {
+        if *root == self.old.root() {
+            self.old.verify_proof(proof, leaf_data, root)
+        } else if *root == self.new.root() {
+            self.new.verify_proof(proof, leaf_data, root)
+        } else {
+            false
+        }
+    }
+
+    /// End the transition, keeping only the tree under the new hasher
+    pub fn finish(self) -> MerkleTree<New> {
+        self.new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{MerkleTreeBuilder, Salting};
+    use crate::hasher::{Blake3Hasher, Keccak256Hasher, Sha256Hasher};
+    use crate::merkle_tree::{NodeDomain, TreeShape};
+
+    #[test]
+    fn test_rehash_with() {
+        let data: Vec<String> = (0..11).map(|i| format!("item_{}", i)).collect();
+        let tree = MerkleTree::new(data.clone(), Sha256Hasher::new())
+            .unwrap()
+            .with_leaf_index();
+        let migrated = tree.rehash_with(Blake3Hasher::new(), &data).unwrap();
+        let expected = MerkleTree::new(data.clone(), Blake3Hasher::new()).unwrap();
+        assert_eq!(migrated.root(), expected.root());
+        assert!(migrated.has_leaf_index());
+        assert_eq!(
+            migrated.position_of(&Blake3Hasher::new().hash(b"item_7")),
+            Some(7)
+        );
+
+        // Data that does not match the tree is refused
+        let mut drifted = data.clone();
+        drifted[4] = "other".to_string();
+        assert!(matches!(
+            tree.rehash_with(Blake3Hasher::new(), &drifted),
+            Err(MerkleError::TreeConstructionError { reason }) if reason.contains("Item 4")
+        ));
+        assert!(tree.rehash_with(Blake3Hasher::new(), &data[1..]).is_err());
+
+        let nested = MerkleTree::from_subtrees(vec![tree.clone(), tree]).unwrap();
+        assert!(nested
+            .rehash_with(Blake3Hasher::new(), &["a", "b"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_rehash_keeps_layout() {
+        let data = vec!["a", "b", "c", "d", "e"];
+        let build = |hasher| {
+            MerkleTreeBuilder::new(hasher)
+                .shape(TreeShape::Rfc6962)
+                .node_domain(NodeDomain::Level)
+                .leaf_encoding(LeafEncoding::DoubleHash)
+                .salting(Salting::Derived(b"secret".to_vec()))
+        };
+        let mut tree = build(Keccak256Hasher::new()).build(data.clone()).unwrap();
+        tree.annotate(4, b"row 5".to_vec()).unwrap();
+        assert!(tree.rehash_with(Sha256Hasher::new(), &data).is_err());
+
+        let migrated = tree
+            .rehash_with_encoding(Sha256Hasher::new(), &data, &LeafEncoding::DoubleHash)
+            .unwrap();
+        assert_eq!(migrated.shape(), TreeShape::Rfc6962);
+        assert_eq!(migrated.node_domain(), NodeDomain::Level);
+        assert_eq!(migrated.salt(3), tree.salt(3));
+        assert_eq!(migrated.annotation(4), Some(b"row 5".as_slice()));
+        assert_eq!(migrated.verify_integrity(), Ok(()));
+
+        // The salted leaf is the new hasher's encoding of `salt || data`
+        let salted = [tree.salt(3).unwrap(), b"d"].concat();
+        let leaf = LeafEncoding::DoubleHash.encode(&Sha256Hasher::new(), &salted);
+        assert_eq!(migrated.get_leaf(3).unwrap(), &leaf);
+    }
+
+    #[test]
+    fn test_transition() {
+        let data = vec!["a", "b", "c", "d"];
+        let old = MerkleTree::new(data.clone(), Sha256Hasher::new()).unwrap();
+        let transition = HashTransition::begin(old, Blake3Hasher::new(), &data).unwrap();
+        let (old_root, new_root) = transition.roots();
+        assert_ne!(old_root, new_root);
+        assert_eq!(transition.old_tree().hasher().name(), "SHA-256");
+
+        for (index, item) in data.iter().enumerate() {
+            let (old_proof, new_proof) = transition.generate_proofs(index).unwrap();
+            assert!(transition.verify(&old_proof, item.as_bytes(), &old_root));
+            assert!(transition.verify(&new_proof, item.as_bytes(), &new_root));
+            // A proof checked against the other root fails
+            assert!(!transition.verify(&old_proof, item.as_bytes(), &new_root));
+        }
+        let (proof, _) = transition.generate_proofs(0).unwrap();
+        assert!(!transition.verify(&proof, b"a", &RootHash::new(vec![0; 32])));
+
+        let other = MerkleTree::new(vec!["a"], Blake3Hasher::new()).unwrap();
+        let old = transition.old_tree().clone();
+        assert!(HashTransition::from_trees(old, other).is_err());
+        assert_eq!(transition.finish().root(), new_root);
+    }
+}
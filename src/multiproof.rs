@@ -0,0 +1,261 @@
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Compressed proof for several leaves of the same tree
+///
+/// This is the `(proof, proofFlags, leaves)` encoding consumed by
+/// OpenZeppelin's `MerkleProof.multiProofVerify`. Each flag tells the verifier
+/// whether the second input of the next hash comes from the queue of already
+/// known nodes (`true`) or from `proof` (`false`).
+///
+/// The encoding does not record which side each node is on, so it can only be
+/// verified with a commutative pair hash such as
+/// [`SortedPairHasher`](crate::hasher::SortedPairHasher).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MultiProof {
+    /// Leaf hashes being proven, in ascending leaf index order
+    pub leaves: Vec<Hash>,
+    /// Sibling hashes that cannot be derived from the leaves
+    pub proof: Vec<Hash>,
+    /// Whether each hashing step takes its second input from the queue
+    pub proof_flags: Vec<bool>,
+}
+
+impl MultiProof {
+    /// Create a new multiproof
+    pub fn new(leaves: Vec<Hash>, proof: Vec<Hash>, proof_flags: Vec<bool>) -> Self {
+        Self {
+            leaves,
+            proof,
+            proof_flags,
+        }
+    }
+
+    /// Verify the multiproof against a root hash
+    pub fn verify<H: Hasher>(&self, hasher: &H, root: &RootHash) -> bool {
+        self.compute_root(hasher)
+            .map(|computed| computed == *root)
+            .unwrap_or(false)
+    }
+
+    /// Compute the root hash implied by the multiproof
+    ///
+    /// Mirrors `processMultiProof` and rejects the same malformed inputs.
+    pub fn compute_root<H: Hasher>(&self, hasher: &H) -> Result<RootHash> {
+        let leaves_len = self.leaves.len();
+        let flags_len = self.proof_flags.len();
+
+        if leaves_len + self.proof.len() != flags_len + 1 {
+            return Err(MerkleError::InvalidProof {
+                reason: format!(
+                    "{} leaves and {} proof hashes do not match {} flags",
+                    leaves_len,
+                    self.proof.len(),
+                    flags_len
+                ),
+            });
+        }
+
+        let mut hashes: Vec<Hash> = Vec::with_capacity(flags_len);
+        let mut leaf_pos = 0;
+        let mut hash_pos = 0;
+        let mut proof_pos = 0;
+
+        for &flag in &self.proof_flags {
+            let a = if leaf_pos < leaves_len {
+                leaf_pos += 1;
+                self.leaves[leaf_pos - 1].clone()
+            } else {
+                hash_pos += 1;
+                hashes
+                    .get(hash_pos - 1)
+                    .cloned()
+                    .ok_or(MerkleError::InvalidProof {
+                        reason: "Flags consume more nodes than are available".to_string(),
+                    })?
+            };
+
+            let b = if !flag {
+                proof_pos += 1;
+                self.proof
+                    .get(proof_pos - 1)
+                    .cloned()
+                    .ok_or(MerkleError::InvalidProof {
+                        reason: "Ran out of proof hashes".to_string(),
+                    })?
+            } else if leaf_pos < leaves_len {
+                leaf_pos += 1;
+                self.leaves[leaf_pos - 1].clone()
+            } else {
+                hash_pos += 1;
+                hashes
+                    .get(hash_pos - 1)
+                    .cloned()
+                    .ok_or(MerkleError::InvalidProof {
+                        reason: "Flags consume more nodes than are available".to_string(),
+                    })?
+            };
+
+            hashes.push(hasher.hash_pair(&a, &b));
+        }
+
+        let root = if let Some(root) = hashes.pop() {
+            if proof_pos != self.proof.len() {
+                return Err(MerkleError::InvalidProof {
+                    reason: "Not all proof hashes were consumed".to_string(),
+                });
+            }
+            root
+        } else if let Some(leaf) = self.leaves.first() {
+            leaf.clone()
+        } else {
+            self.proof[0].clone()
+        };
+
+        Ok(RootHash::from(root))
+    }
+
+    /// Convert multiproof to hex representation for debugging
+    pub fn to_hex(&self) -> String {
+        let leaves: Vec<String> = self.leaves.iter().map(Hash::to_hex).collect();
+        let proof: Vec<String> = self.proof.iter().map(Hash::to_hex).collect();
+        let flags: Vec<&str> = self
+            .proof_flags
+            .iter()
+            .map(|&flag| if flag { "1" } else { "0" })
+            .collect();
+
+        format!(
+            "leaves:[{}], proof:[{}], flags:[{}]",
+            leaves.join(", "),
+            proof.join(", "),
+            flags.join("")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Keccak256Hasher, SortedPairHasher};
+    use crate::merkle_tree::MerkleTree;
+
+    fn tree(len: usize) -> MerkleTree<SortedPairHasher<Keccak256Hasher>> {
+        let data: Vec<Vec<u8>> = (0..len)
+            .map(|i| format!("leaf_{}", i).into_bytes())
+            .collect();
+        MerkleTree::new(data, SortedPairHasher::new(Keccak256Hasher::new())).unwrap()
+    }
+
+    #[test]
+    fn test_multiproof_roundtrip() {
+        for len in 1..=12 {
+            let tree = tree(len);
+            let all: Vec<usize> = (0..len).collect();
+
+            for size in 1..=len.min(4) {
+                for window in all.windows(size) {
+                    let proof = tree.generate_multiproof(window).unwrap();
+                    assert_eq!(
+                        proof.leaves.len() + proof.proof.len(),
+                        proof.proof_flags.len() + 1
+                    );
+                    assert!(
+                        proof.verify(tree.hasher(), &tree.root()),
+                        "len {} {:?}",
+                        len,
+                        window
+                    );
+                }
+            }
+
+            let proof = tree.generate_multiproof(&all).unwrap();
+            if len.is_power_of_two() {
+                // Only odd levels need their duplicated node from the proof
+                assert!(proof.proof.is_empty());
+            }
+            assert!(proof.verify(tree.hasher(), &tree.root()));
+        }
+    }
+
+    #[test]
+    fn test_scattered_indices() {
+        let tree = tree(11);
+        let proof = tree.generate_multiproof(&[9, 0, 4]).unwrap();
+
+        // Leaves are reported in tree order regardless of the requested order
+        assert_eq!(&proof.leaves[0], tree.get_leaf(0).unwrap());
+        assert_eq!(&proof.leaves[1], tree.get_leaf(4).unwrap());
+        assert_eq!(&proof.leaves[2], tree.get_leaf(9).unwrap());
+        assert!(proof.verify(tree.hasher(), &tree.root()));
+    }
+
+    #[test]
+    fn test_single_leaf_matches_regular_proof() {
+        let tree = tree(7);
+        let multi = tree.generate_multiproof(&[5]).unwrap();
+        let single = tree.generate_proof(5).unwrap();
+
+        assert!(multi.proof_flags.iter().all(|&flag| !flag));
+        let siblings: Vec<Hash> = single.steps.into_iter().map(|step| step.hash).collect();
+        assert_eq!(multi.proof, siblings);
+    }
+
+    #[test]
+    fn test_tampered_multiproof() {
+        let tree = tree(8);
+        let mut proof = tree.generate_multiproof(&[1, 2, 6]).unwrap();
+        assert!(proof.verify(tree.hasher(), &tree.root()));
+
+        proof.leaves[1] = tree.hasher().hash(b"forged");
+        assert!(!proof.verify(tree.hasher(), &tree.root()));
+
+        let mut proof = tree.generate_multiproof(&[1, 2, 6]).unwrap();
+        proof.proof_flags.push(true);
+        assert!(matches!(
+            proof.compute_root(tree.hasher()),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+    }
+
+    #[test]
+    fn test_flags_without_leaves() {
+        // With no leaves the first step has no computed hash to consume
+        let hasher = SortedPairHasher::new(Keccak256Hasher::new());
+        let proof = MultiProof::new(
+            vec![],
+            vec![hasher.hash(b"x"), hasher.hash(b"y")],
+            vec![false],
+        );
+        assert!(matches!(
+            proof.compute_root(&hasher),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+        assert!(!proof.verify(&hasher, &RootHash::from(hasher.hash(b"x"))));
+    }
+
+    #[test]
+    fn test_invalid_indices() {
+        let tree = tree(4);
+        assert!(matches!(
+            tree.generate_multiproof(&[1, 4]),
+            Err(MerkleError::InvalidIndex { index: 4, size: 4 })
+        ));
+        assert!(matches!(
+            tree.generate_multiproof(&[2, 2]),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+    }
+
+    #[test]
+    fn test_empty_multiproof() {
+        let tree = tree(5);
+        let proof = tree.generate_multiproof(&[]).unwrap();
+        assert_eq!(proof.proof, vec![tree.root().into_hash()]);
+        assert!(proof.verify(tree.hasher(), &tree.root()));
+    }
+}
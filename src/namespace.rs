@@ -0,0 +1,231 @@
+//! Namespaced sparse Merkle trees
+//!
+//! [`NamespacedTree`] addresses leaves by `(namespace, key)` within a single
+//! [`SparseMerkleTree`]: the namespace picks a subtree from the top bits of
+//! the leaf index and the key a leaf within it. Every namespace then has its
+//! own root, a commitment to just that tenant's state, and a proof ties the
+//! namespace root to the global root without a separate tree per tenant.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::proof::MerkleProof;
+use crate::sparse::SparseMerkleTree;
+
+/// Sparse Merkle tree keyed by `(namespace, key)`
+///
+/// ```rust
+/// use merkle_tree::namespace::NamespacedTree;
+/// use merkle_tree::Sha256Hasher;
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let hasher = Sha256Hasher::new();
+/// let mut tree = NamespacedTree::new(16, 32, hasher.clone())?;
+/// tree.update(7, 1, b"alice")?;
+///
+/// let tenant_root = tree.namespace_root(7)?;
+/// let proof = tree.generate_namespace_proof(7)?;
+/// let root = tree.root().clone();
+/// assert!(proof.verify_with_leaf_hash(&hasher, tenant_root.as_hash(), &root));
+///
+/// let key_proof = tree.generate_key_proof(7, 1)?;
+/// assert!(key_proof.verify(&hasher, b"alice", &tenant_root));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct NamespacedTree<H: Hasher> {
+    tree: SparseMerkleTree<H>,
+    namespace_bits: u8,
+    key_bits: u8,
+}
+
+impl<H: Hasher> NamespacedTree<H> {
+    /// Create a tree of `2^namespace_bits` namespaces with `2^key_bits` keys
+    /// each
+    ///
+    /// Both must be at least 1 and together at most 64, the depth of the
+    /// underlying tree.
+    pub fn new(namespace_bits: u8, key_bits: u8, hasher: H) -> Result<Self> {
+        if namespace_bits == 0 || key_bits == 0 {
+            return Err(MerkleError::TreeConstructionError {
+                reason: "Namespaces and keys need at least one bit each".to_string(),
+            });
+        }
+        let depth = namespace_bits.checked_add(key_bits).filter(|&d| d <= 64);
+        let Some(depth) = depth else {
+            return Err(MerkleError::TreeConstructionError {
+                reason: format!(
+                    "{} namespace bits and {} key bits exceed a depth of 64",
+                    namespace_bits, key_bits
+                ),
+            });
+        };
+
+        Ok(Self {
+            tree: SparseMerkleTree::new(depth, hasher)?,
+            namespace_bits,
+            key_bits,
+        })
+    }
+
+    /// Insert or update the value of `key` in `namespace`
+    pub fn update(&mut self, namespace: u64, key: u64, value: &[u8]) -> Result<()> {
+        let index = self.index(namespace, key)?;
+        self.tree.update(index, value)
+    }
+
+    /// Remove `key` from `namespace`, returning whether it was present
+    pub fn remove(&mut self, namespace: u64, key: u64) -> Result<bool> {
+        let index = self.index(namespace, key)?;
+        self.tree.remove(index)
+    }
+
+    /// Get the leaf hash of `key` in `namespace`
+    pub fn get(&self, namespace: u64, key: u64) -> Option<&Hash> {
+        self.tree.get(self.index(namespace, key).ok()?)
+    }
+
+    /// Get the global root over all namespaces
+    pub fn root(&mut self) -> &RootHash {
+        self.tree.root()
+    }
+
+    /// Get the root of the subtree holding `namespace`
+    pub fn namespace_root(&mut self, namespace: u64) -> Result<RootHash> {
+        self.check_namespace(namespace)?;
+        self.tree.subtree_root(self.key_bits, namespace)
+    }
+
+    /// Generate a proof that `namespace`'s root is part of the global root
+    ///
+    /// It has `namespace_bits` steps and verifies the namespace root as its
+    /// leaf hash, see [`MerkleProof::verify_with_leaf_hash`].
+    pub fn generate_namespace_proof(&mut self, namespace: u64) -> Result<MerkleProof> {
+        self.check_namespace(namespace)?;
+        self.tree.generate_subtree_proof(self.key_bits, namespace)
+    }
+
+    /// Generate a proof of `key` against the root of its namespace
+    ///
+    /// It has `key_bits` steps and its `leaf_index` is `key`.
+    pub fn generate_key_proof(&mut self, namespace: u64, key: u64) -> Result<MerkleProof> {
+        let index = self.index(namespace, key)?;
+        let mut proof = self.tree.generate_proof(index)?;
+        proof.steps.truncate(self.key_bits as usize);
+        proof.leaf_index = key as usize;
+        Ok(proof)
+    }
+
+    /// Generate a proof of `key` in `namespace` against the global root
+    pub fn generate_proof(&mut self, namespace: u64, key: u64) -> Result<MerkleProof> {
+        let index = self.index(namespace, key)?;
+        self.tree.generate_proof(index)
+    }
+
+    /// Number of bits addressing a namespace
+    pub fn namespace_bits(&self) -> u8 {
+        self.namespace_bits
+    }
+
+    /// Number of bits addressing a key within a namespace
+    pub fn key_bits(&self) -> u8 {
+        self.key_bits
+    }
+
+    /// Get the underlying sparse tree
+    pub fn tree(&self) -> &SparseMerkleTree<H> {
+        &self.tree
+    }
+
+    /// Leaf index of `key` in `namespace`
+    fn index(&self, namespace: u64, key: u64) -> Result<u64> {
+        self.check_namespace(namespace)?;
+        check_bits(key, self.key_bits)?;
+        Ok(namespace << self.key_bits | key)
+    }
+
+    fn check_namespace(&self, namespace: u64) -> Result<()> {
+        check_bits(namespace, self.namespace_bits)
+    }
+}
+
+/// Check that `value` fits in `bits` bits, which is less than 64
+fn check_bits(value: u64, bits: u8) -> Result<()> {
+    if value >> bits != 0 {
+        return Err(MerkleError::InvalidIndex {
+            index: value as usize,
+            size: 1 << bits,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    #[test]
+    fn test_namespaces() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = NamespacedTree::new(4, 8, hasher.clone()).unwrap();
+        tree.update(3, 10, b"alice").unwrap();
+        tree.update(3, 200, b"bob").unwrap();
+        tree.update(9, 10, b"carol").unwrap();
+        assert_eq!(tree.get(3, 10), Some(&hasher.hash(b"alice")));
+        assert_eq!(tree.get(9, 200), None);
+
+        // Each namespace root matches a standalone tree of its keys
+        let mut tenant = SparseMerkleTree::new(8, hasher.clone()).unwrap();
+        tenant.update(10, b"alice").unwrap();
+        tenant.update(200, b"bob").unwrap();
+        assert_eq!(&tree.namespace_root(3).unwrap(), tenant.root());
+
+        let root = tree.root().clone();
+        for namespace in [0, 3, 9, 15] {
+            let ns_root = tree.namespace_root(namespace).unwrap();
+            let proof = tree.generate_namespace_proof(namespace).unwrap();
+            assert_eq!(proof.len(), 4);
+            assert!(proof.verify_with_leaf_hash(&hasher, ns_root.as_hash(), &root));
+        }
+
+        let key_proof = tree.generate_key_proof(3, 200).unwrap();
+        assert_eq!(key_proof.leaf_index, 200);
+        assert!(key_proof.verify(&hasher, b"bob", &tree.namespace_root(3).unwrap()));
+        let proof = tree.generate_proof(3, 200).unwrap();
+        assert!(proof.verify(&hasher, b"bob", &root));
+
+        // Changing one tenant leaves the others' roots alone
+        let other = tree.namespace_root(9).unwrap();
+        assert!(tree.remove(3, 10).unwrap());
+        assert_eq!(tree.namespace_root(9).unwrap(), other);
+        assert_ne!(tree.root(), &root);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let hasher = Sha256Hasher::new();
+        assert!(NamespacedTree::new(0, 8, hasher.clone()).is_err());
+        assert!(NamespacedTree::new(32, 33, hasher.clone()).is_err());
+        assert!(NamespacedTree::new(32, 32, hasher.clone()).is_ok());
+
+        let mut tree = NamespacedTree::new(4, 8, hasher).unwrap();
+        assert!(matches!(
+            tree.update(16, 0, b"x"),
+            Err(MerkleError::InvalidIndex {
+                index: 16,
+                size: 16
+            })
+        ));
+        assert!(matches!(
+            tree.update(0, 256, b"x"),
+            Err(MerkleError::InvalidIndex {
+                index: 256,
+                size: 256
+            })
+        ));
+        assert!(tree.namespace_root(16).is_err());
+        assert!(tree.generate_namespace_proof(15).is_ok());
+    }
+}
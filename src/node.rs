@@ -0,0 +1,236 @@
+//! Node.js bindings via napi-rs
+//!
+//! Exposes a `MerkleTree` class shaped after merkletreejs, so existing callers
+//! can switch with few changes. Proofs are arrays of
+//! `{ position: "left" | "right", data: Buffer }`. Pass `"sorted-keccak256"`
+//! as the hasher for the OpenZeppelin / Ethereum convention.
+//!
+//! Build the addon with `@napi-rs/cli` (`napi build --features napi`), which
+//! also sets up linking on macOS.
+
+use crate::error::MerkleError;
+use crate::hash::{Hash, RootHash};
+use crate::hasher::{HashAlgorithm, Hasher};
+use crate::merkle_tree::MerkleTree;
+use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+use napi::bindgen_prelude::Buffer;
+use napi_derive::napi;
+
+fn js_error(e: MerkleError) -> napi::Error {
+    napi::Error::from_reason(e.to_string())
+}
+
+fn algorithm(hasher: Option<String>) -> napi::Result<HashAlgorithm> {
+    hasher
+        .as_deref()
+        .unwrap_or("sha256")
+        .parse()
+        .map_err(js_error)
+}
+
+/// Proof step in merkletreejs form
+#[napi(object)]
+pub struct JsProofStep {
+    /// `"left"` or `"right"`
+    pub position: String,
+    pub data: Buffer,
+}
+
+impl From<&ProofStep> for JsProofStep {
+    fn from(step: &ProofStep) -> Self {
+        let position = match step.direction {
+            ProofDirection::Left => "left",
+            ProofDirection::Right => "right",
+        };
+        Self {
+            position: position.to_string(),
+            data: step.hash.to_vec().into(),
+        }
+    }
+}
+
+fn to_proof(index: usize, steps: Vec<JsProofStep>) -> napi::Result<MerkleProof> {
+    let steps = steps
+        .into_iter()
+        .map(|step| {
+            let direction = match step.position.as_str() {
+                "left" => ProofDirection::Left,
+                "right" => ProofDirection::Right,
+                other => {
+                    return Err(napi::Error::from_reason(format!(
+                        "Invalid proof position {}",
+                        other
+                    )))
+                }
+            };
+            Ok(ProofStep {
+                hash: Hash::from(step.data.as_ref()),
+                direction,
+            })
+        })
+        .collect::<napi::Result<_>>()?;
+    Ok(MerkleProof::new(index, steps))
+}
+
+/// Merkle tree exposed to JavaScript as `MerkleTree`
+#[napi(js_name = "MerkleTree")]
+pub struct JsMerkleTree {
+    tree: MerkleTree<HashAlgorithm>,
+}
+
+#[napi]
+impl JsMerkleTree {
+    /// Build a tree by hashing each data item
+    #[napi(constructor)]
+    pub fn new(data: Vec<Buffer>, hasher: Option<String>) -> napi::Result<Self> {
+        let data: Vec<&[u8]> = data.iter().map(|item| item.as_ref()).collect();
+        let tree = MerkleTree::new(data, algorithm(hasher)?).map_err(js_error)?;
+        Ok(Self { tree })
+    }
+
+    /// Build a tree from pre-hashed leaves
+    #[napi(factory)]
+    pub fn from_leaves(leaves: Vec<Buffer>, hasher: Option<String>) -> napi::Result<Self> {
+        let leaves = leaves
+            .iter()
+            .map(|leaf| Hash::from(leaf.as_ref()))
+            .collect();
+        let tree = MerkleTree::from_leaves(leaves, algorithm(hasher)?).map_err(js_error)?;
+        Ok(Self { tree })
+    }
+
+    /// Get the root hash
+    #[napi]
+    pub fn get_root(&self) -> Buffer {
+        self.tree.root().to_vec().into()
+    }
+
+    /// Get the root hash as `0x`-prefixed hex
+    #[napi]
+    pub fn get_hex_root(&self) -> String {
+        format!("0x{}", self.tree.root().to_hex())
+    }
+
+    /// Get the number of leaves
+    #[napi(getter)]
+    pub fn length(&self) -> u32 {
+        self.tree.len() as u32
+    }
+
+    /// Get a leaf hash
+    #[napi]
+    pub fn get_leaf(&self, index: u32) -> napi::Result<Buffer> {
+        let leaf = self.tree.get_leaf(index as usize).map_err(js_error)?;
+        Ok(leaf.to_vec().into())
+    }
+
+    /// Get the proof of a leaf
+    #[napi]
+    pub fn get_proof(&self, index: u32) -> napi::Result<Vec<JsProofStep>> {
+        let proof = self.tree.generate_proof(index as usize).map_err(js_error)?;
+        Ok(proof.steps.iter().map(JsProofStep::from).collect())
+    }
+
+    /// Get the proof of a leaf as `0x`-prefixed sibling hashes, the form
+    /// taken by OpenZeppelin's `MerkleProof.verify`
+    #[napi]
+    pub fn get_hex_proof(&self, index: u32) -> napi::Result<Vec<String>> {
+        let proof = self.tree.generate_proof(index as usize).map_err(js_error)?;
+        Ok(proof
+            .steps
+            .iter()
+            .map(|step| format!("0x{}", step.hash.to_hex()))
+            .collect())
+    }
+
+    /// Verify that `proof` includes `data` at `index` under `root`
+    #[napi]
+    pub fn verify(
+        &self,
+        proof: Vec<JsProofStep>,
+        index: u32,
+        data: Buffer,
+        root: Buffer,
+    ) -> napi::Result<bool> {
+        let proof = to_proof(index as usize, proof)?;
+        let root = RootHash::from(root.to_vec());
+        Ok(self.tree.verify_proof(&proof, &data, &root))
+    }
+}
+
+/// Hash data with the named algorithm
+#[napi]
+pub fn hash(data: Buffer, hasher: Option<String>) -> napi::Result<Buffer> {
+    Ok(algorithm(hasher)?.hash(&data).to_vec().into())
+}
+
+/// Verify a sorted-pair Keccak-256 proof of a leaf hash, as OpenZeppelin's
+/// `MerkleProof.verify` does
+#[napi]
+pub fn verify_sorted_proof(proof: Vec<Buffer>, leaf: Buffer, root: Buffer) -> bool {
+    let hasher = HashAlgorithm::SortedKeccak256;
+    let computed = proof
+        .iter()
+        .fold(Hash::from(leaf.as_ref()), |acc, sibling| {
+            hasher.hash_pair(&acc, sibling)
+        });
+    computed.as_ref() == root.as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffers(items: &[&str]) -> Vec<Buffer> {
+        items
+            .iter()
+            .map(|item| item.as_bytes().to_vec().into())
+            .collect()
+    }
+
+    #[test]
+    fn test_tree_and_proofs() {
+        let tree = JsMerkleTree::new(buffers(&["a", "b", "c"]), None).unwrap();
+        let native = MerkleTree::new(vec!["a", "b", "c"], HashAlgorithm::Sha256).unwrap();
+        assert_eq!(tree.get_root().as_ref(), native.root().as_ref());
+        assert_eq!(tree.get_hex_root(), format!("0x{}", native.root().to_hex()));
+        assert_eq!(tree.length(), 3);
+
+        let proof = tree.get_proof(1).unwrap();
+        assert_eq!(proof[0].position, "left");
+        assert!(tree
+            .verify(proof, 1, b"b".to_vec().into(), tree.get_root())
+            .unwrap());
+
+        let proof = tree.get_proof(1).unwrap();
+        assert!(!tree
+            .verify(proof, 1, b"c".to_vec().into(), tree.get_root())
+            .unwrap());
+
+        assert!(tree.get_proof(3).is_err());
+        assert!(JsMerkleTree::new(buffers(&["a"]), Some("md5".into())).is_err());
+    }
+
+    #[test]
+    fn test_sorted_keccak() {
+        let tree = JsMerkleTree::new(
+            buffers(&["a", "b", "c", "d", "e"]),
+            Some("sorted-keccak256".into()),
+        )
+        .unwrap();
+        for index in 0..tree.length() {
+            let siblings = tree
+                .get_proof(index)
+                .unwrap()
+                .into_iter()
+                .map(|step| step.data)
+                .collect();
+            assert!(verify_sorted_proof(
+                siblings,
+                tree.get_leaf(index).unwrap(),
+                tree.get_root()
+            ));
+        }
+        assert_eq!(tree.get_hex_proof(0).unwrap()[0].len(), 66);
+    }
+}
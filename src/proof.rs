@@ -1,10 +1,16 @@
 use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::merkle_tree::{NodeDomain, TreeShape};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
 
 /// Direction of a proof step (left or right sibling)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 pub enum ProofDirection {
     Left,
     Right,
@@ -14,7 +20,7 @@ pub enum ProofDirection {
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ProofStep {
-    pub hash: Vec<u8>,
+    pub hash: Hash,
     pub direction: ProofDirection,
 }
 
@@ -24,12 +30,151 @@ pub struct ProofStep {
 pub struct MerkleProof {
     pub leaf_index: usize,
     pub steps: Vec<ProofStep>,
+    /// Number of leaves in the tree the proof belongs to, if known
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub tree_size: Option<usize>,
 }
 
 impl MerkleProof {
     /// Create a new Merkle proof
     pub fn new(leaf_index: usize, steps: Vec<ProofStep>) -> Self {
-        Self { leaf_index, steps }
+        Self {
+            leaf_index,
+            steps,
+            tree_size: None,
+        }
+    }
+
+    /// Build a proof from bare sibling hashes, leaf level first, deriving
+    /// every direction from `leaf_index` and `tree_size`
+    ///
+    /// This is the form used by systems such as Tendermint's simple Merkle
+    /// tree, whose proofs carry only the sibling hashes. Those trees split
+    /// at the largest power of two, which gives the same layout as
+    /// [`TreeShape::Rfc6962`]. The returned proof carries `tree_size` as its
+    /// index hint.
+    pub fn from_siblings(
+        leaf_index: usize,
+        tree_size: usize,
+        shape: TreeShape,
+        siblings: Vec<Hash>,
+    ) -> Result<Self> {
+        if leaf_index >= tree_size {
+            return Err(MerkleError::InvalidIndex {
+                index: leaf_index,
+                size: tree_size,
+            });
+        }
+
+        let path = path_steps(leaf_index, tree_size, shape);
+        if path.len() != siblings.len() {
+            return Err(MerkleError::InvalidProof {
+                reason: format!(
+                    "Got {} siblings, leaf {} of {} needs {}",
+                    siblings.len(),
+                    leaf_index,
+                    tree_size,
+                    path.len()
+                ),
+            });
+        }
+
+        let steps = siblings
+            .into_iter()
+            .zip(path)
+            .map(|(hash, (direction, _))| ProofStep { hash, direction })
+            .collect();
+        Ok(Self::new(leaf_index, steps).with_index_hint(tree_size))
+    }
+
+    /// Attach the number of leaves in the proof's tree
+    ///
+    /// With the hint in place [`MerkleProof::verify_hinted`] derives the
+    /// direction of every step from `(leaf_index, tree_size)` instead of
+    /// trusting the ones stored in the proof.
+    pub fn with_index_hint(mut self, tree_size: usize) -> Self {
+        self.tree_size = Some(tree_size);
+        self
+    }
+
+    /// Strip the proof down to its sibling hashes, the inverse of
+    /// [`MerkleProof::from_siblings`]
+    ///
+    /// The directions are dropped, so they must first pass
+    /// [`MerkleProof::check_position`]; otherwise a verifier deriving them
+    /// from the index would see a different proof.
+    pub fn to_siblings(&self, tree_size: usize, shape: TreeShape) -> Result<Vec<Hash>> {
+        self.check_position(tree_size, shape)?;
+        Ok(self.steps.iter().map(|step| step.hash.clone()).collect())
+    }
+
+    /// Build a proof from sibling hashes and a path bitmask, where bit `i`
+    /// is set when the sibling of step `i` is on the left
+    ///
+    /// Many Solidity and Go verifiers take the proof in this form. In a
+    /// [`TreeShape::Padded`] tree the bitmask of a leaf is its index, so they
+    /// often call it the index. Bits beyond the last sibling must be clear.
+    pub fn from_path_bits(leaf_index: usize, path_bits: u64, siblings: Vec<Hash>) -> Result<Self> {
+        if siblings.len() < 64 && path_bits >> siblings.len() != 0 {
+            return Err(MerkleError::InvalidProof {
+                reason: format!(
+                    "Path bits {:#b} do not fit in {} steps",
+                    path_bits,
+                    siblings.len()
+                ),
+            });
+        }
+        if siblings.len() > 64 {
+            return Err(MerkleError::InvalidProof {
+                reason: format!("Proof of {} steps has no 64-bit path", siblings.len()),
+            });
+        }
+
+        let steps = siblings
+            .into_iter()
+            .enumerate()
+            .map(|(i, hash)| ProofStep {
+                hash,
+                direction: if path_bits >> i & 1 == 1 {
+                    ProofDirection::Left
+                } else {
+                    ProofDirection::Right
+                },
+            })
+            .collect();
+        Ok(Self::new(leaf_index, steps))
+    }
+
+    /// Path bitmask of the proof, the inverse of
+    /// [`MerkleProof::from_path_bits`]
+    ///
+    /// Fails for proofs of more than 64 steps.
+    pub fn to_path_bits(&self) -> Result<u64> {
+        if self.steps.len() > 64 {
+            return Err(MerkleError::InvalidProof {
+                reason: format!("Proof of {} steps has no 64-bit path", self.steps.len()),
+            });
+        }
+        Ok(self
+            .steps
+            .iter()
+            .enumerate()
+            .filter(|(_, step)| step.direction == ProofDirection::Left)
+            .fold(0, |bits, (i, _)| bits | 1 << i))
+    }
+
+    /// Tag the proof with the hasher of its tree, see [`TypedProof`]
+    ///
+    /// Nothing checks that `H` really built the tree; this is the way in for
+    /// proofs that arrive untyped, e.g. deserialized or from another crate.
+    pub fn assume_hasher<H: crate::hasher::Hasher>(self) -> TypedProof<H> {
+        TypedProof {
+            proof: self,
+            hasher: PhantomData,
+        }
     }
 
     /// Get the number of steps in the proof
@@ -42,8 +187,63 @@ impl MerkleProof {
         self.steps.is_empty()
     }
 
+    /// Extend a proof of a leaf up to a subtree root with a proof of that
+    /// subtree root up to the global root
+    ///
+    /// The result verifies the original leaf directly against the outer root.
+    /// Its `leaf_index` is the leaf's global position,
+    /// `outer.leaf_index << self.len() | self.leaf_index`, so index-bound
+    /// verifiers such as [`MerkleProof::verify_indexed`] accept it for the
+    /// combined tree.
+    ///
+    /// Only two things are checked here. The sibling hashes must all have
+    /// the same length, as both proofs must come from the same hash
+    /// function. And this proof must walk a full subtree of `2^self.len()`
+    /// leaves, i.e. pass [`MerkleProof::check_position`] for that size with
+    /// [`TreeShape::Padded`], since otherwise the global position cannot be
+    /// derived; proofs with promoted RFC 6962 steps are rejected. Whether
+    /// `outer` really proves this proof's root is only known once the
+    /// chained proof is verified, and whether its directions match its
+    /// index only once the result is checked against a tree size.
+    pub fn chain(self, outer: MerkleProof) -> Result<MerkleProof> {
+        let mut sizes = self.steps.iter().chain(&outer.steps).map(|s| s.hash.len());
+        if let Some(size) = sizes.next() {
+            if let Some(other) = sizes.find(|&other| other != size) {
+                return Err(MerkleError::InvalidProof {
+                    reason: format!(
+                        "Cannot chain proofs with {}-byte and {}-byte hashes",
+                        size, other
+                    ),
+                });
+            }
+        }
+
+        // The global index needs this leaf's position within an aligned
+        // subtree of 2^height leaves
+        let height = self.steps.len() as u32;
+        let spans_subtree =
+            height < usize::BITS && self.check_position(1 << height, TreeShape::Padded).is_ok();
+        let leaf_index = match outer.leaf_index.checked_shl(height) {
+            Some(base) if spans_subtree && base >> height == outer.leaf_index => {
+                base | self.leaf_index
+            }
+            _ => {
+                return Err(MerkleError::InvalidProof {
+                    reason: format!(
+                        "Proof of leaf {} does not span a subtree of 2^{} leaves",
+                        self.leaf_index, height
+                    ),
+                })
+            }
+        };
+
+        let mut steps = self.steps;
+        steps.extend(outer.steps);
+        Ok(MerkleProof::new(leaf_index, steps))
+    }
+
     /// Verify the proof against a root hash and leaf data
-    pub fn verify<H>(&self, hasher: &H, leaf_data: &[u8], root: &[u8]) -> bool
+    pub fn verify<H>(&self, hasher: &H, leaf_data: &[u8], root: &RootHash) -> bool
     where
         H: crate::hasher::Hasher,
     {
@@ -52,32 +252,191 @@ impl MerkleProof {
     }
 
     /// Verify the proof with a pre-computed leaf hash
-    pub fn verify_with_leaf_hash<H>(&self, hasher: &H, leaf_hash: &[u8], root: &[u8]) -> bool
+    pub fn verify_with_leaf_hash<H>(&self, hasher: &H, leaf_hash: &Hash, root: &RootHash) -> bool
     where
         H: crate::hasher::Hasher,
     {
-        let computed_root = self.compute_root(hasher, leaf_hash);
-        computed_root == root
+        let steps = self.steps.iter().map(|step| (&step.hash, step.direction));
+        *root == fold_steps(hasher, leaf_hash, steps)
     }
 
-    /// Compute the root hash from the proof and leaf hash
-    pub fn compute_root<H>(&self, hasher: &H, leaf_hash: &[u8]) -> Vec<u8>
+    /// Check that the proof's path leads to `leaf_index` in a tree of
+    /// `tree_size` leaves with the given shape
+    ///
+    /// [`MerkleProof::verify`] only checks that the path reaches the root,
+    /// so on its own a proof for one leaf could be relabeled with another
+    /// index. This pins the number of steps and every direction down.
+    pub fn check_position(&self, tree_size: usize, shape: TreeShape) -> Result<()> {
+        if self.leaf_index >= tree_size {
+            return Err(MerkleError::InvalidIndex {
+                index: self.leaf_index,
+                size: tree_size,
+            });
+        }
+
+        let expected = path_steps(self.leaf_index, tree_size, shape);
+        if expected.len() != self.steps.len() {
+            return Err(MerkleError::InvalidProof {
+                reason: format!(
+                    "Proof has {} steps, leaf {} of {} needs {}",
+                    self.steps.len(),
+                    self.leaf_index,
+                    tree_size,
+                    expected.len()
+                ),
+            });
+        }
+        if let Some(step) = (0..expected.len()).find(|&i| self.steps[i].direction != expected[i].0)
+        {
+            return Err(MerkleError::InvalidProof {
+                reason: format!(
+                    "Direction of step {} does not match leaf {}",
+                    step, self.leaf_index
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Verify the proof and that it belongs to `leaf_index` in a tree of
+    /// `tree_size` leaves, see [`MerkleProof::check_position`]
+    pub fn verify_indexed<H>(
+        &self,
+        hasher: &H,
+        leaf_data: &[u8],
+        root: &RootHash,
+        tree_size: usize,
+        shape: TreeShape,
+    ) -> bool
     where
         H: crate::hasher::Hasher,
     {
-        let mut current_hash = leaf_hash.to_vec();
+        self.check_position(tree_size, shape).is_ok() && self.verify(hasher, leaf_data, root)
+    }
 
-        for step in &self.steps {
-            current_hash = match step.direction {
-                ProofDirection::Left => hasher.hash_pair(&step.hash, &current_hash),
-                ProofDirection::Right => hasher.hash_pair(&current_hash, &step.hash),
+    /// Verify a proof from a tree built with [`NodeDomain::Level`]
+    ///
+    /// The level of every parent follows from the leaf's position, so the
+    /// proof must also pass [`MerkleProof::check_position`] for a tree of
+    /// `tree_size` leaves with the given shape.
+    pub fn verify_leveled<H>(
+        &self,
+        hasher: &H,
+        leaf_data: &[u8],
+        root: &RootHash,
+        tree_size: usize,
+        shape: TreeShape,
+    ) -> bool
+    where
+        H: crate::hasher::Hasher,
+    {
+        let leaf_hash = hasher.hash(leaf_data);
+        self.verify_leveled_with_leaf_hash(hasher, &leaf_hash, root, tree_size, shape)
+    }
+
+    /// Verify a proof from a tree built with [`NodeDomain::Level`] with a
+    /// pre-computed leaf hash
+    pub fn verify_leveled_with_leaf_hash<H>(
+        &self,
+        hasher: &H,
+        leaf_hash: &Hash,
+        root: &RootHash,
+        tree_size: usize,
+        shape: TreeShape,
+    ) -> bool
+    where
+        H: crate::hasher::Hasher,
+    {
+        if self.check_position(tree_size, shape).is_err() {
+            return false;
+        }
+
+        let levels = path_steps(self.leaf_index, tree_size, shape);
+        let mut current = leaf_hash.clone();
+        for (step, (_, level)) in self.steps.iter().zip(levels) {
+            current = match step.direction {
+                ProofDirection::Left => {
+                    NodeDomain::Level.hash_node(hasher, level, &step.hash, &current)
+                }
+                ProofDirection::Right => {
+                    NodeDomain::Level.hash_node(hasher, level, &current, &step.hash)
+                }
             };
         }
+        root.as_hash() == &current
+    }
+
+    /// Verify the proof using directions derived from its index hint
+    ///
+    /// The stored directions are ignored: each one follows from
+    /// `leaf_index` and the `tree_size` set by
+    /// [`MerkleProof::with_index_hint`], so the proof also pins the leaf's
+    /// position. Returns `false` when the proof has no hint, the index is
+    /// out of range or the number of steps does not fit the tree.
+    pub fn verify_hinted<H>(
+        &self,
+        hasher: &H,
+        leaf_data: &[u8],
+        root: &RootHash,
+        shape: TreeShape,
+    ) -> bool
+    where
+        H: crate::hasher::Hasher,
+    {
+        let Some(tree_size) = self.tree_size else {
+            return false;
+        };
+        if self.leaf_index >= tree_size {
+            return false;
+        }
+
+        let path = path_steps(self.leaf_index, tree_size, shape);
+        if path.len() != self.steps.len() {
+            return false;
+        }
+
+        let leaf_hash = hasher.hash(leaf_data);
+        let steps = self
+            .steps
+            .iter()
+            .zip(path)
+            .map(|(step, (direction, _))| (&step.hash, direction));
+        *root == fold_steps(hasher, &leaf_hash, steps)
+    }
+
+    /// Compute the root hash from the proof and leaf hash
+    pub fn compute_root<H>(&self, hasher: &H, leaf_hash: &Hash) -> RootHash
+    where
+        H: crate::hasher::Hasher,
+    {
+        RootHash::new(fold_steps(
+            hasher,
+            leaf_hash,
+            self.steps.iter().map(|step| (&step.hash, step.direction)),
+        ))
+    }
 
-        current_hash
+    /// Borrow the proof as a [`MerkleProofRef`]
+    pub fn as_proof_ref(&self) -> MerkleProofRef<'_> {
+        MerkleProofRef {
+            leaf_index: self.leaf_index,
+            steps: self
+                .steps
+                .iter()
+                .map(|step| ProofStepRef {
+                    hash: &step.hash,
+                    direction: step.direction,
+                })
+                .collect(),
+        }
     }
 
-    /// Convert proof to hex representation for debugging
+    /// Convert proof to its textual representation
+    ///
+    /// The format is `index:N, steps:[L:<hex>, R:<hex>, ...]`, with
+    /// `size:M, ` before the steps when the proof has an index hint, and is
+    /// stable: [`MerkleProof::from_hex`] parses it back into an identical
+    /// proof.
     pub fn to_hex(&self) -> String {
         let steps_hex: Vec<String> = self
             .steps
@@ -94,28 +453,681 @@ impl MerkleProof {
             })
             .collect();
 
+        let size = match self.tree_size {
+            Some(size) => format!("size:{}, ", size),
+            None => String::new(),
+        };
         format!(
-            "index:{}, steps:[{}]",
+            "index:{}, {}steps:[{}]",
             self.leaf_index,
+            size,
             steps_hex.join(", ")
         )
     }
+
+    /// Parse a proof from the representation produced by [`MerkleProof::to_hex`]
+    ///
+    /// Whitespace around separators is ignored and hashes may be upper or
+    /// lowercase hex, with or without a `0x` prefix.
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let invalid = |reason: String| MerkleError::SerializationError {
+            message: format!("Invalid proof text: {}", reason),
+        };
+
+        let (index_part, steps_part) = s
+            .trim()
+            .split_once(',')
+            .ok_or_else(|| invalid("missing steps".to_string()))?;
+
+        let leaf_index = index_part
+            .trim()
+            .strip_prefix("index:")
+            .ok_or_else(|| invalid("expected `index:`".to_string()))?
+            .trim()
+            .parse::<usize>()
+            .map_err(|e| invalid(format!("bad leaf index: {}", e)))?;
+
+        let (tree_size, steps_part) = match steps_part.trim().strip_prefix("size:") {
+            Some(rest) => {
+                let (size, steps_part) = rest
+                    .split_once(',')
+                    .ok_or_else(|| invalid("missing steps".to_string()))?;
+                let size = size
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|e| invalid(format!("bad tree size: {}", e)))?;
+                (Some(size), steps_part)
+            }
+            None => (None, steps_part),
+        };
+
+        let steps_list = steps_part
+            .trim()
+            .strip_prefix("steps:")
+            .map(str::trim)
+            .and_then(|list| list.strip_prefix('['))
+            .and_then(|list| list.strip_suffix(']'))
+            .ok_or_else(|| invalid("expected `steps:[...]`".to_string()))?;
+
+        let steps = if steps_list.trim().is_empty() {
+            Vec::new()
+        } else {
+            steps_list
+                .split(',')
+                .map(|step| {
+                    let (direction, hash) = step
+                        .trim()
+                        .split_once(':')
+                        .ok_or_else(|| invalid(format!("bad step {:?}", step.trim())))?;
+                    let direction = match direction.trim() {
+                        "L" => ProofDirection::Left,
+                        "R" => ProofDirection::Right,
+                        other => return Err(invalid(format!("bad direction {:?}", other))),
+                    };
+                    Ok(ProofStep {
+                        hash: Hash::from_hex(hash.trim())?,
+                        direction,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        Ok(Self {
+            leaf_index,
+            steps,
+            tree_size,
+        })
+    }
+}
+
+/// A single step of a [`MerkleProofRef`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofStepRef<'a> {
+    pub hash: &'a Hash,
+    pub direction: ProofDirection,
+}
+
+/// Merkle proof whose sibling hashes are borrowed from the tree
+///
+/// Produced by [`MerkleTree::generate_proof_ref`](crate::MerkleTree::generate_proof_ref)
+/// for callers that verify or serialize proofs while the tree is alive.
+/// Convert it with [`MerkleProofRef::to_proof`] to keep it longer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProofRef<'a> {
+    pub leaf_index: usize,
+    pub steps: Vec<ProofStepRef<'a>>,
+}
+
+impl MerkleProofRef<'_> {
+    /// Get the number of steps in the proof
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Check if the proof is empty
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Verify the proof against a root hash and leaf data
+    pub fn verify<H>(&self, hasher: &H, leaf_data: &[u8], root: &RootHash) -> bool
+    where
+        H: crate::hasher::Hasher,
+    {
+        let leaf_hash = hasher.hash(leaf_data);
+        self.verify_with_leaf_hash(hasher, &leaf_hash, root)
+    }
+
+    /// Verify the proof with a pre-computed leaf hash
+    pub fn verify_with_leaf_hash<H>(&self, hasher: &H, leaf_hash: &Hash, root: &RootHash) -> bool
+    where
+        H: crate::hasher::Hasher,
+    {
+        let steps = self.steps.iter().map(|step| (step.hash, step.direction));
+        *root == fold_steps(hasher, leaf_hash, steps)
+    }
+
+    /// Compute the root hash from the proof and leaf hash
+    pub fn compute_root<H>(&self, hasher: &H, leaf_hash: &Hash) -> RootHash
+    where
+        H: crate::hasher::Hasher,
+    {
+        RootHash::new(fold_steps(
+            hasher,
+            leaf_hash,
+            self.steps.iter().map(|step| (step.hash, step.direction)),
+        ))
+    }
+
+    /// Copy the proof into an owned [`MerkleProof`]
+    pub fn to_proof(&self) -> MerkleProof {
+        MerkleProof::new(
+            self.leaf_index,
+            self.steps
+                .iter()
+                .map(|step| ProofStep {
+                    hash: step.hash.clone(),
+                    direction: step.direction,
+                })
+                .collect(),
+        )
+    }
+}
+
+impl From<MerkleProofRef<'_>> for MerkleProof {
+    fn from(proof: MerkleProofRef<'_>) -> Self {
+        proof.to_proof()
+    }
+}
+
+/// Size and verification cost of a proof, known before generating it
+///
+/// For gas estimation and bandwidth budgets. `bytes` counts only the hashes
+/// the proof carries, which dominate any of its encodings; directions, flags
+/// and framing add little on top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProofCost {
+    /// Number of parent hashes the verifier computes on the way to the root
+    pub steps: usize,
+    /// Total size of the hashes the proof carries
+    pub bytes: usize,
+    /// Number of hash invocations to verify, the steps plus hashing the
+    /// proven leaves
+    pub hashes_to_verify: usize,
+}
+
+/// Sibling directions on the path from leaf `index` to the root of a tree
+/// with `size` leaves, leaf level first, each with the level of the parent
+/// it hashes into
+pub(crate) fn path_steps(
+    index: usize,
+    size: usize,
+    shape: TreeShape,
+) -> Vec<(ProofDirection, usize)> {
+    let mut steps = Vec::new();
+    let (mut position, mut width, mut level) = (index, size, 1);
+    while width > 1 {
+        if !position.is_multiple_of(2) {
+            steps.push((ProofDirection::Left, level));
+        } else if position + 1 < width || shape == TreeShape::Padded {
+            steps.push((ProofDirection::Right, level));
+        }
+        position /= 2;
+        width = width.div_ceil(2);
+        level += 1;
+    }
+    steps
+}
+
+/// Hash a leaf up through sibling hashes, leaf level first
+fn fold_steps<'a, H, I>(hasher: &H, leaf_hash: &Hash, steps: I) -> Vec<u8>
+where
+    H: crate::hasher::Hasher,
+    I: Iterator<Item = (&'a Hash, ProofDirection)>,
+{
+    let mut folder = PathFolder::new(hasher, leaf_hash.to_vec());
+    for (sibling, direction) in steps {
+        folder.step(sibling, direction);
+    }
+    folder.finish()
+}
+
+/// Walks from a leaf to the root one sibling at a time
+///
+/// Parents are written into two reused buffers via
+/// [`Hasher::hash_pair_into`](crate::hasher::Hasher::hash_pair_into), so the
+/// cost does not grow with an allocation per step. The first parent is
+/// allocated with `hash_pair` to check that the hasher's pairs really are
+/// `output_size()` bytes; a hasher whose sizes disagree keeps allocating
+/// instead of overrunning the buffer.
+pub(crate) struct PathFolder<'a, H> {
+    hasher: &'a H,
+    current: Vec<u8>,
+    next: Vec<u8>,
+    buffered: bool,
+}
+
+impl<'a, H: crate::hasher::Hasher> PathFolder<'a, H> {
+    pub(crate) fn new(hasher: &'a H, leaf_hash: Vec<u8>) -> Self {
+        Self {
+            hasher,
+            current: leaf_hash,
+            next: Vec::new(),
+            buffered: false,
+        }
+    }
+
+    /// Replace the current node with its parent
+    pub(crate) fn step(&mut self, sibling: &[u8], direction: ProofDirection) {
+        let (left, right) = match direction {
+            ProofDirection::Left => (sibling, self.current.as_slice()),
+            ProofDirection::Right => (self.current.as_slice(), sibling),
+        };
+        if self.buffered {
+            self.hasher.hash_pair_into(left, right, &mut self.next);
+        } else {
+            self.next = self.hasher.hash_pair(left, right).to_vec();
+            self.buffered = self.next.len() == self.hasher.output_size();
+        }
+        std::mem::swap(&mut self.current, &mut self.next);
+        // Leaves need not be hasher-sized, but every parent is
+        if self.buffered && self.next.len() != self.current.len() {
+            self.next.resize(self.current.len(), 0);
+        }
+    }
+
+    /// Get the node reached so far
+    pub(crate) fn current(&self) -> &[u8] {
+        &self.current
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.current
+    }
+}
+
+/// [`MerkleProof`] tied to the hasher of the tree it came from
+///
+/// A proof only verifies with the hash function that built its tree, but an
+/// untyped proof handed the wrong hasher just fails to verify. Here the
+/// hasher is part of the type, so verifying a BLAKE3 proof with SHA-256 does
+/// not compile. [`MerkleProof::assume_hasher`] attaches a hasher to a proof
+/// whose origin is known some other way, and [`HashAlgorithm`] covers
+/// hashers chosen at runtime.
+///
+/// [`HashAlgorithm`]: crate::hasher::HashAlgorithm
+///
+/// ```compile_fail
+/// use merkle_tree::{Blake3Hasher, MerkleTree, Sha256Hasher};
+///
+/// let tree = MerkleTree::new(vec![b"a", b"b"], Blake3Hasher::new()).unwrap();
+/// let proof = tree.generate_typed_proof(0).unwrap();
+/// proof.verify(&Sha256Hasher::new(), b"a", &tree.root());
+/// ```
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent, bound = ""))]
+pub struct TypedProof<H> {
+    proof: MerkleProof,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hasher: PhantomData<fn() -> H>,
+}
+
+impl<H: crate::hasher::Hasher> TypedProof<H> {
+    /// Borrow the untyped proof
+    pub fn proof(&self) -> &MerkleProof {
+        &self.proof
+    }
+
+    /// Drop the hasher tag
+    pub fn into_untyped(self) -> MerkleProof {
+        self.proof
+    }
+
+    /// Verify the proof against a root hash and leaf data
+    pub fn verify(&self, hasher: &H, leaf_data: &[u8], root: &RootHash) -> bool {
+        self.proof.verify(hasher, leaf_data, root)
+    }
+
+    /// Verify the proof with a pre-computed leaf hash
+    pub fn verify_with_leaf_hash(&self, hasher: &H, leaf_hash: &Hash, root: &RootHash) -> bool {
+        self.proof.verify_with_leaf_hash(hasher, leaf_hash, root)
+    }
+
+    /// Compute the root hash from the proof and leaf hash
+    pub fn compute_root(&self, hasher: &H, leaf_hash: &Hash) -> RootHash {
+        self.proof.compute_root(hasher, leaf_hash)
+    }
+}
+
+impl<H> Clone for TypedProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            proof: self.proof.clone(),
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<H> fmt::Debug for TypedProof<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedProof")
+            .field("hasher", &std::any::type_name::<H>())
+            .field("proof", &self.proof)
+            .finish()
+    }
+}
+
+impl<H> PartialEq for TypedProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.proof == other.proof
+    }
+}
+
+impl<H> Eq for TypedProof<H> {}
+
+impl<H> From<TypedProof<H>> for MerkleProof {
+    fn from(proof: TypedProof<H>) -> Self {
+        proof.proof
+    }
+}
+
+/// Merkle proof for a salted leaf, carrying the salt needed to rebuild it
+///
+/// Salted leaves are derived from `salt || data`, so the salt has to be
+/// handed to the claimant together with the proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SaltedProof {
+    pub proof: MerkleProof,
+    pub salt: Vec<u8>,
+}
+
+impl SaltedProof {
+    /// Create a new salted proof
+    pub fn new(proof: MerkleProof, salt: Vec<u8>) -> Self {
+        Self { proof, salt }
+    }
+
+    /// Prefix the leaf data with the salt, as done when building the tree
+    pub fn salted_data(&self, leaf_data: &[u8]) -> Vec<u8> {
+        let mut salted = Vec::with_capacity(self.salt.len() + leaf_data.len());
+        salted.extend_from_slice(&self.salt);
+        salted.extend_from_slice(leaf_data);
+        salted
+    }
+
+    /// Verify the proof for a tree built with the default leaf encoding
+    ///
+    /// For other encodings, encode [`SaltedProof::salted_data`] and use
+    /// [`MerkleProof::verify_with_leaf_hash`].
+    pub fn verify<H>(&self, hasher: &H, leaf_data: &[u8], root: &RootHash) -> bool
+    where
+        H: crate::hasher::Hasher,
+    {
+        self.proof
+            .verify(hasher, &self.salted_data(leaf_data), root)
+    }
+}
+
+/// Merkle proof handed out with the annotation of its leaf
+///
+/// The annotation is caller metadata stored next to the leaf, see
+/// [`MerkleTree::annotate`](crate::MerkleTree::annotate). It is not part of
+/// the leaf hash, so verifying the proof says nothing about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AnnotatedProof {
+    pub proof: MerkleProof,
+    pub annotation: Option<Vec<u8>>,
+}
+
+impl AnnotatedProof {
+    /// Create a new annotated proof
+    pub fn new(proof: MerkleProof, annotation: Option<Vec<u8>>) -> Self {
+        Self { proof, annotation }
+    }
+
+    /// Verify the proof, ignoring the annotation
+    pub fn verify<H>(&self, hasher: &H, leaf_data: &[u8], root: &RootHash) -> bool
+    where
+        H: crate::hasher::Hasher,
+    {
+        self.proof.verify(hasher, leaf_data, root)
+    }
+}
+
+/// Outcome of [`verify_proofs_parallel`]
+#[cfg(feature = "rayon")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationReport {
+    /// Whether each item verified, in input order
+    pub results: Vec<bool>,
+    pub passed: usize,
+    pub failed: usize,
+    pub elapsed: std::time::Duration,
+}
+
+#[cfg(feature = "rayon")]
+impl VerificationReport {
+    /// Check whether every item verified
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+
+    /// Get the indices of the items that failed to verify
+    pub fn failures(&self) -> Vec<usize> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter(|(_, passed)| !**passed)
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Verify many `(proof, leaf data, root)` items on the rayon thread pool
+///
+/// Each item is checked as by [`MerkleProof::verify`]; a failure does not
+/// stop the others, and the report records which items failed.
+#[cfg(feature = "rayon")]
+pub fn verify_proofs_parallel<H, T>(
+    hasher: &H,
+    items: &[(MerkleProof, T, RootHash)],
+) -> VerificationReport
+where
+    H: crate::hasher::Hasher + Sync,
+    T: AsRef<[u8]> + Sync,
+{
+    use rayon::prelude::*;
+
+    let start = std::time::Instant::now();
+    let results: Vec<bool> = items
+        .par_iter()
+        .map(|(proof, leaf_data, root)| proof.verify(hasher, leaf_data.as_ref(), root))
+        .collect();
+    let passed = results.iter().filter(|&&passed| passed).count();
+
+    VerificationReport {
+        failed: results.len() - passed,
+        passed,
+        results,
+        elapsed: start.elapsed(),
+    }
+}
+
+impl fmt::Display for MerkleProof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl FromStr for MerkleProof {
+    type Err = MerkleError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_hex(s)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::builder::MerkleTreeBuilder;
     use crate::hasher::{Hasher, Sha256Hasher};
 
+    #[test]
+    fn test_check_position() {
+        let hasher = Sha256Hasher::new();
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            for size in 1..=9 {
+                let data: Vec<String> = (0..size).map(|i| format!("item_{}", i)).collect();
+                let tree = MerkleTreeBuilder::new(hasher.clone())
+                    .shape(shape)
+                    .build(data.clone())
+                    .unwrap();
+                for (index, item) in data.iter().enumerate() {
+                    let proof = tree.generate_proof(index).unwrap();
+                    assert!(proof.verify_indexed(
+                        &hasher,
+                        item.as_bytes(),
+                        &tree.root(),
+                        size,
+                        shape
+                    ));
+                }
+            }
+        }
+
+        // A valid path for leaf 3 cannot be passed off as leaf 5
+        let data: Vec<String> = (0..8).map(|i| format!("item_{}", i)).collect();
+        let tree = MerkleTreeBuilder::new(hasher.clone()).build(data).unwrap();
+        let mut proof = tree.generate_proof(3).unwrap();
+        proof.leaf_index = 5;
+        assert!(proof.verify(&hasher, b"item_3", &tree.root()));
+        assert!(!proof.verify_indexed(&hasher, b"item_3", &tree.root(), 8, TreeShape::Padded));
+        assert!(matches!(
+            proof.check_position(8, TreeShape::Padded),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+
+        proof.leaf_index = 3;
+        assert!(proof.check_position(8, TreeShape::Padded).is_ok());
+        assert!(proof.check_position(16, TreeShape::Padded).is_err());
+        assert!(matches!(
+            proof.check_position(3, TreeShape::Padded),
+            Err(MerkleError::InvalidIndex { index: 3, size: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_index_hint() {
+        let hasher = Sha256Hasher::new();
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            for size in 1..=9 {
+                let data: Vec<String> = (0..size).map(|i| format!("item_{}", i)).collect();
+                let tree = MerkleTreeBuilder::new(hasher.clone())
+                    .shape(shape)
+                    .build(data.clone())
+                    .unwrap();
+                for (index, item) in data.iter().enumerate() {
+                    let proof = tree.generate_proof(index).unwrap();
+                    assert!(!proof.verify_hinted(&hasher, item.as_bytes(), &tree.root(), shape));
+
+                    // Only the sibling hashes travel, as in Tendermint proofs
+                    let siblings = proof.steps.iter().map(|s| s.hash.clone()).collect();
+                    let hinted = MerkleProof::from_siblings(index, size, shape, siblings).unwrap();
+                    assert_eq!(hinted, proof.clone().with_index_hint(size));
+                    assert!(hinted.verify_hinted(&hasher, item.as_bytes(), &tree.root(), shape));
+                    assert_eq!(MerkleProof::from_hex(&hinted.to_hex()).unwrap(), hinted);
+                }
+            }
+        }
+
+        // Stored directions are ignored, and the hint pins the position
+        let data: Vec<String> = (0..8).map(|i| format!("item_{}", i)).collect();
+        let tree = MerkleTreeBuilder::new(hasher.clone()).build(data).unwrap();
+        let mut proof = tree.generate_proof(3).unwrap().with_index_hint(8);
+        for step in &mut proof.steps {
+            step.direction = ProofDirection::Left;
+        }
+        assert!(proof.verify_hinted(&hasher, b"item_3", &tree.root(), TreeShape::Padded));
+        proof.leaf_index = 5;
+        assert!(!proof.verify_hinted(&hasher, b"item_3", &tree.root(), TreeShape::Padded));
+        proof.leaf_index = 3;
+        proof.tree_size = Some(16);
+        assert!(!proof.verify_hinted(&hasher, b"item_3", &tree.root(), TreeShape::Padded));
+
+        assert!(matches!(
+            MerkleProof::from_siblings(8, 8, TreeShape::Padded, vec![]),
+            Err(MerkleError::InvalidIndex { index: 8, size: 8 })
+        ));
+        assert!(matches!(
+            MerkleProof::from_siblings(0, 8, TreeShape::Padded, vec![]),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+    }
+
+    #[test]
+    fn test_proof_translation() {
+        let hasher = Sha256Hasher::new();
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            for size in 1..=11 {
+                let data: Vec<String> = (0..size).map(|i| format!("item_{}", i)).collect();
+                let tree = MerkleTreeBuilder::new(hasher.clone())
+                    .shape(shape)
+                    .build(data.clone())
+                    .unwrap();
+                for index in 0..size {
+                    let proof = tree.generate_proof(index).unwrap();
+
+                    let siblings = proof.to_siblings(size, shape).unwrap();
+                    let back = MerkleProof::from_siblings(index, size, shape, siblings).unwrap();
+                    assert_eq!(back, proof.clone().with_index_hint(size));
+
+                    let bits = proof.to_path_bits().unwrap();
+                    if shape == TreeShape::Padded {
+                        assert_eq!(bits, index as u64);
+                    }
+                    let siblings = proof.steps.iter().map(|s| s.hash.clone()).collect();
+                    let back = MerkleProof::from_path_bits(index, bits, siblings).unwrap();
+                    assert_eq!(back, proof);
+                }
+            }
+        }
+
+        // Directions that disagree with the index cannot be dropped
+        let data: Vec<String> = (0..8).map(|i| format!("item_{}", i)).collect();
+        let tree = MerkleTreeBuilder::new(hasher.clone()).build(data).unwrap();
+        let mut proof = tree.generate_proof(3).unwrap();
+        proof.steps[2].direction = ProofDirection::Left;
+        assert!(proof.to_siblings(8, TreeShape::Padded).is_err());
+        assert_eq!(proof.to_path_bits().unwrap(), 0b111);
+
+        let siblings = vec![hasher.hash(b"a"); 2];
+        assert!(MerkleProof::from_path_bits(0, 0b100, siblings.clone()).is_err());
+        assert!(MerkleProof::from_path_bits(0, 0b11, siblings).is_ok());
+        assert!(MerkleProof::from_path_bits(0, 0, vec![hasher.hash(b"a"); 65]).is_err());
+    }
+
+    #[test]
+    fn test_typed_proof() {
+        use crate::hasher::{Blake3Hasher, HashAlgorithm};
+        use crate::merkle_tree::MerkleTree;
+
+        let data: Vec<&[u8]> = vec![b"a", b"b", b"c"];
+        let tree = MerkleTree::new(data.clone(), Blake3Hasher::new()).unwrap();
+        let proof = tree.generate_typed_proof(1).unwrap();
+        assert!(proof.verify(&Blake3Hasher::new(), b"b", &tree.root()));
+        assert!(!proof.verify(&Blake3Hasher::new(), b"a", &tree.root()));
+        assert_eq!(proof.proof(), &tree.generate_proof(1).unwrap());
+
+        // Runtime-selected hashers go through the escape hatch
+        let algorithm: HashAlgorithm = "blake3".parse().unwrap();
+        let dynamic = proof
+            .clone()
+            .into_untyped()
+            .assume_hasher::<HashAlgorithm>();
+        assert!(dynamic.verify(&algorithm, b"b", &tree.root()));
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&proof).unwrap();
+            assert_eq!(json, serde_json::to_string(proof.proof()).unwrap());
+            let back: TypedProof<Blake3Hasher> = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, proof);
+        }
+    }
+
     #[test]
     fn test_proof_creation() {
         let steps = vec![
             ProofStep {
-                hash: vec![1, 2, 3],
+                hash: Hash::from([1, 2, 3]),
                 direction: ProofDirection::Left,
             },
             ProofStep {
-                hash: vec![4, 5, 6],
+                hash: Hash::from([4, 5, 6]),
                 direction: ProofDirection::Right,
             },
         ];
@@ -137,11 +1149,11 @@ mod tests {
     fn test_proof_to_hex() {
         let steps = vec![
             ProofStep {
-                hash: vec![0x01, 0x02],
+                hash: Hash::from([0x01, 0x02]),
                 direction: ProofDirection::Left,
             },
             ProofStep {
-                hash: vec![0x03, 0x04],
+                hash: Hash::from([0x03, 0x04]),
                 direction: ProofDirection::Right,
             },
         ];
@@ -153,6 +1165,65 @@ mod tests {
         assert!(hex_repr.contains("R:0304"));
     }
 
+    #[test]
+    fn test_proof_from_hex_roundtrip() {
+        let hasher = Sha256Hasher::new();
+        let steps = vec![
+            ProofStep {
+                hash: hasher.hash(b"a"),
+                direction: ProofDirection::Left,
+            },
+            ProofStep {
+                hash: hasher.hash(b"b"),
+                direction: ProofDirection::Right,
+            },
+        ];
+
+        let proof = MerkleProof::new(5, steps);
+        let text = proof.to_hex();
+        assert_eq!(MerkleProof::from_hex(&text).unwrap(), proof);
+        assert_eq!(text.parse::<MerkleProof>().unwrap(), proof);
+        assert_eq!(proof.to_string(), text);
+
+        let empty = MerkleProof::new(0, vec![]);
+        assert_eq!(empty.to_hex(), "index:0, steps:[]");
+        assert_eq!(MerkleProof::from_hex(&empty.to_hex()).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_proof_from_hex_lenient_whitespace() {
+        let proof = MerkleProof::from_hex("  index: 3 ,steps: [ L:0xAB01 ,R:cd ]\n").unwrap();
+        assert_eq!(proof.leaf_index, 3);
+        assert_eq!(proof.steps[0].hash, [0xab, 0x01]);
+        assert_eq!(proof.steps[0].direction, ProofDirection::Left);
+        assert_eq!(proof.steps[1].hash, [0xcd]);
+        assert_eq!(proof.steps[1].direction, ProofDirection::Right);
+    }
+
+    #[test]
+    fn test_proof_from_hex_invalid() {
+        for text in [
+            "",
+            "index:1",
+            "index:x, steps:[]",
+            "index:1, steps:",
+            "index:1, steps:[L:0102",
+            "index:1, steps:[X:0102]",
+            "index:1, steps:[L0102]",
+            "index:1, steps:[L:zz]",
+            "index:1, steps:[L:0102,]",
+        ] {
+            assert!(
+                matches!(
+                    MerkleProof::from_hex(text),
+                    Err(MerkleError::SerializationError { .. })
+                ),
+                "{:?}",
+                text
+            );
+        }
+    }
+
     #[test]
     fn test_compute_root() {
         let hasher = Sha256Hasher::new();
@@ -169,7 +1240,7 @@ mod tests {
 
         // The computed root should be the hash of leaf_hash + sibling_hash
         let expected_root = hasher.hash_pair(&leaf_hash, &sibling_hash);
-        assert_eq!(root, expected_root);
+        assert_eq!(root, RootHash::from(expected_root));
     }
 
     #[test]
@@ -177,7 +1248,7 @@ mod tests {
         let hasher = Sha256Hasher::new();
         let leaf_hash = hasher.hash(b"leaf");
         let sibling_hash = hasher.hash(b"sibling");
-        let root = hasher.hash_pair(&leaf_hash, &sibling_hash);
+        let root = RootHash::from(hasher.hash_pair(&leaf_hash, &sibling_hash));
 
         let steps = vec![ProofStep {
             hash: sibling_hash,
@@ -188,17 +1259,102 @@ mod tests {
         assert!(proof.verify_with_leaf_hash(&hasher, &leaf_hash, &root));
 
         // Test with wrong root
-        let wrong_root = hasher.hash(b"wrong");
+        let wrong_root = RootHash::from(hasher.hash(b"wrong"));
         assert!(!proof.verify_with_leaf_hash(&hasher, &leaf_hash, &wrong_root));
     }
 
+    #[test]
+    fn test_proof_ref_roundtrip() {
+        let hasher = Sha256Hasher::new();
+        let leaf_hash = hasher.hash(b"leaf");
+        let sibling_hash = hasher.hash(b"sibling");
+        let root = RootHash::from(hasher.hash_pair(&sibling_hash, &leaf_hash));
+
+        let proof = MerkleProof::new(
+            1,
+            vec![ProofStep {
+                hash: sibling_hash,
+                direction: ProofDirection::Left,
+            }],
+        );
+        let borrowed = proof.as_proof_ref();
+        assert_eq!(borrowed.len(), 1);
+        assert!(borrowed.verify(&hasher, b"leaf", &root));
+        assert!(!borrowed.verify(&hasher, b"wrong", &root));
+        assert_eq!(MerkleProof::from(borrowed), proof);
+    }
+
+    #[test]
+    fn test_chain() {
+        use crate::merkle_tree::MerkleTree;
+
+        let hasher = Sha256Hasher::new();
+        let inner = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+        let outer_leaves = vec![hasher.hash(b"x"), inner.root().into_hash()];
+        let outer = MerkleTree::from_leaves(outer_leaves, hasher.clone()).unwrap();
+
+        let chained = inner
+            .generate_proof(2)
+            .unwrap()
+            .chain(outer.generate_proof(1).unwrap())
+            .unwrap();
+        // Leaf 2 of the 4-leaf subtree under outer leaf 1
+        assert_eq!(chained.leaf_index, 6);
+        assert_eq!(chained.len(), 3);
+        assert!(chained.verify(&hasher, b"c", &outer.root()));
+        assert!(!chained.verify(&hasher, b"a", &outer.root()));
+
+        // A flat tree over the same leaves accepts it at its global index
+        let data = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let left = MerkleTree::new(data[..4].to_vec(), hasher.clone()).unwrap();
+        let right = MerkleTree::new(data[4..].to_vec(), hasher.clone()).unwrap();
+        let flat = MerkleTree::new(data.to_vec(), hasher.clone()).unwrap();
+        let parent = MerkleTree::from_leaves(
+            vec![left.root().into_hash(), right.root().into_hash()],
+            hasher.clone(),
+        )
+        .unwrap();
+        let chained = right
+            .generate_proof(1)
+            .unwrap()
+            .chain(parent.generate_proof(1).unwrap())
+            .unwrap();
+        assert_eq!(chained, flat.generate_proof(5).unwrap());
+        assert!(chained.verify_indexed(&hasher, b"f", &flat.root(), 8, TreeShape::Padded));
+        assert!(flat.verify_proof_against_root(&chained, b"f"));
+
+        // Promoted RFC 6962 steps leave the position within the subtree open
+        let promoted = crate::builder::MerkleTreeBuilder::new(hasher.clone())
+            .shape(TreeShape::Rfc6962)
+            .build(vec!["a", "b", "c"])
+            .unwrap()
+            .generate_proof(2)
+            .unwrap();
+        assert!(matches!(
+            promoted.chain(outer.generate_proof(1).unwrap()),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+
+        let short = MerkleProof::new(
+            0,
+            vec![ProofStep {
+                hash: Hash::from([0u8; 20]),
+                direction: ProofDirection::Left,
+            }],
+        );
+        assert!(matches!(
+            inner.generate_proof(0).unwrap().chain(short),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+    }
+
     #[test]
     fn test_verify() {
         let hasher = Sha256Hasher::new();
         let leaf_data = b"leaf";
         let leaf_hash = hasher.hash(leaf_data);
         let sibling_hash = hasher.hash(b"sibling");
-        let root = hasher.hash_pair(&leaf_hash, &sibling_hash);
+        let root = RootHash::from(hasher.hash_pair(&leaf_hash, &sibling_hash));
 
         let steps = vec![ProofStep {
             hash: sibling_hash,
@@ -211,4 +1367,78 @@ mod tests {
         // Test with wrong leaf data
         assert!(!proof.verify(&hasher, b"wrong", &root));
     }
+
+    /// Reports 32 bytes but truncates SHA-256 to 20
+    #[derive(Clone)]
+    struct ShortHasher;
+
+    impl Hasher for ShortHasher {
+        fn hash(&self, data: &[u8]) -> Hash {
+            Hash::new(Sha256Hasher::new().hash(data).as_bytes()[..20].to_vec())
+        }
+
+        fn output_size(&self) -> usize {
+            32
+        }
+
+        fn name(&self) -> &'static str {
+            "short"
+        }
+    }
+
+    #[test]
+    fn test_verify_mismatched_output_size() {
+        let hasher = ShortHasher;
+        let data: Vec<String> = (0..5).map(|i| format!("item_{}", i)).collect();
+        let leaves: Vec<Hash> = data
+            .iter()
+            .map(|item| hasher.hash(item.as_bytes()))
+            .collect();
+        let left = hasher.hash_pair(&leaves[0], &leaves[1]);
+        let right = hasher.hash_pair(&leaves[2], &leaves[3]);
+        let root = RootHash::from(hasher.hash_pair(&left, &right));
+
+        let proof = MerkleProof::new(
+            2,
+            vec![
+                ProofStep {
+                    hash: leaves[3].clone(),
+                    direction: ProofDirection::Right,
+                },
+                ProofStep {
+                    hash: left,
+                    direction: ProofDirection::Left,
+                },
+            ],
+        );
+        assert_eq!(proof.compute_root(&hasher, &leaves[2]), root);
+        assert!(proof.verify(&hasher, b"item_2", &root));
+        assert!(!proof.verify(&hasher, b"item_4", &root));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_verify_proofs_parallel() {
+        use crate::merkle_tree::MerkleTree;
+
+        let hasher = Sha256Hasher::new();
+        let data: Vec<String> = (0..100).map(|i| format!("item_{}", i)).collect();
+        let tree = MerkleTree::new(data.clone(), hasher.clone()).unwrap();
+
+        let mut items: Vec<(MerkleProof, String, RootHash)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (tree.generate_proof(i).unwrap(), item.clone(), tree.root()))
+            .collect();
+        let report = verify_proofs_parallel(&hasher, &items);
+        assert!(report.all_passed());
+        assert_eq!(report.passed, 100);
+
+        items[3].1 = "tampered".to_string();
+        items[70].2 = RootHash::from(hasher.hash(b"other"));
+        let report = verify_proofs_parallel(&hasher, &items);
+        assert_eq!((report.passed, report.failed), (98, 2));
+        assert_eq!(report.failures(), vec![3, 70]);
+        assert!(!report.results[3] && report.results[4]);
+    }
 }
@@ -0,0 +1,362 @@
+//! Protobuf messages for exchanging proofs
+//!
+//! The messages mirror `proto/merkle.proto` (package `merkle.v1`) and are
+//! declared with `prost` derives directly, so building the crate does not need
+//! `protoc`. Services that generate their own types from the `.proto` file
+//! interoperate on the wire.
+//!
+//! Each proof type converts to its message with [`From`] and back with
+//! [`TryFrom`], and has `encode_protobuf` / `decode_protobuf` shortcuts for
+//! the encoded bytes.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::Hash;
+use prost::Message;
+
+/// Side of a proof step
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Direction {
+    Unspecified = 0,
+    Left = 1,
+    Right = 2,
+}
+
+/// Wire form of [`crate::proof::ProofStep`]
+#[derive(Clone, PartialEq, Message)]
+pub struct ProofStep {
+    #[prost(bytes = "vec", tag = "1")]
+    pub hash: Vec<u8>,
+    #[prost(enumeration = "Direction", tag = "2")]
+    pub direction: i32,
+}
+
+/// Wire form of [`crate::MerkleProof`]
+#[derive(Clone, PartialEq, Message)]
+pub struct MerkleProof {
+    #[prost(uint64, tag = "1")]
+    pub leaf_index: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub steps: Vec<ProofStep>,
+}
+
+/// Wire form of [`crate::MultiProof`]
+#[derive(Clone, PartialEq, Message)]
+pub struct MultiProof {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub leaves: Vec<Vec<u8>>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub proof: Vec<Vec<u8>>,
+    #[prost(bool, repeated, tag = "3")]
+    pub proof_flags: Vec<bool>,
+}
+
+/// Wire form of [`crate::SparseProof`]
+///
+/// Empty subtree siblings are sent as empty bytes.
+#[derive(Clone, PartialEq, Message)]
+pub struct SparseProof {
+    #[prost(uint64, tag = "1")]
+    pub index: u64,
+    #[prost(uint32, tag = "2")]
+    pub depth: u32,
+    #[prost(bytes = "vec", repeated, tag = "3")]
+    pub siblings: Vec<Vec<u8>>,
+}
+
+/// Request for `MerkleService.GetRoot`
+#[derive(Clone, PartialEq, Message)]
+pub struct GetRootRequest {}
+
+/// Response for `MerkleService.GetRoot`
+#[derive(Clone, PartialEq, Message)]
+pub struct GetRootResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub tree_size: u64,
+}
+
+/// Request for `MerkleService.GetProof`
+#[derive(Clone, PartialEq, Message)]
+pub struct GetProofRequest {
+    #[prost(uint64, tag = "1")]
+    pub leaf_index: u64,
+    /// Size of the tree to prove against, 0 for the current size
+    #[prost(uint64, tag = "2")]
+    pub tree_size: u64,
+}
+
+/// Response for `MerkleService.GetProof`
+#[derive(Clone, PartialEq, Message)]
+pub struct GetProofResponse {
+    #[prost(message, optional, tag = "1")]
+    pub proof: Option<MerkleProof>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub root: Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub tree_size: u64,
+}
+
+/// Request for `MerkleService.VerifyProof`
+#[derive(Clone, PartialEq, Message)]
+pub struct VerifyProofRequest {
+    #[prost(message, optional, tag = "1")]
+    pub proof: Option<MerkleProof>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub leaf_data: Vec<u8>,
+    /// Root to verify against, empty for the current root
+    #[prost(bytes = "vec", tag = "3")]
+    pub root: Vec<u8>,
+}
+
+/// Response for `MerkleService.VerifyProof`
+#[derive(Clone, PartialEq, Message)]
+pub struct VerifyProofResponse {
+    #[prost(bool, tag = "1")]
+    pub valid: bool,
+}
+
+/// Request for `MerkleService.AppendLeaf`
+#[derive(Clone, PartialEq, Message)]
+pub struct AppendLeafRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+}
+
+/// Response for `MerkleService.AppendLeaf`
+#[derive(Clone, PartialEq, Message)]
+pub struct AppendLeafResponse {
+    #[prost(uint64, tag = "1")]
+    pub leaf_index: u64,
+    #[prost(bytes = "vec", tag = "2")]
+    pub root: Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub tree_size: u64,
+    /// Inclusion proof of the new leaf against `root`
+    #[prost(message, optional, tag = "4")]
+    pub proof: Option<MerkleProof>,
+}
+
+impl From<&crate::proof::ProofStep> for ProofStep {
+    fn from(step: &crate::proof::ProofStep) -> Self {
+        let direction = match step.direction {
+            crate::proof::ProofDirection::Left => Direction::Left,
+            crate::proof::ProofDirection::Right => Direction::Right,
+        };
+        Self {
+            hash: step.hash.to_vec(),
+            direction: direction as i32,
+        }
+    }
+}
+
+impl TryFrom<ProofStep> for crate::proof::ProofStep {
+    type Error = MerkleError;
+
+    fn try_from(step: ProofStep) -> Result<Self> {
+        let direction = match Direction::try_from(step.direction) {
+            Ok(Direction::Left) => crate::proof::ProofDirection::Left,
+            Ok(Direction::Right) => crate::proof::ProofDirection::Right,
+            _ => {
+                return Err(invalid(&format!(
+                    "Invalid proof step direction {}",
+                    step.direction
+                )))
+            }
+        };
+        Ok(Self {
+            hash: Hash::new(step.hash),
+            direction,
+        })
+    }
+}
+
+impl From<&crate::MerkleProof> for MerkleProof {
+    fn from(proof: &crate::MerkleProof) -> Self {
+        Self {
+            leaf_index: proof.leaf_index as u64,
+            steps: proof.steps.iter().map(ProofStep::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<MerkleProof> for crate::MerkleProof {
+    type Error = MerkleError;
+
+    fn try_from(proof: MerkleProof) -> Result<Self> {
+        let leaf_index = usize::try_from(proof.leaf_index)
+            .map_err(|_| invalid("Leaf index does not fit in usize"))?;
+        let steps = proof
+            .steps
+            .into_iter()
+            .map(crate::proof::ProofStep::try_from)
+            .collect::<Result<_>>()?;
+        Ok(Self::new(leaf_index, steps))
+    }
+}
+
+impl From<&crate::MultiProof> for MultiProof {
+    fn from(proof: &crate::MultiProof) -> Self {
+        Self {
+            leaves: proof.leaves.iter().map(|hash| hash.to_vec()).collect(),
+            proof: proof.proof.iter().map(|hash| hash.to_vec()).collect(),
+            proof_flags: proof.proof_flags.clone(),
+        }
+    }
+}
+
+impl TryFrom<MultiProof> for crate::MultiProof {
+    type Error = MerkleError;
+
+    fn try_from(proof: MultiProof) -> Result<Self> {
+        Ok(Self::new(
+            proof.leaves.into_iter().map(Hash::new).collect(),
+            proof.proof.into_iter().map(Hash::new).collect(),
+            proof.proof_flags,
+        ))
+    }
+}
+
+impl From<&crate::SparseProof> for SparseProof {
+    fn from(proof: &crate::SparseProof) -> Self {
+        Self {
+            index: proof.index,
+            depth: proof.depth as u32,
+            siblings: proof
+                .siblings
+                .iter()
+                .map(|sibling| {
+                    sibling
+                        .as_ref()
+                        .map(|hash| hash.to_vec())
+                        .unwrap_or_default()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<SparseProof> for crate::SparseProof {
+    type Error = MerkleError;
+
+    fn try_from(proof: SparseProof) -> Result<Self> {
+        let depth = u8::try_from(proof.depth)
+            .ok()
+            .filter(|depth| (1..=64).contains(depth))
+            .ok_or_else(|| invalid(&format!("Invalid sparse tree depth {}", proof.depth)))?;
+        if proof.siblings.len() != depth as usize {
+            return Err(invalid(&format!(
+                "Sparse proof has {} siblings, expected depth {}",
+                proof.siblings.len(),
+                depth
+            )));
+        }
+        let siblings = proof
+            .siblings
+            .into_iter()
+            .map(|sibling| (!sibling.is_empty()).then(|| Hash::new(sibling)))
+            .collect();
+        Ok(Self {
+            index: proof.index,
+            depth,
+            siblings,
+        })
+    }
+}
+
+/// Add `encode_protobuf` / `decode_protobuf` to a proof type
+macro_rules! impl_protobuf_codec {
+    ($native:ty, $message:ty) => {
+        impl $native {
+            /// Encode as a protobuf message
+            pub fn encode_protobuf(&self) -> Vec<u8> {
+                <$message>::from(self).encode_to_vec()
+            }
+
+            /// Decode from a protobuf message
+            pub fn decode_protobuf(bytes: &[u8]) -> Result<Self> {
+                let message =
+                    <$message>::decode(bytes).map_err(|e| MerkleError::SerializationError {
+                        message: e.to_string(),
+                    })?;
+                Self::try_from(message)
+            }
+        }
+    };
+}
+
+impl_protobuf_codec!(crate::MerkleProof, MerkleProof);
+impl_protobuf_codec!(crate::MultiProof, MultiProof);
+impl_protobuf_codec!(crate::SparseProof, SparseProof);
+
+fn invalid(message: &str) -> MerkleError {
+    MerkleError::SerializationError {
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Keccak256Hasher, Sha256Hasher, SortedPairHasher};
+    use crate::merkle_tree::MerkleTree;
+    use crate::sparse::SparseMerkleTree;
+
+    #[test]
+    fn test_merkle_proof_roundtrip() {
+        let tree = MerkleTree::new(vec!["a", "b", "c", "d", "e"], Sha256Hasher::new()).unwrap();
+        for index in 0..tree.len() {
+            let proof = tree.generate_proof(index).unwrap();
+            let decoded = crate::MerkleProof::decode_protobuf(&proof.encode_protobuf()).unwrap();
+            assert_eq!(decoded, proof);
+        }
+    }
+
+    #[test]
+    fn test_multiproof_roundtrip() {
+        let hasher = SortedPairHasher::new(Keccak256Hasher::new());
+        let tree = MerkleTree::new(vec!["a", "b", "c", "d", "e"], hasher).unwrap();
+        let proof = tree.generate_multiproof(&[0, 3, 4]).unwrap();
+        let decoded = crate::MultiProof::decode_protobuf(&proof.encode_protobuf()).unwrap();
+        assert_eq!(decoded, proof);
+    }
+
+    #[test]
+    fn test_sparse_proof_roundtrip() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = SparseMerkleTree::new(16, hasher.clone()).unwrap();
+        tree.update(5, b"five").unwrap();
+        tree.update(40_000, b"big").unwrap();
+
+        let proof = tree.generate_sparse_proof(5).unwrap();
+        let decoded = crate::SparseProof::decode_protobuf(&proof.encode_protobuf()).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(decoded.verify(&hasher, b"five", tree.root()));
+    }
+
+    #[test]
+    fn test_rejects_invalid_messages() {
+        let step = ProofStep {
+            hash: vec![1; 32],
+            direction: Direction::Unspecified as i32,
+        };
+        let message = MerkleProof {
+            leaf_index: 0,
+            steps: vec![step],
+        };
+        assert!(crate::MerkleProof::try_from(message).is_err());
+
+        let message = SparseProof {
+            index: 0,
+            depth: 2,
+            siblings: vec![vec![]],
+        };
+        assert!(crate::SparseProof::try_from(message).is_err());
+
+        assert!(matches!(
+            crate::MerkleProof::decode_protobuf(&[0xff, 0xff]),
+            Err(MerkleError::SerializationError { .. })
+        ));
+    }
+}
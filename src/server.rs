@@ -0,0 +1,342 @@
+//! gRPC proof service
+//!
+//! [`ProofService`] serves `merkle.v1.MerkleService` from
+//! `proto/merkle.proto` over a shared [`MerkleLog`]. It is a plain tower
+//! service, so it can be mounted directly on a tonic server:
+//!
+//! ```rust,ignore
+//! let service = ProofService::new(MerkleLog::new(Sha256Hasher::new()));
+//! tonic::transport::Server::builder()
+//!     .add_service(service)
+//!     .serve(addr)
+//!     .await?;
+//! ```
+//!
+//! The same handlers are exposed as methods for in-process callers.
+
+use crate::error::MerkleError;
+use crate::hash::RootHash;
+use crate::hasher::Hasher;
+use crate::log::{LogStore, MerkleLog, NoStore};
+use crate::proto::{
+    AppendLeafRequest, AppendLeafResponse, GetProofRequest, GetProofResponse, GetRootRequest,
+    GetRootResponse, VerifyProofRequest, VerifyProofResponse,
+};
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::task::{Context, Poll};
+use tonic::codegen::{http, Body, BoxFuture, Service, StdError};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::Status;
+use tonic_prost::ProstCodec;
+
+/// Handler signature shared by all RPCs
+type Handler<H, S, Req, Resp> = fn(&ProofService<H, S>, Req) -> Result<Resp, Status>;
+
+/// gRPC service answering root and proof queries for a shared log
+pub struct ProofService<H: Hasher, S: LogStore = NoStore> {
+    log: Arc<RwLock<MerkleLog<H, S>>>,
+}
+
+impl<H: Hasher, S: LogStore> ProofService<H, S> {
+    /// Serve a log
+    pub fn new(log: MerkleLog<H, S>) -> Self {
+        Self::from_shared(Arc::new(RwLock::new(log)))
+    }
+
+    /// Serve a log that is also appended to outside the service
+    pub fn from_shared(log: Arc<RwLock<MerkleLog<H, S>>>) -> Self {
+        Self { log }
+    }
+
+    /// Get the shared log
+    pub fn log(&self) -> &Arc<RwLock<MerkleLog<H, S>>> {
+        &self.log
+    }
+
+    /// Handle `GetRoot`
+    pub fn get_root(&self, _request: GetRootRequest) -> Result<GetRootResponse, Status> {
+        let log = self.read()?;
+        Ok(GetRootResponse {
+            root: log.root().to_vec(),
+            tree_size: log.len() as u64,
+        })
+    }
+
+    /// Handle `GetProof`
+    pub fn get_proof(&self, request: GetProofRequest) -> Result<GetProofResponse, Status> {
+        let log = self.read()?;
+        let tree_size = match request.tree_size {
+            0 => log.len(),
+            size => to_usize(size)?,
+        };
+        let proof = log
+            .prove_inclusion(to_usize(request.leaf_index)?, tree_size)
+            .map_err(status)?;
+        let root = log.root_at(tree_size).map_err(status)?;
+
+        Ok(GetProofResponse {
+            proof: Some((&proof).into()),
+            root: root.to_vec(),
+            tree_size: tree_size as u64,
+        })
+    }
+
+    /// Handle `VerifyProof`
+    pub fn verify_proof(&self, request: VerifyProofRequest) -> Result<VerifyProofResponse, Status> {
+        let proof: crate::MerkleProof = request
+            .proof
+            .ok_or_else(|| Status::invalid_argument("Missing proof"))?
+            .try_into()
+            .map_err(status)?;
+
+        let log = self.read()?;
+        let root = if request.root.is_empty() {
+            log.root()
+        } else {
+            RootHash::from(request.root)
+        };
+
+        Ok(VerifyProofResponse {
+            valid: proof.verify(log.hasher(), &request.leaf_data, &root),
+        })
+    }
+
+    /// Handle `AppendLeaf`
+    pub fn append_leaf(&self, request: AppendLeafRequest) -> Result<AppendLeafResponse, Status> {
+        let mut log = self.write()?;
+        let (index, proof) = log.append(&request.data).map_err(status)?;
+
+        Ok(AppendLeafResponse {
+            leaf_index: index as u64,
+            root: log.root().to_vec(),
+            tree_size: log.len() as u64,
+            proof: Some((&proof).into()),
+        })
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, MerkleLog<H, S>>, Status> {
+        self.log
+            .read()
+            .map_err(|_| Status::internal("Log lock poisoned"))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, MerkleLog<H, S>>, Status> {
+        self.log
+            .write()
+            .map_err(|_| Status::internal("Log lock poisoned"))
+    }
+
+    fn unary<B, Req, Resp>(
+        &self,
+        request: http::Request<B>,
+        handler: Handler<H, S, Req, Resp>,
+    ) -> BoxFuture<http::Response<tonic::body::Body>, Infallible>
+    where
+        H: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+        Req: prost::Message + Default + Send + 'static,
+        Resp: prost::Message + Send + 'static,
+    {
+        let method = Method {
+            service: self.clone(),
+            handler,
+        };
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::<Resp, Req>::default());
+            Ok(grpc.unary(method, request).await)
+        })
+    }
+}
+
+impl<H: Hasher, S: LogStore> Clone for ProofService<H, S> {
+    fn clone(&self) -> Self {
+        Self {
+            log: Arc::clone(&self.log),
+        }
+    }
+}
+
+impl<H, S, B> Service<http::Request<B>> for ProofService<H, S>
+where
+    H: Hasher + Send + Sync + 'static,
+    S: LogStore + Send + Sync + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        match request.uri().path() {
+            "/merkle.v1.MerkleService/GetRoot" => self.unary(request, Self::get_root),
+            "/merkle.v1.MerkleService/GetProof" => self.unary(request, Self::get_proof),
+            "/merkle.v1.MerkleService/VerifyProof" => self.unary(request, Self::verify_proof),
+            "/merkle.v1.MerkleService/AppendLeaf" => self.unary(request, Self::append_leaf),
+            _ => Box::pin(async { Ok(Status::unimplemented("Unknown method").into_http()) }),
+        }
+    }
+}
+
+impl<H: Hasher, S: LogStore> NamedService for ProofService<H, S> {
+    const NAME: &'static str = "merkle.v1.MerkleService";
+}
+
+/// Adapter running a synchronous handler as a tonic unary service
+struct Method<H: Hasher, S: LogStore, Req, Resp> {
+    service: ProofService<H, S>,
+    handler: Handler<H, S, Req, Resp>,
+}
+
+impl<H: Hasher, S: LogStore, Req, Resp> UnaryService<Req> for Method<H, S, Req, Resp> {
+    type Response = Resp;
+    type Future = Ready<Result<tonic::Response<Resp>, Status>>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        ready((self.handler)(&self.service, request.into_inner()).map(tonic::Response::new))
+    }
+}
+
+fn to_usize(value: u64) -> Result<usize, Status> {
+    usize::try_from(value).map_err(|_| Status::out_of_range(format!("{} is too large", value)))
+}
+
+fn status(error: MerkleError) -> Status {
+    match error {
+        MerkleError::InvalidIndex { .. } => Status::out_of_range(error.to_string()),
+        MerkleError::InvalidProof { .. } | MerkleError::SerializationError { .. } => {
+            Status::invalid_argument(error.to_string())
+        }
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+    use http_body_util::{BodyExt, Full};
+    use prost::Message;
+    use tonic::codegen::Bytes;
+
+    fn service() -> ProofService<Sha256Hasher> {
+        let service = ProofService::new(MerkleLog::new(Sha256Hasher::new()));
+        for data in ["a", "b", "c"] {
+            service
+                .append_leaf(AppendLeafRequest {
+                    data: data.as_bytes().to_vec(),
+                })
+                .unwrap();
+        }
+        service
+    }
+
+    #[test]
+    fn test_handlers() {
+        let service = service();
+        let root = service.get_root(GetRootRequest {}).unwrap();
+        assert_eq!(root.tree_size, 3);
+
+        let response = service
+            .get_proof(GetProofRequest {
+                leaf_index: 1,
+                tree_size: 0,
+            })
+            .unwrap();
+        assert_eq!(response.root, root.root);
+
+        let verify = |leaf_data: &[u8], root: Vec<u8>| {
+            service
+                .verify_proof(VerifyProofRequest {
+                    proof: response.proof.clone(),
+                    leaf_data: leaf_data.to_vec(),
+                    root,
+                })
+                .unwrap()
+                .valid
+        };
+        assert!(verify(b"b", Vec::new()));
+        assert!(verify(b"b", root.root.clone()));
+        assert!(!verify(b"c", Vec::new()));
+
+        // Proofs against an older size stay valid for that size's root
+        let old = service
+            .get_proof(GetProofRequest {
+                leaf_index: 0,
+                tree_size: 2,
+            })
+            .unwrap();
+        let appended = service
+            .append_leaf(AppendLeafRequest {
+                data: b"d".to_vec(),
+            })
+            .unwrap();
+        assert_eq!(appended.leaf_index, 3);
+        assert_ne!(appended.root, root.root);
+        assert!(
+            service
+                .verify_proof(VerifyProofRequest {
+                    proof: old.proof,
+                    leaf_data: b"a".to_vec(),
+                    root: old.root,
+                })
+                .unwrap()
+                .valid
+        );
+    }
+
+    #[test]
+    fn test_handler_errors() {
+        let service = service();
+        let error = service
+            .get_proof(GetProofRequest {
+                leaf_index: 3,
+                tree_size: 0,
+            })
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::OutOfRange);
+
+        let error = service
+            .verify_proof(VerifyProofRequest::default())
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    }
+
+    /// Frame a message as a gRPC request body
+    fn grpc_request(path: &str, message: impl Message) -> http::Request<Full<Bytes>> {
+        let encoded = message.encode_to_vec();
+        let mut body = vec![0];
+        body.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        body.extend_from_slice(&encoded);
+
+        http::Request::builder()
+            .method("POST")
+            .uri(path)
+            .header("content-type", "application/grpc")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_grpc_routing() {
+        let mut service = service();
+
+        let request = grpc_request("/merkle.v1.MerkleService/GetRoot", GetRootRequest {});
+        let response = service.call(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let root = GetRootResponse::decode(&body[5..]).unwrap();
+        assert_eq!(root, service.get_root(GetRootRequest {}).unwrap());
+
+        let request = grpc_request("/merkle.v1.MerkleService/Nope", GetRootRequest {});
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.headers()["grpc-status"], "12");
+    }
+}
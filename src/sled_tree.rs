@@ -0,0 +1,372 @@
+//! Merkle tree persisted in a sled keyspace
+//!
+//! [`SledTree`] stores every node of a [`MerkleTree`] level by level in a
+//! [`sled::Tree`], so an append-only log survives restarts without replaying
+//! its leaves: [`SledTree::open`] only reads the leaf count, and appending or
+//! proving touches `O(log n)` nodes. Each append writes the new leaf, the
+//! changed nodes above it and the new leaf count in one atomic batch.
+//!
+//! Keys are the level as a `u8` followed by the index as a big-endian `u64`;
+//! the key `meta` holds the hasher name, the tree shape and the leaf count.
+//! Roots and proofs match a [`MerkleTree`] of the same shape built over the
+//! same leaves.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::merkle_tree::{MerkleTree, NodeDomain, TreeShape};
+use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+
+const META_KEY: &[u8] = b"meta";
+
+/// Append-only Merkle tree whose levels live in a sled keyspace
+///
+/// ```rust
+/// use merkle_tree::{Sha256Hasher, SledTree, TreeShape};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let db = sled::Config::new().temporary(true).open().unwrap();
+/// let keyspace = db.open_tree("log").unwrap();
+///
+/// let mut tree = SledTree::open(keyspace.clone(), Sha256Hasher::new(), TreeShape::Padded)?;
+/// tree.append(b"alice")?;
+/// tree.append(b"bob")?;
+/// drop(tree);
+///
+/// // Reopening reads the leaf count, not the leaves
+/// let tree = SledTree::open(keyspace, Sha256Hasher::new(), TreeShape::Padded)?;
+/// let proof = tree.generate_proof(1)?;
+/// assert!(proof.verify(tree.hasher(), b"bob", &tree.root()?));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SledTree<H: Hasher> {
+    keyspace: sled::Tree,
+    hasher: H,
+    shape: TreeShape,
+    len: usize,
+}
+
+impl<H: Hasher> SledTree<H> {
+    /// Open the tree stored in `keyspace`, or start an empty one
+    ///
+    /// A keyspace written with another hasher or shape is rejected with
+    /// [`MerkleError::SerializationError`].
+    pub fn open(keyspace: sled::Tree, hasher: H, shape: TreeShape) -> Result<Self> {
+        let mut tree = Self {
+            keyspace,
+            hasher,
+            shape,
+            len: 0,
+        };
+
+        if let Some(meta) = tree.keyspace.get(META_KEY).map_err(sled_error)? {
+            let (name, stored_shape, len) = decode_meta(&meta)?;
+            if name != tree.hasher.name().as_bytes() {
+                return Err(invalid(&format!(
+                    "Tree was written with hasher {:?}, not {}",
+                    String::from_utf8_lossy(name),
+                    tree.hasher.name()
+                )));
+            }
+            if stored_shape != shape {
+                return Err(invalid(&format!(
+                    "Tree was written with shape {:?}, not {:?}",
+                    stored_shape, shape
+                )));
+            }
+            tree.len = len;
+        }
+
+        Ok(tree)
+    }
+
+    /// Hash and append a data item, returning its index
+    pub fn append(&mut self, data: &[u8]) -> Result<usize> {
+        let leaf = self.hasher.hash(data);
+        self.append_leaf(leaf)
+    }
+
+    /// Append a pre-hashed leaf, returning its index
+    ///
+    /// The leaf, every node above it and the new leaf count are written in
+    /// one atomic batch. Call [`SledTree::flush`] to make it durable.
+    pub fn append_leaf(&mut self, leaf: Hash) -> Result<usize> {
+        let index = self.len;
+        let len = index + 1;
+        let mut batch = sled::Batch::default();
+        batch.insert(&node_key(0, index)[..], leaf.as_bytes());
+
+        let (mut level, mut position, mut width) = (0, index, len);
+        let mut node = leaf;
+        while width > 1 {
+            // The new leaf's ancestors are the last node of every level, so
+            // they are either a right child or a lone left child
+            let parent = if position % 2 == 1 {
+                let left = self.node(level, position - 1)?;
+                self.hasher.hash_pair(&left, &node)
+            } else {
+                match self.shape {
+                    TreeShape::Padded => self.hasher.hash_pair(&node, &node),
+                    TreeShape::Rfc6962 => node,
+                }
+            };
+
+            level += 1;
+            position /= 2;
+            width = width.div_ceil(2);
+            batch.insert(&node_key(level, position)[..], parent.as_bytes());
+            node = parent;
+        }
+
+        batch.insert(META_KEY, self.encode_meta(len));
+        self.keyspace.apply_batch(batch).map_err(sled_error)?;
+        self.len = len;
+        Ok(index)
+    }
+
+    /// Get the number of leaves
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the hasher used by the tree
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get how the last node of an odd level is handled
+    pub fn shape(&self) -> TreeShape {
+        self.shape
+    }
+
+    /// Get the leaf at the given index
+    pub fn get_leaf(&self, index: usize) -> Result<Hash> {
+        self.check_index(index)?;
+        self.node(0, index)
+    }
+
+    /// Get the root hash
+    ///
+    /// Fails with [`MerkleError::EmptyData`] before the first append.
+    pub fn root(&self) -> Result<RootHash> {
+        if self.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let height = MerkleTree::<H>::level_lens(self.len).len() - 1;
+        Ok(RootHash::from(self.node(height, 0)?))
+    }
+
+    /// Generate a proof for the leaf at the given index
+    pub fn generate_proof(&self, index: usize) -> Result<MerkleProof> {
+        self.check_index(index)?;
+
+        let mut steps = Vec::new();
+        let (mut level, mut position, mut width) = (0, index, self.len);
+        while width > 1 {
+            let sibling = position ^ 1;
+            if sibling < width {
+                let direction = if position % 2 == 1 {
+                    ProofDirection::Left
+                } else {
+                    ProofDirection::Right
+                };
+                steps.push(ProofStep {
+                    hash: self.node(level, sibling)?,
+                    direction,
+                });
+            } else if self.shape == TreeShape::Padded {
+                steps.push(ProofStep {
+                    hash: self.node(level, position)?,
+                    direction: ProofDirection::Right,
+                });
+            }
+
+            level += 1;
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+
+        Ok(MerkleProof::new(index, steps))
+    }
+
+    /// Load the whole tree into memory
+    pub fn to_tree(&self) -> Result<MerkleTree<H>> {
+        let levels = MerkleTree::<H>::level_lens(self.len)
+            .into_iter()
+            .enumerate()
+            .map(|(level, len)| (0..len).map(|index| self.node(level, index)).collect())
+            .collect::<Result<Vec<Vec<Hash>>>>()?;
+        MerkleTree::from_levels(
+            levels,
+            self.hasher.clone(),
+            self.shape,
+            NodeDomain::None,
+            Vec::new(),
+        )
+    }
+
+    /// Flush all appended nodes to disk
+    pub fn flush(&self) -> Result<()> {
+        self.keyspace.flush().map(|_| ()).map_err(sled_error)
+    }
+
+    fn check_index(&self, index: usize) -> Result<()> {
+        if index >= self.len {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Read a stored node, which must exist
+    fn node(&self, level: usize, index: usize) -> Result<Hash> {
+        match self
+            .keyspace
+            .get(node_key(level, index))
+            .map_err(sled_error)?
+        {
+            Some(bytes) => Ok(Hash::new(bytes.to_vec())),
+            None => Err(MerkleError::IntegrityError {
+                path: format!("level {} index {}", level, index),
+                reason: "node is missing from the store".to_string(),
+            }),
+        }
+    }
+
+    /// Hasher name (u8 length + bytes) | shape u8 | leaf count u64
+    fn encode_meta(&self, len: usize) -> Vec<u8> {
+        let name = self.hasher.name().as_bytes();
+        let mut meta = Vec::with_capacity(name.len() + 10);
+        meta.push(name.len() as u8);
+        meta.extend_from_slice(name);
+        meta.push(match self.shape {
+            TreeShape::Padded => 0,
+            TreeShape::Rfc6962 => 1,
+        });
+        meta.extend_from_slice(&(len as u64).to_be_bytes());
+        meta
+    }
+}
+
+fn decode_meta(meta: &[u8]) -> Result<(&[u8], TreeShape, usize)> {
+    let truncated = || invalid("Truncated tree metadata");
+    let (&name_len, rest) = meta.split_first().ok_or_else(truncated)?;
+    let (name, rest) = rest
+        .split_at_checked(name_len as usize)
+        .ok_or_else(truncated)?;
+    let (&shape, rest) = rest.split_first().ok_or_else(truncated)?;
+    let shape = match shape {
+        0 => TreeShape::Padded,
+        1 => TreeShape::Rfc6962,
+        other => return Err(invalid(&format!("Unknown tree shape {}", other))),
+    };
+    let len: [u8; 8] = rest.try_into().map_err(|_| truncated())?;
+    let len = usize::try_from(u64::from_be_bytes(len))
+        .map_err(|_| invalid("Leaf count does not fit in memory"))?;
+    Ok((name, shape, len))
+}
+
+fn node_key(level: usize, index: usize) -> [u8; 9] {
+    let mut key = [0u8; 9];
+    key[0] = level as u8;
+    key[1..].copy_from_slice(&(index as u64).to_be_bytes());
+    key
+}
+
+fn invalid(message: &str) -> MerkleError {
+    MerkleError::SerializationError {
+        message: message.to_string(),
+    }
+}
+
+fn sled_error(e: sled::Error) -> MerkleError {
+    MerkleError::IoError {
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MerkleTreeBuilder;
+    use crate::hasher::{Blake3Hasher, Sha256Hasher};
+
+    fn keyspace() -> sled::Tree {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.open_tree("tree").unwrap()
+    }
+
+    #[test]
+    fn test_matches_in_memory_tree() {
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            let mut tree = SledTree::open(keyspace(), Sha256Hasher::new(), shape).unwrap();
+            assert_eq!(tree.root(), Err(MerkleError::EmptyData));
+
+            let mut data = Vec::new();
+            for i in 0..21 {
+                data.push(format!("item_{}", i));
+                assert_eq!(tree.append(data[i].as_bytes()).unwrap(), i);
+
+                let expected = MerkleTreeBuilder::new(Sha256Hasher::new())
+                    .shape(shape)
+                    .build(data.clone())
+                    .unwrap();
+                assert_eq!(tree.root().unwrap(), expected.root());
+                for j in 0..=i {
+                    assert_eq!(
+                        tree.generate_proof(j).unwrap(),
+                        expected.generate_proof(j).unwrap()
+                    );
+                }
+            }
+
+            let loaded = tree.to_tree().unwrap();
+            assert_eq!(loaded.verify_integrity(), Ok(()));
+            assert_eq!(loaded.root(), tree.root().unwrap());
+            assert!(tree.generate_proof(21).is_err());
+        }
+    }
+
+    #[test]
+    fn test_reopen() {
+        let keyspace = keyspace();
+        let mut tree =
+            SledTree::open(keyspace.clone(), Sha256Hasher::new(), TreeShape::Padded).unwrap();
+        for i in 0..5u32 {
+            tree.append(&i.to_be_bytes()).unwrap();
+        }
+        let root = tree.root().unwrap();
+        tree.flush().unwrap();
+        drop(tree);
+
+        let mut tree =
+            SledTree::open(keyspace.clone(), Sha256Hasher::new(), TreeShape::Padded).unwrap();
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.root().unwrap(), root);
+        assert_eq!(
+            tree.get_leaf(4).unwrap(),
+            Sha256Hasher::new().hash(&4u32.to_be_bytes())
+        );
+        tree.append(b"more").unwrap();
+        assert_ne!(tree.root().unwrap(), root);
+        drop(tree);
+
+        assert!(matches!(
+            SledTree::open(keyspace.clone(), Blake3Hasher::new(), TreeShape::Padded),
+            Err(MerkleError::SerializationError { .. })
+        ));
+        assert!(matches!(
+            SledTree::open(keyspace, Sha256Hasher::new(), TreeShape::Rfc6962),
+            Err(MerkleError::SerializationError { .. })
+        ));
+    }
+}
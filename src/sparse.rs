@@ -1,83 +1,310 @@
 use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
 use crate::hasher::Hasher;
-use crate::proof::{MerkleProof, ProofDirection, ProofStep};
+use crate::proof::{MerkleProof, ProofCost, ProofDirection, ProofStep};
+use crate::stream::{
+    invalid, io_error, read_array, read_bounded_bytes, read_count, write_bytes, MAX_HASH_LEN,
+    MAX_PREALLOCATE,
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-
-/// Default value for empty nodes in sparse Merkle tree
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::ops::RangeInclusive;
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+/// Default value for empty leaves of a sparse Merkle tree with a 32-byte hasher
+///
+/// Trees created with [`SparseMerkleTree::new`] use an all-zero leaf as long
+/// as the hasher's output; see [`SparseMerkleTree::with_default_leaf`] to
+/// choose another value.
 pub const DEFAULT_HASH: [u8; 32] = [0u8; 32];
 
+/// How a sparse Merkle tree derives the leaf hash of a value
+///
+/// Hashing the index into the leaf, as most deployed sparse Merkle trees do,
+/// gives the same value different leaves at different indices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SparseLeafEncoding {
+    /// `H(value)`
+    #[default]
+    Value,
+    /// `H(index || value)`, with the index as 8 big-endian bytes
+    IndexValue,
+    /// `H(index || H(value))`, with the index as 8 big-endian bytes
+    IndexValueHash,
+}
+
+impl SparseLeafEncoding {
+    /// Number of hash invocations per leaf hash
+    fn hash_count(&self) -> usize {
+        match self {
+            Self::Value | Self::IndexValue => 1,
+            Self::IndexValueHash => 2,
+        }
+    }
+
+    /// Get the leaf hash of `value` stored at `index`
+    pub fn leaf_hash<H: Hasher>(&self, hasher: &H, index: u64, value: &[u8]) -> Hash {
+        let indexed = |bytes: &[u8]| {
+            let mut input = index.to_be_bytes().to_vec();
+            input.extend_from_slice(bytes);
+            hasher.hash(&input)
+        };
+        match self {
+            Self::Value => hasher.hash(value),
+            Self::IndexValue => indexed(value),
+            Self::IndexValueHash => indexed(&hasher.hash(value)),
+        }
+    }
+}
+
 /// A sparse Merkle tree implementation optimized for sparse data
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SparseMerkleTree<H: Hasher> {
     /// Map from leaf index to leaf hash
-    leaves: HashMap<u64, Vec<u8>>,
+    leaves: BTreeMap<u64, Hash>,
     /// Cached internal nodes for efficiency
-    nodes: HashMap<(u64, u8), Vec<u8>>, // (index, level) -> hash
+    nodes: HashMap<(u64, u8), Hash>, // (index, level) -> hash
     /// Tree depth (height)
     depth: u8,
     /// Hash function
     hasher: H,
+    /// Hash of an empty leaf
+    default_leaf: Hash,
+    /// How values are turned into leaf hashes
+    #[cfg_attr(feature = "serde", serde(default))]
+    leaf_encoding: SparseLeafEncoding,
     /// Root hash cache
-    root_cache: Option<Vec<u8>>,
+    root_cache: Option<RootHash>,
+    /// Hash of an empty subtree at each level, computed on first use
+    #[cfg_attr(feature = "serde", serde(skip))]
+    empty_hashes: Vec<Hash>,
+    /// Recently generated proofs, if enabled
+    #[cfg_attr(feature = "serde", serde(skip))]
+    proof_cache: Option<ProofCache>,
+    /// Undo log of the open transaction, if any
+    #[cfg_attr(feature = "serde", serde(skip))]
+    transaction: Option<Transaction>,
+}
+
+/// Leaves as they were before the first change of an open transaction
+#[derive(Debug, Clone)]
+struct Transaction {
+    previous: BTreeMap<u64, Option<Hash>>,
+    root: Option<RootHash>,
 }
 
 impl<H: Hasher> SparseMerkleTree<H> {
     /// Create a new sparse Merkle tree with the given depth
+    ///
+    /// Empty leaves are all zeros, as long as the hasher's output.
     pub fn new(depth: u8, hasher: H) -> Result<Self> {
+        let default_leaf = zero_leaf(&hasher);
+        Self::with_default_leaf(depth, hasher, default_leaf)
+    }
+
+    /// Create a new sparse Merkle tree whose empty leaves hash to `default_leaf`
+    ///
+    /// Protocols differ in what an empty slot holds, e.g. `H("")` or a fixed
+    /// constant, and roots only match when the same value is used. The value
+    /// must be as long as the hasher's output.
+    ///
+    /// A default like `H("")` is also the leaf hash of a real value, here
+    /// `b""`. Storing such a value fails, since it could not be told apart
+    /// from an empty slot, so pick a default that no stored value hashes to.
+    pub fn with_default_leaf(depth: u8, hasher: H, default_leaf: Hash) -> Result<Self> {
         if depth == 0 || depth > 64 {
             return Err(MerkleError::TreeConstructionError {
                 reason: format!("Invalid depth: {}. Must be between 1 and 64", depth),
             });
         }
+        if default_leaf.len() != hasher.output_size() {
+            return Err(MerkleError::TreeConstructionError {
+                reason: format!(
+                    "Default leaf is {} bytes, expected {}",
+                    default_leaf.len(),
+                    hasher.output_size()
+                ),
+            });
+        }
 
         Ok(Self {
-            leaves: HashMap::new(),
+            leaves: BTreeMap::new(),
             nodes: HashMap::new(),
             depth,
             hasher,
+            default_leaf,
+            leaf_encoding: SparseLeafEncoding::Value,
             root_cache: None,
+            empty_hashes: Vec::new(),
+            proof_cache: None,
+            transaction: None,
         })
     }
 
+    /// Derive leaf hashes with `encoding` instead of `H(value)`
+    ///
+    /// Set this before storing any value: leaves already in the tree, e.g.
+    /// after [`SparseMerkleTree::deserialize_from`], keep their hashes. The
+    /// binary format does not record the encoding.
+    pub fn with_leaf_encoding(mut self, encoding: SparseLeafEncoding) -> Self {
+        self.leaf_encoding = encoding;
+        self
+    }
+
+    /// Get the encoding of leaf hashes
+    pub fn leaf_encoding(&self) -> SparseLeafEncoding {
+        self.leaf_encoding
+    }
+
+    /// Get the leaf hash of `value` stored at `index`
+    pub fn leaf_hash(&self, index: u64, value: &[u8]) -> Hash {
+        self.leaf_encoding.leaf_hash(&self.hasher, index, value)
+    }
+
     /// Insert or update a leaf at the given index
+    ///
+    /// Fails with [`MerkleError::UnsupportedOperation`] if the value's leaf
+    /// hash equals the default leaf, since it could not be told apart from
+    /// an empty slot.
     pub fn update(&mut self, index: u64, value: &[u8]) -> Result<()> {
-        let max_index = (1u64 << self.depth) - 1;
-        if index > max_index {
-            return Err(MerkleError::InvalidIndex {
-                index: index as usize,
-                size: (max_index + 1) as usize,
-            });
-        }
-
-        let leaf_hash = self.hasher.hash(value);
-        self.leaves.insert(index, leaf_hash);
+        let leaf_hash = self.leaf_hash(index, value);
+        self.insert_leaf(index, leaf_hash)
+    }
 
-        // Invalidate caches
-        self.root_cache = None;
-        self.nodes.clear();
+    /// Insert or update an already hashed leaf
+    pub(crate) fn insert_leaf(&mut self, index: u64, leaf_hash: Hash) -> Result<()> {
+        self.check_index(index)?;
+        self.check_leaf(index, &leaf_hash)?;
+        self.set_leaf(index, Some(leaf_hash));
+        self.invalidate();
 
         Ok(())
     }
 
     /// Remove a leaf at the given index
     pub fn remove(&mut self, index: u64) -> Result<bool> {
-        let removed = self.leaves.remove(&index).is_some();
+        let removed = self.set_leaf(index, None).is_some();
 
         if removed {
-            // Invalidate caches
-            self.root_cache = None;
-            self.nodes.clear();
+            self.invalidate();
         }
 
         Ok(removed)
     }
 
+    /// Apply a batch of changes only if the tree currently has `expected_root`
+    ///
+    /// Each entry sets the leaf at its index to the given value, or removes
+    /// it if the value is `None`, in order. The batch is applied atomically:
+    /// if the root differs, failing with [`MerkleError::RootMismatch`], or
+    /// any index is out of range or value hashes to the default leaf, the
+    /// tree is left unchanged. Returns the new root.
+    pub fn apply_batch_if_root<T: AsRef<[u8]>>(
+        &mut self,
+        expected_root: &RootHash,
+        batch: &[(u64, Option<T>)],
+    ) -> Result<RootHash> {
+        let current = self.root();
+        if current != expected_root {
+            return Err(MerkleError::RootMismatch {
+                expected: expected_root.to_hex(),
+                actual: current.to_hex(),
+            });
+        }
+        let mut hashed = Vec::with_capacity(batch.len());
+        for (index, value) in batch {
+            self.check_index(*index)?;
+            let leaf_hash = value
+                .as_ref()
+                .map(|value| self.leaf_hash(*index, value.as_ref()));
+            if let Some(leaf_hash) = &leaf_hash {
+                self.check_leaf(*index, leaf_hash)?;
+            }
+            hashed.push((*index, leaf_hash));
+        }
+
+        for (index, leaf_hash) in hashed {
+            self.set_leaf(index, leaf_hash);
+        }
+        if !batch.is_empty() {
+            self.invalidate();
+        }
+
+        Ok(self.root().clone())
+    }
+
+    /// Apply a batch of changes and prove the transition from the old root
+    /// to the new one
+    ///
+    /// Entries work as in [`SparseMerkleTree::apply_batch_if_root`]. The
+    /// proof holds the old and new hash of every changed leaf and the
+    /// siblings of their paths, taken before the change, which are all the
+    /// verifier needs to recompute both roots. Fails without changing the
+    /// tree if any index is out of range or value hashes to the default leaf.
+    pub fn apply_batch_with_proof<T: AsRef<[u8]>>(
+        &mut self,
+        batch: &[(u64, Option<T>)],
+    ) -> Result<TransitionProof> {
+        for (index, _) in batch {
+            self.check_index(*index)?;
+        }
+
+        let mut changes: BTreeMap<u64, (Option<Hash>, Option<Hash>)> = BTreeMap::new();
+        for (index, value) in batch {
+            let new = value
+                .as_ref()
+                .map(|value| self.leaf_hash(*index, value.as_ref()));
+            if let Some(new) = &new {
+                self.check_leaf(*index, new)?;
+            }
+            let old = self.leaves.get(index).cloned();
+            changes.entry(*index).or_insert((old, None)).1 = new;
+        }
+
+        self.ensure_empty_hashes();
+        let mut siblings = Vec::new();
+        let mut nodes: Vec<u64> = changes.keys().copied().collect();
+        for level in 0..self.depth {
+            for (i, &node) in nodes.iter().enumerate() {
+                let paired = match node & 1 {
+                    0 => nodes.get(i + 1) == Some(&(node | 1)),
+                    _ => i > 0 && nodes[i - 1] == node ^ 1,
+                };
+                if !paired {
+                    let hash = self.get_node_hash(node ^ 1, level);
+                    siblings.push((hash != self.empty_hashes[level as usize]).then_some(hash));
+                }
+            }
+            nodes = parents(&nodes);
+        }
+
+        for (index, (_, new)) in &changes {
+            self.set_leaf(*index, new.clone());
+        }
+        if !changes.is_empty() {
+            self.invalidate();
+        }
+
+        Ok(TransitionProof {
+            depth: self.depth,
+            changes: changes
+                .into_iter()
+                .map(|(index, (old, new))| (index, old, new))
+                .collect(),
+            siblings,
+        })
+    }
+
     /// Get the value hash at the given index
-    pub fn get(&self, index: u64) -> Option<&[u8]> {
-        self.leaves.get(&index).map(|h| h.as_slice())
+    pub fn get(&self, index: u64) -> Option<&Hash> {
+        self.leaves.get(&index)
     }
 
     /// Check if a leaf exists at the given index
@@ -86,7 +313,7 @@ impl<H: Hasher> SparseMerkleTree<H> {
     }
 
     /// Get the root hash of the tree
-    pub fn root(&mut self) -> &[u8] {
+    pub fn root(&mut self) -> &RootHash {
         if self.root_cache.is_none() {
             self.root_cache = Some(self.compute_root());
         }
@@ -108,20 +335,93 @@ impl<H: Hasher> SparseMerkleTree<H> {
         self.depth
     }
 
+    /// Get the number of steps in every proof from
+    /// [`SparseMerkleTree::generate_proof`], which is the depth
+    pub fn proof_len(&self) -> usize {
+        self.depth as usize
+    }
+
+    /// Get the hash of an empty leaf
+    pub fn default_leaf(&self) -> &Hash {
+        &self.default_leaf
+    }
+
+    /// Get the hasher used by the tree
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
     /// Generate a Merkle proof for the given index
+    ///
+    /// With a proof cache enabled, see [`SparseMerkleTree::with_proof_cache`],
+    /// proofs generated since the last change are returned from the cache.
     pub fn generate_proof(&mut self, index: u64) -> Result<MerkleProof> {
-        let max_index = (1u64 << self.depth) - 1;
-        if index > max_index {
-            return Err(MerkleError::InvalidIndex {
-                index: index as usize,
-                size: (max_index + 1) as usize,
-            });
+        self.check_index(index)?;
+        if let Some(proof) = self.proof_cache.as_mut().and_then(|c| c.get(index)) {
+            return Ok(proof);
+        }
+
+        let proof = self.compute_path(index, 0);
+        if let Some(cache) = &mut self.proof_cache {
+            cache.insert(index, proof.clone());
         }
+        Ok(proof)
+    }
 
+    /// Get the value hash at the given index together with its proof
+    ///
+    /// Serves a state query in one call. For an empty index the value is
+    /// `None` and the proof verifies the default leaf, e.g. with
+    /// [`MerkleProof::verify_with_leaf_hash`] and
+    /// [`SparseMerkleTree::default_leaf`].
+    pub fn get_with_proof(&mut self, index: u64) -> Result<(Option<Hash>, MerkleProof)> {
+        let proof = self.generate_proof(index)?;
+        Ok((self.leaves.get(&index).cloned(), proof))
+    }
+
+    /// Keep up to `capacity` recently generated proofs
+    ///
+    /// Proofs for popular leaves are then built once per root instead of on
+    /// every request. The cache holds proofs against the current root only
+    /// and is emptied whenever the tree changes, least recently used proofs
+    /// being evicted when it is full. A capacity of zero disables it.
+    pub fn with_proof_cache(mut self, capacity: usize) -> Self {
+        self.proof_cache = (capacity > 0).then(|| ProofCache::new(capacity));
+        self
+    }
+
+    /// Get the number of proof cache hits and misses so far
+    pub fn proof_cache_stats(&self) -> Option<(u64, u64)> {
+        self.proof_cache.as_ref().map(|c| (c.hits, c.misses))
+    }
+
+    /// Hash of the subtree rooted at node `index` on `level`
+    ///
+    /// Level 0 holds the leaves and level `depth` the root, so node `index`
+    /// on `level` covers leaves `index << level` through
+    /// `((index + 1) << level) - 1`.
+    pub fn subtree_root(&mut self, level: u8, index: u64) -> Result<RootHash> {
+        self.check_node(level, index)?;
+        Ok(RootHash::from(self.get_node_hash(index, level)))
+    }
+
+    /// Generate a proof that the subtree rooted at node `index` on `level`
+    /// is part of the tree
+    ///
+    /// The proof verifies the subtree's root, see
+    /// [`SparseMerkleTree::subtree_root`], as its leaf hash with
+    /// [`MerkleProof::verify_with_leaf_hash`].
+    pub fn generate_subtree_proof(&mut self, level: u8, index: u64) -> Result<MerkleProof> {
+        self.check_node(level, index)?;
+        Ok(self.compute_path(index, level))
+    }
+
+    /// Sibling hashes from node `index` on `level` up to the root
+    fn compute_path(&mut self, index: u64, level: u8) -> MerkleProof {
         let mut steps = Vec::new();
         let mut current_index = index;
 
-        for level in 0..self.depth {
+        for level in level..self.depth {
             let sibling_index = current_index ^ 1; // Flip the last bit
             let sibling_hash = self.get_node_hash(sibling_index, level);
 
@@ -139,20 +439,93 @@ impl<H: Hasher> SparseMerkleTree<H> {
             current_index >>= 1; // Move to parent
         }
 
-        Ok(MerkleProof::new(index as usize, steps))
+        MerkleProof::new(index as usize, steps)
+    }
+
+    /// Generate a compressed proof for the given index
+    ///
+    /// Siblings that are empty subtrees are omitted, which keeps proofs for
+    /// sparsely populated trees small. See [`SparseProof::to_bytes`] for the
+    /// wire format.
+    pub fn generate_sparse_proof(&mut self, index: u64) -> Result<SparseProof> {
+        let proof = self.generate_proof(index)?;
+        self.ensure_empty_hashes();
+
+        let siblings = proof
+            .steps
+            .into_iter()
+            .zip(&self.empty_hashes)
+            .map(|(step, empty)| (step.hash != *empty).then_some(step.hash))
+            .collect();
+
+        Ok(SparseProof {
+            index,
+            depth: self.depth,
+            siblings,
+        })
+    }
+
+    /// Get the size and verification cost of the proof from
+    /// [`SparseMerkleTree::generate_sparse_proof`] without generating it
+    ///
+    /// The proof carries only the siblings that are not empty subtrees, but
+    /// verifying still hashes through every level.
+    pub fn sparse_proof_cost(&self, index: u64) -> Result<ProofCost> {
+        self.check_index(index)?;
+        let present = (0..self.depth)
+            .filter(|&level| {
+                let sibling = (index >> level) ^ 1;
+                self.leaves
+                    .range(leaf_range(sibling, level))
+                    .next()
+                    .is_some()
+            })
+            .count();
+        Ok(ProofCost {
+            steps: self.depth as usize,
+            bytes: present * self.hasher.output_size(),
+            hashes_to_verify: self.depth as usize + self.leaf_encoding.hash_count(),
+        })
     }
 
     /// Verify a proof for the given index and value
+    ///
+    /// The value is always hashed, so this proves a stored value even if it
+    /// happens to equal the default leaf; use
+    /// [`SparseMerkleTree::verify_empty`] to prove that an index is empty.
     pub fn verify_proof(&mut self, proof: &MerkleProof, index: u64, value: &[u8]) -> bool {
         if proof.leaf_index != index as usize {
             return false;
         }
 
-        let leaf_hash = self.hasher.hash(value);
+        let leaf_hash = self.leaf_hash(index, value);
         let computed_root = proof.compute_root(&self.hasher, &leaf_hash);
         let actual_root = self.root();
 
-        computed_root == actual_root
+        computed_root == *actual_root
+    }
+
+    /// Generate a proof that no value is stored at the given index
+    ///
+    /// Fails with [`MerkleError::InvalidProof`] if the index holds a value,
+    /// whatever that value is.
+    pub fn prove_empty(&mut self, index: u64) -> Result<EmptyProof> {
+        self.check_index(index)?;
+        if self.contains(index) {
+            return Err(MerkleError::InvalidProof {
+                reason: format!("Leaf {} is not empty", index),
+            });
+        }
+        Ok(EmptyProof {
+            proof: self.generate_sparse_proof(index)?,
+        })
+    }
+
+    /// Verify that `proof` shows the given index empty under `root`
+    pub fn verify_empty(&self, root: &RootHash, index: u64, proof: &EmptyProof) -> bool {
+        proof.proof.index == index
+            && proof.proof.depth == self.depth
+            && proof.verify_with_default(&self.hasher, &self.default_leaf, root)
     }
 
     /// Get all non-empty leaf indices
@@ -163,30 +536,36 @@ impl<H: Hasher> SparseMerkleTree<H> {
     }
 
     /// Get all non-empty leaves as (index, hash) pairs
-    pub fn leaves(&self) -> Vec<(u64, &[u8])> {
-        let mut leaves: Vec<(u64, &[u8])> = self
+    pub fn leaves(&self) -> Vec<(u64, &Hash)> {
+        let mut leaves: Vec<(u64, &Hash)> = self
             .leaves
             .iter()
-            .map(|(&index, hash)| (index, hash.as_slice()))
+            .map(|(&index, hash)| (index, hash))
             .collect();
         leaves.sort_unstable_by_key(|&(index, _)| index);
         leaves
     }
 
     /// Compute the root hash
-    fn compute_root(&mut self) -> Vec<u8> {
-        self.get_node_hash(1, self.depth)
+    fn compute_root(&mut self) -> RootHash {
+        RootHash::from(self.get_node_hash(0, self.depth))
     }
 
     /// Get the hash of a node at the given index and level
-    fn get_node_hash(&mut self, index: u64, level: u8) -> Vec<u8> {
+    fn get_node_hash(&mut self, index: u64, level: u8) -> Hash {
         if level == 0 {
             // Leaf level
             return self
                 .leaves
                 .get(&index)
                 .cloned()
-                .unwrap_or_else(|| DEFAULT_HASH.to_vec());
+                .unwrap_or_else(|| self.default_leaf.clone());
+        }
+
+        // Subtrees without any leaves hash to a precomputed constant
+        if self.leaves.range(leaf_range(index, level)).next().is_none() {
+            self.ensure_empty_hashes();
+            return self.empty_hashes[level as usize].clone();
         }
 
         // Check cache first
@@ -209,23 +588,1072 @@ impl<H: Hasher> SparseMerkleTree<H> {
         hash
     }
 
+    /// Drop everything derived from the leaves after a change
+    fn invalidate(&mut self) {
+        self.root_cache = None;
+        self.nodes.clear();
+        if let Some(cache) = &mut self.proof_cache {
+            cache.clear();
+        }
+    }
+
+    fn ensure_empty_hashes(&mut self) {
+        if self.empty_hashes.is_empty() {
+            self.empty_hashes =
+                empty_hashes_with_default(&self.hasher, &self.default_leaf, self.depth);
+        }
+    }
+
+    /// Check that an index is addressable at this tree's depth
+    fn check_index(&self, index: u64) -> Result<()> {
+        let max_index = max_index(self.depth);
+        if index > max_index {
+            return Err(MerkleError::InvalidIndex {
+                index: index as usize,
+                size: max_index.saturating_add(1) as usize,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that a leaf hash can be stored, i.e. is not the default leaf
+    ///
+    /// A stored default leaf would leave the root unchanged, so anyone could
+    /// prove the slot empty while it holds a value.
+    fn check_leaf(&self, index: u64, leaf_hash: &Hash) -> Result<()> {
+        if *leaf_hash == self.default_leaf {
+            return Err(MerkleError::UnsupportedOperation {
+                reason: format!(
+                    "Leaf {} hashes to the default leaf, which marks empty slots",
+                    index
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that node `index` exists on `level`
+    fn check_node(&self, level: u8, index: u64) -> Result<()> {
+        if level > self.depth {
+            return Err(MerkleError::InvalidIndex {
+                index: level as usize,
+                size: self.depth as usize + 1,
+            });
+        }
+        let max_index = match self.depth - level {
+            0 => 0,
+            height => max_index(height),
+        };
+        if index > max_index {
+            return Err(MerkleError::InvalidIndex {
+                index: index as usize,
+                size: max_index.saturating_add(1) as usize,
+            });
+        }
+        Ok(())
+    }
+
+    /// Insert or update many leaves, hashing them on the rayon thread pool
+    ///
+    /// Keys are partitioned by their top bits into disjoint subtrees, which
+    /// are hashed in parallel and then combined up to the root, so the root
+    /// and proofs are ready afterwards. Fails without changing the tree if
+    /// any index is out of range or value hashes to the default leaf.
+    #[cfg(feature = "rayon")]
+    pub fn par_update_batch<T>(&mut self, entries: &[(u64, T)]) -> Result<()>
+    where
+        H: Sync,
+        T: AsRef<[u8]> + Sync,
+    {
+        for (index, _) in entries {
+            self.check_index(*index)?;
+        }
+
+        let hashed: Vec<(u64, Hash)> = entries
+            .par_iter()
+            .map(|(index, value)| (*index, self.leaf_hash(*index, value.as_ref())))
+            .collect();
+        for (index, leaf_hash) in &hashed {
+            self.check_leaf(*index, leaf_hash)?;
+        }
+        for (index, leaf_hash) in hashed {
+            self.set_leaf(index, Some(leaf_hash));
+        }
+        self.invalidate();
+        self.ensure_empty_hashes();
+
+        // Enough subtrees to keep every thread busy, at most one per leaf
+        let split_bits = (rayon::current_num_threads() * 4)
+            .next_power_of_two()
+            .trailing_zeros()
+            .min(self.depth as u32) as u8;
+        let split = self.depth - split_bits;
+
+        let mut prefixes: Vec<u64> = self.leaves.keys().map(|&index| index >> split).collect();
+        prefixes.dedup();
+
+        let (hasher, leaves, empty) = (&self.hasher, &self.leaves, &self.empty_hashes);
+        let subtrees: Vec<HashMap<(u64, u8), Hash>> = prefixes
+            .into_par_iter()
+            .map(|prefix| {
+                let mut nodes = HashMap::new();
+                hash_subtree(hasher, leaves, empty, prefix, split, &mut nodes);
+                nodes
+            })
+            .collect();
+        for nodes in subtrees {
+            self.nodes.extend(nodes);
+        }
+
+        // Only the few nodes above the split are left to hash
+        self.root_cache = Some(self.compute_root());
+        Ok(())
+    }
+
     /// Get tree statistics
+    ///
+    /// Counting the occupied nodes walks every leaf once per level.
     pub fn stats(&mut self) -> SparseTreeStats {
+        let occupied_nodes = (0..=self.depth)
+            .map(|level| {
+                let mut parents: Vec<u64> = self
+                    .leaves
+                    .keys()
+                    .map(|&index| index.checked_shr(level as u32).unwrap_or(0))
+                    .collect();
+                parents.dedup();
+                parents.len() as u64
+            })
+            .collect();
+
+        let subtree_height = self.depth.min(SUBTREE_STATS_HEIGHT);
+        let capacity = 1u128 << subtree_height;
+        let mut fill_histogram = [0; 10];
+        let mut indices = self.leaves.keys().peekable();
+        while let Some(&first) = indices.next() {
+            let prefix = first.checked_shr(subtree_height as u32).unwrap_or(0);
+            let mut count = 1u128;
+            while indices
+                .next_if(|&&index| index.checked_shr(subtree_height as u32).unwrap_or(0) == prefix)
+                .is_some()
+            {
+                count += 1;
+            }
+            fill_histogram[((count * 10 - 1) / capacity) as usize] += 1;
+        }
+
         SparseTreeStats {
             depth: self.depth,
             leaf_count: self.leaves.len(),
-            max_leaves: 1u64 << self.depth,
+            max_leaves: max_index(self.depth).saturating_add(1),
             cached_nodes: self.nodes.len(),
             hasher_name: self.hasher.name().to_string(),
-            root_hash: hex::encode(self.root()),
+            root_hash: self.root().to_hex(),
+            occupied_nodes,
+            subtree_height,
+            fill_histogram,
         }
     }
 
     /// Clear all data and caches
     pub fn clear(&mut self) {
+        if let Some(transaction) = &mut self.transaction {
+            for (index, hash) in std::mem::take(&mut self.leaves) {
+                transaction.previous.entry(index).or_insert(Some(hash));
+            }
+        }
+        self.leaves.clear();
+        self.invalidate();
+    }
+
+    /// Start recording changes so they can be undone with
+    /// [`SparseMerkleTree::rollback`]
+    ///
+    /// Only the previous value of each leaf changed in the transaction is
+    /// kept, not a copy of the tree. Fails with
+    /// [`MerkleError::TransactionState`] if a transaction is already open.
+    pub fn begin_transaction(&mut self) -> Result<()> {
+        if self.transaction.is_some() {
+            return Err(MerkleError::TransactionState {
+                reason: "A transaction is already open".to_string(),
+            });
+        }
+        self.transaction = Some(Transaction {
+            previous: BTreeMap::new(),
+            root: self.root_cache.clone(),
+        });
+        Ok(())
+    }
+
+    /// Undo every change since [`SparseMerkleTree::begin_transaction`],
+    /// restoring the previous leaves and root exactly
+    pub fn rollback(&mut self) -> Result<()> {
+        let transaction = self.transaction.take().ok_or_else(no_transaction)?;
+        if transaction.previous.is_empty() {
+            return Ok(());
+        }
+
+        for (index, hash) in transaction.previous {
+            match hash {
+                Some(hash) => self.leaves.insert(index, hash),
+                None => self.leaves.remove(&index),
+            };
+        }
+        self.invalidate();
+        self.root_cache = transaction.root;
+        Ok(())
+    }
+
+    /// Keep every change since [`SparseMerkleTree::begin_transaction`],
+    /// returning the new root
+    pub fn commit(&mut self) -> Result<RootHash> {
+        self.transaction.take().ok_or_else(no_transaction)?;
+        Ok(self.root().clone())
+    }
+
+    /// Check if a transaction is open
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Set or remove a leaf, recording its previous value in the open
+    /// transaction; returns the previous value
+    fn set_leaf(&mut self, index: u64, hash: Option<Hash>) -> Option<Hash> {
+        let previous = match hash {
+            Some(hash) => self.leaves.insert(index, hash),
+            None => self.leaves.remove(&index),
+        };
+        if let Some(transaction) = &mut self.transaction {
+            transaction
+                .previous
+                .entry(index)
+                .or_insert_with(|| previous.clone());
+        }
+        previous
+    }
+}
+
+/// Magic of the binary sparse tree encoding
+const SPARSE_TREE_MAGIC: &[u8; 4] = b"MRKS";
+
+/// Version byte of the binary sparse tree encoding
+const SPARSE_TREE_VERSION: u8 = 1;
+
+/// Binary persistence including the internal node cache
+///
+/// Layout, with all integers big-endian and every hash written as a `u32`
+/// length followed by its bytes:
+///
+/// ```text
+/// magic "MRKS" | version u8 | hasher name (u8 length + bytes) | depth u8
+/// default leaf | root
+/// leaf count u64 | per leaf: index u64, hash
+/// node count u64 | per node: index u64, level u8, hash
+/// ```
+impl<H: Hasher> SparseMerkleTree<H> {
+    /// Write the tree together with its cached internal nodes to `writer`
+    ///
+    /// The cache is filled first, so a tree restored with
+    /// [`SparseMerkleTree::deserialize_from`] serves proofs without
+    /// rehashing. Wrap files in a [`std::io::BufWriter`] for best throughput.
+    pub fn serialize_into<W: Write>(&mut self, mut writer: W) -> Result<()> {
+        let root = self.root().clone();
+
+        writer.write_all(SPARSE_TREE_MAGIC).map_err(io_error)?;
+        let name = self.hasher.name().as_bytes();
+        writer
+            .write_all(&[SPARSE_TREE_VERSION, name.len() as u8])
+            .map_err(io_error)?;
+        writer.write_all(name).map_err(io_error)?;
+        writer.write_all(&[self.depth]).map_err(io_error)?;
+        write_bytes(&mut writer, &self.default_leaf)?;
+        write_bytes(&mut writer, &root)?;
+
+        writer
+            .write_all(&(self.leaves.len() as u64).to_be_bytes())
+            .map_err(io_error)?;
+        for (index, hash) in &self.leaves {
+            writer.write_all(&index.to_be_bytes()).map_err(io_error)?;
+            write_bytes(&mut writer, hash)?;
+        }
+
+        writer
+            .write_all(&(self.nodes.len() as u64).to_be_bytes())
+            .map_err(io_error)?;
+        for ((index, level), hash) in &self.nodes {
+            writer.write_all(&index.to_be_bytes()).map_err(io_error)?;
+            writer.write_all(&[*level]).map_err(io_error)?;
+            write_bytes(&mut writer, hash)?;
+        }
+
+        writer.flush().map_err(io_error)
+    }
+
+    /// Read a tree written by [`SparseMerkleTree::serialize_into`]
+    ///
+    /// The stored hasher name must match `hasher`. The root and cached nodes
+    /// are trusted as read; call [`SparseMerkleTree::verify_integrity`] on
+    /// data from untrusted storage.
+    pub fn deserialize_from<R: Read>(mut reader: R, hasher: H) -> Result<Self> {
+        if &read_array::<_, 4>(&mut reader)? != SPARSE_TREE_MAGIC {
+            return Err(invalid("Not a serialized sparse tree"));
+        }
+        let [version, name_len] = read_array(&mut reader)?;
+        if version != SPARSE_TREE_VERSION {
+            return Err(invalid(&format!("Unsupported version {}", version)));
+        }
+        let mut name = vec![0u8; name_len as usize];
+        reader.read_exact(&mut name).map_err(io_error)?;
+        if name != hasher.name().as_bytes() {
+            return Err(invalid(&format!(
+                "Tree was written with hasher {:?}, not {}",
+                String::from_utf8_lossy(&name),
+                hasher.name()
+            )));
+        }
+
+        let [depth] = read_array(&mut reader)?;
+        let default_leaf = Hash::new(read_bounded_bytes(&mut reader, MAX_HASH_LEN)?);
+        let root = RootHash::new(read_bounded_bytes(&mut reader, MAX_HASH_LEN)?);
+        let mut tree = Self::with_default_leaf(depth, hasher, default_leaf)
+            .map_err(|e| invalid(&e.to_string()))?;
+
+        for _ in 0..read_count(&mut reader)? {
+            let index = u64::from_be_bytes(read_array(&mut reader)?);
+            if index > max_index(depth) {
+                return Err(invalid(&format!("Leaf index {} out of range", index)));
+            }
+            let leaf_hash = Hash::new(read_bounded_bytes(&mut reader, MAX_HASH_LEN)?);
+            tree.check_leaf(index, &leaf_hash)
+                .map_err(|e| invalid(&e.to_string()))?;
+            tree.leaves.insert(index, leaf_hash);
+        }
+
+        let node_count = read_count(&mut reader)?;
+        tree.nodes.reserve(node_count.min(MAX_PREALLOCATE));
+        for _ in 0..node_count {
+            let index = u64::from_be_bytes(read_array(&mut reader)?);
+            let [level] = read_array(&mut reader)?;
+            if level == 0 || level > depth || (index as u128) << level > max_index(depth) as u128 {
+                return Err(invalid(&format!(
+                    "Node {} on level {} out of range",
+                    index, level
+                )));
+            }
+            tree.nodes.insert(
+                (index, level),
+                Hash::new(read_bounded_bytes(&mut reader, MAX_HASH_LEN)?),
+            );
+        }
+
+        tree.root_cache = Some(root);
+        Ok(tree)
+    }
+
+    /// Recompute every internal node from the leaves and check the cached
+    /// nodes and root against them
+    pub fn verify_integrity(&mut self) -> Result<()> {
+        self.ensure_empty_hashes();
+        let mut fresh = HashMap::new();
+        let root = hash_subtree(
+            &self.hasher,
+            &self.leaves,
+            &self.empty_hashes,
+            0,
+            self.depth,
+            &mut fresh,
+        );
+
+        if self
+            .root_cache
+            .as_ref()
+            .is_some_and(|cached| *cached.as_hash() != root)
+        {
+            return Err(MerkleError::IntegrityError {
+                path: "root".to_string(),
+                reason: "root does not match the leaves".to_string(),
+            });
+        }
+        if let Some(((index, level), _)) = self
+            .nodes
+            .iter()
+            .find(|(key, hash)| fresh.get(key) != Some(hash))
+        {
+            return Err(MerkleError::IntegrityError {
+                path: format!("level {} index {}", level, index),
+                reason: "cached node does not match the leaves".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<H: Hasher> SparseMerkleTree<H> {
+    /// Wipe all leaf and cached node hashes held by the tree
+    fn scrub(&mut self) {
+        self.leaves.values_mut().for_each(Zeroize::zeroize);
+        self.nodes.values_mut().for_each(Zeroize::zeroize);
+        self.root_cache.zeroize();
+        if let Some(cache) = &mut self.proof_cache {
+            for (proof, _) in cache.proofs.values_mut() {
+                proof.steps.iter_mut().for_each(|step| step.hash.zeroize());
+            }
+        }
+        if let Some(transaction) = &mut self.transaction {
+            transaction
+                .previous
+                .values_mut()
+                .flatten()
+                .for_each(Zeroize::zeroize);
+        }
         self.leaves.clear();
         self.nodes.clear();
-        self.root_cache = None;
+        self.proof_cache = None;
+        self.transaction = None;
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<H: Hasher> Drop for SparseMerkleTree<H> {
+    fn drop(&mut self) {
+        self.scrub();
+    }
+}
+
+fn no_transaction() -> MerkleError {
+    MerkleError::TransactionState {
+        reason: "No transaction is open".to_string(),
+    }
+}
+
+/// Distinct parents of sorted node indices
+fn parents(nodes: &[u64]) -> Vec<u64> {
+    let mut parents: Vec<u64> = nodes.iter().map(|node| node >> 1).collect();
+    parents.dedup();
+    parents
+}
+
+/// Largest leaf index addressable in a tree of the given depth
+fn max_index(depth: u8) -> u64 {
+    u64::MAX >> (64 - depth as u32)
+}
+
+/// Leaf indices covered by the node at `index` on `level`
+fn leaf_range(index: u64, level: u8) -> RangeInclusive<u64> {
+    let first = (index as u128) << level;
+    let last = first + (1u128 << level) - 1;
+    first as u64..=last.min(u64::MAX as u128) as u64
+}
+
+/// Hash the subtree rooted at `index` on `level`, recording every internal
+/// node that is not an empty subtree in `nodes`
+fn hash_subtree<H: Hasher>(
+    hasher: &H,
+    leaves: &BTreeMap<u64, Hash>,
+    empty: &[Hash],
+    index: u64,
+    level: u8,
+    nodes: &mut HashMap<(u64, u8), Hash>,
+) -> Hash {
+    if level == 0 {
+        return leaves
+            .get(&index)
+            .cloned()
+            .unwrap_or_else(|| empty[0].clone());
+    }
+    if leaves.range(leaf_range(index, level)).next().is_none() {
+        return empty[level as usize].clone();
+    }
+
+    let left = hash_subtree(hasher, leaves, empty, index << 1, level - 1, nodes);
+    let right = hash_subtree(hasher, leaves, empty, (index << 1) + 1, level - 1, nodes);
+    let hash = hasher.hash_pair(&left, &right);
+    nodes.insert((index, level), hash.clone());
+    hash
+}
+
+/// All-zero leaf as long as the hasher's output
+fn zero_leaf<H: Hasher>(hasher: &H) -> Hash {
+    Hash::new(vec![0u8; hasher.output_size()])
+}
+
+/// Height of the subtrees summarized in [`SparseTreeStats::fill_histogram`]
+const SUBTREE_STATS_HEIGHT: u8 = 8;
+
+/// Hash of an empty subtree at each level of a tree of the given depth
+///
+/// Entry `0` is the empty leaf, all zeros as long as the hasher's output,
+/// and entry `depth` the root of an empty tree, as used by
+/// [`SparseMerkleTree::new`]. Verifiers in other languages or on chain can
+/// embed these constants instead of rebuilding the tree.
+///
+/// ```rust
+/// use merkle_tree::sparse::{empty_hashes, SparseMerkleTree};
+/// use merkle_tree::Sha256Hasher;
+///
+/// let empties = empty_hashes(&Sha256Hasher::new(), 16);
+/// let mut tree = SparseMerkleTree::new(16, Sha256Hasher::new()).unwrap();
+/// assert_eq!(empties.len(), 17);
+/// assert_eq!(tree.root().as_hash(), &empties[16]);
+/// ```
+pub fn empty_hashes<H: Hasher>(hasher: &H, depth: u8) -> Vec<Hash> {
+    empty_hashes_with_default(hasher, &zero_leaf(hasher), depth)
+}
+
+/// Hash of an empty subtree at each level, for trees created with
+/// [`SparseMerkleTree::with_default_leaf`]
+pub fn empty_hashes_with_default<H: Hasher>(
+    hasher: &H,
+    default_leaf: &Hash,
+    depth: u8,
+) -> Vec<Hash> {
+    let mut hashes = Vec::with_capacity(depth as usize + 1);
+    hashes.push(default_leaf.clone());
+
+    for level in 0..depth as usize {
+        let below = &hashes[level];
+        hashes.push(hasher.hash_pair(below, below));
+    }
+
+    hashes
+}
+
+/// Least recently used cache of proofs against the current root
+#[derive(Debug, Clone)]
+struct ProofCache {
+    capacity: usize,
+    /// Proof and last use of each cached index
+    proofs: HashMap<u64, (MerkleProof, u64)>,
+    /// Cached indices by last use
+    recency: BTreeMap<u64, u64>,
+    /// Use counter
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl ProofCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            proofs: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, index: u64) -> Option<MerkleProof> {
+        let Some((proof, used)) = self.proofs.get_mut(&index) else {
+            self.misses += 1;
+            return None;
+        };
+        self.recency.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.recency.insert(self.tick, index);
+        self.hits += 1;
+        Some(proof.clone())
+    }
+
+    fn insert(&mut self, index: u64, proof: MerkleProof) {
+        if self.proofs.len() >= self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.proofs.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.recency.insert(self.tick, index);
+        self.proofs.insert(index, (proof, self.tick));
+    }
+
+    fn clear(&mut self) {
+        self.proofs.clear();
+        self.recency.clear();
+    }
+}
+
+/// Version byte of the binary sparse proof encoding
+const SPARSE_PROOF_VERSION: u8 = 1;
+
+/// Compressed proof for a single index of a sparse Merkle tree
+///
+/// Unlike a plain [`MerkleProof`], this keeps the depth and the full index,
+/// and omits siblings that are empty subtrees. Directions are implied by the
+/// bits of the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SparseProof {
+    /// Index of the proven leaf
+    pub index: u64,
+    /// Depth of the tree the proof was generated from
+    pub depth: u8,
+    /// Sibling hashes from the leaf level upwards, `None` for empty subtrees
+    pub siblings: Vec<Option<Hash>>,
+}
+
+impl SparseProof {
+    /// Compress a plain proof produced by a sparse tree of the given depth
+    pub fn from_merkle_proof<H: Hasher>(
+        proof: &MerkleProof,
+        depth: u8,
+        hasher: &H,
+    ) -> Result<Self> {
+        Self::from_merkle_proof_with_default(proof, depth, hasher, &zero_leaf(hasher))
+    }
+
+    /// Compress a plain proof produced by a sparse tree with a custom default leaf
+    pub fn from_merkle_proof_with_default<H: Hasher>(
+        proof: &MerkleProof,
+        depth: u8,
+        hasher: &H,
+        default_leaf: &Hash,
+    ) -> Result<Self> {
+        if depth == 0 || depth > 64 || proof.len() != depth as usize {
+            return Err(MerkleError::InvalidProof {
+                reason: format!("Proof has {} steps, expected depth {}", proof.len(), depth),
+            });
+        }
+
+        let index = proof.leaf_index as u64;
+        let empties = empty_hashes_with_default(hasher, default_leaf, depth);
+        let mut siblings = Vec::with_capacity(proof.len());
+
+        for (level, step) in proof.steps.iter().enumerate() {
+            if step.direction != direction_at(index, level) {
+                return Err(MerkleError::InvalidProof {
+                    reason: format!(
+                        "Direction at level {} does not match index {}",
+                        level, index
+                    ),
+                });
+            }
+            siblings.push((step.hash != empties[level]).then(|| step.hash.clone()));
+        }
+
+        Ok(Self {
+            index,
+            depth,
+            siblings,
+        })
+    }
+
+    /// Expand into a plain proof, filling in empty subtree hashes
+    pub fn to_merkle_proof<H: Hasher>(&self, hasher: &H) -> MerkleProof {
+        self.to_merkle_proof_with_default(hasher, &zero_leaf(hasher))
+    }
+
+    /// Expand into a plain proof for a tree with a custom default leaf
+    pub fn to_merkle_proof_with_default<H: Hasher>(
+        &self,
+        hasher: &H,
+        default_leaf: &Hash,
+    ) -> MerkleProof {
+        let empties = empty_hashes_with_default(hasher, default_leaf, self.depth);
+        let steps = self
+            .siblings
+            .iter()
+            .enumerate()
+            .map(|(level, sibling)| ProofStep {
+                hash: sibling.clone().unwrap_or_else(|| empties[level].clone()),
+                direction: direction_at(self.index, level),
+            })
+            .collect();
+
+        MerkleProof::new(self.index as usize, steps)
+    }
+
+    /// Encode the proof into its compact binary form
+    ///
+    /// Layout: version (1 byte), depth (2 bytes, big-endian), index
+    /// (`ceil(depth / 8)` bytes, big-endian), hash length (1 byte), a bitmap
+    /// of present siblings (`ceil(depth / 8)` bytes, bit `i` for level `i`),
+    /// followed by the present sibling hashes from the leaf level upwards.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if self.siblings.len() != self.depth as usize {
+            return Err(MerkleError::SerializationError {
+                message: format!(
+                    "Proof has {} siblings, expected depth {}",
+                    self.siblings.len(),
+                    self.depth
+                ),
+            });
+        }
+
+        let present: Vec<&Hash> = self.siblings.iter().flatten().collect();
+        let hash_len = present.first().map_or(0, |hash| hash.len());
+        if hash_len > u8::MAX as usize || present.iter().any(|hash| hash.len() != hash_len) {
+            return Err(MerkleError::SerializationError {
+                message: "Sibling hashes must all have the same length of at most 255 bytes"
+                    .to_string(),
+            });
+        }
+
+        let width = (self.depth as usize).div_ceil(8);
+        let mut bytes = Vec::with_capacity(4 + 2 * width + present.len() * hash_len);
+        bytes.push(SPARSE_PROOF_VERSION);
+        bytes.extend_from_slice(&(self.depth as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.index.to_be_bytes()[8 - width..]);
+        bytes.push(hash_len as u8);
+
+        let mut bitmap = vec![0u8; width];
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            if sibling.is_some() {
+                bitmap[level / 8] |= 1 << (level % 8);
+            }
+        }
+        bytes.extend_from_slice(&bitmap);
+
+        for hash in present {
+            bytes.extend_from_slice(hash);
+        }
+
+        Ok(bytes)
+    }
+
+    /// Decode a proof from its compact binary form
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = |message: &str| MerkleError::SerializationError {
+            message: message.to_string(),
+        };
+
+        let (&version, rest) = bytes.split_first().ok_or_else(|| invalid("Empty input"))?;
+        if version != SPARSE_PROOF_VERSION {
+            return Err(MerkleError::SerializationError {
+                message: format!("Unsupported sparse proof version {}", version),
+            });
+        }
+
+        if rest.len() < 2 {
+            return Err(invalid("Truncated depth"));
+        }
+        let depth = u16::from_be_bytes([rest[0], rest[1]]);
+        if depth == 0 || depth > 64 {
+            return Err(MerkleError::SerializationError {
+                message: format!("Invalid depth: {}", depth),
+            });
+        }
+        let depth = depth as u8;
+        let rest = &rest[2..];
+
+        let width = (depth as usize).div_ceil(8);
+        if rest.len() < 2 * width + 1 {
+            return Err(invalid("Truncated header"));
+        }
+
+        let mut index_bytes = [0u8; 8];
+        index_bytes[8 - width..].copy_from_slice(&rest[..width]);
+        let index = u64::from_be_bytes(index_bytes);
+        if index > max_index(depth) {
+            return Err(invalid("Index does not fit the depth"));
+        }
+
+        let hash_len = rest[width] as usize;
+        let bitmap = &rest[width + 1..2 * width + 1];
+        let mut hashes = rest[2 * width + 1..].chunks(hash_len.max(1));
+
+        let mut siblings = Vec::with_capacity(depth as usize);
+        for level in 0..depth as usize {
+            if bitmap[level / 8] & (1 << (level % 8)) == 0 {
+                siblings.push(None);
+                continue;
+            }
+            match hashes.next() {
+                Some(hash) if hash_len > 0 && hash.len() == hash_len => {
+                    siblings.push(Some(Hash::from(hash)))
+                }
+                _ => return Err(invalid("Truncated sibling hashes")),
+            }
+        }
+
+        if hashes.next().is_some() {
+            return Err(invalid("Trailing bytes after proof"));
+        }
+        if bitmap.iter().enumerate().any(|(byte, &bits)| {
+            (0..8).any(|bit| bits & (1 << bit) != 0 && byte * 8 + bit >= depth as usize)
+        }) {
+            return Err(invalid("Bitmap marks levels beyond the depth"));
+        }
+
+        Ok(Self {
+            index,
+            depth,
+            siblings,
+        })
+    }
+
+    /// Verify the proof for the given value against a root hash
+    ///
+    /// The value is always hashed, as `H(value)`; empty indices are proven
+    /// with an [`EmptyProof`] instead.
+    pub fn verify<H: Hasher>(&self, hasher: &H, value: &[u8], root: &RootHash) -> bool {
+        self.verify_with_default(hasher, &zero_leaf(hasher), value, root)
+    }
+
+    /// Verify the proof for a tree deriving leaves with `encoding`
+    ///
+    /// `default_leaf` is the tree's [`SparseMerkleTree::default_leaf`], which
+    /// fills the siblings the proof leaves out.
+    pub fn verify_with_encoding<H: Hasher>(
+        &self,
+        hasher: &H,
+        default_leaf: &Hash,
+        encoding: SparseLeafEncoding,
+        value: &[u8],
+        root: &RootHash,
+    ) -> bool {
+        let leaf_hash = encoding.leaf_hash(hasher, self.index, value);
+        self.verify_leaf(hasher, default_leaf, &leaf_hash, root)
+    }
+
+    /// Verify the proof against the root of a tree with a custom default leaf
+    pub fn verify_with_default<H: Hasher>(
+        &self,
+        hasher: &H,
+        default_leaf: &Hash,
+        value: &[u8],
+        root: &RootHash,
+    ) -> bool {
+        self.verify_with_encoding(hasher, default_leaf, SparseLeafEncoding::Value, value, root)
+    }
+
+    /// Check that `leaf_hash` at the proven index leads to `root`
+    fn verify_leaf<H: Hasher>(
+        &self,
+        hasher: &H,
+        default_leaf: &Hash,
+        leaf_hash: &Hash,
+        root: &RootHash,
+    ) -> bool {
+        self.siblings.len() == self.depth as usize
+            && self
+                .to_merkle_proof_with_default(hasher, default_leaf)
+                .compute_root(hasher, leaf_hash)
+                == *root
+    }
+}
+
+/// Proof that an index of a sparse Merkle tree holds no value
+///
+/// Generated by [`SparseMerkleTree::prove_empty`]. Unlike verifying a
+/// [`SparseProof`] for some value, it cannot be confused with a proof of a
+/// stored value that happens to equal the default leaf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EmptyProof {
+    /// Compressed path from the empty leaf to the root
+    pub proof: SparseProof,
+}
+
+impl EmptyProof {
+    /// Get the index proven empty
+    pub fn index(&self) -> u64 {
+        self.proof.index
+    }
+
+    /// Verify that the index is empty under `root`
+    pub fn verify<H: Hasher>(&self, hasher: &H, root: &RootHash) -> bool {
+        self.verify_with_default(hasher, &zero_leaf(hasher), root)
+    }
+
+    /// Verify against the root of a tree with a custom default leaf
+    pub fn verify_with_default<H: Hasher>(
+        &self,
+        hasher: &H,
+        default_leaf: &Hash,
+        root: &RootHash,
+    ) -> bool {
+        self.proof
+            .verify_leaf(hasher, default_leaf, default_leaf, root)
+    }
+}
+
+/// Proof that a set of leaf changes turns one sparse root into another
+///
+/// Generated by [`SparseMerkleTree::apply_batch_with_proof`]. Both roots are
+/// recomputed from the same siblings, so they can only both match if the
+/// listed leaves are the only ones that changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TransitionProof {
+    /// Depth of the tree the proof was generated from
+    pub depth: u8,
+    /// Changed leaves in index order, with their hash before and after the
+    /// change, `None` for an empty leaf
+    pub changes: Vec<(u64, Option<Hash>, Option<Hash>)>,
+    /// Siblings of the changed paths that are not themselves on a changed
+    /// path, from the leaf level upwards and in index order within a level,
+    /// `None` for empty subtrees
+    pub siblings: Vec<Option<Hash>>,
+}
+
+impl TransitionProof {
+    /// Verify the transition from `old_root` to `new_root` for a tree with
+    /// the all-zero default leaf of [`SparseMerkleTree::new`]
+    pub fn verify<H: Hasher>(&self, hasher: &H, old_root: &RootHash, new_root: &RootHash) -> bool {
+        self.verify_with_default(hasher, &zero_leaf(hasher), old_root, new_root)
+    }
+
+    /// Verify the transition for a tree with the given default leaf
+    pub fn verify_with_default<H: Hasher>(
+        &self,
+        hasher: &H,
+        default_leaf: &Hash,
+        old_root: &RootHash,
+        new_root: &RootHash,
+    ) -> bool {
+        let old = self.changes.iter().map(|(index, old, _)| (*index, old));
+        let new = self.changes.iter().map(|(index, _, new)| (*index, new));
+        self.compute_root(hasher, default_leaf, old)
+            .is_some_and(|root| root == *old_root)
+            && self
+                .compute_root(hasher, default_leaf, new)
+                .is_some_and(|root| root == *new_root)
+    }
+
+    /// Verify the transition and that it changes exactly the given leaves
+    ///
+    /// Each update is `(index, old value, new value)`, with `None` for an
+    /// empty leaf, and the updates must be listed in index order. Values are
+    /// hashed as `H(value)`; for other encodings compare
+    /// [`SparseLeafEncoding::leaf_hash`] with the listed changes.
+    pub fn verify_updates<H: Hasher, T: AsRef<[u8]>>(
+        &self,
+        hasher: &H,
+        old_root: &RootHash,
+        new_root: &RootHash,
+        updates: &[(u64, Option<T>, Option<T>)],
+    ) -> bool {
+        let hash = |value: &Option<T>| value.as_ref().map(|value| hasher.hash(value.as_ref()));
+        updates.len() == self.changes.len()
+            && updates.iter().zip(&self.changes).all(|(update, change)| {
+                update.0 == change.0 && hash(&update.1) == change.1 && hash(&update.2) == change.2
+            })
+            && self.verify(hasher, old_root, new_root)
+    }
+
+    /// Root of the tree with the changed leaves set to `leaves`, or `None` if
+    /// the proof is malformed
+    fn compute_root<'a, H: Hasher>(
+        &self,
+        hasher: &H,
+        default_leaf: &Hash,
+        leaves: impl Iterator<Item = (u64, &'a Option<Hash>)>,
+    ) -> Option<RootHash> {
+        if self.depth == 0 || self.depth > 64 {
+            return None;
+        }
+        let empties = empty_hashes_with_default(hasher, default_leaf, self.depth);
+        let max_index = max_index(self.depth);
+
+        let mut nodes: Vec<(u64, Hash)> = Vec::with_capacity(self.changes.len());
+        for (index, hash) in leaves {
+            if index > max_index || nodes.last().is_some_and(|(last, _)| *last >= index) {
+                return None;
+            }
+            nodes.push((index, hash.clone().unwrap_or_else(|| default_leaf.clone())));
+        }
+        if nodes.is_empty() {
+            return None;
+        }
+
+        let mut siblings = self.siblings.iter();
+        for empty in &empties[..self.depth as usize] {
+            let mut parents = Vec::with_capacity(nodes.len());
+            let mut i = 0;
+            while i < nodes.len() {
+                let (index, hash) = &nodes[i];
+                let (left, right) = match nodes.get(i + 1) {
+                    Some((next, sibling)) if index & 1 == 0 && *next == index | 1 => {
+                        i += 1;
+                        (hash.clone(), sibling.clone())
+                    }
+                    _ => {
+                        let sibling = siblings.next()?.clone();
+                        let sibling = sibling.unwrap_or_else(|| empty.clone());
+                        match index & 1 {
+                            0 => (hash.clone(), sibling),
+                            _ => (sibling, hash.clone()),
+                        }
+                    }
+                };
+                parents.push((index >> 1, hasher.hash_pair(&left, &right)));
+                i += 1;
+            }
+            nodes = parents;
+        }
+
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(RootHash::from(nodes.pop()?.1))
+    }
+}
+
+/// Verify a proof from a sparse tree without a tree instance
+///
+/// `value` is `None` to prove that `index` is empty, i.e. holds the all-zero
+/// default leaf of [`SparseMerkleTree::new`]. The proof must have one step
+/// per level, with directions following the bits of `index`, so a proof for
+/// one index cannot be passed off for another. Leaves are `H(value)`; see
+/// [`verify_with_encoding`] for trees using another [`SparseLeafEncoding`].
+pub fn verify<H: Hasher>(
+    root: &RootHash,
+    depth: u8,
+    index: u64,
+    value: Option<&[u8]>,
+    proof: &MerkleProof,
+    hasher: &H,
+) -> bool {
+    verify_with_encoding(
+        root,
+        depth,
+        index,
+        value,
+        proof,
+        hasher,
+        SparseLeafEncoding::Value,
+    )
+}
+
+/// Verify a proof from a sparse tree deriving leaves with `encoding`, without
+/// a tree instance
+pub fn verify_with_encoding<H: Hasher>(
+    root: &RootHash,
+    depth: u8,
+    index: u64,
+    value: Option<&[u8]>,
+    proof: &MerkleProof,
+    hasher: &H,
+    encoding: SparseLeafEncoding,
+) -> bool {
+    if depth == 0 || depth > 64 || index > max_index(depth) {
+        return false;
+    }
+    if proof.leaf_index as u64 != index || proof.steps.len() != depth as usize {
+        return false;
+    }
+    if (0..proof.steps.len())
+        .any(|level| proof.steps[level].direction != direction_at(index, level))
+    {
+        return false;
+    }
+
+    let leaf_hash = match value {
+        Some(value) => encoding.leaf_hash(hasher, index, value),
+        None => zero_leaf(hasher),
+    };
+    proof.compute_root(hasher, &leaf_hash) == *root
+}
+
+/// Side of the sibling at `level` on the path to `index`
+fn direction_at(index: u64, level: usize) -> ProofDirection {
+    if (index >> level) & 1 == 0 {
+        ProofDirection::Right
+    } else {
+        ProofDirection::Left
     }
 }
 
@@ -239,12 +1667,32 @@ pub struct SparseTreeStats {
     pub cached_nodes: usize,
     pub hasher_name: String,
     pub root_hash: String,
+    /// Number of non-empty nodes on each level, leaves first and the root last
+    pub occupied_nodes: Vec<u64>,
+    /// Height of the subtrees summarized by `fill_histogram`
+    pub subtree_height: u8,
+    /// Non-empty subtrees of height `subtree_height` by the share of their
+    /// leaves that are set: bucket `i` counts those with more than `i / 10`
+    /// and at most `(i + 1) / 10` of their leaves set
+    pub fill_histogram: [usize; 10],
+}
+
+impl SparseTreeStats {
+    /// Get the share of the nodes on `level` that are not empty
+    pub fn fill_factor(&self, level: u8) -> f64 {
+        let occupied = self
+            .occupied_nodes
+            .get(level as usize)
+            .copied()
+            .unwrap_or(0);
+        occupied as f64 / 2f64.powi(self.depth.saturating_sub(level) as i32)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::hasher::{Hasher, Sha256Hasher};
+    use crate::hasher::{Blake3Hasher, Hasher, Sha256Hasher};
 
     #[test]
     fn test_create_sparse_tree() {
@@ -275,7 +1723,7 @@ mod tests {
 
         let hash10 = tree.get(10).unwrap();
         let expected_hash = tree.hasher.hash("hello".as_bytes());
-        assert_eq!(hash10, expected_hash);
+        assert_eq!(*hash10, expected_hash);
     }
 
     #[test]
@@ -297,16 +1745,16 @@ mod tests {
     fn test_root_computation() {
         let mut tree = SparseMerkleTree::new(4, Sha256Hasher::new()).unwrap();
 
-        let empty_root = tree.root().to_vec();
+        let empty_root = tree.root().clone();
 
         tree.update(0, "test".as_bytes()).unwrap();
-        let root_with_data = tree.root().to_vec();
+        let root_with_data = tree.root().clone();
 
         // Root should change after adding data
         assert_ne!(empty_root, root_with_data);
 
         tree.remove(0).unwrap();
-        let root_after_removal = tree.root().to_vec();
+        let root_after_removal = tree.root().clone();
 
         // Root should return to original state
         assert_eq!(empty_root, root_after_removal);
@@ -323,8 +1771,241 @@ mod tests {
         assert!(tree.verify_proof(&proof, 10, "hello".as_bytes()));
         assert!(!tree.verify_proof(&proof, 10, "wrong".as_bytes()));
 
-        let proof_empty = tree.generate_proof(30).unwrap();
-        assert!(tree.verify_proof(&proof_empty, 30, &DEFAULT_HASH));
+        let root = tree.root().clone();
+        let proof_empty = tree.prove_empty(30).unwrap();
+        assert_eq!(proof_empty.index(), 30);
+        assert!(tree.verify_empty(&root, 30, &proof_empty));
+        assert!(proof_empty.verify(tree.hasher(), &root));
+        assert!(!tree.verify_empty(&root, 31, &proof_empty));
+        assert!(tree.prove_empty(10).is_err());
+        assert!(tree.prove_empty(256).is_err());
+    }
+
+    #[test]
+    fn test_leaf_encoding() {
+        let hasher = Sha256Hasher::new();
+        let mut plain = SparseMerkleTree::new(8, hasher.clone()).unwrap();
+        plain.update(1, b"same").unwrap();
+        plain.update(2, b"same").unwrap();
+        assert_eq!(plain.get(1), plain.get(2));
+
+        for encoding in [
+            SparseLeafEncoding::IndexValue,
+            SparseLeafEncoding::IndexValueHash,
+        ] {
+            let mut tree = SparseMerkleTree::new(8, hasher.clone())
+                .unwrap()
+                .with_leaf_encoding(encoding);
+            tree.update(1, b"same").unwrap();
+            tree.update(2, b"same").unwrap();
+            assert_ne!(tree.get(1), tree.get(2));
+            assert_ne!(tree.root(), plain.root());
+            let root = tree.root().clone();
+
+            let proof = tree.generate_proof(2).unwrap();
+            assert!(tree.verify_proof(&proof, 2, b"same"));
+            assert!(verify_with_encoding(
+                &root,
+                8,
+                2,
+                Some(b"same"),
+                &proof,
+                &hasher,
+                encoding
+            ));
+            assert!(!verify(&root, 8, 2, Some(b"same"), &proof, &hasher));
+            let sparse = tree.generate_sparse_proof(2).unwrap();
+            assert!(sparse.verify_with_encoding(
+                &hasher,
+                tree.default_leaf(),
+                encoding,
+                b"same",
+                &root
+            ));
+            assert!(!sparse.verify(&hasher, b"same", &root));
+        }
+
+        // A custom default leaf fills the siblings the proof leaves out
+        let default_leaf = hasher.hash(b"");
+        let mut tree = SparseMerkleTree::with_default_leaf(8, hasher.clone(), default_leaf.clone())
+            .unwrap()
+            .with_leaf_encoding(SparseLeafEncoding::IndexValue);
+        tree.update(2, b"same").unwrap();
+        let root = tree.root().clone();
+        let sparse = tree.generate_sparse_proof(2).unwrap();
+        assert!(sparse.verify_with_encoding(
+            &hasher,
+            &default_leaf,
+            SparseLeafEncoding::IndexValue,
+            b"same",
+            &root
+        ));
+        assert!(!sparse.verify_with_encoding(
+            &hasher,
+            &zero_leaf(&hasher),
+            SparseLeafEncoding::IndexValue,
+            b"same",
+            &root
+        ));
+
+        let expected = hasher.hash(&[&2u64.to_be_bytes()[..], b"same"].concat());
+        assert_eq!(
+            SparseLeafEncoding::IndexValue.leaf_hash(&hasher, 2, b"same"),
+            expected
+        );
+        let expected = hasher.hash(&[&2u64.to_be_bytes()[..], &hasher.hash(b"same")].concat());
+        assert_eq!(
+            SparseLeafEncoding::IndexValueHash.leaf_hash(&hasher, 2, b"same"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_sparse_proof_cost() {
+        let mut tree = SparseMerkleTree::new(16, Sha256Hasher::new()).unwrap();
+        for index in [3, 4, 1000, 40_000] {
+            tree.update(index, b"value").unwrap();
+        }
+        for index in [0, 3, 4, 999, 40_000, 65_535] {
+            let cost = tree.sparse_proof_cost(index).unwrap();
+            let proof = tree.generate_sparse_proof(index).unwrap();
+            assert_eq!(cost.steps, 16);
+            assert_eq!(cost.bytes, proof.siblings.iter().flatten().count() * 32);
+            assert_eq!(cost.hashes_to_verify, 17);
+        }
+        assert!(tree.sparse_proof_cost(65_536).is_err());
+    }
+
+    #[test]
+    fn test_get_with_proof() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = SparseMerkleTree::new(8, hasher.clone()).unwrap();
+        tree.update(10, b"hello").unwrap();
+        let root = tree.root().clone();
+
+        let (value, proof) = tree.get_with_proof(10).unwrap();
+        assert_eq!(value, Some(hasher.hash(b"hello")));
+        assert_eq!(proof, tree.generate_proof(10).unwrap());
+        assert!(proof.verify_with_leaf_hash(&hasher, &value.unwrap(), &root));
+
+        let (value, proof) = tree.get_with_proof(11).unwrap();
+        assert_eq!(value, None);
+        assert!(proof.verify_with_leaf_hash(&hasher, tree.default_leaf(), &root));
+        assert!(tree.get_with_proof(256).is_err());
+    }
+
+    #[test]
+    fn test_zero_value_is_not_empty() {
+        let mut tree = SparseMerkleTree::new(8, Sha256Hasher::new()).unwrap();
+        tree.update(7, &DEFAULT_HASH).unwrap();
+        let root = tree.root().clone();
+
+        // A stored all-zero value is proven like any other value
+        let proof = tree.generate_proof(7).unwrap();
+        assert!(tree.verify_proof(&proof, 7, &DEFAULT_HASH));
+        let sparse = tree.generate_sparse_proof(7).unwrap();
+        assert!(sparse.verify(tree.hasher(), &DEFAULT_HASH, &root));
+        assert!(tree.prove_empty(7).is_err());
+        let forged = EmptyProof { proof: sparse };
+        assert!(!tree.verify_empty(&root, 7, &forged));
+
+        // while an empty index does not verify as holding zeroes
+        let proof = tree.generate_proof(8).unwrap();
+        assert!(!tree.verify_proof(&proof, 8, &DEFAULT_HASH));
+        let empty = tree.prove_empty(8).unwrap();
+        assert!(tree.verify_empty(&root, 8, &empty));
+    }
+
+    #[test]
+    fn test_apply_batch_if_root() {
+        let mut tree = SparseMerkleTree::new(8, Sha256Hasher::new()).unwrap();
+        tree.update(1, b"one").unwrap();
+        tree.update(2, b"two").unwrap();
+        let start = tree.root().clone();
+
+        let root = tree
+            .apply_batch_if_root(&start, &[(3, Some("three")), (1, None), (3, Some("3"))])
+            .unwrap();
+        assert_eq!(tree.root(), &root);
+        assert!(!tree.contains(1));
+
+        let mut expected = SparseMerkleTree::new(8, Sha256Hasher::new()).unwrap();
+        expected.update(2, b"two").unwrap();
+        expected.update(3, b"3").unwrap();
+        assert_eq!(expected.root(), &root);
+
+        // A stale root is rejected without touching the tree
+        assert!(matches!(
+            tree.apply_batch_if_root(&start, &[(4, Some(b"four"))]),
+            Err(MerkleError::RootMismatch { .. })
+        ));
+        // As is a batch with an index out of range
+        assert!(tree
+            .apply_batch_if_root(&root, &[(4, Some("four")), (256, Some("x"))])
+            .is_err());
+        assert_eq!(tree.root(), &root);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn test_proof_cache() {
+        let mut tree = SparseMerkleTree::new(8, Sha256Hasher::new())
+            .unwrap()
+            .with_proof_cache(2);
+        tree.update(1, b"one").unwrap();
+        tree.update(2, b"two").unwrap();
+
+        let proof = tree.generate_proof(1).unwrap();
+        assert_eq!(tree.generate_proof(1).unwrap(), proof);
+        assert_eq!(tree.proof_cache_stats(), Some((1, 1)));
+
+        // Index 2 evicts the least recently used proof, for index 3
+        tree.generate_proof(3).unwrap();
+        tree.generate_proof(1).unwrap();
+        tree.generate_proof(2).unwrap();
+        tree.generate_proof(1).unwrap();
+        assert_eq!(tree.proof_cache_stats(), Some((3, 3)));
+        tree.generate_proof(3).unwrap();
+        assert_eq!(tree.proof_cache_stats(), Some((3, 4)));
+
+        // Changes empty the cache, so proofs follow the new root
+        tree.update(1, b"uno").unwrap();
+        let proof = tree.generate_proof(2).unwrap();
+        assert!(tree.verify_proof(&proof, 2, b"two"));
+        assert_eq!(tree.proof_cache_stats(), Some((3, 5)));
+
+        // Disabled by default
+        let mut tree = SparseMerkleTree::new(8, Sha256Hasher::new()).unwrap();
+        tree.generate_proof(1).unwrap();
+        assert_eq!(tree.proof_cache_stats(), None);
+    }
+
+    #[test]
+    fn test_standalone_verify() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = SparseMerkleTree::new(8, hasher.clone()).unwrap();
+        tree.update(10, b"hello").unwrap();
+        tree.update(255, b"last").unwrap();
+        let root = tree.root().clone();
+
+        let proof = tree.generate_proof(10).unwrap();
+        assert!(verify(&root, 8, 10, Some(b"hello"), &proof, &hasher));
+        assert!(!verify(&root, 8, 10, Some(b"wrong"), &proof, &hasher));
+        assert!(!verify(&root, 8, 10, None, &proof, &hasher));
+        assert!(!verify(&root, 7, 10, Some(b"hello"), &proof, &hasher));
+
+        let proof = tree.generate_proof(255).unwrap();
+        assert!(verify(&root, 8, 255, Some(b"last"), &proof, &hasher));
+
+        let empty = tree.generate_proof(11).unwrap();
+        assert!(verify(&root, 8, 11, None, &empty, &hasher));
+        assert!(!verify(&root, 8, 11, Some(b"hello"), &empty, &hasher));
+
+        // A proof relabeled with another index is rejected
+        let mut relabeled = empty.clone();
+        relabeled.leaf_index = 12;
+        assert!(!verify(&root, 8, 12, None, &relabeled, &hasher));
+        assert!(!verify(&root, 8, 256, None, &empty, &hasher));
     }
 
     #[test]
@@ -367,6 +2048,204 @@ mod tests {
         assert_eq!(stats.max_leaves, 256);
         assert_eq!(stats.hasher_name, "SHA-256");
         assert!(!stats.root_hash.is_empty());
+        // 10 and 20 share an ancestor from level 5 up
+        assert_eq!(stats.occupied_nodes, vec![2, 2, 2, 2, 2, 1, 1, 1, 1]);
+        assert_eq!(stats.fill_factor(0), 2.0 / 256.0);
+        assert_eq!(stats.fill_factor(8), 1.0);
+        assert_eq!(stats.subtree_height, 8);
+        assert_eq!(stats.fill_histogram, [1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_proof_len() {
+        let mut tree = SparseMerkleTree::new(12, Sha256Hasher::new()).unwrap();
+        tree.update(7, b"a").unwrap();
+        assert_eq!(tree.proof_len(), 12);
+        assert_eq!(tree.generate_proof(7).unwrap().len(), tree.proof_len());
+        assert_eq!(tree.generate_proof(8).unwrap().len(), tree.proof_len());
+    }
+
+    #[test]
+    fn test_subtrees() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = SparseMerkleTree::new(8, hasher.clone()).unwrap();
+        tree.update(0x35, b"a").unwrap();
+        tree.update(0x3f, b"b").unwrap();
+
+        let mut subtree = SparseMerkleTree::new(4, hasher.clone()).unwrap();
+        subtree.update(0x5, b"a").unwrap();
+        subtree.update(0xf, b"b").unwrap();
+        let subtree_root = tree.subtree_root(4, 0x3).unwrap();
+        assert_eq!(&subtree_root, subtree.root());
+
+        let root = tree.root().clone();
+        let proof = tree.generate_subtree_proof(4, 0x3).unwrap();
+        assert_eq!(proof.len(), 4);
+        assert!(proof.verify_with_leaf_hash(&hasher, subtree_root.as_hash(), &root));
+
+        assert_eq!(tree.subtree_root(8, 0).unwrap(), root);
+        assert!(tree.generate_subtree_proof(8, 0).unwrap().is_empty());
+        assert_eq!(
+            tree.subtree_root(0, 0x35).unwrap().as_hash(),
+            &hasher.hash(b"a")
+        );
+        assert!(tree.subtree_root(9, 0).is_err());
+        assert!(tree.subtree_root(8, 1).is_err());
+        assert!(tree.subtree_root(4, 16).is_err());
+    }
+
+    #[test]
+    fn test_transactions() {
+        let mut tree = SparseMerkleTree::new(16, Sha256Hasher::new()).unwrap();
+        tree.update(1, b"a").unwrap();
+        tree.update(2, b"b").unwrap();
+        let root = tree.root().clone();
+
+        tree.begin_transaction().unwrap();
+        assert!(tree.in_transaction());
+        assert!(matches!(
+            tree.begin_transaction(),
+            Err(MerkleError::TransactionState { .. })
+        ));
+        tree.update(1, b"changed").unwrap();
+        tree.update(1, b"changed again").unwrap();
+        tree.update(3, b"c").unwrap();
+        tree.remove(2).unwrap();
+        let current = tree.root().clone();
+        tree.apply_batch_if_root(&current, &[(4, Some("d")), (1, None)])
+            .unwrap();
+        assert_ne!(tree.root(), &root);
+        tree.rollback().unwrap();
+
+        assert!(!tree.in_transaction());
+        assert_eq!(tree.root(), &root);
+        assert_eq!(tree.leaf_indices(), vec![1, 2]);
+        let proof = tree.generate_proof(1).unwrap();
+        assert!(tree.verify_proof(&proof, 1, b"a"));
+
+        tree.begin_transaction().unwrap();
+        tree.clear();
+        tree.rollback().unwrap();
+        assert_eq!(tree.root(), &root);
+
+        tree.begin_transaction().unwrap();
+        tree.update(5, b"e").unwrap();
+        let committed = tree.commit().unwrap();
+        assert_eq!(tree.root(), &committed);
+        assert!(tree.contains(5));
+
+        assert!(matches!(
+            tree.rollback(),
+            Err(MerkleError::TransactionState { .. })
+        ));
+        assert!(matches!(
+            tree.commit(),
+            Err(MerkleError::TransactionState { .. })
+        ));
+    }
+
+    #[test]
+    fn test_transition_proof() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = SparseMerkleTree::new(16, hasher.clone()).unwrap();
+        for index in [0, 1, 7, 300, 65535] {
+            tree.update(index, format!("v{}", index).as_bytes())
+                .unwrap();
+        }
+        let old_root = tree.root().clone();
+
+        let batch = [
+            (1, Some("new1")),
+            (2, Some("new2")),
+            (300, None),
+            (9000, Some("x")),
+            (9000, Some("new9000")),
+        ];
+        let proof = tree.apply_batch_with_proof(&batch).unwrap();
+        let new_root = tree.root().clone();
+        assert_eq!(proof.changes.len(), 4);
+        assert!(proof.verify(&hasher, &old_root, &new_root));
+        assert!(!proof.verify(&hasher, &new_root, &old_root));
+
+        let updates = [
+            (1, Some("v1"), Some("new1")),
+            (2, None, Some("new2")),
+            (300, Some("v300"), None),
+            (9000, None, Some("new9000")),
+        ];
+        assert!(proof.verify_updates(&hasher, &old_root, &new_root, &updates));
+        assert!(!proof.verify_updates(&hasher, &old_root, &new_root, &updates[..3]));
+        let mut wrong = updates;
+        wrong[1].2 = Some("other");
+        assert!(!proof.verify_updates(&hasher, &old_root, &new_root, &wrong));
+
+        // Hiding an extra change breaks one of the roots
+        let mut other = tree.clone();
+        other.update(7, b"sneaky").unwrap();
+        assert!(!proof.verify(&hasher, &old_root, other.root()));
+
+        let mut tampered = proof.clone();
+        tampered.changes.remove(0);
+        assert!(!tampered.verify(&hasher, &old_root, &new_root));
+        let mut tampered = proof.clone();
+        tampered.siblings.push(None);
+        assert!(!tampered.verify(&hasher, &old_root, &new_root));
+
+        assert!(tree
+            .apply_batch_with_proof(&[(1 << 16, Some("x"))])
+            .is_err());
+        assert_eq!(tree.root(), &new_root);
+
+        // Changes are also recorded in an open transaction
+        tree.begin_transaction().unwrap();
+        tree.apply_batch_with_proof(&[(5, Some("y"))]).unwrap();
+        tree.rollback().unwrap();
+        assert_eq!(tree.root(), &new_root);
+    }
+
+    #[test]
+    fn test_empty_hashes() {
+        let hasher = Sha256Hasher::new();
+        let empties = empty_hashes(&hasher, 8);
+        assert_eq!(empties.len(), 9);
+        assert_eq!(empties[0].as_bytes(), &[0u8; 32]);
+        for level in 1..=8 {
+            assert_eq!(
+                empties[level],
+                hasher.hash_pair(&empties[level - 1], &empties[level - 1])
+            );
+        }
+        assert_eq!(
+            SparseMerkleTree::new(8, hasher.clone())
+                .unwrap()
+                .root()
+                .as_hash(),
+            &empties[8]
+        );
+
+        let default_leaf = hasher.hash(b"empty");
+        let empties = empty_hashes_with_default(&hasher, &default_leaf, 8);
+        let mut tree = SparseMerkleTree::with_default_leaf(8, hasher, default_leaf).unwrap();
+        assert_eq!(tree.root().as_hash(), &empties[8]);
+    }
+
+    #[test]
+    fn test_fill_histogram() {
+        let mut tree = SparseMerkleTree::new(16, Sha256Hasher::new()).unwrap();
+        tree.update(10, b"a").unwrap();
+        tree.update(300, b"b").unwrap();
+        for index in 512..712 {
+            tree.update(index, b"c").unwrap();
+        }
+        for index in 768..1024 {
+            tree.update(index, b"d").unwrap();
+        }
+
+        let stats = tree.stats();
+        assert_eq!(stats.occupied_nodes[8], 4);
+        assert_eq!(stats.occupied_nodes[16], 1);
+        // 200 of 256 leaves set is in the 70-80% bucket, a full subtree in the last
+        assert_eq!(stats.fill_histogram, [2, 0, 0, 0, 0, 0, 0, 1, 0, 1]);
     }
 
     #[test]
@@ -384,6 +2263,257 @@ mod tests {
         assert!(!tree.contains(20));
     }
 
+    #[test]
+    fn test_sparse_proof_bytes_roundtrip() {
+        let mut tree = SparseMerkleTree::new(16, Sha256Hasher::new()).unwrap();
+        tree.update(3, "three".as_bytes()).unwrap();
+        tree.update(40000, "far".as_bytes()).unwrap();
+        let root = tree.root().clone();
+
+        let proof = tree.generate_sparse_proof(3).unwrap();
+        assert_eq!(proof.depth, 16);
+        // Only the top-level sibling holding index 40000 is non-empty
+        assert_eq!(proof.siblings.iter().flatten().count(), 1);
+
+        let bytes = proof.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 1 + 2 + 2 + 1 + 2 + 32);
+
+        let decoded = SparseProof::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(decoded.verify(&tree.hasher, "three".as_bytes(), &root));
+        assert!(!decoded.verify(&tree.hasher, "four".as_bytes(), &root));
+
+        let expanded = decoded.to_merkle_proof(&tree.hasher);
+        assert_eq!(expanded, tree.generate_proof(3).unwrap());
+        assert!(tree.verify_proof(&expanded, 3, "three".as_bytes()));
+    }
+
+    #[test]
+    fn test_custom_default_leaf() {
+        let hasher = Sha256Hasher::new();
+        let default_leaf = hasher.hash(b"");
+        let mut tree =
+            SparseMerkleTree::with_default_leaf(4, hasher.clone(), default_leaf.clone()).unwrap();
+        assert_eq!(tree.default_leaf(), &default_leaf);
+
+        // An empty tree is a perfect tree of default leaves
+        let mut level = vec![default_leaf.clone(); 16];
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| hasher.hash_pair(&pair[0], &pair[1]))
+                .collect();
+        }
+        assert_eq!(*tree.root(), RootHash::from(level[0].clone()));
+        assert_ne!(
+            *tree.root(),
+            *SparseMerkleTree::new(4, hasher.clone()).unwrap().root()
+        );
+
+        tree.update(5, b"five").unwrap();
+        let root = tree.root().clone();
+        let proof = tree.generate_proof(9).unwrap();
+        let empty = tree.prove_empty(9).unwrap();
+        assert!(tree.verify_empty(&root, 9, &empty));
+        assert!(empty.verify_with_default(&hasher, &default_leaf, &root));
+        assert!(!empty.verify(&hasher, &root));
+
+        let sparse = tree.generate_sparse_proof(9).unwrap();
+        assert_eq!(sparse, empty.proof);
+        assert_eq!(sparse.siblings.iter().flatten().count(), 1);
+        assert!(!sparse.verify(&hasher, &DEFAULT_HASH, &root));
+        assert_eq!(
+            SparseProof::from_merkle_proof_with_default(&proof, 4, &hasher, &default_leaf).unwrap(),
+            sparse
+        );
+        assert_eq!(
+            sparse.to_merkle_proof_with_default(&hasher, &default_leaf),
+            proof
+        );
+
+        assert!(matches!(
+            SparseMerkleTree::with_default_leaf(4, hasher, Hash::from(&[0u8; 20][..])),
+            Err(MerkleError::TreeConstructionError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_default_leaf_value_rejected() {
+        let hasher = Sha256Hasher::new();
+        let mut tree =
+            SparseMerkleTree::with_default_leaf(4, hasher.clone(), hasher.hash(b"")).unwrap();
+        tree.update(5, b"five").unwrap();
+        let root = tree.root().clone();
+
+        // b"" hashes to the default leaf, so storing it would look empty
+        assert!(matches!(
+            tree.update(3, b""),
+            Err(MerkleError::UnsupportedOperation { .. })
+        ));
+        assert!(matches!(
+            tree.apply_batch_if_root(&root, &[(2, Some(&b"two"[..])), (3, Some(&b""[..]))]),
+            Err(MerkleError::UnsupportedOperation { .. })
+        ));
+        assert!(tree.apply_batch_with_proof(&[(3, Some(b""))]).is_err());
+        assert_eq!(tree.root(), &root);
+        assert!(!tree.contains(2) && !tree.contains(3));
+
+        let empty = tree.prove_empty(3).unwrap();
+        assert!(tree.verify_empty(&root, 3, &empty));
+    }
+
+    #[test]
+    fn test_sparse_proof_from_merkle_proof() {
+        let mut tree = SparseMerkleTree::new(8, Sha256Hasher::new()).unwrap();
+        tree.update(200, "value".as_bytes()).unwrap();
+
+        let plain = tree.generate_proof(17).unwrap();
+        let compressed = SparseProof::from_merkle_proof(&plain, 8, &tree.hasher).unwrap();
+        assert_eq!(compressed, tree.generate_sparse_proof(17).unwrap());
+        let root = tree.root().clone();
+        let empty = EmptyProof { proof: compressed };
+        assert!(empty.verify(&tree.hasher, &root));
+
+        assert!(SparseProof::from_merkle_proof(&plain, 9, &tree.hasher).is_err());
+
+        let mut wrong_index = plain.clone();
+        wrong_index.leaf_index = 16;
+        assert!(SparseProof::from_merkle_proof(&wrong_index, 8, &tree.hasher).is_err());
+    }
+
+    #[test]
+    fn test_sparse_proof_malformed_bytes() {
+        let mut tree = SparseMerkleTree::new(12, Sha256Hasher::new()).unwrap();
+        tree.update(1, "one".as_bytes()).unwrap();
+        let bytes = tree.generate_sparse_proof(0).unwrap().to_bytes().unwrap();
+
+        assert!(SparseProof::from_bytes(&[]).is_err());
+        assert!(SparseProof::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(SparseProof::from_bytes(&trailing).is_err());
+
+        let mut bad_version = bytes.clone();
+        bad_version[0] = 99;
+        assert!(SparseProof::from_bytes(&bad_version).is_err());
+
+        // Index 4096 does not fit in a depth-12 tree
+        let mut bad_index = bytes;
+        bad_index[3] = 0x10;
+        assert!(SparseProof::from_bytes(&bad_index).is_err());
+    }
+
+    #[test]
+    fn test_full_depth_tree() {
+        let mut tree = SparseMerkleTree::new(64, Sha256Hasher::new()).unwrap();
+        tree.update(u64::MAX, "last".as_bytes()).unwrap();
+        tree.update(0, "first".as_bytes()).unwrap();
+
+        let proof = tree.generate_proof(u64::MAX).unwrap();
+        assert_eq!(proof.len(), 64);
+        assert!(tree.verify_proof(&proof, u64::MAX, "last".as_bytes()));
+
+        let compressed = tree.generate_sparse_proof(u64::MAX).unwrap();
+        let decoded = SparseProof::from_bytes(&compressed.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.index, u64::MAX);
+        let root = tree.root().clone();
+        assert!(decoded.verify(&tree.hasher, "last".as_bytes(), &root));
+        assert_eq!(tree.stats().max_leaves, u64::MAX);
+    }
+
+    #[test]
+    fn test_persist_node_cache() {
+        let hasher = Sha256Hasher::new();
+        let mut tree = SparseMerkleTree::new(16, hasher.clone()).unwrap();
+        for i in 0..100u64 {
+            tree.update(i * 613, format!("value_{}", i).as_bytes())
+                .unwrap();
+        }
+
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+        let mut restored =
+            SparseMerkleTree::deserialize_from(buf.as_slice(), hasher.clone()).unwrap();
+        assert_eq!(restored.stats().cached_nodes, tree.stats().cached_nodes);
+        assert_eq!(restored.root(), tree.root());
+        assert_eq!(restored.leaves(), tree.leaves());
+        assert_eq!(
+            restored.generate_proof(613).unwrap(),
+            tree.generate_proof(613).unwrap()
+        );
+        assert_eq!(restored.verify_integrity(), Ok(()));
+
+        // A tampered cached node is caught, as is a different hasher
+        let last = buf.len() - 1;
+        buf[last] ^= 1;
+        let mut tampered = SparseMerkleTree::deserialize_from(buf.as_slice(), hasher).unwrap();
+        assert!(matches!(
+            tampered.verify_integrity(),
+            Err(MerkleError::IntegrityError { .. })
+        ));
+        assert!(matches!(
+            SparseMerkleTree::deserialize_from(buf.as_slice(), Blake3Hasher::new()),
+            Err(MerkleError::SerializationError { .. })
+        ));
+        for len in [0, 4, 10, buf.len() - 1] {
+            assert!(SparseMerkleTree::deserialize_from(&buf[..len], Sha256Hasher::new()).is_err());
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_scrub_wipes_leaves() {
+        let mut tree = SparseMerkleTree::new(8, Sha256Hasher::new()).unwrap();
+        tree.update(10, "secret".as_bytes()).unwrap();
+        tree.root();
+
+        tree.scrub();
+        assert!(tree.is_empty());
+        assert!(tree.nodes.is_empty());
+        assert!(tree.root_cache.is_none());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_update_batch() {
+        for depth in [3u8, 16, 64] {
+            let entries: Vec<(u64, String)> = (0..200u64)
+                .map(|i| {
+                    (
+                        i.wrapping_mul(0x9e37_79b9_7f4a_7c15) & max_index(depth),
+                        format!("v{}", i),
+                    )
+                })
+                .collect();
+
+            let mut sequential = SparseMerkleTree::new(depth, Sha256Hasher::new()).unwrap();
+            for (index, value) in &entries {
+                sequential.update(*index, value.as_bytes()).unwrap();
+            }
+
+            let mut parallel = SparseMerkleTree::new(depth, Sha256Hasher::new()).unwrap();
+            parallel.update(0, b"existing").unwrap();
+            parallel.par_update_batch(&entries).unwrap();
+            if !entries.iter().any(|(index, _)| *index == 0) {
+                sequential.update(0, b"existing").unwrap();
+            }
+            assert_eq!(parallel.root(), sequential.root(), "depth {}", depth);
+
+            // Later entries win when indices repeat
+            let (index, value) = entries.last().unwrap();
+            let proof = parallel.generate_proof(*index).unwrap();
+            assert!(parallel.verify_proof(&proof, *index, value.as_bytes()));
+        }
+
+        let mut tree = SparseMerkleTree::new(4, Sha256Hasher::new()).unwrap();
+        assert!(matches!(
+            tree.par_update_batch(&[(1, b"a"), (16, b"b")]),
+            Err(MerkleError::InvalidIndex { .. })
+        ));
+        assert!(tree.is_empty());
+    }
+
     #[test]
     fn test_large_sparse_tree() {
         let mut tree = SparseMerkleTree::new(20, Sha256Hasher::new()).unwrap();
@@ -403,7 +2533,8 @@ mod tests {
         }
 
         // Test proof for non-existent value
-        let empty_proof = tree.generate_proof(999).unwrap();
-        assert!(tree.verify_proof(&empty_proof, 999, &DEFAULT_HASH));
+        let root = tree.root().clone();
+        let empty_proof = tree.prove_empty(999).unwrap();
+        assert!(tree.verify_empty(&root, 999, &empty_proof));
     }
 }
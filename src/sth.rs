@@ -0,0 +1,586 @@
+//! Signed tree heads for authenticated publication of roots
+//!
+//! A log operator signs its `(tree_size, timestamp, root)` with a [`Signer`]
+//! and publishes the resulting [`SignedTreeHead`]. Clients holding the matching
+//! [`Verifier`] can then check an inclusion proof and the head together.
+//!
+//! The signed message follows the `TreeHeadSignature` layout of RFC 6962:
+//! version `0`, signature type `1` (tree hash), the timestamp and tree size as
+//! big-endian `u64`s, then the root hash.
+//!
+//! [`TreeHead`] is the general form for publishing a root: it also names the
+//! hasher and carries free-form metadata, has a canonical byte encoding, and
+//! can be signed with the same [`Signer`]s.
+//!
+//! Clients gossiping the heads they were served compare them with
+//! [`SignedTreeHead::same_view`] and consistency proofs; heads that conflict
+//! become a [`SplitView`], evidence of the operator's misbehavior that
+//! anyone can check.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::RootHash;
+use crate::hasher::Hasher;
+use crate::log::ConsistencyProof;
+use crate::merkle_tree::MerkleTree;
+use crate::proof::MerkleProof;
+use crate::stream::{
+    invalid, read_array, read_bounded_bytes, read_bytes, write_bytes, MAX_HASH_LEN,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Produces signatures over tree heads
+pub trait Signer {
+    /// Sign a message
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Checks signatures over tree heads
+pub trait Verifier {
+    /// Check a signature over a message
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Root of a log at a given size, signed by the log operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub root: RootHash,
+    pub signature: Vec<u8>,
+}
+
+impl SignedTreeHead {
+    /// Sign a tree head timestamped with the current time
+    pub fn sign<S: Signer>(signer: &S, tree_size: u64, root: RootHash) -> Result<Self> {
+        Self::sign_at(signer, tree_size, now_millis()?, root)
+    }
+
+    /// Sign a tree head with an explicit timestamp
+    pub fn sign_at<S: Signer>(
+        signer: &S,
+        tree_size: u64,
+        timestamp: u64,
+        root: RootHash,
+    ) -> Result<Self> {
+        let signature = signer.sign(&Self::signing_input(tree_size, timestamp, &root))?;
+
+        Ok(Self {
+            tree_size,
+            timestamp,
+            root,
+            signature,
+        })
+    }
+
+    /// Encode the message covered by the signature
+    pub fn signing_input(tree_size: u64, timestamp: u64, root: &RootHash) -> Vec<u8> {
+        let mut message = Vec::with_capacity(18 + root.len());
+        message.push(0); // version v1
+        message.push(1); // signature type tree_hash
+        message.extend_from_slice(&timestamp.to_be_bytes());
+        message.extend_from_slice(&tree_size.to_be_bytes());
+        message.extend_from_slice(root);
+        message
+    }
+
+    /// Check the signature
+    pub fn verify<V: Verifier>(&self, verifier: &V) -> bool {
+        let message = Self::signing_input(self.tree_size, self.timestamp, &self.root);
+        verifier.verify(&message, &self.signature)
+    }
+
+    /// Check the signature and that `proof` includes `leaf_data` under the
+    /// signed root
+    pub fn verify_inclusion<V: Verifier, H: Hasher>(
+        &self,
+        verifier: &V,
+        hasher: &H,
+        proof: &MerkleProof,
+        leaf_data: &[u8],
+    ) -> bool {
+        (proof.leaf_index as u64) < self.tree_size
+            && self.verify(verifier)
+            && proof.verify(hasher, leaf_data, &self.root)
+    }
+
+    /// Check that `other` shows the same view of the log: for heads of the
+    /// same size, the same root
+    ///
+    /// Heads of different sizes are comparable only through a consistency
+    /// proof, see [`SplitView::from_consistency`]. Signatures are not checked.
+    pub fn same_view(&self, other: &SignedTreeHead) -> bool {
+        self.tree_size != other.tree_size || self.root == other.root
+    }
+}
+
+/// Evidence that a log operator signed two views of its log that cannot both
+/// be true
+///
+/// Either two heads of the same size with different roots, or a smaller
+/// head and a larger one with a consistency proof that leads to the larger
+/// root from a different root at the smaller size. Anyone holding the
+/// operator's [`Verifier`] can check it with [`SplitView::verify`], so it
+/// can be published as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SplitView {
+    /// The smaller head, or either of two heads of the same size
+    pub older: SignedTreeHead,
+    /// The larger head
+    pub newer: SignedTreeHead,
+    /// Proof from the older size to the newer one, for heads of different
+    /// sizes
+    pub proof: Option<ConsistencyProof>,
+}
+
+impl SplitView {
+    /// Compare two heads of the same size, returning evidence if their roots
+    /// differ
+    pub fn from_heads(first: &SignedTreeHead, second: &SignedTreeHead) -> Option<Self> {
+        (first.tree_size == second.tree_size && first.root != second.root).then(|| Self {
+            older: first.clone(),
+            newer: second.clone(),
+            proof: None,
+        })
+    }
+
+    /// Check a consistency proof between two heads of different sizes,
+    /// returning evidence if it shows they diverge
+    ///
+    /// Returns `Ok(None)` if the proof shows the older head is a prefix of
+    /// the newer one, and fails if it shows neither: a bad proof only
+    /// implicates whoever served it. For an older size that is a power of
+    /// two a proof cannot show divergence, see
+    /// [`ConsistencyProof::implied_old_root`]; compare a head of the same
+    /// size instead.
+    pub fn from_consistency<H: Hasher>(
+        hasher: &H,
+        older: &SignedTreeHead,
+        newer: &SignedTreeHead,
+        proof: &ConsistencyProof,
+    ) -> Result<Option<Self>> {
+        if proof.old_size as u64 != older.tree_size || proof.new_size as u64 != newer.tree_size {
+            return Err(MerkleError::InvalidProof {
+                reason: format!(
+                    "Proof is from size {} to {}, heads are at {} and {}",
+                    proof.old_size, proof.new_size, older.tree_size, newer.tree_size
+                ),
+            });
+        }
+        if proof.verify(hasher, &older.root, &newer.root) {
+            return Ok(None);
+        }
+        match proof.implied_old_root(hasher, &newer.root) {
+            Some(implied) if implied != older.root => Ok(Some(Self {
+                older: older.clone(),
+                newer: newer.clone(),
+                proof: Some(proof.clone()),
+            })),
+            _ => Err(MerkleError::InvalidProof {
+                reason: "Proof shows neither consistency nor a split view".to_string(),
+            }),
+        }
+    }
+
+    /// Verify the evidence: both signatures, and that the heads conflict
+    pub fn verify<V: Verifier, H: Hasher>(&self, verifier: &V, hasher: &H) -> bool {
+        if !self.older.verify(verifier) || !self.newer.verify(verifier) {
+            return false;
+        }
+        match &self.proof {
+            None => Self::from_heads(&self.older, &self.newer).is_some(),
+            Some(proof) => {
+                proof.old_size as u64 == self.older.tree_size
+                    && proof.new_size as u64 == self.newer.tree_size
+                    && proof
+                        .implied_old_root(hasher, &self.newer.root)
+                        .is_some_and(|implied| implied != self.older.root)
+            }
+        }
+    }
+}
+
+/// Magic of the canonical tree head encoding
+const TREE_HEAD_MAGIC: &[u8; 4] = b"MRKH";
+
+/// Version byte of the canonical tree head encoding
+const TREE_HEAD_VERSION: u8 = 1;
+
+/// Root commitment of a tree, as published to its users
+///
+/// [`TreeHead::to_bytes`] gives the canonical encoding, which is also what
+/// [`TreeHead::sign`] signs. With all integers big-endian:
+///
+/// ```text
+/// magic "MRKH" | version u8 | size u64 | created_at u64
+/// hasher name (u8 length + bytes) | root (u32 length + bytes)
+/// metadata count u32 | per entry, in key order: key, value (u32 length + bytes each)
+/// ```
+///
+/// ```rust
+/// use merkle_tree::{MerkleTree, Sha256Hasher, TreeHead};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new())?;
+/// let head = TreeHead::from_tree(&tree)?.with_metadata("epoch", "42");
+///
+/// let bytes = head.to_bytes();
+/// assert_eq!(TreeHead::from_bytes(&bytes)?, head);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TreeHead {
+    pub root: RootHash,
+    /// Number of leaves
+    pub size: u64,
+    /// Name of the hash function, as given by [`Hasher::name`]
+    pub hasher: String,
+    /// Milliseconds since the Unix epoch
+    pub created_at: u64,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl TreeHead {
+    /// Create a head timestamped with the current time
+    pub fn new(root: RootHash, size: u64, hasher: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            root,
+            size,
+            hasher: hasher.into(),
+            created_at: now_millis()?,
+            metadata: BTreeMap::new(),
+        })
+    }
+
+    /// Create a head for the current root of `tree`
+    pub fn from_tree<H: Hasher>(tree: &MerkleTree<H>) -> Result<Self> {
+        Self::new(tree.root(), tree.len() as u64, tree.hasher().name())
+    }
+
+    /// Set the creation time, in milliseconds since the Unix epoch
+    pub fn with_created_at(mut self, created_at: u64) -> Self {
+        self.created_at = created_at;
+        self
+    }
+
+    /// Add a metadata entry, replacing any previous value for `key`
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Encode the head canonically
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(TREE_HEAD_MAGIC);
+        bytes.push(TREE_HEAD_VERSION);
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.created_at.to_be_bytes());
+
+        // Hasher names are short; anything past 255 bytes is cut off
+        let name = &self.hasher.as_bytes()[..self.hasher.len().min(u8::MAX as usize)];
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name);
+        // Writes to a Vec cannot fail
+        write_bytes(&mut bytes, &self.root).unwrap();
+
+        bytes.extend_from_slice(&(self.metadata.len() as u32).to_be_bytes());
+        for (key, value) in &self.metadata {
+            write_bytes(&mut bytes, key.as_bytes()).unwrap();
+            write_bytes(&mut bytes, value.as_bytes()).unwrap();
+        }
+        bytes
+    }
+
+    /// Decode a head written by [`TreeHead::to_bytes`]
+    ///
+    /// Only the canonical encoding is accepted: metadata keys must be in
+    /// strictly increasing order and nothing may follow the last entry.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let magic: [u8; 4] = read_array(&mut reader)?;
+        if &magic != TREE_HEAD_MAGIC {
+            return Err(invalid("Not a tree head"));
+        }
+        let [version] = read_array(&mut reader)?;
+        if version != TREE_HEAD_VERSION {
+            return Err(invalid(&format!(
+                "Unsupported tree head version {}",
+                version
+            )));
+        }
+
+        let size = u64::from_be_bytes(read_array(&mut reader)?);
+        let created_at = u64::from_be_bytes(read_array(&mut reader)?);
+        let [name_len] = read_array(&mut reader)?;
+        if reader.len() < name_len as usize {
+            return Err(invalid("Unexpected end of input"));
+        }
+        let (name, rest) = reader.split_at(name_len as usize);
+        reader = rest;
+        let hasher = utf8(name.to_vec())?;
+        let root = RootHash::from(read_bounded_bytes(&mut reader, MAX_HASH_LEN)?);
+
+        let count = u32::from_be_bytes(read_array(&mut reader)?);
+        let mut metadata = BTreeMap::new();
+        let mut last_key: Option<String> = None;
+        for _ in 0..count {
+            let key = utf8(read_bytes(&mut reader)?)?;
+            let value = utf8(read_bytes(&mut reader)?)?;
+            if last_key.as_ref().is_some_and(|last| *last >= key) {
+                return Err(invalid("Metadata keys are not in canonical order"));
+            }
+            last_key = Some(key.clone());
+            metadata.insert(key, value);
+        }
+        if !reader.is_empty() {
+            return Err(invalid("Trailing bytes after tree head"));
+        }
+
+        Ok(Self {
+            root,
+            size,
+            hasher,
+            created_at,
+            metadata,
+        })
+    }
+
+    /// Sign the canonical encoding of the head
+    pub fn sign<S: Signer>(&self, signer: &S) -> Result<Vec<u8>> {
+        signer.sign(&self.to_bytes())
+    }
+
+    /// Check a signature made by [`TreeHead::sign`]
+    pub fn verify<V: Verifier>(&self, verifier: &V, signature: &[u8]) -> bool {
+        verifier.verify(&self.to_bytes(), signature)
+    }
+}
+
+/// Current time in milliseconds since the Unix epoch
+fn now_millis() -> Result<u64> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| MerkleError::HashError {
+            message: format!("System clock is before the Unix epoch: {}", e),
+        })?
+        .as_millis() as u64)
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String> {
+    String::from_utf8(bytes).map_err(|_| invalid("Invalid UTF-8"))
+}
+
+#[cfg(feature = "ed25519")]
+impl Signer for ed25519_dalek::SigningKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        Ok(ed25519_dalek::Signer::sign(self, message)
+            .to_bytes()
+            .to_vec())
+    }
+}
+
+#[cfg(feature = "ed25519")]
+impl Verifier for ed25519_dalek::VerifyingKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        ed25519_dalek::Signature::from_slice(signature)
+            .map(|signature| self.verify_strict(message, &signature).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    /// Keyed-hash stand-in for a real signature scheme
+    struct KeyedHash(&'static [u8]);
+
+    impl Signer for KeyedHash {
+        fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+            Ok(Sha256Hasher::new()
+                .hash(&[self.0, message].concat())
+                .into_bytes())
+        }
+    }
+
+    impl Verifier for KeyedHash {
+        fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+            self.sign(message).unwrap() == signature
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let sth =
+            SignedTreeHead::sign_at(&KeyedHash(b"key"), 3, 1_700_000_000_000, tree.root()).unwrap();
+
+        assert!(sth.verify(&KeyedHash(b"key")));
+        assert!(!sth.verify(&KeyedHash(b"other")));
+
+        let mut tampered = sth.clone();
+        tampered.tree_size = 4;
+        assert!(!tampered.verify(&KeyedHash(b"key")));
+    }
+
+    #[test]
+    fn test_verify_inclusion() {
+        let hasher = Sha256Hasher::new();
+        let tree = MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap();
+        let sth = SignedTreeHead::sign(&KeyedHash(b"key"), 3, tree.root()).unwrap();
+        let proof = tree.generate_proof(1).unwrap();
+
+        assert!(sth.verify_inclusion(&KeyedHash(b"key"), &hasher, &proof, b"b"));
+        assert!(!sth.verify_inclusion(&KeyedHash(b"key"), &hasher, &proof, b"c"));
+        assert!(!sth.verify_inclusion(&KeyedHash(b"bad"), &hasher, &proof, b"b"));
+    }
+
+    #[test]
+    fn test_split_view() {
+        use crate::log::MerkleLog;
+
+        let hasher = Sha256Hasher::new();
+        let key = KeyedHash(b"key");
+        let mut honest = MerkleLog::new(hasher.clone());
+        let mut forked = MerkleLog::new(hasher.clone());
+        for leaf in [b"a", b"b", b"c", b"d", b"e"] {
+            honest.append(leaf).unwrap();
+            forked
+                .append(if leaf == b"b" { b"x" } else { leaf })
+                .unwrap();
+        }
+        let head = |log: &MerkleLog<Sha256Hasher>, size: usize| {
+            let root = log.root_at(size).unwrap();
+            SignedTreeHead::sign_at(&key, size as u64, size as u64, root).unwrap()
+        };
+
+        // Same size: the two heads are the evidence
+        let (a, b) = (head(&honest, 5), head(&forked, 5));
+        assert!(a.same_view(&a) && !a.same_view(&b) && a.same_view(&head(&forked, 1)));
+        let evidence = SplitView::from_heads(&a, &b).unwrap();
+        assert!(evidence.verify(&key, &hasher));
+        assert!(!evidence.verify(&KeyedHash(b"other"), &hasher));
+        assert!(SplitView::from_heads(&a, &a).is_none());
+
+        // Different sizes: the forked log's own proof implies another root
+        let (old, new) = (head(&honest, 3), head(&forked, 5));
+        let proof = forked.prove_consistency(3, 5).unwrap();
+        let evidence = SplitView::from_consistency(&hasher, &old, &new, &proof)
+            .unwrap()
+            .unwrap();
+        assert!(evidence.verify(&key, &hasher));
+
+        // Consistent heads give no evidence, a bad proof an error
+        let honest_proof = honest.prove_consistency(3, 5).unwrap();
+        let honest_new = head(&honest, 5);
+        assert_eq!(
+            SplitView::from_consistency(&hasher, &old, &honest_new, &honest_proof).unwrap(),
+            None
+        );
+        assert!(SplitView::from_consistency(&hasher, &old, &new, &honest_proof).is_err());
+        assert!(SplitView::from_consistency(&hasher, &old, &honest_new, &proof).is_err());
+
+        // Tampered evidence fails
+        let mut tampered = evidence.clone();
+        tampered.older = head(&forked, 3);
+        assert!(!tampered.verify(&key, &hasher));
+        let mut tampered = evidence;
+        tampered.older.tree_size = 4;
+        assert!(!tampered.verify(&key, &hasher));
+    }
+
+    #[test]
+    fn test_signing_input_layout() {
+        let root = RootHash::from([0xaau8; 4]);
+        let message = SignedTreeHead::signing_input(2, 1, &root);
+        assert_eq!(
+            message,
+            [
+                &[0, 1][..],
+                &1u64.to_be_bytes(),
+                &2u64.to_be_bytes(),
+                &[0xaa; 4]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_tree_head() {
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let head = TreeHead::from_tree(&tree)
+            .unwrap()
+            .with_created_at(1_700_000_000_000)
+            .with_metadata("region", "eu")
+            .with_metadata("epoch", "7");
+        assert_eq!(head.size, 3);
+        assert_eq!(head.hasher, "SHA-256");
+
+        let bytes = head.to_bytes();
+        assert_eq!(&bytes[..5], b"MRKH\x01");
+        assert_eq!(TreeHead::from_bytes(&bytes).unwrap(), head);
+        // Metadata is encoded in key order whatever the insertion order
+        let reordered = TreeHead::new(tree.root(), 3, "SHA-256")
+            .unwrap()
+            .with_created_at(1_700_000_000_000)
+            .with_metadata("epoch", "7")
+            .with_metadata("region", "eu");
+        assert_eq!(reordered.to_bytes(), bytes);
+
+        let signature = head.sign(&KeyedHash(b"key")).unwrap();
+        assert!(head.verify(&KeyedHash(b"key"), &signature));
+        assert!(!head.verify(&KeyedHash(b"other"), &signature));
+        let tampered = head.clone().with_metadata("epoch", "8");
+        assert!(!tampered.verify(&KeyedHash(b"key"), &signature));
+    }
+
+    #[test]
+    fn test_tree_head_rejects_non_canonical() {
+        let head = TreeHead::new(RootHash::from([1u8; 32]), 1, "SHA-256")
+            .unwrap()
+            .with_metadata("a", "1")
+            .with_metadata("b", "2");
+        let bytes = head.to_bytes();
+
+        assert!(TreeHead::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(TreeHead::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // Swap the two entries, "a" = "1" and "b" = "2", which are the same size
+        let entries = bytes.len() - 20;
+        let mut swapped = bytes[..entries].to_vec();
+        swapped.extend_from_slice(&bytes[entries + 10..]);
+        swapped.extend_from_slice(&bytes[entries..entries + 10]);
+        assert!(matches!(
+            TreeHead::from_bytes(&swapped),
+            Err(MerkleError::SerializationError { .. })
+        ));
+    }
+
+    #[cfg(feature = "ed25519")]
+    #[test]
+    fn test_ed25519() {
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let verifying_key = signing_key.verifying_key();
+        let root = RootHash::from([1u8; 32]);
+
+        let sth = SignedTreeHead::sign_at(&signing_key, 10, 42, root).unwrap();
+        assert_eq!(sth.signature.len(), 64);
+        assert!(sth.verify(&verifying_key));
+
+        let other = ed25519_dalek::SigningKey::from_bytes(&[8u8; 32]).verifying_key();
+        assert!(!sth.verify(&other));
+
+        let mut truncated = sth.clone();
+        truncated.signature.pop();
+        assert!(!truncated.verify(&verifying_key));
+    }
+}
@@ -0,0 +1,903 @@
+//! Streaming binary serialization of whole trees
+//!
+//! Unlike serde, which materializes the full node graph in an intermediate
+//! representation, [`MerkleTree::serialize_into`] writes the tree one level at
+//! a time straight to the writer and [`MerkleTree::deserialize_from`] rebuilds
+//! it from the levels without rehashing.
+//!
+//! Layout, with all integers big-endian:
+//!
+//! ```text
+//! magic "MRKL" | version u8 | hasher name (u8 length + bytes) | shape u8
+//! [version 3: node domain u8]
+//! level count u32 | per level: hash count u64, hashes
+//! salt count u64 | salts
+//! subtree count u64 | subtrees, each in this same layout
+//! ```
+//!
+//! Every hash and salt is written as a `u32` length followed by its bytes.
+//! The shape is 0 for [`TreeShape::Padded`] and 1 for [`TreeShape::Rfc6962`];
+//! version 1 streams have no shape byte and are always padded. Trees built
+//! with [`NodeDomain::Level`] are written as version 3, whose node domain
+//! byte is 1; all other trees are written as version 2, so their bytes do not
+//! change.
+//!
+//! Since every node is a hash of the same size, the position of any node can
+//! be computed from the level counts; [`SerializedTree`] uses this to serve
+//! proofs from a serialized tree without loading it.
+//!
+//! Proofs have a layout of their own, written by
+//! [`MerkleProof::serialize_into`]:
+//!
+//! ```text
+//! magic "MRKV" | version u8 | leaf index u64 | step count u64
+//! per step: direction u8, hash
+//! ```
+//!
+//! The magic differs from the `"MRKP"` of [`MerkleProof::to_canonical_bytes`],
+//! whose header also names the hasher, so neither decoder misreads the
+//! other's bytes. The direction is 0 when the sibling is on the left and 1 when it is on
+//! the right. Steps come leaf level first, so
+//! [`MerkleProof::verify_streaming`] can fold them into the root as they
+//! arrive.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::merkle_tree::{MerkleTree, NodeDomain, TreeShape};
+use crate::proof::{MerkleProof, PathFolder, ProofDirection, ProofStep};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"MRKL";
+const VERSION: u8 = 3;
+const PROOF_MAGIC: &[u8; 4] = b"MRKV";
+const PROOF_VERSION: u8 = 1;
+
+/// Upper bound on entries preallocated from an untrusted count
+pub(crate) const MAX_PREALLOCATE: usize = 1 << 16;
+
+/// Longest hash accepted from a stream, well above any digest in use
+pub(crate) const MAX_HASH_LEN: usize = 1024;
+
+/// Deepest subtree nesting accepted from a stream, which bounds the
+/// recursion of [`MerkleTree::deserialize_from`]
+const MAX_NESTING: usize = 64;
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Write the tree to `writer` level by level
+    ///
+    /// Wrap files in a [`std::io::BufWriter`] for best throughput.
+    pub fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
+        self.write_to(&mut writer)?;
+        writer.flush().map_err(io_error)
+    }
+
+    /// Read a tree written by [`MerkleTree::serialize_into`]
+    ///
+    /// The stored hasher name must match `hasher`. Node hashes are trusted as
+    /// read; call [`MerkleTree::verify_integrity`] on data from untrusted
+    /// storage.
+    pub fn deserialize_from<R: Read>(mut reader: R, hasher: H) -> Result<Self> {
+        Self::read_from(&mut reader, hasher, 0)
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(MAGIC).map_err(io_error)?;
+        let version = match self.node_domain() {
+            NodeDomain::None => 2,
+            NodeDomain::Level => VERSION,
+        };
+        writer.write_all(&[version]).map_err(io_error)?;
+
+        let name = self.hasher().name().as_bytes();
+        writer.write_all(&[name.len() as u8]).map_err(io_error)?;
+        writer.write_all(name).map_err(io_error)?;
+        let shape = match self.shape() {
+            TreeShape::Padded => 0,
+            TreeShape::Rfc6962 => 1,
+        };
+        writer.write_all(&[shape]).map_err(io_error)?;
+        if version == VERSION {
+            writer.write_all(&[1]).map_err(io_error)?;
+        }
+
+        let levels = self.levels();
+        writer
+            .write_all(&(levels.len() as u32).to_be_bytes())
+            .map_err(io_error)?;
+        for level in levels {
+            writer
+                .write_all(&(level.len() as u64).to_be_bytes())
+                .map_err(io_error)?;
+            for hash in level {
+                write_bytes(writer, hash)?;
+            }
+        }
+
+        let salts = self.salts();
+        writer
+            .write_all(&(salts.len() as u64).to_be_bytes())
+            .map_err(io_error)?;
+        for salt in salts {
+            write_bytes(writer, salt)?;
+        }
+
+        let subtrees = self.subtrees();
+        writer
+            .write_all(&(subtrees.len() as u64).to_be_bytes())
+            .map_err(io_error)?;
+        for subtree in subtrees {
+            subtree.write_to(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_from<R: Read>(reader: &mut R, hasher: H, nesting: usize) -> Result<Self> {
+        if nesting > MAX_NESTING {
+            return Err(invalid("Subtrees are nested too deeply"));
+        }
+        let (shape, domain) = read_header(reader, &hasher)?;
+
+        let level_count = u32::from_be_bytes(read_array(reader)?) as usize;
+        let mut levels = Vec::with_capacity(level_count.min(64));
+        for _ in 0..level_count {
+            let count = read_count(reader)?;
+            let mut level = Vec::with_capacity(count.min(MAX_PREALLOCATE));
+            for _ in 0..count {
+                level.push(Hash::new(read_bounded_bytes(reader, MAX_HASH_LEN)?));
+            }
+            levels.push(level);
+        }
+
+        let salt_count = read_count(reader)?;
+        let mut salts = Vec::with_capacity(salt_count.min(MAX_PREALLOCATE));
+        for _ in 0..salt_count {
+            salts.push(read_bytes(reader)?);
+        }
+
+        let subtree_count = read_count(reader)?;
+        let mut subtrees = Vec::with_capacity(subtree_count.min(MAX_PREALLOCATE));
+        for _ in 0..subtree_count {
+            subtrees.push(Self::read_from(reader, hasher.clone(), nesting + 1)?);
+        }
+
+        let tree =
+            Self::from_levels(levels, hasher, shape, domain, subtrees).map_err(|e| match e {
+                MerkleError::TreeConstructionError { reason } => invalid(&reason),
+                other => other,
+            })?;
+        if !salts.is_empty() && salts.len() != tree.len() {
+            return Err(invalid("Salt count does not match leaf count"));
+        }
+
+        Ok(tree.with_salts(salts))
+    }
+}
+
+/// Read the header up to the level count, checking the hasher name
+fn read_header<R: Read, H: Hasher>(reader: &mut R, hasher: &H) -> Result<(TreeShape, NodeDomain)> {
+    let magic: [u8; 4] = read_array(reader)?;
+    if &magic != MAGIC {
+        return Err(invalid("Not a serialized Merkle tree"));
+    }
+    let [version] = read_array(reader)?;
+    if version == 0 || version > VERSION {
+        return Err(invalid(&format!("Unsupported version {}", version)));
+    }
+
+    let [name_len] = read_array(reader)?;
+    let mut name = vec![0u8; name_len as usize];
+    reader.read_exact(&mut name).map_err(io_error)?;
+    if name != hasher.name().as_bytes() {
+        return Err(invalid(&format!(
+            "Tree was written with hasher {:?}, not {}",
+            String::from_utf8_lossy(&name),
+            hasher.name()
+        )));
+    }
+
+    let shape = match version {
+        1 => TreeShape::Padded,
+        _ => match read_array(reader)? {
+            [0] => TreeShape::Padded,
+            [1] => TreeShape::Rfc6962,
+            [other] => return Err(invalid(&format!("Unknown tree shape {}", other))),
+        },
+    };
+    let domain = match version {
+        1 | 2 => NodeDomain::None,
+        _ => match read_array(reader)? {
+            [1] => NodeDomain::Level,
+            [other] => return Err(invalid(&format!("Unknown node domain {}", other))),
+        },
+    };
+
+    Ok((shape, domain))
+}
+
+/// Serialized tree that serves proofs straight from its stream
+///
+/// Opening reads only the header and the level counts, seeking over the
+/// hashes, and each proof then reads one sibling per level. This answers
+/// proof queries for a tree written by [`MerkleTree::serialize_into`] without
+/// loading it, which matters once trees outgrow memory. Every node must be a
+/// `hasher.output_size()`-byte hash, as in any tree built by this crate.
+///
+/// ```rust
+/// use merkle_tree::{MerkleTree, SerializedTree, Sha256Hasher};
+/// use std::io::Cursor;
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new())?;
+/// let mut bytes = Vec::new();
+/// tree.serialize_into(&mut bytes)?;
+///
+/// let mut stored = SerializedTree::from_reader(Cursor::new(bytes), Sha256Hasher::new())?;
+/// assert_eq!(stored.root(), &tree.root());
+/// assert_eq!(stored.generate_proof(2)?, tree.generate_proof(2)?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SerializedTree<H: Hasher, R = File> {
+    reader: R,
+    hasher: H,
+    shape: TreeShape,
+    domain: NodeDomain,
+    /// Stream offset of the first hash and hash count of each level
+    levels: Vec<(u64, usize)>,
+    root: RootHash,
+}
+
+impl<H: Hasher> SerializedTree<H, File> {
+    /// Open a tree serialized to the file at `path`
+    pub fn open(path: impl AsRef<Path>, hasher: H) -> Result<Self> {
+        let file = File::open(path).map_err(io_error)?;
+        Self::from_reader(file, hasher)
+    }
+}
+
+impl<H: Hasher, R: Read + Seek> SerializedTree<H, R> {
+    /// Index a tree serialized at the current position of `reader`
+    ///
+    /// Checks the header and that the level counts fit the leaf count, but
+    /// does not rehash the levels.
+    pub fn from_reader(mut reader: R, hasher: H) -> Result<Self> {
+        let (shape, domain) = read_header(&mut reader, &hasher)?;
+        let entry_size = 4 + hasher.output_size() as u64;
+
+        let level_count = u32::from_be_bytes(read_array(&mut reader)?) as usize;
+        let mut levels = Vec::with_capacity(level_count.min(64));
+        for _ in 0..level_count {
+            let count = read_count(&mut reader)?;
+            let offset = reader.stream_position().map_err(io_error)?;
+            let size = (count as u64)
+                .checked_mul(entry_size)
+                .ok_or_else(|| invalid("Level does not fit in the stream"))?;
+            reader
+                .seek(SeekFrom::Current(
+                    i64::try_from(size).map_err(|_| invalid("Level does not fit in the stream"))?,
+                ))
+                .map_err(io_error)?;
+            levels.push((offset, count));
+        }
+
+        let leaf_count = levels.first().map_or(0, |&(_, count)| count);
+        if leaf_count == 0 {
+            return Err(MerkleError::EmptyData);
+        }
+        let expected = MerkleTree::<H>::level_lens(leaf_count);
+        if !levels.iter().map(|&(_, count)| count).eq(expected) {
+            return Err(invalid("Level sizes do not match the leaf count"));
+        }
+
+        let mut tree = Self {
+            reader,
+            hasher,
+            shape,
+            domain,
+            levels,
+            root: RootHash::default(),
+        };
+        tree.root = RootHash::from(tree.read_hash(tree.levels.len() - 1, 0)?);
+        Ok(tree)
+    }
+
+    /// Get the root hash of the tree
+    pub fn root(&self) -> &RootHash {
+        &self.root
+    }
+
+    /// Get the number of leaves in the tree
+    pub fn len(&self) -> usize {
+        self.levels[0].1
+    }
+
+    /// Check if the tree is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the hasher used by this tree
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the shape the tree was built with
+    pub fn shape(&self) -> TreeShape {
+        self.shape
+    }
+
+    /// Get the domain separation of the tree's internal nodes
+    pub fn node_domain(&self) -> NodeDomain {
+        self.domain
+    }
+
+    /// Read the leaf hash at the given index
+    pub fn get_leaf(&mut self, index: usize) -> Result<Hash> {
+        self.check_index(index)?;
+        self.read_hash(0, index)
+    }
+
+    /// Generate a Merkle proof for the leaf at the given index
+    ///
+    /// Reads one sibling hash per level, the same proof
+    /// [`MerkleTree::generate_proof`] gives on the loaded tree.
+    pub fn generate_proof(&mut self, index: usize) -> Result<MerkleProof> {
+        self.check_index(index)?;
+
+        let mut steps = Vec::with_capacity(self.levels.len() - 1);
+        for level in 0..self.levels.len() - 1 {
+            let len = self.levels[level].1;
+            let position = index >> level;
+            let step = if position.is_multiple_of(2) {
+                // The last node of an odd level is paired with itself, or
+                // promoted without a sibling
+                let sibling = match (position + 1 < len, self.shape) {
+                    (true, _) => position + 1,
+                    (false, TreeShape::Padded) => position,
+                    (false, TreeShape::Rfc6962) => continue,
+                };
+                ProofStep {
+                    hash: self.read_hash(level, sibling)?,
+                    direction: ProofDirection::Right,
+                }
+            } else {
+                ProofStep {
+                    hash: self.read_hash(level, position - 1)?,
+                    direction: ProofDirection::Left,
+                }
+            };
+            steps.push(step);
+        }
+
+        Ok(MerkleProof::new(index, steps))
+    }
+
+    fn check_index(&self, index: usize) -> Result<()> {
+        if index >= self.len() {
+            return Err(MerkleError::InvalidIndex {
+                index,
+                size: self.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn read_hash(&mut self, level: usize, index: usize) -> Result<Hash> {
+        let hash_size = self.hasher.output_size();
+        let offset = self.levels[level].0 + (index * (4 + hash_size)) as u64;
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .map_err(io_error)?;
+
+        let hash = read_bounded_bytes(&mut self.reader, hash_size)?;
+        if hash.len() != hash_size {
+            return Err(invalid(&format!(
+                "Node {} on level {} is not a {}-byte hash",
+                index, level, hash_size
+            )));
+        }
+        Ok(Hash::new(hash))
+    }
+}
+
+impl MerkleProof {
+    /// Write the proof to `writer` in the binary proof layout
+    pub fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(PROOF_MAGIC).map_err(io_error)?;
+        writer.write_all(&[PROOF_VERSION]).map_err(io_error)?;
+        writer
+            .write_all(&(self.leaf_index as u64).to_be_bytes())
+            .map_err(io_error)?;
+        writer
+            .write_all(&(self.steps.len() as u64).to_be_bytes())
+            .map_err(io_error)?;
+        for step in &self.steps {
+            let direction = match step.direction {
+                ProofDirection::Left => 0,
+                ProofDirection::Right => 1,
+            };
+            writer.write_all(&[direction]).map_err(io_error)?;
+            write_bytes(&mut writer, &step.hash)?;
+        }
+        writer.flush().map_err(io_error)
+    }
+
+    /// Read a proof written by [`MerkleProof::serialize_into`]
+    pub fn deserialize_from<R: Read>(mut reader: R) -> Result<Self> {
+        let (leaf_index, count) = read_proof_header(&mut reader)?;
+        let mut steps = Vec::with_capacity(count.min(MAX_PREALLOCATE));
+        for _ in 0..count {
+            steps.push(read_step(&mut reader, MAX_HASH_LEN)?);
+        }
+        Ok(MerkleProof::new(leaf_index, steps))
+    }
+
+    /// Verify a proof as it is read from `reader`, one step at a time
+    ///
+    /// Only the current node and one sibling are held in memory, so proofs
+    /// of any length, such as those of deep sparse trees sent over the
+    /// network, verify without being buffered. Returns `Ok(false)` if the
+    /// proof does not lead from `leaf_data` to `root`, and an error if the
+    /// stream is malformed or a sibling is not a hash of the hasher's size.
+    pub fn verify_streaming<R: Read, H: Hasher>(
+        mut reader: R,
+        hasher: &H,
+        leaf_data: &[u8],
+        root: &RootHash,
+    ) -> Result<bool> {
+        let (_, count) = read_proof_header(&mut reader)?;
+        let size = hasher.output_size();
+        let mut folder = PathFolder::new(hasher, hasher.hash(leaf_data).to_vec());
+        for _ in 0..count {
+            // Cap the read at the hasher's size so a bad length fails early
+            let step = read_step(&mut reader, size)?;
+            if step.hash.len() != size {
+                return Err(invalid(&format!("Proof step is not a {}-byte hash", size)));
+            }
+            folder.step(&step.hash, step.direction);
+        }
+        Ok(root.as_hash().as_bytes() == folder.current())
+    }
+}
+
+fn read_proof_header<R: Read>(reader: &mut R) -> Result<(usize, usize)> {
+    if &read_array::<R, 4>(reader)? != PROOF_MAGIC {
+        return Err(invalid("Not a serialized Merkle proof"));
+    }
+    let version = read_array::<R, 1>(reader)?[0];
+    if version != PROOF_VERSION {
+        return Err(invalid(&format!("Unsupported proof version {}", version)));
+    }
+    let leaf_index = read_count(reader)?;
+    let count = read_count(reader)?;
+    Ok((leaf_index, count))
+}
+
+fn read_step<R: Read>(reader: &mut R, max_hash_len: usize) -> Result<ProofStep> {
+    let direction = match read_array::<R, 1>(reader)?[0] {
+        0 => ProofDirection::Left,
+        1 => ProofDirection::Right,
+        other => return Err(invalid(&format!("Invalid proof direction {}", other))),
+    };
+    Ok(ProofStep {
+        hash: Hash::new(read_bounded_bytes(reader, max_hash_len)?),
+        direction,
+    })
+}
+
+pub(crate) fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<()> {
+    writer
+        .write_all(&(bytes.len() as u32).to_be_bytes())
+        .map_err(io_error)?;
+    writer.write_all(bytes).map_err(io_error)
+}
+
+pub(crate) fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf).map_err(io_error)?;
+    Ok(buf)
+}
+
+pub(crate) fn read_count<R: Read>(reader: &mut R) -> Result<usize> {
+    let count = u64::from_be_bytes(read_array(reader)?);
+    usize::try_from(count).map_err(|_| invalid("Count does not fit in memory"))
+}
+
+pub(crate) fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    read_bounded_bytes(reader, u32::MAX as usize)
+}
+
+/// Read a length-prefixed byte string, rejecting a length above `max`
+/// before reading any of it
+pub(crate) fn read_bounded_bytes<R: Read>(reader: &mut R, max: usize) -> Result<Vec<u8>> {
+    let len = u32::from_be_bytes(read_array(reader)?) as u64;
+    if len > max as u64 {
+        return Err(invalid(&format!(
+            "Length {} exceeds the limit of {} bytes",
+            len, max
+        )));
+    }
+    let mut bytes = Vec::new();
+    // Read through `take` so a corrupt length cannot trigger a huge allocation
+    reader.take(len).read_to_end(&mut bytes).map_err(io_error)?;
+    if bytes.len() as u64 != len {
+        return Err(invalid("Unexpected end of input"));
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn invalid(message: &str) -> MerkleError {
+    MerkleError::SerializationError {
+        message: message.to_string(),
+    }
+}
+
+pub(crate) fn io_error(e: std::io::Error) -> MerkleError {
+    MerkleError::IoError {
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{MerkleTreeBuilder, Salting};
+    use crate::hasher::{Blake3Hasher, Sha256Hasher};
+
+    fn roundtrip<H: Hasher>(tree: &MerkleTree<H>, hasher: H) -> MerkleTree<H> {
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+        MerkleTree::deserialize_from(buf.as_slice(), hasher).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for len in 1..=17 {
+            let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+            let tree = MerkleTree::new(data, Sha256Hasher::new()).unwrap();
+            let restored = roundtrip(&tree, Sha256Hasher::new());
+
+            assert_eq!(restored.root(), tree.root());
+            assert_eq!(restored.leaves(), tree.leaves());
+            assert_eq!(restored.verify_integrity(), Ok(()));
+            for index in 0..len {
+                assert_eq!(
+                    restored.generate_proof(index).unwrap(),
+                    tree.generate_proof(index).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_salts_and_subtrees() {
+        let salted = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .salting(Salting::Derived(b"secret".to_vec()))
+            .build(vec!["a", "b", "c"])
+            .unwrap();
+        let restored = roundtrip(&salted, Sha256Hasher::new());
+        assert_eq!(restored.salt(2), salted.salt(2));
+
+        let parent = MerkleTree::from_subtrees(vec![
+            salted,
+            MerkleTree::new(vec!["d", "e"], Sha256Hasher::new()).unwrap(),
+        ])
+        .unwrap();
+        let restored = roundtrip(&parent, Sha256Hasher::new());
+        assert_eq!(restored.subtrees().len(), 2);
+        assert_eq!(
+            restored.generate_nested_proof(0, 1).unwrap(),
+            parent.generate_nested_proof(0, 1).unwrap()
+        );
+        assert_eq!(restored.verify_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_roundtrip_shape() {
+        let tree = MerkleTreeBuilder::new(Sha256Hasher::new())
+            .shape(TreeShape::Rfc6962)
+            .build(vec!["a", "b", "c", "d", "e"])
+            .unwrap();
+        let restored = roundtrip(&tree, Sha256Hasher::new());
+        assert_eq!(restored.shape(), TreeShape::Rfc6962);
+        assert_eq!(restored.verify_integrity(), Ok(()));
+        assert_eq!(
+            restored.generate_proof(4).unwrap(),
+            tree.generate_proof(4).unwrap()
+        );
+
+        // Version 1 streams predate the shape byte
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+        buf[4] = 1;
+        buf.remove(4 + 1 + 1 + "SHA-256".len());
+        let restored = MerkleTree::deserialize_from(buf.as_slice(), Sha256Hasher::new()).unwrap();
+        assert_eq!(restored.shape(), TreeShape::Padded);
+        assert_eq!(restored.root(), tree.root());
+    }
+
+    #[test]
+    fn test_roundtrip_node_domain() {
+        let builder = MerkleTreeBuilder::new(Sha256Hasher::new()).node_domain(NodeDomain::Level);
+        let tree = builder.build(vec!["a", "b", "c"]).unwrap();
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+        assert_eq!(buf[4], 3);
+
+        let restored = MerkleTree::deserialize_from(buf.as_slice(), Sha256Hasher::new()).unwrap();
+        assert_eq!(restored.node_domain(), NodeDomain::Level);
+        assert_eq!(restored.verify_integrity(), Ok(()));
+
+        // Standard trees keep the version 2 layout
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+        assert_eq!(buf[4], 2);
+    }
+
+    #[test]
+    fn test_roundtrip_empty() {
+        let tree = MerkleTree::empty(Sha256Hasher::new());
+        let restored = roundtrip(&tree, Sha256Hasher::new());
+        assert!(restored.is_empty());
+        assert_eq!(restored.root(), tree.root());
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+
+        assert!(matches!(
+            MerkleTree::deserialize_from(buf.as_slice(), Blake3Hasher::new()),
+            Err(MerkleError::SerializationError { .. })
+        ));
+
+        let mut bad_magic = buf.clone();
+        bad_magic[0] = b'X';
+        assert!(MerkleTree::deserialize_from(bad_magic.as_slice(), Sha256Hasher::new()).is_err());
+
+        for len in 0..buf.len() {
+            assert!(
+                MerkleTree::deserialize_from(&buf[..len], Sha256Hasher::new()).is_err(),
+                "truncated to {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_inconsistent_levels() {
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+
+        // Claim two levels instead of three
+        let level_count_offset = 4 + 1 + 1 + "SHA-256".len() + 1;
+        buf[level_count_offset + 3] = 2;
+        assert!(matches!(
+            MerkleTree::deserialize_from(buf.as_slice(), Sha256Hasher::new()),
+            Err(MerkleError::SerializationError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_serialized_tree_proofs() {
+        for shape in [TreeShape::Padded, TreeShape::Rfc6962] {
+            for len in 1..=17 {
+                let data: Vec<String> = (0..len).map(|i| format!("item_{}", i)).collect();
+                let tree = MerkleTreeBuilder::new(Sha256Hasher::new())
+                    .shape(shape)
+                    .build(data)
+                    .unwrap();
+                let mut buf = b"prefix".to_vec();
+                tree.serialize_into(&mut buf).unwrap();
+
+                let mut reader = std::io::Cursor::new(buf);
+                reader.set_position(6);
+                let mut stored = SerializedTree::from_reader(reader, Sha256Hasher::new()).unwrap();
+                assert_eq!(stored.root(), &tree.root());
+                assert_eq!(stored.len(), len);
+                assert_eq!(stored.shape(), shape);
+                for index in 0..len {
+                    assert_eq!(stored.get_leaf(index).unwrap(), tree.leaves()[index]);
+                    assert_eq!(
+                        stored.generate_proof(index).unwrap(),
+                        tree.generate_proof(index).unwrap()
+                    );
+                }
+                assert!(matches!(
+                    stored.generate_proof(len),
+                    Err(MerkleError::InvalidIndex { .. })
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialized_tree_rejects_bad_input() {
+        let tree = MerkleTree::new(vec!["a", "b", "c"], Sha256Hasher::new()).unwrap();
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+
+        let open = |bytes: &[u8]| {
+            SerializedTree::from_reader(std::io::Cursor::new(bytes.to_vec()), Sha256Hasher::new())
+        };
+        assert!(open(&buf).is_ok());
+        assert!(SerializedTree::from_reader(
+            std::io::Cursor::new(buf.clone()),
+            Blake3Hasher::new()
+        )
+        .is_err());
+        // Cut off inside the root level
+        assert!(open(&buf[..buf.len() - 20]).is_err());
+    }
+
+    #[test]
+    fn test_proof_roundtrip() {
+        let hasher = Sha256Hasher::new();
+        let data: Vec<String> = (0..13).map(|i| format!("item_{}", i)).collect();
+        let tree = MerkleTree::new(data, hasher.clone()).unwrap();
+        let proof = tree.generate_proof(6).unwrap();
+
+        let mut buf = Vec::new();
+        proof.serialize_into(&mut buf).unwrap();
+        assert_eq!(&buf[..4], b"MRKV");
+        assert_eq!(
+            MerkleProof::deserialize_from(buf.as_slice()).unwrap(),
+            proof
+        );
+
+        let root = tree.root();
+        assert!(MerkleProof::verify_streaming(buf.as_slice(), &hasher, b"item_6", &root).unwrap());
+        assert!(!MerkleProof::verify_streaming(buf.as_slice(), &hasher, b"item_7", &root).unwrap());
+
+        assert!(MerkleProof::deserialize_from(&buf[..buf.len() - 1]).is_err());
+        assert!(MerkleProof::verify_streaming(&buf[1..], &hasher, b"item_6", &root).is_err());
+        let blake3 = Blake3Hasher::new();
+        assert!(!MerkleProof::verify_streaming(buf.as_slice(), &blake3, b"item_6", &root).unwrap());
+        let mut bad = buf.clone();
+        bad[21] = 7;
+        assert!(MerkleProof::deserialize_from(bad.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_proof_formats_are_distinct() {
+        let hasher = Sha256Hasher::new();
+        let tree = MerkleTree::new(vec![b"a", b"b", b"c"], hasher.clone()).unwrap();
+        let proof = tree.generate_proof(1).unwrap();
+
+        let mut streamed = Vec::new();
+        proof.serialize_into(&mut streamed).unwrap();
+        let canonical = proof.to_canonical_bytes(&hasher);
+
+        assert!(MerkleProof::from_canonical_bytes(&streamed, &hasher).is_err());
+        assert!(MerkleProof::deserialize_from(canonical.as_slice()).is_err());
+        let root = tree.root();
+        assert!(MerkleProof::verify_streaming(canonical.as_slice(), &hasher, b"b", &root).is_err());
+    }
+
+    #[test]
+    fn test_verify_streaming_deep_proof() {
+        /// Reader handing out one byte per call, like a slow connection
+        struct Trickle<'a>(&'a [u8]);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let Some((&first, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = first;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+
+        let hasher = Sha256Hasher::new();
+        let mut tree = crate::sparse::SparseMerkleTree::new(64, hasher.clone()).unwrap();
+        tree.update(u64::MAX - 5, b"deep").unwrap();
+        tree.update(12, b"shallow").unwrap();
+        let proof = tree.generate_proof(u64::MAX - 5).unwrap();
+        assert_eq!(proof.len(), 64);
+
+        let mut buf = Vec::new();
+        proof.serialize_into(&mut buf).unwrap();
+        let root = tree.root().clone();
+        assert!(MerkleProof::verify_streaming(Trickle(&buf), &hasher, b"deep", &root).unwrap());
+    }
+
+    /// Every truncation and single-byte corruption of `bytes`
+    fn mutations(bytes: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let truncated = (0..bytes.len()).map(|len| bytes[..len].to_vec());
+        let flipped = (0..bytes.len()).flat_map(move |i| {
+            [0x01, 0x80, 0xff].map(|mask| {
+                let mut bytes = bytes.to_vec();
+                bytes[i] ^= mask;
+                bytes
+            })
+        });
+        truncated.chain(flipped)
+    }
+
+    #[test]
+    fn test_decoders_survive_mutation() {
+        let hasher = Sha256Hasher::new();
+        let tree = MerkleTree::from_subtrees(vec![
+            MerkleTree::new(vec!["a", "b", "c"], hasher.clone()).unwrap(),
+            MerkleTree::new(vec!["d"], hasher.clone()).unwrap(),
+        ])
+        .unwrap();
+        let mut buf = Vec::new();
+        tree.serialize_into(&mut buf).unwrap();
+        for bytes in mutations(&buf) {
+            if let Ok(tree) = MerkleTree::deserialize_from(bytes.as_slice(), hasher.clone()) {
+                let _ = tree.verify_integrity();
+                let _ = tree.generate_proof(tree.len().saturating_sub(1));
+            }
+            let cursor = std::io::Cursor::new(bytes);
+            if let Ok(mut stored) = SerializedTree::from_reader(cursor, hasher.clone()) {
+                let _ = stored.generate_proof(stored.len() - 1);
+            }
+        }
+
+        let tree = MerkleTree::new(vec!["a", "b", "c", "d", "e"], hasher.clone()).unwrap();
+        let proof = tree.generate_proof(2).unwrap();
+        let mut buf = Vec::new();
+        proof.serialize_into(&mut buf).unwrap();
+        let root = tree.root();
+        for bytes in mutations(&buf) {
+            let _ = MerkleProof::deserialize_from(bytes.as_slice());
+            let _ = MerkleProof::verify_streaming(bytes.as_slice(), &hasher, b"c", &root);
+        }
+    }
+
+    #[test]
+    fn test_rejects_deep_nesting() {
+        // Each tree has no levels or salts and one subtree, so the nesting
+        // only ends when the input does
+        let mut nested = Vec::new();
+        for _ in 0..100_000 {
+            nested.extend_from_slice(b"MRKL\x02\x07SHA-256\x00");
+            nested.extend_from_slice(&0u32.to_be_bytes());
+            nested.extend_from_slice(&0u64.to_be_bytes());
+            nested.extend_from_slice(&1u64.to_be_bytes());
+        }
+        assert!(matches!(
+            MerkleTree::deserialize_from(nested.as_slice(), Sha256Hasher::new()),
+            Err(MerkleError::SerializationError { message }) if message.contains("nested")
+        ));
+    }
+
+    #[test]
+    fn test_rejects_oversized_length() {
+        let mut header = b"MRKV\x01".to_vec();
+        header.extend_from_slice(&0u64.to_be_bytes());
+        header.extend_from_slice(&1u64.to_be_bytes());
+        header.push(1);
+        header.extend_from_slice(&u32::MAX.to_be_bytes());
+
+        // The length is rejected before the endless body is read
+        let endless = || header.as_slice().chain(std::io::repeat(0));
+        assert!(MerkleProof::deserialize_from(endless()).is_err());
+        let root = RootHash::new(vec![0; 32]);
+        let hasher = Sha256Hasher::new();
+        assert!(MerkleProof::verify_streaming(endless(), &hasher, b"a", &root).is_err());
+
+        let mut long = header.clone();
+        let offset = long.len() - 4;
+        long[offset..].copy_from_slice(&33u32.to_be_bytes());
+        long.extend_from_slice(&[0; 33]);
+        assert!(MerkleProof::deserialize_from(long.as_slice()).is_ok());
+        assert!(MerkleProof::verify_streaming(long.as_slice(), &hasher, b"a", &root).is_err());
+    }
+}
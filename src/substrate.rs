@@ -0,0 +1,256 @@
+//! Substrate `binary-merkle-tree` compatibility
+//!
+//! Substrate's `binary-merkle-tree` crate, used by BEEFY to commit to
+//! validator sets and parachain heads, hashes every leaf once, pairs nodes as
+//! `H(left || right)` without sorting and promotes the last node of an odd
+//! level unchanged. That is [`TreeShape::Rfc6962`] with
+//! [`LeafEncoding::PreHash`](crate::builder::LeafEncoding::PreHash). An empty
+//! list has the all-zero root.
+//!
+//! BEEFY uses [`Keccak256Hasher`](crate::Keccak256Hasher); with the `blake2`
+//! feature `Blake2_256Hasher` gives Substrate's default `blake2_256`. The
+//! base-16 Patricia trie behind `sp-trie` storage roots is a different
+//! structure and is not covered here.
+
+use crate::builder::MerkleTreeBuilder;
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::merkle_tree::{MerkleTree, TreeShape};
+use crate::proof::MerkleProof;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// BLAKE2b with a 32-byte output, Substrate's `blake2_256`
+#[cfg(feature = "blake2")]
+pub type Blake2_256Hasher =
+    crate::hasher::DigestHasher<blake2::Blake2b<blake2::digest::consts::U32>>;
+
+/// Build the tree `binary-merkle-tree` builds for `leaves`
+///
+/// Fails for an empty list, whose root [`merkle_root`] still defines.
+pub fn merkle_tree<H: Hasher, T: AsRef<[u8]>>(hasher: &H, leaves: &[T]) -> Result<MerkleTree<H>> {
+    MerkleTreeBuilder::new(hasher.clone())
+        .shape(TreeShape::Rfc6962)
+        .build(leaves.iter().map(AsRef::as_ref).collect())
+}
+
+/// Root of `leaves`, as `binary_merkle_tree::merkle_root` computes it
+///
+/// An empty list has the all-zero root; any other failure to build the tree
+/// is returned.
+pub fn merkle_root<H: Hasher, T: AsRef<[u8]>>(hasher: &H, leaves: &[T]) -> Result<RootHash> {
+    if leaves.is_empty() {
+        return Ok(RootHash::new(vec![0u8; hasher.output_size()]));
+    }
+    Ok(merkle_tree(hasher, leaves)?.root())
+}
+
+/// Proof for the leaf at `leaf_index`, as `binary_merkle_tree::merkle_proof`
+/// builds it
+///
+/// An empty list has no leaf to prove and fails with
+/// [`MerkleError::InvalidIndex`]; any other failure to build the tree is
+/// returned.
+pub fn merkle_proof<H: Hasher, T: AsRef<[u8]>>(
+    hasher: &H,
+    leaves: &[T],
+    leaf_index: usize,
+) -> Result<SubstrateProof> {
+    if leaves.is_empty() {
+        return Err(MerkleError::InvalidIndex {
+            index: leaf_index,
+            size: leaves.len(),
+        });
+    }
+    let tree = merkle_tree(hasher, leaves)?;
+    let proof = tree.generate_proof(leaf_index)?;
+    Ok(SubstrateProof {
+        root: tree.root(),
+        proof: proof.steps.into_iter().map(|step| step.hash).collect(),
+        number_of_leaves: leaves.len() as u64,
+        leaf_index: leaf_index as u64,
+        leaf: leaves[leaf_index].as_ref().to_vec(),
+    })
+}
+
+/// Verify that `leaf` is the leaf at `leaf_index` of `number_of_leaves`
+/// under `root`, as `binary_merkle_tree::verify_proof` does for a leaf value
+///
+/// `proof` holds the sibling hashes from the leaf up; their directions
+/// follow from the index and leaf count.
+pub fn verify_proof<H: Hasher>(
+    hasher: &H,
+    root: &RootHash,
+    proof: &[Hash],
+    number_of_leaves: u64,
+    leaf_index: u64,
+    leaf: &[u8],
+) -> bool {
+    let (Ok(index), Ok(size)) = (
+        usize::try_from(leaf_index),
+        usize::try_from(number_of_leaves),
+    ) else {
+        return false;
+    };
+    MerkleProof::from_siblings(index, size, TreeShape::Rfc6962, proof.to_vec())
+        .is_ok_and(|proof| proof.verify(hasher, leaf, root))
+}
+
+/// Proof of inclusion in the format of `binary_merkle_tree::MerkleProof`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SubstrateProof {
+    /// Root of the tree the proof was generated from
+    pub root: RootHash,
+    /// Sibling hashes from the leaf up to the root
+    pub proof: Vec<Hash>,
+    /// Number of leaves in the tree
+    pub number_of_leaves: u64,
+    /// Index of the proven leaf
+    pub leaf_index: u64,
+    /// The proven leaf, before hashing
+    pub leaf: Vec<u8>,
+}
+
+impl SubstrateProof {
+    /// Verify the proof against its own root
+    ///
+    /// Check [`SubstrateProof::root`] against a trusted commitment as well,
+    /// or use [`verify_proof`] with the trusted root directly.
+    pub fn verify<H: Hasher>(&self, hasher: &H) -> bool {
+        verify_proof(
+            hasher,
+            &self.root,
+            &self.proof,
+            self.number_of_leaves,
+            self.leaf_index,
+            &self.leaf,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Keccak256Hasher;
+
+    fn addresses() -> Vec<Vec<u8>> {
+        [
+            "E04CC55ebEE1cBCE552f250e85c57B70B2E2625b",
+            "25451A4de12dcCc2D166922fA938E900fCc4ED24",
+        ]
+        .iter()
+        .map(|address| hex::decode(address).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        // Test vectors from Substrate's binary-merkle-tree crate
+        let hasher = Keccak256Hasher::new();
+        let leaves = addresses();
+        assert_eq!(
+            merkle_root(&hasher, &Vec::<Vec<u8>>::new())
+                .unwrap()
+                .to_hex(),
+            "0000000000000000000000000000000000000000000000000000000000000000"
+        );
+        assert_eq!(
+            merkle_root(&hasher, &leaves[..1]).unwrap().to_hex(),
+            "aeb47a269393297f4b0a3c9c9cfd00c7a4195255274cf39d83dabc2fcc9ff3d7"
+        );
+        assert_eq!(
+            merkle_root(&hasher, &leaves).unwrap().to_hex(),
+            "697ea2a8fe5b03468548a7a413424a6292ab44a82a6f5cc594c3fa7dda7ce402"
+        );
+
+        // The last node of an odd level moves up unchanged
+        let leaves: Vec<&[u8]> = vec![b"a", b"b", b"c"];
+        let ab = hasher.hash_pair(&hasher.hash(b"a"), &hasher.hash(b"b"));
+        let expected = hasher.hash_pair(&ab, &hasher.hash(b"c"));
+        assert_eq!(merkle_root(&hasher, &leaves).unwrap().as_hash(), &expected);
+    }
+
+    #[test]
+    fn test_proofs() {
+        let hasher = Keccak256Hasher::new();
+        for size in 1..=13 {
+            let leaves: Vec<String> = (0..size).map(|i| format!("leaf_{}", i)).collect();
+            let root = merkle_root(&hasher, &leaves).unwrap();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&hasher, &leaves, index).unwrap();
+                assert_eq!(proof.root, root);
+                assert!(proof.verify(&hasher));
+                assert!(verify_proof(
+                    &hasher,
+                    &root,
+                    &proof.proof,
+                    size as u64,
+                    index as u64,
+                    leaf.as_bytes()
+                ));
+                assert!(!verify_proof(
+                    &hasher,
+                    &root,
+                    &proof.proof,
+                    size as u64,
+                    index as u64,
+                    b"other"
+                ));
+            }
+        }
+
+        assert!(merkle_proof(&hasher, &addresses(), 2).is_err());
+        assert!(matches!(
+            merkle_proof(&hasher, &Vec::<Vec<u8>>::new(), 0),
+            Err(MerkleError::InvalidIndex { index: 0, size: 0 })
+        ));
+    }
+
+    /// Reports 32 bytes but truncates Keccak-256 to 20
+    #[derive(Clone)]
+    struct ShortHasher;
+
+    impl Hasher for ShortHasher {
+        fn hash(&self, data: &[u8]) -> Hash {
+            Hash::new(Keccak256Hasher::new().hash(data).as_bytes()[..20].to_vec())
+        }
+
+        fn output_size(&self) -> usize {
+            32
+        }
+
+        fn name(&self) -> &'static str {
+            "short"
+        }
+    }
+
+    #[test]
+    fn test_build_errors_are_returned() {
+        // Only the empty list has a defined root; other failures surface
+        let leaves = addresses();
+        assert!(matches!(
+            merkle_root(&ShortHasher, &leaves),
+            Err(MerkleError::HashSizeMismatch { index: 0, .. })
+        ));
+        assert!(matches!(
+            merkle_proof(&ShortHasher, &leaves, 0),
+            Err(MerkleError::HashSizeMismatch { index: 0, .. })
+        ));
+    }
+
+    #[cfg(feature = "blake2")]
+    #[test]
+    fn test_blake2_256() {
+        let hasher = Blake2_256Hasher::new();
+        // blake2_256(b"") from sp-core
+        assert_eq!(
+            hasher.hash(b"").to_hex(),
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+        );
+        let leaves = addresses();
+        let proof = merkle_proof(&hasher, &leaves, 1).unwrap();
+        assert!(proof.verify(&hasher));
+    }
+}
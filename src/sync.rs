@@ -0,0 +1,657 @@
+//! Anti-entropy helpers for repairing replicas of a Merkle tree
+//!
+//! Two replicas are compared top-down: subtrees whose hashes agree are
+//! skipped, and only mismatching subtrees are descended into. The result is
+//! the minimal set of leaf ranges that has to be transferred to bring the
+//! replicas back in sync.
+//!
+//! For append-only logs gossiping their heads, [`compare_heads`] compares the
+//! announced `(replica, size, root)` triples directly: replicas behind the
+//! largest agreed head need the leaves past their size, and replicas
+//! disagreeing on the root at the same size have diverged.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+use crate::log::ConsistencyProof;
+use crate::merkle_tree::MerkleTree;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Source of node digests for one side of a comparison
+///
+/// Implement this for a remote replica to fetch digests over the network.
+/// Levels are numbered from the leaves (level 0) up to the root.
+pub trait DigestSource {
+    /// Get the number of leaves in the tree
+    fn leaf_count(&mut self) -> Result<usize>;
+
+    /// Fetch the node hashes at `level` for the given node indices
+    ///
+    /// The returned hashes must be in the same order as `indices`.
+    fn fetch_digests(&mut self, level: usize, indices: &[usize]) -> Result<Vec<Hash>>;
+}
+
+/// Digest source backed by a local tree
+pub struct TreeDigests<'a> {
+    levels: &'a [Vec<Hash>],
+}
+
+impl<'a> TreeDigests<'a> {
+    /// Create a digest source over a local tree
+    pub fn new<H: Hasher>(tree: &'a MerkleTree<H>) -> Self {
+        Self {
+            levels: tree.levels(),
+        }
+    }
+}
+
+impl DigestSource for TreeDigests<'_> {
+    fn leaf_count(&mut self) -> Result<usize> {
+        Ok(self.levels.first().map_or(0, |leaves| leaves.len()))
+    }
+
+    fn fetch_digests(&mut self, level: usize, indices: &[usize]) -> Result<Vec<Hash>> {
+        let nodes = self.levels.get(level).ok_or(MerkleError::SyncError {
+            reason: format!("Level {} does not exist", level),
+        })?;
+
+        indices
+            .iter()
+            .map(|&index| {
+                nodes
+                    .get(index)
+                    .cloned()
+                    .ok_or(MerkleError::InvalidIndex {
+                        index,
+                        size: nodes.len(),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Find the leaf ranges that differ between two local trees
+pub fn diff_trees<H: Hasher>(
+    local: &MerkleTree<H>,
+    remote: &MerkleTree<H>,
+) -> Result<Vec<Range<usize>>> {
+    diff(&mut TreeDigests::new(local), &mut TreeDigests::new(remote))
+}
+
+/// Find the leaf ranges that differ between two digest sources
+///
+/// Leaves present in only one of the trees are always reported. The returned
+/// ranges are sorted and adjacent ranges are merged.
+pub fn diff<A, B>(local: &mut A, remote: &mut B) -> Result<Vec<Range<usize>>>
+where
+    A: DigestSource,
+    B: DigestSource,
+{
+    let mut walk = Walk::new(local.leaf_count()?, remote.leaf_count()?);
+    while let Some((level, indices)) = walk.next_level() {
+        let local_digests = fetch(local, level, &indices)?;
+        let remote_digests = fetch(remote, level, &indices)?;
+        walk.compare(level, &indices, &local_digests, &remote_digests);
+    }
+    Ok(walk.finish())
+}
+
+/// Digests a [`SyncSession`] needs from the remote replica next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestRequest {
+    /// Level of the requested nodes, leaves being level 0
+    pub level: usize,
+    /// Indices of the requested nodes within the level
+    pub indices: Vec<usize>,
+}
+
+/// Top-down comparison of a local tree with a remote replica, without I/O
+///
+/// The session asks for remote digests one level at a time through
+/// [`SyncSession::next_request`] and takes the answers with
+/// [`SyncSession::receive`], so it runs over any transport, blocking or
+/// not. Once no request is left, [`SyncSession::finish`] gives the same
+/// ranges [`diff`] would. [`SyncSession::run`] drives it with a callback
+/// instead.
+///
+/// ```rust
+/// use merkle_tree::sync::SyncSession;
+/// use merkle_tree::{MerkleTree, Sha256Hasher};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// let local = MerkleTree::new(vec!["a", "b", "c", "d"], Sha256Hasher::new())?;
+/// let remote = MerkleTree::new(vec!["a", "b", "x", "d"], Sha256Hasher::new())?;
+///
+/// let mut session = SyncSession::new(&local, remote.len());
+/// while let Some(request) = session.next_request() {
+///     // Sent over the wire; the remote answers from its level digests
+///     let level = remote.level_digests(request.level);
+///     let digests = request.indices.iter().map(|&i| level[i].clone()).collect();
+///     session.receive(digests)?;
+/// }
+/// assert_eq!(session.finish()?, vec![2..3]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct SyncSession<'a, H: Hasher> {
+    local: &'a MerkleTree<H>,
+    walk: Walk,
+    pending: Option<DigestRequest>,
+}
+
+impl<'a, H: Hasher> SyncSession<'a, H> {
+    /// Start comparing `local` with a remote replica of `remote_len` leaves
+    pub fn new(local: &'a MerkleTree<H>, remote_len: usize) -> Self {
+        let mut walk = Walk::new(local.len(), remote_len);
+        let pending = walk
+            .next_level()
+            .map(|(level, indices)| DigestRequest { level, indices });
+        Self {
+            local,
+            walk,
+            pending,
+        }
+    }
+
+    /// Get the digests to fetch from the remote next, or `None` once the
+    /// comparison is complete
+    pub fn next_request(&self) -> Option<&DigestRequest> {
+        self.pending.as_ref()
+    }
+
+    /// Feed the remote digests for the current request, in its order
+    pub fn receive(&mut self, digests: Vec<Hash>) -> Result<()> {
+        let Some(request) = self.pending.take() else {
+            return Err(MerkleError::SyncError {
+                reason: "No digests were requested".to_string(),
+            });
+        };
+        if digests.len() != request.indices.len() {
+            let reason = format!(
+                "Expected {} digests at level {}, got {}",
+                request.indices.len(),
+                request.level,
+                digests.len()
+            );
+            self.pending = Some(request);
+            return Err(MerkleError::SyncError { reason });
+        }
+
+        let local = request
+            .indices
+            .iter()
+            .map(|&index| self.local.node_hash(request.level, index).cloned())
+            .collect::<Result<Vec<_>>>()?;
+        self.walk
+            .compare(request.level, &request.indices, &local, &digests);
+        self.pending = self
+            .walk
+            .next_level()
+            .map(|(level, indices)| DigestRequest { level, indices });
+        Ok(())
+    }
+
+    /// Check if the comparison is complete
+    pub fn is_done(&self) -> bool {
+        self.pending.is_none()
+    }
+
+    /// Get the differing leaf ranges, sorted and merged
+    ///
+    /// Fails if digests are still outstanding.
+    pub fn finish(self) -> Result<Vec<Range<usize>>> {
+        if let Some(request) = self.pending {
+            return Err(MerkleError::SyncError {
+                reason: format!("Digests at level {} are still outstanding", request.level),
+            });
+        }
+        Ok(self.walk.finish())
+    }
+
+    /// Run the session to completion, fetching remote digests with `fetch`
+    pub fn run<F>(mut self, mut fetch: F) -> Result<Vec<Range<usize>>>
+    where
+        F: FnMut(&DigestRequest) -> Result<Vec<Hash>>,
+    {
+        while let Some(request) = &self.pending {
+            let digests = fetch(request)?;
+            self.receive(digests)?;
+        }
+        self.finish()
+    }
+}
+
+/// State of a top-down comparison, visiting one level at a time
+struct Walk {
+    local_len: usize,
+    remote_len: usize,
+    local_height: usize,
+    remote_height: usize,
+    /// Next level to visit, `None` once done
+    level: Option<usize>,
+    /// Nodes to visit on `level`
+    frontier: Vec<usize>,
+    /// Children of mismatching nodes, the frontier of the level below
+    next: Vec<usize>,
+    ranges: Vec<Range<usize>>,
+}
+
+impl Walk {
+    fn new(local_len: usize, remote_len: usize) -> Self {
+        let local_height = height(local_len);
+        let remote_height = height(remote_len);
+        let mut walk = Self {
+            local_len,
+            remote_len,
+            local_height,
+            remote_height,
+            level: Some(local_height.max(remote_height)),
+            frontier: vec![0],
+            next: Vec::new(),
+            ranges: Vec::new(),
+        };
+        let total_len = local_len.max(remote_len);
+        if local_len.min(remote_len) == 0 {
+            if total_len > 0 {
+                walk.ranges.push(0..total_len);
+            }
+            walk.level = None;
+        }
+        walk
+    }
+
+    /// Move on to the next level with nodes present on both sides, returning
+    /// it and the nodes whose digests to compare
+    fn next_level(&mut self) -> Option<(usize, Vec<usize>)> {
+        let common_len = self.local_len.min(self.remote_len);
+        let total_len = self.local_len.max(self.remote_len);
+        while let Some(level) = self.level {
+            let span = 1usize << level;
+            let mut comparable = Vec::new();
+            for index in std::mem::take(&mut self.frontier) {
+                let start = index * span;
+                if start >= common_len {
+                    // Subtree exists on only one side
+                    self.ranges.push(start..((index + 1) * span).min(total_len));
+                } else if level > self.local_height.min(self.remote_height) {
+                    // Above the shorter tree's root there is nothing to compare
+                    push_children(index, level, total_len, &mut self.next);
+                } else {
+                    comparable.push(index);
+                }
+            }
+            if !comparable.is_empty() {
+                return Some((level, comparable));
+            }
+            self.descend();
+        }
+        None
+    }
+
+    /// Record the digests of the nodes [`Walk::next_level`] returned
+    fn compare(&mut self, level: usize, indices: &[usize], local: &[Hash], remote: &[Hash]) {
+        let common_len = self.local_len.min(self.remote_len);
+        let total_len = self.local_len.max(self.remote_len);
+        let span = 1usize << level;
+        for ((&index, ours), theirs) in indices.iter().zip(local).zip(remote) {
+            let covered = ((index + 1) * span <= common_len) || self.local_len == self.remote_len;
+            if ours == theirs && covered {
+                continue;
+            }
+
+            if level == 0 {
+                self.ranges.push(index..index + 1);
+            } else {
+                push_children(index, level, total_len, &mut self.next);
+            }
+        }
+        self.descend();
+    }
+
+    fn descend(&mut self) {
+        self.frontier = std::mem::take(&mut self.next);
+        self.level = self.level.and_then(|level| level.checked_sub(1));
+    }
+
+    fn finish(self) -> Vec<Range<usize>> {
+        merge_ranges(self.ranges)
+    }
+}
+
+/// Head announced by a replica of an append-only log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaHead<R> {
+    pub replica: R,
+    /// Number of leaves the replica holds
+    pub size: usize,
+    /// Root over those leaves
+    pub root: RootHash,
+}
+
+impl<R> ReplicaHead<R> {
+    /// Check that this head is a prefix of `newer`
+    pub fn is_prefix_of<H: Hasher, S>(
+        &self,
+        newer: &ReplicaHead<S>,
+        proof: &ConsistencyProof,
+        hasher: &H,
+    ) -> bool {
+        if self.size == newer.size {
+            return self.root == newer.root;
+        }
+        proof.old_size == self.size
+            && proof.new_size == newer.size
+            && proof.verify(hasher, &self.root, &newer.root)
+    }
+}
+
+/// Outcome of [`compare_heads`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadComparison<R> {
+    /// Largest announced size
+    pub size: usize,
+    /// Root most replicas agree on at that size
+    pub root: RootHash,
+    /// Replicas holding that head
+    pub up_to_date: Vec<R>,
+    /// Replicas at a smaller size, with the leaf range each one is missing
+    pub behind: Vec<(R, Range<usize>)>,
+    /// Replicas whose root disagrees with the majority at their size
+    pub diverged: Vec<R>,
+}
+
+/// Compare the heads announced by a set of replicas
+///
+/// At each size, the root announced most often is taken as correct, ties
+/// going to the smaller root so every replica reaches the same verdict.
+/// Heads alone cannot show whether a smaller head is a prefix of a larger
+/// one; check that with [`ReplicaHead::is_prefix_of`] before fetching the
+/// missing range. Returns `None` if no heads are given.
+pub fn compare_heads<R: Clone>(heads: &[ReplicaHead<R>]) -> Option<HeadComparison<R>> {
+    let mut votes: BTreeMap<usize, BTreeMap<&RootHash, usize>> = BTreeMap::new();
+    for head in heads {
+        *votes
+            .entry(head.size)
+            .or_default()
+            .entry(&head.root)
+            .or_default() += 1;
+    }
+    let majority: BTreeMap<usize, &RootHash> = votes
+        .iter()
+        .map(|(&size, roots)| {
+            // `max_by_key` keeps the last of equal counts, so walking the
+            // roots in descending order breaks ties towards the smaller one
+            let (&root, _) = roots.iter().rev().max_by_key(|(_, &count)| count)?;
+            Some((size, root))
+        })
+        .collect::<Option<_>>()?;
+    let (&size, &root) = majority.last_key_value()?;
+
+    let mut comparison = HeadComparison {
+        size,
+        root: root.clone(),
+        up_to_date: Vec::new(),
+        behind: Vec::new(),
+        diverged: Vec::new(),
+    };
+    for head in heads {
+        if head.root != *majority[&head.size] {
+            comparison.diverged.push(head.replica.clone());
+        } else if head.size == size {
+            comparison.up_to_date.push(head.replica.clone());
+        } else {
+            comparison
+                .behind
+                .push((head.replica.clone(), head.size..size));
+        }
+    }
+    Some(comparison)
+}
+
+/// Fetch digests and check the source returned one per requested index
+fn fetch<S: DigestSource>(source: &mut S, level: usize, indices: &[usize]) -> Result<Vec<Hash>> {
+    let digests = source.fetch_digests(level, indices)?;
+    if digests.len() != indices.len() {
+        return Err(MerkleError::SyncError {
+            reason: format!(
+                "Expected {} digests at level {}, got {}",
+                indices.len(),
+                level,
+                digests.len()
+            ),
+        });
+    }
+    Ok(digests)
+}
+
+fn push_children(index: usize, level: usize, total_len: usize, next: &mut Vec<usize>) {
+    let child_span = 1usize << (level - 1);
+    for child in [2 * index, 2 * index + 1] {
+        if child * child_span < total_len {
+            next.push(child);
+        }
+    }
+}
+
+fn height(leaf_count: usize) -> usize {
+    let mut height = 0;
+    let mut nodes = leaf_count;
+
+    while nodes > 1 {
+        nodes = nodes.div_ceil(2);
+        height += 1;
+    }
+
+    height
+}
+
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_unstable_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    fn tree_of(items: &[&str]) -> MerkleTree<Sha256Hasher> {
+        MerkleTree::new(items.to_vec(), Sha256Hasher::new()).unwrap()
+    }
+
+    fn numbered(len: usize) -> Vec<String> {
+        (0..len).map(|i| format!("item_{}", i)).collect()
+    }
+
+    /// Wraps a source and counts the digests requested from it
+    struct Counting<'a> {
+        inner: TreeDigests<'a>,
+        fetched: usize,
+    }
+
+    impl DigestSource for Counting<'_> {
+        fn leaf_count(&mut self) -> Result<usize> {
+            self.inner.leaf_count()
+        }
+
+        fn fetch_digests(&mut self, level: usize, indices: &[usize]) -> Result<Vec<Hash>> {
+            self.fetched += indices.len();
+            self.inner.fetch_digests(level, indices)
+        }
+    }
+
+    #[test]
+    fn test_identical_trees() {
+        let a = tree_of(&["a", "b", "c", "d", "e"]);
+        let b = tree_of(&["a", "b", "c", "d", "e"]);
+        assert!(diff_trees(&a, &b).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_single_changed_leaf() {
+        let a = tree_of(&["a", "b", "c", "d", "e"]);
+        let b = tree_of(&["a", "b", "x", "d", "e"]);
+        assert_eq!(diff_trees(&a, &b).unwrap(), vec![2..3]);
+    }
+
+    #[test]
+    fn test_adjacent_ranges_are_merged() {
+        let a = tree_of(&["a", "b", "c", "d", "e", "f", "g", "h"]);
+        let b = tree_of(&["a", "x", "y", "d", "e", "f", "g", "z"]);
+        assert_eq!(diff_trees(&a, &b).unwrap(), vec![1..3, 7..8]);
+    }
+
+    #[test]
+    fn test_different_sizes() {
+        let a = tree_of(&["a", "b"]);
+        let b = tree_of(&["a", "b", "c", "d", "e"]);
+        assert_eq!(diff_trees(&a, &b).unwrap(), vec![2..5]);
+        assert_eq!(diff_trees(&b, &a).unwrap(), vec![2..5]);
+
+        // A duplicated padding node must not hide a missing leaf
+        let a = tree_of(&["a"]);
+        let b = tree_of(&["a", "a"]);
+        assert_eq!(diff_trees(&a, &b).unwrap(), vec![1..2]);
+    }
+
+    #[test]
+    fn test_remote_fetches_are_minimal() {
+        let items = numbered(1024);
+        let mut changed = items.clone();
+        changed[700] = "changed".to_string();
+
+        let local = MerkleTree::new(items, Sha256Hasher::new()).unwrap();
+        let remote_tree = MerkleTree::new(changed, Sha256Hasher::new()).unwrap();
+        let mut remote = Counting {
+            inner: TreeDigests::new(&remote_tree),
+            fetched: 0,
+        };
+
+        let ranges = diff(&mut TreeDigests::new(&local), &mut remote).unwrap();
+        assert_eq!(ranges, vec![700..701]);
+
+        // Root plus two children per level on the way down
+        assert_eq!(remote.fetched, 1 + 2 * 10);
+    }
+
+    #[test]
+    fn test_short_digest_response() {
+        struct Broken;
+
+        impl DigestSource for Broken {
+            fn leaf_count(&mut self) -> Result<usize> {
+                Ok(2)
+            }
+
+            fn fetch_digests(&mut self, _level: usize, _indices: &[usize]) -> Result<Vec<Hash>> {
+                Ok(Vec::new())
+            }
+        }
+
+        let local = tree_of(&["a", "b"]);
+        let result = diff(&mut TreeDigests::new(&local), &mut Broken);
+        assert!(matches!(result, Err(MerkleError::SyncError { .. })));
+    }
+
+    #[test]
+    fn test_sync_session() {
+        let base = numbered(37);
+        let mut changed = base.clone();
+        changed[3] = "changed".to_string();
+        changed[30] = "changed".to_string();
+        let local = MerkleTree::new(base.clone(), Sha256Hasher::new()).unwrap();
+
+        for remote in [&changed[..], &base[..20], &changed[..25], &base] {
+            let remote = MerkleTree::new(remote.to_vec(), Sha256Hasher::new()).unwrap();
+            let expected = diff_trees(&local, &remote).unwrap();
+
+            let mut session = SyncSession::new(&local, remote.len());
+            while let Some(request) = session.next_request() {
+                let level = remote.level_digests(request.level);
+                let digests = request.indices.iter().map(|&i| level[i].clone()).collect();
+                session.receive(digests).unwrap();
+            }
+            assert!(session.is_done());
+            assert_eq!(session.finish().unwrap(), expected);
+
+            let session = SyncSession::new(&local, remote.len());
+            let ranges = session.run(|request| {
+                let mut source = TreeDigests::new(&remote);
+                source.fetch_digests(request.level, &request.indices)
+            });
+            assert_eq!(ranges.unwrap(), expected);
+        }
+        assert_eq!(local.level_digests(0).len(), 37);
+        assert!(local.level_digests(7).is_empty());
+
+        // A short answer is refused and the request stays outstanding
+        let mut session = SyncSession::new(&local, 37);
+        assert!(session.receive(Vec::new()).is_err());
+        assert_eq!(session.next_request().unwrap().level, 6);
+        assert!(session.finish().is_err());
+        assert_eq!(SyncSession::new(&local, 0).finish().unwrap(), vec![0..37]);
+    }
+
+    #[test]
+    fn test_compare_heads() {
+        use crate::log::MerkleLog;
+
+        let hasher = Sha256Hasher::new();
+        let mut log = MerkleLog::new(hasher.clone());
+        for i in 0..10 {
+            log.append(format!("item_{}", i).as_bytes()).unwrap();
+        }
+        let head = |replica: &'static str, size: usize| ReplicaHead {
+            replica,
+            size,
+            root: log.root_at(size).unwrap(),
+        };
+        let forked = ReplicaHead {
+            replica: "forked",
+            size: 6,
+            root: RootHash::from(hasher.hash(b"fork")),
+        };
+        let heads = vec![
+            head("a", 10),
+            head("b", 6),
+            forked.clone(),
+            head("c", 10),
+            head("d", 3),
+            head("e", 6),
+        ];
+
+        let comparison = compare_heads(&heads).unwrap();
+        assert_eq!(comparison.size, 10);
+        assert_eq!(comparison.root, log.root());
+        assert_eq!(comparison.up_to_date, vec!["a", "c"]);
+        assert_eq!(
+            comparison.behind,
+            vec![("b", 6..10), ("d", 3..10), ("e", 6..10)]
+        );
+        assert_eq!(comparison.diverged, vec!["forked"]);
+
+        let proof = log.prove_consistency(6, 10).unwrap();
+        assert!(head("b", 6).is_prefix_of(&head("a", 10), &proof, &hasher));
+        assert!(!forked.is_prefix_of(&head("a", 10), &proof, &hasher));
+        assert!(head("a", 10).is_prefix_of(&head("c", 10), &proof, &hasher));
+
+        // An even split is broken the same way whatever the order
+        let split = vec![forked.clone(), head("b", 6)];
+        let reversed = vec![head("b", 6), forked];
+        assert_eq!(
+            compare_heads(&split).unwrap().root,
+            compare_heads(&reversed).unwrap().root
+        );
+        assert_eq!(compare_heads::<&str>(&[]), None);
+    }
+}
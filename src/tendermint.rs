@@ -0,0 +1,220 @@
+//! Tendermint / CometBFT simple Merkle trees
+//!
+//! CometBFT commits to transactions, validator sets and block headers with
+//! `SimpleHashFromByteSlices`: a leaf is `SHA-256(0x00 || item)`, an inner
+//! node `SHA-256(0x01 || left || right)`, and a list of `n > 1` items splits
+//! after the largest power of two smaller than `n`. An empty list hashes to
+//! `SHA-256("")`. The split rule gives the same layout as
+//! [`TreeShape::Rfc6962`] and the hashing is that of RFC 6962, so these trees
+//! are regular [`MerkleTree`]s built with [`Rfc6962Hasher`].
+//!
+//! [`TendermintProof`] mirrors the Go `merkle.Proof` message: it holds the
+//! leaf's index, the number of leaves and the sibling hashes ("aunts"), but
+//! no directions, which follow from the index and total.
+
+use crate::builder::MerkleTreeBuilder;
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::{Hasher, Rfc6962Hasher};
+use crate::merkle_tree::{MerkleTree, TreeShape};
+use crate::proof::MerkleProof;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Most aunts a proof may carry, as in CometBFT
+pub const MAX_AUNTS: usize = 100;
+
+/// Build the simple Merkle tree of `items`
+///
+/// Fails for an empty list, whose root [`hash_from_byte_slices`] still
+/// defines.
+pub fn tree_from_byte_slices<T: AsRef<[u8]>>(items: &[T]) -> Result<MerkleTree<Rfc6962Hasher>> {
+    MerkleTreeBuilder::new(Rfc6962Hasher)
+        .shape(TreeShape::Rfc6962)
+        .build(items.iter().map(AsRef::as_ref).collect())
+}
+
+/// Root of `items`, as `SimpleHashFromByteSlices` computes it
+///
+/// An empty list hashes to [`Hasher::empty_root`]; any other failure to
+/// build the tree is returned.
+pub fn hash_from_byte_slices<T: AsRef<[u8]>>(items: &[T]) -> Result<RootHash> {
+    if items.is_empty() {
+        return Ok(RootHash::from(Rfc6962Hasher.empty_root()));
+    }
+    Ok(tree_from_byte_slices(items)?.root())
+}
+
+/// Root of `items` and a proof for each of them, as `ProofsFromByteSlices`
+/// computes them
+pub fn proofs_from_byte_slices<T: AsRef<[u8]>>(
+    items: &[T],
+) -> Result<(RootHash, Vec<TendermintProof>)> {
+    if items.is_empty() {
+        return Ok((hash_from_byte_slices(items)?, Vec::new()));
+    }
+    let tree = tree_from_byte_slices(items)?;
+
+    let total = items.len() as u64;
+    let proofs = (0..items.len())
+        .map(|index| {
+            let proof = tree.generate_proof(index).expect("index is in range");
+            TendermintProof {
+                total,
+                index: index as u64,
+                leaf_hash: tree.leaves()[index].clone(),
+                aunts: proof.steps.into_iter().map(|step| step.hash).collect(),
+            }
+        })
+        .collect();
+    Ok((tree.root(), proofs))
+}
+
+/// Proof of inclusion in a simple Merkle tree, in CometBFT's format
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TendermintProof {
+    /// Number of leaves in the tree
+    pub total: u64,
+    /// Index of the proven leaf
+    pub index: u64,
+    /// Hash of the proven leaf
+    pub leaf_hash: Hash,
+    /// Sibling hashes from the leaf up to the root
+    pub aunts: Vec<Hash>,
+}
+
+impl TendermintProof {
+    /// Check the proof's fields as `Proof.ValidateBasic` does
+    pub fn validate_basic(&self) -> Result<()> {
+        let invalid = |reason: String| MerkleError::InvalidProof { reason };
+        if self.leaf_hash.len() != 32 {
+            return Err(invalid(format!(
+                "Leaf hash has {} bytes, expected 32",
+                self.leaf_hash.len()
+            )));
+        }
+        if self.aunts.len() > MAX_AUNTS {
+            return Err(invalid(format!(
+                "Proof has {} aunts, at most {} are allowed",
+                self.aunts.len(),
+                MAX_AUNTS
+            )));
+        }
+        if let Some(i) = self.aunts.iter().position(|aunt| aunt.len() != 32) {
+            return Err(invalid(format!("Aunt {} is not 32 bytes long", i)));
+        }
+        Ok(())
+    }
+
+    /// Convert into a [`MerkleProof`] with its directions filled in
+    ///
+    /// The result carries `total` as its index hint, see
+    /// [`MerkleProof::verify_hinted`].
+    pub fn to_merkle_proof(&self) -> Result<MerkleProof> {
+        let (index, total) = match (usize::try_from(self.index), usize::try_from(self.total)) {
+            (Ok(index), Ok(total)) => (index, total),
+            _ => {
+                return Err(MerkleError::InvalidProof {
+                    reason: format!("Leaf {} of {} is out of range", self.index, self.total),
+                })
+            }
+        };
+        MerkleProof::from_siblings(index, total, TreeShape::Rfc6962, self.aunts.clone())
+    }
+
+    /// Recompute the root from the leaf hash and aunts
+    pub fn compute_root(&self) -> Result<RootHash> {
+        self.validate_basic()?;
+        let proof = self.to_merkle_proof()?;
+        Ok(proof.compute_root(&Rfc6962Hasher, &self.leaf_hash))
+    }
+
+    /// Verify that `leaf` is the item at `index` under `root`
+    pub fn verify(&self, root: &RootHash, leaf: &[u8]) -> bool {
+        Rfc6962Hasher.hash(leaf) == self.leaf_hash
+            && self.compute_root().is_ok_and(|computed| computed == *root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slices(items: &[&[u8]]) -> Vec<Vec<u8>> {
+        items.iter().map(|item| item.to_vec()).collect()
+    }
+
+    #[test]
+    fn test_hash_from_byte_slices() {
+        // Test vectors from CometBFT's crypto/merkle/tree_test.go
+        let cases: [(Vec<Vec<u8>>, &str); 5] = [
+            (
+                vec![],
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                slices(&[&[1, 2, 3]]),
+                "054edec1d0211f624fed0cbca9d4f9400b0e491c43742af2c5b0abebf0c990d8",
+            ),
+            (
+                slices(&[&[]]),
+                "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            ),
+            (
+                slices(&[&[1, 2, 3], &[4, 5, 6]]),
+                "82e6cfce00453804379b53962939eaa7906b39904be0813fcadd31b100773c4b",
+            ),
+            (
+                slices(&[&[1, 2], &[3, 4], &[5, 6], &[7, 8], &[9, 10]]),
+                "f326493eceab4f2d9ffbc78c59432a0a005d6ea98392045c74df5d14a113be18",
+            ),
+        ];
+
+        for (items, expected) in cases {
+            assert_eq!(hash_from_byte_slices(&items).unwrap().to_hex(), expected);
+            let (root, proofs) = proofs_from_byte_slices(&items).unwrap();
+            assert_eq!(root.to_hex(), expected);
+            assert_eq!(proofs.len(), items.len());
+        }
+    }
+
+    #[test]
+    fn test_proofs() {
+        for total in 1..=17 {
+            let items: Vec<Vec<u8>> = (0..total).map(|i| vec![i as u8; i % 5]).collect();
+            let (root, proofs) = proofs_from_byte_slices(&items).unwrap();
+            for (i, proof) in proofs.iter().enumerate() {
+                assert_eq!(proof.index, i as u64);
+                assert_eq!(proof.total, total as u64);
+                assert!(proof.verify(&root, &items[i]));
+                assert!(!proof.verify(&root, b"other"));
+
+                let merkle_proof = proof.to_merkle_proof().unwrap();
+                assert!(merkle_proof.verify_hinted(
+                    &Rfc6962Hasher,
+                    &items[i],
+                    &root,
+                    TreeShape::Rfc6962
+                ));
+            }
+        }
+
+        let items = slices(&[b"a", b"b", b"c", b"d", b"e"]);
+        let (root, proofs) = proofs_from_byte_slices(&items).unwrap();
+        let mut proof = proofs[2].clone();
+        proof.index = 3;
+        assert!(!proof.verify(&root, b"c"));
+        proof.index = 2;
+        proof.total = 3;
+        assert!(!proof.verify(&root, b"c"));
+
+        let mut proof = proofs[0].clone();
+        proof.aunts[0] = Hash::from([0u8; 31]);
+        assert!(matches!(
+            proof.validate_basic(),
+            Err(MerkleError::InvalidProof { .. })
+        ));
+        assert!(!proof.verify(&root, b"a"));
+    }
+}
@@ -0,0 +1,908 @@
+//! Urkel-style authenticated key-value store in a single append-only file
+//!
+//! [`UrkelTree`] is a base-2 Merkle trie over `hasher.hash(key)`. A leaf sits
+//! at the shallowest depth where no other key shares its path, so the root
+//! only depends on the stored keys and values. Nodes are never rewritten:
+//! every update appends the changed leaf and the internal nodes above it, and
+//! parents refer to their children by file offset. [`UrkelTree::commit`]
+//! appends a checksummed meta record naming the root; on open the file is
+//! scanned for the last valid meta record and anything after it is discarded,
+//! so a crash loses at most the uncommitted updates. Superseded nodes are
+//! reclaimed with [`UrkelTree::compact`].
+//!
+//! Hashes are domain-separated: a leaf is `H(0x00 || key || H(value))`, an
+//! internal node `H(0x01 || left || right)` and an empty subtree all zeroes.
+//! Unlike [`SparseMerkleTree`](crate::SparseMerkleTree) the store only holds
+//! what is on the path to its leaves, so it scales to key sets far larger
+//! than memory.
+
+use crate::error::{MerkleError, Result};
+use crate::hash::{Hash, RootHash};
+use crate::hasher::Hasher;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const TAG_LEAF: u8 = 1;
+const TAG_INTERNAL: u8 = 2;
+const TAG_META: u8 = 3;
+
+const KIND_EMPTY: u8 = 0;
+const KIND_LEAF: u8 = 1;
+const KIND_INTERNAL: u8 = 2;
+
+const LEAF_PREFIX: u8 = 0x00;
+const INTERNAL_PREFIX: u8 = 0x01;
+
+/// Reference from a parent to a child node
+#[derive(Debug, Clone, PartialEq)]
+enum Child {
+    Empty,
+    Leaf { pos: u64, hash: Hash },
+    Internal { pos: u64, hash: Hash },
+}
+
+/// Node read back from the file
+enum Node {
+    Leaf { key: Hash, value: Vec<u8> },
+    Internal { left: Child, right: Child },
+}
+
+/// How a proof ends below its last sibling
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UrkelTerminal {
+    /// The key is stored with a value of this hash
+    Exists { value_hash: Hash },
+    /// The path ends in the leaf of another key, so the key is absent
+    ///
+    /// As in Jellyfish and Aptos proofs, the conflicting leaf is embedded so
+    /// the verifier can check that its key hash differs from the queried one
+    /// while sharing the path down to it.
+    Collision { key: Hash, value_hash: Hash },
+    /// The path ends in an empty subtree, so the key is absent
+    Empty,
+}
+
+/// Membership or non-membership proof for a key of an [`UrkelTree`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UrkelProof {
+    /// Sibling hashes from the root downwards
+    pub siblings: Vec<Hash>,
+    pub terminal: UrkelTerminal,
+}
+
+impl UrkelProof {
+    /// Verify the proof for `key` against a root
+    ///
+    /// With `Some(value)` this checks that the key is stored with that value,
+    /// with `None` that the key is absent.
+    pub fn verify<H: Hasher>(
+        &self,
+        hasher: &H,
+        root: &RootHash,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> bool {
+        let key = hasher.hash(key);
+        if self.siblings.len() >= key.len() * 8 {
+            return false;
+        }
+
+        let terminal = match (&self.terminal, value) {
+            (UrkelTerminal::Exists { value_hash }, Some(value)) => {
+                if *value_hash != hasher.hash(value) {
+                    return false;
+                }
+                leaf_hash(hasher, &key, value_hash)
+            }
+            (
+                UrkelTerminal::Collision {
+                    key: other,
+                    value_hash,
+                },
+                None,
+            ) => {
+                // The other leaf must sit on this key's path without being it
+                let depth = self.siblings.len();
+                if *other == key
+                    || other.len() != key.len()
+                    || (0..depth).any(|i| bit(other, i) != bit(&key, i))
+                {
+                    return false;
+                }
+                leaf_hash(hasher, other, value_hash)
+            }
+            (UrkelTerminal::Empty, None) => empty_hash(hasher),
+            _ => return false,
+        };
+
+        let computed =
+            self.siblings
+                .iter()
+                .enumerate()
+                .rev()
+                .fold(terminal, |node, (depth, sibling)| {
+                    if bit(&key, depth) {
+                        internal_hash(hasher, sibling, &node)
+                    } else {
+                        internal_hash(hasher, &node, sibling)
+                    }
+                });
+        computed == *root.as_hash()
+    }
+}
+
+/// Authenticated key-value store backed by an append-only node file
+///
+/// Updates are buffered in memory until [`commit`](Self::commit) writes them
+/// out together with the new root.
+///
+/// ```rust
+/// use merkle_tree::{Sha256Hasher, UrkelTree};
+///
+/// # fn main() -> merkle_tree::Result<()> {
+/// # let path = std::env::temp_dir().join(format!("merkle-urkel-doc-{}", std::process::id()));
+/// let mut tree = UrkelTree::open(&path, Sha256Hasher::new())?;
+/// tree.insert(b"alice", b"100")?;
+/// tree.insert(b"bob", b"42")?;
+/// let root = tree.commit()?;
+///
+/// let proof = tree.prove(b"alice")?;
+/// assert!(proof.verify(tree.hasher(), &root, b"alice", Some(b"100")));
+/// let proof = tree.prove(b"carol")?;
+/// assert!(proof.verify(tree.hasher(), &root, b"carol", None));
+/// # std::fs::remove_file(&path).ok();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct UrkelTree<H: Hasher> {
+    hasher: H,
+    path: PathBuf,
+    file: File,
+    /// Length of the file; offsets past it refer to `pending`
+    flushed: u64,
+    /// Records appended since the last commit
+    pending: Vec<u8>,
+    root: Child,
+}
+
+impl<H: Hasher> UrkelTree<H> {
+    /// Open the store at `path`, creating an empty one if the file is missing
+    ///
+    /// The whole file is scanned for the last committed root. Records after
+    /// it, left behind by a crash or by updates that were never committed,
+    /// are truncated away.
+    pub fn open(path: impl Into<PathBuf>, hasher: H) -> Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+
+        let (root, committed) = scan(&hasher, &file)?;
+        file.set_len(committed).map_err(io_error)?;
+
+        Ok(Self {
+            hasher,
+            path,
+            file,
+            flushed: committed,
+            pending: Vec::new(),
+            root,
+        })
+    }
+
+    /// Get the root hash, including uncommitted updates
+    pub fn root(&self) -> RootHash {
+        RootHash::from(self.child_hash(&self.root))
+    }
+
+    /// Check if the store holds no keys
+    pub fn is_empty(&self) -> bool {
+        self.root == Child::Empty
+    }
+
+    /// Get the hasher used by this store
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the path of the node file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the size of the node file in bytes, including uncommitted records
+    pub fn file_size(&self) -> u64 {
+        self.flushed + self.pending.len() as u64
+    }
+
+    /// Get the value stored under `key`
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.hasher.hash(key);
+        let mut node = self.root.clone();
+        let mut depth = 0;
+        loop {
+            match self.read_node(&node)? {
+                None => return Ok(None),
+                Some(Node::Leaf { key: found, value }) => {
+                    return Ok((found == key).then_some(value));
+                }
+                Some(Node::Internal { left, right }) => {
+                    node = if bit(&key, depth) { right } else { left };
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Check if `key` is stored
+    pub fn contains(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Insert or replace the value stored under `key`
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let key = self.hasher.hash(key);
+        let root = self.root.clone();
+        self.root = self.insert_at(root, 0, &key, value)?;
+        Ok(())
+    }
+
+    /// Remove `key`, returning whether it was stored
+    pub fn remove(&mut self, key: &[u8]) -> Result<bool> {
+        let key = self.hasher.hash(key);
+        let root = self.root.clone();
+        match self.remove_at(root, 0, &key)? {
+            Some(root) => {
+                self.root = root;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Generate a membership or non-membership proof for `key`
+    pub fn prove(&self, key: &[u8]) -> Result<UrkelProof> {
+        let key = self.hasher.hash(key);
+        let mut siblings = Vec::new();
+        let mut node = self.root.clone();
+        loop {
+            let terminal = match self.read_node(&node)? {
+                None => UrkelTerminal::Empty,
+                Some(Node::Leaf { key: found, value }) => {
+                    let value_hash = self.hasher.hash(&value);
+                    if found == key {
+                        UrkelTerminal::Exists { value_hash }
+                    } else {
+                        UrkelTerminal::Collision {
+                            key: found,
+                            value_hash,
+                        }
+                    }
+                }
+                Some(Node::Internal { left, right }) => {
+                    let (next, sibling) = if bit(&key, siblings.len()) {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    siblings.push(self.child_hash(&sibling));
+                    node = next;
+                    continue;
+                }
+            };
+            return Ok(UrkelProof { siblings, terminal });
+        }
+    }
+
+    /// Write buffered updates and a meta record naming the current root
+    ///
+    /// Returns the committed root. The file is synced before returning.
+    pub fn commit(&mut self) -> Result<RootHash> {
+        let meta = meta_record(&self.hasher, &self.root);
+        self.pending.extend_from_slice(&meta);
+
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.flushed)).map_err(io_error)?;
+        file.write_all(&self.pending).map_err(io_error)?;
+        file.sync_data().map_err(io_error)?;
+
+        self.flushed += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(self.root())
+    }
+
+    /// Rewrite the file with only the nodes reachable from the current root
+    ///
+    /// The live nodes are copied to a temporary file next to the store, which
+    /// then replaces it. This also commits the current root.
+    pub fn compact(&mut self) -> Result<()> {
+        let mut tmp_name = self.path.clone().into_os_string();
+        tmp_name.push(".compact");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let mut records = Vec::new();
+        let root = self.copy_live(&self.root, &mut records)?;
+        records.extend_from_slice(&meta_record(&self.hasher, &root));
+
+        let mut tmp = File::create(&tmp_path).map_err(io_error)?;
+        tmp.write_all(&records).map_err(io_error)?;
+        tmp.sync_data().map_err(io_error)?;
+        drop(tmp);
+        fs::rename(&tmp_path, &self.path).map_err(io_error)?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .map_err(io_error)?;
+        self.flushed = records.len() as u64;
+        self.pending.clear();
+        self.root = root;
+        Ok(())
+    }
+
+    fn insert_at(&mut self, node: Child, depth: usize, key: &Hash, value: &[u8]) -> Result<Child> {
+        match self.read_node(&node)? {
+            None => self.write_leaf(key, value),
+            Some(Node::Leaf { key: other, .. }) if other == *key => {
+                let value_hash = self.hasher.hash(value);
+                if self.child_hash(&node) == leaf_hash(&self.hasher, key, &value_hash) {
+                    return Ok(node);
+                }
+                self.write_leaf(key, value)
+            }
+            Some(Node::Leaf { key: other, .. }) => {
+                let leaf = self.write_leaf(key, value)?;
+                self.split(depth, key, leaf, &other, node)
+            }
+            Some(Node::Internal { left, right }) => {
+                if bit(key, depth) {
+                    let right = self.insert_at(right, depth + 1, key, value)?;
+                    Ok(self.write_internal(left, right))
+                } else {
+                    let left = self.insert_at(left, depth + 1, key, value)?;
+                    Ok(self.write_internal(left, right))
+                }
+            }
+        }
+    }
+
+    /// Join two leaves under internal nodes down to the bit where their keys
+    /// first differ
+    fn split(
+        &mut self,
+        depth: usize,
+        key: &Hash,
+        leaf: Child,
+        other_key: &Hash,
+        other: Child,
+    ) -> Result<Child> {
+        if depth >= key.len() * 8 {
+            return Err(MerkleError::TreeConstructionError {
+                reason: "Keys with distinct hashes never diverged".to_string(),
+            });
+        }
+
+        let goes_right = bit(key, depth);
+        if goes_right != bit(other_key, depth) {
+            return Ok(if goes_right {
+                self.write_internal(other, leaf)
+            } else {
+                self.write_internal(leaf, other)
+            });
+        }
+
+        let child = self.split(depth + 1, key, leaf, other_key, other)?;
+        Ok(if goes_right {
+            self.write_internal(Child::Empty, child)
+        } else {
+            self.write_internal(child, Child::Empty)
+        })
+    }
+
+    /// Remove `key` below `node`, or `None` if it is not there
+    ///
+    /// A leaf left alone in its subtree moves up to replace the subtree, so
+    /// the shape stays the one a fresh insertion of the remaining keys gives.
+    fn remove_at(&mut self, node: Child, depth: usize, key: &Hash) -> Result<Option<Child>> {
+        match self.read_node(&node)? {
+            None => Ok(None),
+            Some(Node::Leaf { key: found, .. }) => Ok((found == *key).then_some(Child::Empty)),
+            Some(Node::Internal { left, right }) => {
+                let goes_right = bit(key, depth);
+                let (target, sibling) = if goes_right {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                let Some(target) = self.remove_at(target, depth + 1, key)? else {
+                    return Ok(None);
+                };
+
+                let collapsed = match (&target, &sibling) {
+                    (Child::Empty, Child::Empty | Child::Leaf { .. }) => Some(sibling.clone()),
+                    (Child::Leaf { .. }, Child::Empty) => Some(target.clone()),
+                    _ => None,
+                };
+                Ok(Some(match collapsed {
+                    Some(child) => child,
+                    None if goes_right => self.write_internal(sibling, target),
+                    None => self.write_internal(target, sibling),
+                }))
+            }
+        }
+    }
+
+    /// Append the nodes reachable from `node` to `records`, as laid out in a
+    /// file that starts with them
+    fn copy_live(&self, node: &Child, records: &mut Vec<u8>) -> Result<Child> {
+        match self.read_node(node)? {
+            None => Ok(Child::Empty),
+            Some(Node::Leaf { key, value }) => {
+                let pos = records.len() as u64;
+                records.extend_from_slice(&leaf_record(&key, &value)?);
+                Ok(Child::Leaf {
+                    pos,
+                    hash: self.child_hash(node),
+                })
+            }
+            Some(Node::Internal { left, right }) => {
+                let left = self.copy_live(&left, records)?;
+                let right = self.copy_live(&right, records)?;
+                let pos = records.len() as u64;
+                records.extend_from_slice(&internal_record(
+                    &left,
+                    &right,
+                    self.hasher.output_size(),
+                ));
+                Ok(Child::Internal {
+                    pos,
+                    hash: self.child_hash(node),
+                })
+            }
+        }
+    }
+
+    fn write_leaf(&mut self, key: &Hash, value: &[u8]) -> Result<Child> {
+        let record = leaf_record(key, value)?;
+        let hash = leaf_hash(&self.hasher, key, &self.hasher.hash(value));
+        Ok(Child::Leaf {
+            pos: self.append(&record),
+            hash,
+        })
+    }
+
+    fn write_internal(&mut self, left: Child, right: Child) -> Child {
+        let hash = internal_hash(
+            &self.hasher,
+            &self.child_hash(&left),
+            &self.child_hash(&right),
+        );
+        let record = internal_record(&left, &right, self.hasher.output_size());
+        Child::Internal {
+            pos: self.append(&record),
+            hash,
+        }
+    }
+
+    fn append(&mut self, record: &[u8]) -> u64 {
+        let pos = self.file_size();
+        self.pending.extend_from_slice(record);
+        pos
+    }
+
+    fn child_hash(&self, child: &Child) -> Hash {
+        match child {
+            Child::Empty => empty_hash(&self.hasher),
+            Child::Leaf { hash, .. } | Child::Internal { hash, .. } => hash.clone(),
+        }
+    }
+
+    fn read_node(&self, child: &Child) -> Result<Option<Node>> {
+        let hash_size = self.hasher.output_size();
+        match *child {
+            Child::Empty => Ok(None),
+            Child::Leaf { pos, .. } => {
+                let header = self.read_at(pos, 1 + hash_size + 4)?;
+                let len = u32::from_be_bytes(header[1 + hash_size..].try_into().unwrap());
+                let value = self.read_at(pos + header.len() as u64, len as usize)?;
+                Ok(Some(Node::Leaf {
+                    key: Hash::from(&header[1..1 + hash_size]),
+                    value,
+                }))
+            }
+            Child::Internal { pos, .. } => {
+                let child_size = 9 + hash_size;
+                let record = self.read_at(pos, 1 + 2 * child_size)?;
+                Ok(Some(Node::Internal {
+                    left: decode_child(&record[1..1 + child_size])?,
+                    right: decode_child(&record[1 + child_size..])?,
+                }))
+            }
+        }
+    }
+
+    fn read_at(&self, pos: u64, len: usize) -> Result<Vec<u8>> {
+        if pos >= self.flushed {
+            let start = (pos - self.flushed) as usize;
+            return self
+                .pending
+                .get(start..start + len)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| corrupt(pos));
+        }
+
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(pos)).map_err(io_error)?;
+        let mut bytes = vec![0u8; len];
+        file.read_exact(&mut bytes).map_err(|_| corrupt(pos))?;
+        Ok(bytes)
+    }
+}
+
+/// Find the last valid meta record, returning its root and the file length
+/// up to and including it
+fn scan<H: Hasher>(hasher: &H, file: &File) -> Result<(Child, u64)> {
+    let hash_size = hasher.output_size();
+    let child_size = 9 + hash_size;
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0)).map_err(io_error)?;
+
+    let mut root = Child::Empty;
+    let mut committed = 0;
+    let mut pos = 0;
+    loop {
+        // A record cut short is the torn tail of an interrupted write
+        let record = match read_record(&mut reader, hash_size, child_size) {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(io_error(e)),
+        };
+        pos += record.len() as u64;
+
+        if record[0] == TAG_META {
+            let body = &record[..1 + child_size];
+            if hasher.hash(body).as_ref() != &record[1 + child_size..] {
+                break;
+            }
+            root = match decode_child(&body[1..]) {
+                Ok(child) => child,
+                Err(_) => break,
+            };
+            committed = pos;
+        }
+    }
+
+    Ok((root, committed))
+}
+
+/// Read one whole record, or `None` at the end of the file or at an unknown
+/// tag
+fn read_record<R: Read>(
+    reader: &mut R,
+    hash_size: usize,
+    child_size: usize,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let mut tag = [0u8; 1];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+
+    let rest = match tag[0] {
+        TAG_LEAF => {
+            let mut header = vec![0u8; hash_size + 4];
+            reader.read_exact(&mut header)?;
+            let len = u32::from_be_bytes(header[hash_size..].try_into().unwrap()) as usize;
+            let mut value = vec![0u8; len];
+            reader.read_exact(&mut value)?;
+            header.extend_from_slice(&value);
+            header
+        }
+        TAG_INTERNAL => {
+            let mut body = vec![0u8; 2 * child_size];
+            reader.read_exact(&mut body)?;
+            body
+        }
+        TAG_META => {
+            let mut body = vec![0u8; child_size + hash_size];
+            reader.read_exact(&mut body)?;
+            body
+        }
+        _ => return Ok(None),
+    };
+
+    let mut record = tag.to_vec();
+    record.extend_from_slice(&rest);
+    Ok(Some(record))
+}
+
+fn leaf_record(key: &Hash, value: &[u8]) -> Result<Vec<u8>> {
+    let len = u32::try_from(value.len()).map_err(|_| MerkleError::SerializationError {
+        message: format!("Value of {} bytes is too large", value.len()),
+    })?;
+
+    let mut record = Vec::with_capacity(1 + key.len() + 4 + value.len());
+    record.push(TAG_LEAF);
+    record.extend_from_slice(key);
+    record.extend_from_slice(&len.to_be_bytes());
+    record.extend_from_slice(value);
+    Ok(record)
+}
+
+fn internal_record(left: &Child, right: &Child, hash_size: usize) -> Vec<u8> {
+    let mut record = vec![TAG_INTERNAL];
+    encode_child(left, hash_size, &mut record);
+    encode_child(right, hash_size, &mut record);
+    record
+}
+
+/// Meta record naming `root`, followed by a checksum of the record
+fn meta_record<H: Hasher>(hasher: &H, root: &Child) -> Vec<u8> {
+    let mut record = vec![TAG_META];
+    encode_child(root, hasher.output_size(), &mut record);
+    let checksum = hasher.hash(&record);
+    record.extend_from_slice(&checksum);
+    record
+}
+
+/// Encode a child as its kind, big-endian offset and hash, with a zero
+/// offset and hash for empty subtrees
+fn encode_child(child: &Child, hash_size: usize, out: &mut Vec<u8>) {
+    match child {
+        Child::Empty => {
+            out.push(KIND_EMPTY);
+            out.resize(out.len() + 8 + hash_size, 0);
+        }
+        Child::Leaf { pos, hash } | Child::Internal { pos, hash } => {
+            let kind = if matches!(child, Child::Leaf { .. }) {
+                KIND_LEAF
+            } else {
+                KIND_INTERNAL
+            };
+            out.push(kind);
+            out.extend_from_slice(&pos.to_be_bytes());
+            out.extend_from_slice(hash);
+        }
+    }
+}
+
+fn decode_child(bytes: &[u8]) -> Result<Child> {
+    let pos = u64::from_be_bytes(bytes[1..9].try_into().unwrap());
+    let hash = Hash::from(&bytes[9..]);
+    match bytes[0] {
+        KIND_EMPTY => Ok(Child::Empty),
+        KIND_LEAF => Ok(Child::Leaf { pos, hash }),
+        KIND_INTERNAL => Ok(Child::Internal { pos, hash }),
+        kind => Err(MerkleError::SerializationError {
+            message: format!("Unknown node kind {}", kind),
+        }),
+    }
+}
+
+/// Bit `index` of `key`, most significant bit first
+pub(crate) fn bit(key: &[u8], index: usize) -> bool {
+    key[index / 8] >> (7 - index % 8) & 1 == 1
+}
+
+pub(crate) fn leaf_hash<H: Hasher>(hasher: &H, key: &Hash, value_hash: &Hash) -> Hash {
+    let mut input = Vec::with_capacity(1 + key.len() + value_hash.len());
+    input.push(LEAF_PREFIX);
+    input.extend_from_slice(key);
+    input.extend_from_slice(value_hash);
+    hasher.hash(&input)
+}
+
+pub(crate) fn internal_hash<H: Hasher>(hasher: &H, left: &Hash, right: &Hash) -> Hash {
+    let mut input = Vec::with_capacity(1 + left.len() + right.len());
+    input.push(INTERNAL_PREFIX);
+    input.extend_from_slice(left);
+    input.extend_from_slice(right);
+    hasher.hash(&input)
+}
+
+pub(crate) fn empty_hash<H: Hasher>(hasher: &H) -> Hash {
+    Hash::new(vec![0u8; hasher.output_size()])
+}
+
+fn corrupt(pos: u64) -> MerkleError {
+    MerkleError::SerializationError {
+        message: format!("No node record at offset {}", pos),
+    }
+}
+
+fn io_error(e: std::io::Error) -> MerkleError {
+    MerkleError::IoError {
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+
+    /// Fresh file path under the system temp dir, removed on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("merkle-urkel-{}-{}", std::process::id(), name));
+            fs::remove_file(&path).ok();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+        }
+    }
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("key_{}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_insert_get_prove() {
+        let file = TempFile::new("prove");
+        let hasher = Sha256Hasher::new();
+        let mut tree = UrkelTree::open(&file.0, hasher.clone()).unwrap();
+        assert!(tree.is_empty());
+        assert_eq!(tree.root().as_ref(), &[0u8; 32]);
+
+        for i in 0..100 {
+            tree.insert(&key(i), &i.to_be_bytes()).unwrap();
+        }
+        let root = tree.root();
+
+        for i in 0..100 {
+            assert_eq!(tree.get(&key(i)).unwrap(), Some(i.to_be_bytes().to_vec()));
+            let proof = tree.prove(&key(i)).unwrap();
+            assert!(proof.verify(&hasher, &root, &key(i), Some(&i.to_be_bytes())));
+            assert!(!proof.verify(&hasher, &root, &key(i), Some(b"other")));
+            assert!(!proof.verify(&hasher, &root, &key(i), None));
+        }
+
+        for i in 100..150 {
+            assert_eq!(tree.get(&key(i)).unwrap(), None);
+            let proof = tree.prove(&key(i)).unwrap();
+            assert!(proof.verify(&hasher, &root, &key(i), None));
+            assert!(!proof.verify(&hasher, &root, &key(i), Some(b"value")));
+        }
+    }
+
+    #[test]
+    fn test_collision_proofs() {
+        let file = TempFile::new("collision");
+        let hasher = Sha256Hasher::new();
+        let mut tree = UrkelTree::open(&file.0, hasher.clone()).unwrap();
+        tree.insert(b"stored", b"value").unwrap();
+        let root = tree.root();
+
+        // With a single leaf every absent key's path ends in it
+        let proof = tree.prove(b"absent").unwrap();
+        assert!(proof.siblings.is_empty());
+        assert_eq!(
+            proof.terminal,
+            UrkelTerminal::Collision {
+                key: hasher.hash(b"stored"),
+                value_hash: hasher.hash(b"value"),
+            }
+        );
+        assert!(proof.verify(&hasher, &root, b"absent", None));
+        assert!(!proof.verify(&hasher, &root, b"stored", None));
+
+        // The embedded leaf must lie on the queried key's path
+        for i in 0..50 {
+            tree.insert(&key(i), b"value").unwrap();
+        }
+        let root = tree.root();
+        let proof = (50..)
+            .map(|i| (i, tree.prove(&key(i)).unwrap()))
+            .find(|(_, proof)| matches!(proof.terminal, UrkelTerminal::Collision { .. }));
+        let (absent, proof) = proof.unwrap();
+        assert!(proof.verify(&hasher, &root, &key(absent), None));
+        let mut moved = proof.clone();
+        if let UrkelTerminal::Collision { key: other, .. } = &mut moved.terminal {
+            let mut bytes = other.to_vec();
+            bytes[0] ^= 0x80;
+            *other = Hash::new(bytes);
+        }
+        assert!(!moved.verify(&hasher, &root, &key(absent), None));
+    }
+
+    #[test]
+    fn test_root_is_order_independent() {
+        let (a, b) = (TempFile::new("order-a"), TempFile::new("order-b"));
+        let mut forward = UrkelTree::open(&a.0, Sha256Hasher::new()).unwrap();
+        let mut backward = UrkelTree::open(&b.0, Sha256Hasher::new()).unwrap();
+        for i in 0..50 {
+            forward.insert(&key(i), b"v").unwrap();
+            backward.insert(&key(49 - i), b"v").unwrap();
+        }
+        assert_eq!(forward.root(), backward.root());
+
+        // Removing keys gives the same root as never inserting them
+        for i in 25..50 {
+            assert!(forward.remove(&key(i)).unwrap());
+        }
+        assert!(!forward.remove(&key(25)).unwrap());
+        let c = TempFile::new("order-c");
+        let mut fresh = UrkelTree::open(&c.0, Sha256Hasher::new()).unwrap();
+        for i in 0..25 {
+            fresh.insert(&key(i), b"v").unwrap();
+        }
+        assert_eq!(forward.root(), fresh.root());
+
+        for i in 0..25 {
+            forward.remove(&key(i)).unwrap();
+        }
+        assert!(forward.is_empty());
+    }
+
+    #[test]
+    fn test_commit_and_reopen() {
+        let file = TempFile::new("reopen");
+        let mut tree = UrkelTree::open(&file.0, Sha256Hasher::new()).unwrap();
+        tree.insert(b"a", b"1").unwrap();
+        tree.insert(b"b", b"2").unwrap();
+        let committed = tree.commit().unwrap();
+
+        // Uncommitted updates are lost on reopen
+        tree.insert(b"c", b"3").unwrap();
+        drop(tree);
+        let mut tree = UrkelTree::open(&file.0, Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), committed);
+        assert_eq!(tree.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(tree.get(b"c").unwrap(), None);
+
+        // So is a torn tail after the last commit
+        tree.insert(b"c", b"3").unwrap();
+        tree.commit().unwrap();
+        let size = tree.file_size();
+        drop(tree);
+        let mut bytes = fs::read(&file.0).unwrap();
+        bytes.truncate(size as usize - 1);
+        fs::write(&file.0, &bytes).unwrap();
+        let tree = UrkelTree::open(&file.0, Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), committed);
+    }
+
+    #[test]
+    fn test_compact() {
+        let file = TempFile::new("compact");
+        let mut tree = UrkelTree::open(&file.0, Sha256Hasher::new()).unwrap();
+        for round in 0..5u32 {
+            for i in 0..20 {
+                tree.insert(&key(i), &round.to_be_bytes()).unwrap();
+            }
+            tree.commit().unwrap();
+        }
+        let root = tree.root();
+        let size = tree.file_size();
+
+        tree.compact().unwrap();
+        assert!(tree.file_size() < size);
+        assert_eq!(tree.root(), root);
+        assert_eq!(
+            tree.get(&key(7)).unwrap(),
+            Some(4u32.to_be_bytes().to_vec())
+        );
+
+        drop(tree);
+        let tree = UrkelTree::open(&file.0, Sha256Hasher::new()).unwrap();
+        assert_eq!(tree.root(), root);
+    }
+}
@@ -1,6 +1,6 @@
 use merkle_tree::{
-    sparse, Blake3Hasher, MerkleError, MerkleTree, Result, Sha256Hasher, Sha3Hasher,
-    SparseMerkleTree,
+    Blake3Hasher, Blake3XofHasher, MerkleError, MerkleProof, MerkleTree, Result, SerializedTree,
+    Sha256Hasher, Sha3Hasher, SparseMerkleTree, XofHasher,
 };
 
 #[cfg(test)]
@@ -67,6 +67,56 @@ mod integration_tests {
         }
     }
 
+    #[test]
+    fn test_xof_output_length() {
+        let hasher = XofHasher::shake256(64);
+        let data: Vec<String> = (0..7).map(|i| format!("item_{}", i)).collect();
+        let tree = MerkleTree::new(data, hasher.clone()).unwrap();
+        assert_eq!(tree.root().len(), 64);
+        assert!(tree.leaves().iter().all(|hash| hash.len() == 64));
+        assert_eq!(tree.verify_integrity(), Ok(()));
+
+        let proof = tree.generate_proof(4).unwrap();
+        assert!(proof.steps.iter().all(|step| step.hash.len() == 64));
+        assert!(proof.verify(&hasher, b"item_4", &tree.root()));
+
+        let mut proof_bytes = Vec::new();
+        proof.serialize_into(&mut proof_bytes).unwrap();
+        let root = tree.root();
+        assert!(
+            MerkleProof::verify_streaming(proof_bytes.as_slice(), &hasher, b"item_4", &root)
+                .unwrap()
+        );
+        let shorter = XofHasher::shake256(32);
+        assert!(
+            MerkleProof::verify_streaming(proof_bytes.as_slice(), &shorter, b"item_4", &root)
+                .is_err()
+        );
+
+        // Serialized trees keep the length, and another length is refused
+        let mut tree_bytes = Vec::new();
+        tree.serialize_into(&mut tree_bytes).unwrap();
+        let cursor = std::io::Cursor::new(tree_bytes.clone());
+        let mut stored = SerializedTree::from_reader(cursor, hasher.clone()).unwrap();
+        assert_eq!(stored.generate_proof(4).unwrap(), proof);
+        let cursor = std::io::Cursor::new(tree_bytes.clone());
+        assert!(SerializedTree::from_reader(cursor, shorter.clone())
+            .and_then(|mut stored| stored.generate_proof(4))
+            .is_err());
+        let restored = MerkleTree::deserialize_from(tree_bytes.as_slice(), shorter).unwrap();
+        assert!(restored.verify_integrity().is_err());
+
+        let hasher = Blake3XofHasher::new(48);
+        let mut sparse = SparseMerkleTree::new(16, hasher.clone()).unwrap();
+        sparse.update(9, b"value").unwrap();
+        let root = sparse.root().clone();
+        assert_eq!(root.len(), 48);
+        let proof = sparse.generate_proof(9).unwrap();
+        assert!(proof.verify(&hasher, b"value", &root));
+        let compressed = sparse.generate_sparse_proof(9).unwrap();
+        assert!(compressed.verify(&hasher, b"value", &root));
+    }
+
     #[test]
     fn test_sparse_vs_regular_tree() {
         let hasher = Sha256Hasher::new();
@@ -137,8 +187,9 @@ mod integration_tests {
         }
 
         // Verify empty slots
-        let empty_proof = tree.generate_proof(50).unwrap();
-        assert!(tree.verify_proof(&empty_proof, 50, &sparse::DEFAULT_HASH));
+        let root = tree.root().clone();
+        let empty_proof = tree.prove_empty(50).unwrap();
+        assert!(tree.verify_empty(&root, 50, &empty_proof));
     }
 
     #[test]
@@ -151,7 +202,7 @@ mod integration_tests {
         // Test hex representation
         let hex_repr = proof.to_hex();
         assert!(hex_repr.contains("index:1"));
-        assert!(hex_repr.len() > 0);
+        assert!(!hex_repr.is_empty());
 
         // Verify proof still works
         assert!(tree.verify_proof_against_root(&proof, "world".as_bytes()));
@@ -198,7 +249,7 @@ mod integration_tests {
         assert_eq!(stats.leaf_count, 5);
         assert!(stats.tree_height > 0);
         assert_eq!(stats.hasher_name, "BLAKE3");
-        assert!(stats.root_hash.len() > 0);
+        assert!(!stats.root_hash.is_empty());
 
         // Test sparse tree stats
         let mut sparse_tree = SparseMerkleTree::new(10, Sha3Hasher::new()).unwrap();
@@ -328,8 +379,46 @@ mod integration_tests {
         for tree in [&pow2_tree, &non_pow2_tree] {
             for i in 0..tree.len() {
                 let proof = tree.generate_proof(i).unwrap();
-                assert!(proof.len() > 0 || tree.len() == 1);
+                assert!(!proof.is_empty() || tree.len() == 1);
             }
         }
     }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_merkle_leaf() {
+        use merkle_tree::MerkleLeaf;
+
+        #[derive(MerkleLeaf)]
+        struct Named {
+            id: u32,
+            name: String,
+            tags: Vec<String>,
+        }
+
+        #[derive(MerkleLeaf)]
+        struct Tuple(u8, Option<bool>);
+
+        #[derive(MerkleLeaf)]
+        struct Generic<T> {
+            value: T,
+        }
+
+        #[derive(MerkleLeaf)]
+        struct Unit;
+
+        let named = Named {
+            id: 1,
+            name: "a".to_string(),
+            tags: vec!["x".to_string()],
+        };
+        let mut expected = vec![0, 0, 0, 1];
+        expected.extend_from_slice(&[0, 0, 0, 1, b'a']);
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, b'x']);
+        assert_eq!(named.to_leaf_bytes(), expected);
+
+        assert_eq!(Tuple(5, Some(false)).to_leaf_bytes(), [5, 1, 0]);
+        assert_eq!(Generic { value: 2u16 }.to_leaf_bytes(), [0, 2]);
+        assert!(Unit.to_leaf_bytes().is_empty());
+    }
 }